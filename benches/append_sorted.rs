@@ -0,0 +1,68 @@
+// ascending keys per second written one put at a time and as append_sorted
+// batches, for each memtable rep. the entries are made before the clock
+// starts, so that only the writes are timed. run with
+// `cargo bench --bench append_sorted`
+use std::time::Instant;
+
+use mini_lsm::{Db, DbOptions, Entry, MemTableRepType};
+use tempfile::tempdir;
+
+const NUM_KEYS: usize = 200_000;
+const BATCH_SIZE: usize = 1_000;
+
+fn entries() -> Vec<Entry> {
+    (0..NUM_KEYS)
+        .map(|i| Entry::new(format!("key{:08}", i), format!("value{}", i)))
+        .collect()
+}
+
+// keys written per second
+fn measure_writes(memtable_rep: MemTableRepType, write: fn(&Db, Vec<Entry>)) -> f64 {
+    let dir = tempdir().unwrap();
+    let options = DbOptions {
+        path: dir.path().to_owned(),
+        memtable_rep,
+        // one memtable holds every key, so no flush runs while timing
+        sst_max_size_bytes: 64 * 1024 * 1024,
+        ..Default::default()
+    };
+    let store = Db::open(options).unwrap();
+    let entries = entries();
+    let started = Instant::now();
+    write(&store, entries);
+    let elapsed = started.elapsed();
+    store.close().unwrap();
+    NUM_KEYS as f64 / elapsed.as_secs_f64()
+}
+
+fn put(store: &Db, entries: Vec<Entry>) {
+    for entry in entries {
+        store.put(entry.key, entry.value).unwrap();
+    }
+}
+
+fn append_sorted(store: &Db, entries: Vec<Entry>) {
+    let mut entries = entries.into_iter();
+    loop {
+        let batch: Vec<Entry> = entries.by_ref().take(BATCH_SIZE).collect();
+        if batch.is_empty() {
+            break;
+        }
+        store.append_sorted(batch).unwrap();
+    }
+}
+
+fn main() {
+    for memtable_rep in [
+        MemTableRepType::SkipList,
+        MemTableRepType::BTreeMap,
+        MemTableRepType::HashSharded,
+    ] {
+        let puts = measure_writes(memtable_rep, put);
+        let appends = measure_writes(memtable_rep, append_sorted);
+        println!(
+            "{:?}: {:>10.0} keys/s with put, {:>10.0} keys/s with append_sorted",
+            memtable_rep, puts, appends
+        );
+    }
+}
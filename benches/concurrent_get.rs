@@ -0,0 +1,116 @@
+// gets and short scans per second from several reader threads, alone and
+// while a writer keeps putting, freezing and flushing memtables, with a
+// single block cache and with a sharded one. each scan takes a snapshot, so
+// the mixed scan case shows whether snapshots wait on the writer. run with
+// `cargo bench --bench concurrent_get`
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use mini_lsm::{Db, DbOptions};
+use tempfile::tempdir;
+
+const NUM_KEYS: usize = 10_000;
+const SCAN_LEN: usize = 10;
+const RUN_TIME: Duration = Duration::from_secs(2);
+
+fn key(i: usize) -> String {
+    format!("key{:08}", i)
+}
+
+fn get(store: &Db, i: usize) {
+    store.get(key(i % NUM_KEYS)).unwrap();
+}
+
+fn scan(store: &Db, i: usize) {
+    let start = i % (NUM_KEYS - SCAN_LEN);
+    let num_keys = store.scan(key(start)..key(start + SCAN_LEN)).unwrap().count();
+    assert_eq!(num_keys, SCAN_LEN);
+}
+
+// total reads per second across num_readers threads
+fn measure_reads(store: &Arc<Db>, num_readers: usize, with_writer: bool, read: fn(&Db, usize)) -> f64 {
+    let stop = Arc::new(AtomicBool::new(false));
+    let writer = with_writer.then(|| {
+        let store = store.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut i = 0;
+            while !stop.load(Ordering::Relaxed) {
+                store.put(key(i % NUM_KEYS), format!("value{}", i)).unwrap();
+                i += 1;
+            }
+        })
+    });
+    let readers: Vec<_> = (0..num_readers)
+        .map(|reader| {
+            let store = store.clone();
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut num_reads = 0u64;
+                let mut i = reader;
+                while !stop.load(Ordering::Relaxed) {
+                    read(&store, i);
+                    num_reads += 1;
+                    i += 7919;
+                }
+                num_reads
+            })
+        })
+        .collect();
+    let started = Instant::now();
+    thread::sleep(RUN_TIME);
+    stop.store(true, Ordering::Relaxed);
+    let num_reads: u64 = readers.into_iter().map(|reader| reader.join().unwrap()).sum();
+    let elapsed = started.elapsed();
+    if let Some(writer) = writer {
+        writer.join().unwrap();
+    }
+    num_reads as f64 / elapsed.as_secs_f64()
+}
+
+fn run(block_cache_shards: usize) {
+    let dir = tempdir().unwrap();
+    let options = DbOptions {
+        path: dir.path().to_owned(),
+        // small memtables, so the writer freezes and flushes often
+        sst_max_size_bytes: 64 * 1024,
+        block_cache_shards,
+        ..Default::default()
+    };
+    let store = Arc::new(Db::open(options).unwrap());
+    for i in 0..NUM_KEYS {
+        store.put(key(i), format!("value{}", i)).unwrap();
+    }
+    store.wait_for_flush().unwrap();
+
+    for num_readers in [1, 2, 4, 8] {
+        let read_only = measure_reads(&store, num_readers, false, get);
+        let mixed = measure_reads(&store, num_readers, true, get);
+        println!(
+            "{} readers: {:>10.0} gets/s read-only, {:>10.0} gets/s with a writer",
+            num_readers, read_only, mixed
+        );
+    }
+    for num_readers in [1, 2, 4, 8] {
+        let read_only = measure_reads(&store, num_readers, false, scan);
+        let mixed = measure_reads(&store, num_readers, true, scan);
+        println!(
+            "{} readers: {:>10.0} scans/s read-only, {:>10.0} scans/s with a writer",
+            num_readers, read_only, mixed
+        );
+    }
+    store.close().unwrap();
+}
+
+fn main() {
+    for block_cache_shards in [1, 8] {
+        println!("block cache shards: {}", block_cache_shards);
+        run(block_cache_shards);
+    }
+}
@@ -0,0 +1,214 @@
+use std::ops::Bound;
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    iterator::{scan_iterator::ScanIterator, tracked_iterator::TrackedIterator, IteratorStats, StorageIterator},
+    kv::{entry::Entry, key_range::KeyRange},
+    memory::memtable::{iterator::MemTableIterator, MemTable},
+    state::read_options::{ReadOptions, ReadOptionsIterator},
+    store::LsmStore,
+};
+
+use iterator::WriteBatchIterator;
+
+pub(crate) mod iterator;
+
+// what iter returns. the batch's entries, so it never fails
+pub struct BatchIter(ScanIterator<MemTableIterator>);
+
+impl Iterator for BatchIter {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        self.0.next()
+    }
+}
+
+// what scan returns. like Scan, check_error tells a failed scan apart from a
+// finished one
+pub struct BatchScan(ScanIterator<WriteBatchIterator<MemTableIterator, TrackedIterator<ReadOptionsIterator>>>);
+
+impl BatchScan {
+    pub fn is_valid(&self) -> bool {
+        self.0.is_valid()
+    }
+
+    pub fn error(&self) -> Option<&anyhow::Error> {
+        self.0.error()
+    }
+
+    pub fn check_error(&self) -> Result<()> {
+        self.0.check_error()
+    }
+
+    pub fn stats(&self) -> IteratorStats {
+        self.0.stats()
+    }
+}
+
+impl Iterator for BatchScan {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        self.0.next()
+    }
+}
+
+// batch of uncommitted writes that also indexes its own contents, so reads
+// through the batch see its writes layered on top of the store
+pub struct WriteBatchWithIndex {
+    // latest write for each key in the batch; deletes are stored as tombstones
+    index: MemTable,
+}
+
+impl Default for WriteBatchWithIndex {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl WriteBatchWithIndex {
+    pub fn new() -> Self {
+        Self {
+            index: MemTable::new(0),
+        }
+    }
+
+    pub fn put(&mut self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
+        self.index.put(key.as_ref(), value.as_ref())
+    }
+
+    pub fn delete(&mut self, key: impl AsRef<[u8]>) -> Result<()> {
+        self.index.delete(key.as_ref())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries().peek().is_none()
+    }
+
+    // iterate over batch entries in key order, including tombstones
+    pub fn iter(&self) -> BatchIter {
+        BatchIter(ScanIterator::new(self.entries()))
+    }
+
+    pub(crate) fn entries(&self) -> MemTableIterator {
+        self.index.scan(Bound::Unbounded, Bound::Unbounded)
+    }
+
+    pub fn get(&self, store: &LsmStore, key: impl AsRef<[u8]>) -> Result<Option<Bytes>> {
+        let key = key.as_ref();
+        match self.index.get(key) {
+            Some(value) => Ok(value),
+            None => store.get(key),
+        }
+    }
+
+    pub fn scan(
+        &self,
+        store: &LsmStore,
+        range: impl KeyRange,
+    ) -> Result<BatchScan> {
+        let (lower, upper) = range.bounds();
+        let batch_iterator = self.index.scan(lower, upper);
+        let store_iterator = store.tracked_scan((lower, upper), &ReadOptions::default())?;
+        Ok(BatchScan(ScanIterator::new(WriteBatchIterator::new(batch_iterator, store_iterator))))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use tempfile::tempdir;
+
+    use crate::{state::storage_state_options::StorageStateOptions, store::LsmStore};
+
+    use super::WriteBatchWithIndex;
+
+    fn open_store(path: &std::path::Path) -> LsmStore {
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 128,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: path.to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        LsmStore::open(options).unwrap()
+    }
+
+    #[test]
+    fn test_get_reads_own_writes() {
+        let dir = tempdir().unwrap();
+        let store = open_store(dir.path());
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        store.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+
+        let mut batch = WriteBatchWithIndex::new();
+        assert!(batch.is_empty());
+        batch.put("k1".as_bytes(), "new_v1".as_bytes()).unwrap();
+        batch.delete("k2".as_bytes()).unwrap();
+        batch.put("k3".as_bytes(), "v3".as_bytes()).unwrap();
+        assert!(!batch.is_empty());
+
+        assert_eq!(batch.get(&store, "k1".as_bytes()).unwrap().unwrap(), "new_v1".as_bytes());
+        assert!(batch.get(&store, "k2".as_bytes()).unwrap().is_none());
+        assert_eq!(batch.get(&store, "k3".as_bytes()).unwrap().unwrap(), "v3".as_bytes());
+
+        // store is untouched until the batch is written
+        assert_eq!(store.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+        assert!(store.get("k3".as_bytes()).unwrap().is_none());
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_scan_merges_batch_and_store() {
+        let dir = tempdir().unwrap();
+        let store = open_store(dir.path());
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        store.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        store.put("k4".as_bytes(), "v4".as_bytes()).unwrap();
+
+        let mut batch = WriteBatchWithIndex::new();
+        batch.put("k1".as_bytes(), "new_v1".as_bytes()).unwrap();
+        batch.delete("k2".as_bytes()).unwrap();
+        batch.put("k3".as_bytes(), "v3".as_bytes()).unwrap();
+
+        let items: Vec<(String, String)> = batch
+            .scan(&store, ..)
+            .unwrap()
+            .map(|kv| {
+                (
+                    String::from_utf8(kv.key.to_vec()).unwrap(),
+                    String::from_utf8(kv.value.to_vec()).unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            items,
+            vec![
+                ("k1".to_string(), "new_v1".to_string()),
+                ("k3".to_string(), "v3".to_string()),
+                ("k4".to_string(), "v4".to_string()),
+            ]
+        );
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_write_batch() {
+        let dir = tempdir().unwrap();
+        let store = open_store(dir.path());
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+
+        let mut batch = WriteBatchWithIndex::new();
+        batch.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        batch.delete("k1".as_bytes()).unwrap();
+        store.write(&batch).unwrap();
+
+        assert!(store.get("k1".as_bytes()).unwrap().is_none());
+        assert_eq!(store.get("k2".as_bytes()).unwrap().unwrap(), "v2".as_bytes());
+        store.close().unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::cmp::Ordering;
+
+use crate::{iterator::{IteratorStats, StorageIterator}, kv::kv_pair::KeyValuePair};
+
+// overlays batch entries on top of store entries. when both contain a key, the
+// batch entry wins and tombstones written by the batch hide the key entirely
+pub(crate) struct WriteBatchIterator<X: StorageIterator, Y: StorageIterator> {
+    batch_iter: X,
+    store_iter: Y,
+    current_kv: Option<KeyValuePair>,
+}
+
+impl<X, Y> WriteBatchIterator<X, Y>
+where
+    X: StorageIterator + Iterator<Item = KeyValuePair>,
+    Y: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    pub(crate) fn new(batch_iter: X, store_iter: Y) -> Self {
+        let mut res = Self {
+            batch_iter,
+            store_iter,
+            current_kv: None,
+        };
+        res.current_kv = res.advance();
+        res
+    }
+
+    // consume and return the next visible entry from either sub-iterator
+    fn advance(&mut self) -> Option<KeyValuePair> {
+        loop {
+            let next_kv = match (self.batch_iter.peek(), self.store_iter.peek()) {
+                (Some(batch_kv), Some(store_kv)) => {
+                    match batch_kv.key.get_key().cmp(&store_kv.key.get_key()) {
+                        Ordering::Less => self.batch_iter.next(),
+                        Ordering::Equal => {
+                            // skip every store entry shadowed by the batch
+                            let key = batch_kv.key.get_key();
+                            while self
+                                .store_iter
+                                .peek()
+                                .is_some_and(|kv| kv.key.get_key() == key)
+                            {
+                                self.store_iter.next();
+                            }
+                            self.batch_iter.next()
+                        }
+                        Ordering::Greater => return self.store_iter.next(),
+                    }
+                }
+                (Some(_), None) => self.batch_iter.next(),
+                (None, Some(_)) => return self.store_iter.next(),
+                (None, None) => return None,
+            };
+            // only batch entries reach this point
+            match next_kv {
+                Some(kv) if kv.is_tombstone() => continue,
+                other => return other,
+            }
+        }
+    }
+}
+
+impl<X, Y> StorageIterator for WriteBatchIterator<X, Y>
+where
+    X: StorageIterator + Iterator<Item = KeyValuePair>,
+    Y: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    fn peek(&self) -> Option<&KeyValuePair> {
+        self.current_kv.as_ref()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.batch_iter.is_valid() && self.store_iter.is_valid()
+    }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.batch_iter.error().or_else(|| self.store_iter.error())
+    }
+
+    fn stats(&self) -> IteratorStats {
+        self.batch_iter.stats() + self.store_iter.stats()
+    }
+}
+
+impl<X, Y> Iterator for WriteBatchIterator<X, Y>
+where
+    X: StorageIterator + Iterator<Item = KeyValuePair>,
+    Y: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    type Item = KeyValuePair;
+
+    fn next(&mut self) -> Option<KeyValuePair> {
+        let res = self.current_kv.take()?;
+        self.current_kv = self.advance();
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::{
+        iterator::StorageIterator,
+        memory::memtable::{iterator::MemTableIterator, MemTable},
+    };
+
+    use super::WriteBatchIterator;
+
+    #[test]
+    fn test_iterate() {
+        let batch = MemTable::new(0);
+        let _ = batch.put("k1".as_bytes(), "batch_v1".as_bytes());
+        let _ = batch.delete("k3".as_bytes());
+        let _ = batch.put("k5".as_bytes(), "batch_v5".as_bytes());
+        let store = MemTable::new(1);
+        let _ = store.put("k1".as_bytes(), "v1".as_bytes());
+        let _ = store.put("k2".as_bytes(), "v2".as_bytes());
+        let _ = store.put("k3".as_bytes(), "v3".as_bytes());
+        let _ = store.put("k4".as_bytes(), "v4".as_bytes());
+
+        let batch_iter = MemTableIterator::new(&batch, Bound::Unbounded, Bound::Unbounded);
+        let store_iter = MemTableIterator::new(&store, Bound::Unbounded, Bound::Unbounded);
+        let mut iterator = WriteBatchIterator::new(batch_iter, store_iter);
+
+        let expected = [("k1", "batch_v1"), ("k2", "v2"), ("k4", "v4"), ("k5", "batch_v5")];
+        for (key, value) in expected {
+            assert!(iterator.peek().is_some_and(|kv| kv.key.get_key() == key));
+            let kv = iterator.next().unwrap();
+            assert_eq!(kv.key.get_key(), key.as_bytes());
+            assert_eq!(kv.value, value.as_bytes());
+        }
+        assert!(iterator.next().is_none());
+        assert!(iterator.is_valid());
+    }
+}
@@ -1,29 +1,83 @@
+use std::{ops::Range, sync::OnceLock};
+
 use bytes::Bytes;
 
-pub mod builder;
-pub mod iterator;
-pub mod metadata;
+use crate::kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey};
+
+pub(crate) mod builder;
+pub(crate) mod iterator;
+pub(crate) mod metadata;
+
+// keys are prefix-compressed against the previous key, with a full key every
+// restart interval entries. a smaller interval makes seeks cheaper, a larger
+// one compresses better
+pub(crate) const DEFAULT_RESTART_INTERVAL: usize = 16;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug)]
 pub struct Block {
-    data: Vec<u8>,
+    // kept as Bytes so values can be handed out as slices instead of copies
+    data: Bytes,
     // offsets for each key-value pair. allows for binary search over the block
     offsets: Vec<u16>,
     end_of_data_offset: u16,
+    // not part of the encoded block, the SST records it per block. 0 means
+    // every key is compressed against the first key of the block, as blocks
+    // were before restart points, so any entry decodes on its own
+    restart_interval: usize,
+    // decoded on first iteration and kept with the block, so cached blocks
+    // aren't parsed again on every scan
+    entries: OnceLock<BlockEntries>,
+}
+
+// keys are stored whole, back to back, because prefix compression means they
+// can't be sliced out of the block data. values are sliced from the data
+#[derive(Debug)]
+struct BlockEntries {
+    keys: Bytes,
+    entries: Vec<BlockEntry>,
+}
+
+#[derive(Debug)]
+struct BlockEntry {
+    key: Range<usize>,
+    value: Range<usize>,
+}
+
+impl PartialEq for Block {
+    // whether the entries have been decoded yet doesn't matter
+    fn eq(&self, other: &Self) -> bool {
+        self.data == other.data
+            && self.offsets == other.offsets
+            && self.end_of_data_offset == other.end_of_data_offset
+            && self.restart_interval == other.restart_interval
+    }
 }
 
+impl Eq for Block {}
+
 impl Block {
     pub fn new(data: Vec<u8>, offsets: Vec<u16>, end_of_data_offset: u16) -> Self {
+        Self::new_with_restart_interval(data, offsets, end_of_data_offset, 0)
+    }
+
+    pub fn new_with_restart_interval(
+        data: Vec<u8>,
+        offsets: Vec<u16>,
+        end_of_data_offset: u16,
+        restart_interval: usize,
+    ) -> Self {
         Self {
-            data,
+            data: Bytes::from(data),
             offsets,
             end_of_data_offset,
+            restart_interval,
+            entries: OnceLock::new(),
         }
     }
 
     pub fn encode(&self) -> Vec<u8> {
         let mut encoded: Vec<u8> = Vec::new();
-        encoded.extend(self.data.clone());
+        encoded.extend_from_slice(&self.data);
         // u16 offsets are stored in big-endian order
         encoded.extend(
             self.offsets
@@ -34,25 +88,142 @@ impl Block {
         encoded
     }
 
-    pub fn decode(encoded_block: Vec<u8>) -> Self {
+    pub fn decode(encoded_block: Vec<u8>, restart_interval: usize) -> Self {
+        Self::try_decode(encoded_block, restart_interval).expect("block must end with its offsets")
+    }
+
+    // None if the offsets at the end of the block don't fit in it. the
+    // entries themselves are only checked by verify
+    pub fn try_decode(encoded_block: Vec<u8>, restart_interval: usize) -> Option<Self> {
         let encoded_block_size = encoded_block.len();
-        let end_of_data_offset_le_bytes = [
-            encoded_block[encoded_block_size - 2],
-            encoded_block[encoded_block_size - 1],
-        ];
-        let end_of_data_offset = u16::from_be_bytes(end_of_data_offset_le_bytes);
-
-        let data = encoded_block[..end_of_data_offset.into()].to_vec();
-        let offsets_bytes = &encoded_block[end_of_data_offset.into()..encoded_block_size - 2];
+        let offsets_end = encoded_block_size.checked_sub(2)?;
+        let end_of_data_offset = u16::from_be_bytes([encoded_block[offsets_end], encoded_block[offsets_end + 1]]);
+
+        let offsets_bytes = encoded_block.get(end_of_data_offset.into()..offsets_end)?;
+        if offsets_bytes.len() % 2 != 0 {
+            return None;
+        }
         let offsets: Vec<u16> = offsets_bytes
             .chunks_exact(2)
             .map(|chunk| u16::from_be_bytes(chunk.try_into().expect("chunk of size 2")))
             .collect();
-        Self {
+        let data = Bytes::from(encoded_block).slice(..end_of_data_offset as usize);
+        Some(Self {
             data,
             offsets,
             end_of_data_offset,
+            restart_interval,
+            entries: OnceLock::new(),
+        })
+    }
+
+    // size of the encoded block
+    pub fn size_bytes(&self) -> usize {
+        self.data.len() + 2 * self.offsets.len() + 2
+    }
+
+    pub fn num_entries(&self) -> usize {
+        self.offsets.len()
+    }
+
+    // the entry at index, with its key and value sliced from the decoded
+    // entries rather than copied
+    pub(crate) fn entry(&self, index: usize) -> Option<KeyValuePair> {
+        let decoded = self.decoded_entries();
+        let entry = decoded.entries.get(index)?;
+        // the value type is part of the value, for the SST to resolve
+        Some(KeyValuePair::new(
+            TimestampedKey::new(decoded.keys.slice(entry.key.clone())),
+            self.data.slice(entry.value.clone()),
+        ))
+    }
+
+    // like entry, but the value is cut down to its first byte, which holds the
+    // value type, for reads that only need keys
+    pub(crate) fn entry_without_value(&self, index: usize) -> Option<KeyValuePair> {
+        let decoded = self.decoded_entries();
+        let entry = decoded.entries.get(index)?;
+        let value_type_end = entry.value.end.min(entry.value.start + 1);
+        Some(KeyValuePair::new(
+            TimestampedKey::new(decoded.keys.slice(entry.key.clone())),
+            self.data.slice(entry.value.start..value_type_end),
+        ))
+    }
+
+    // index of the first entry with a key greater than or equal to key, or
+    // num_entries if there is none
+    pub(crate) fn find_key(&self, key: &[u8]) -> usize {
+        let decoded = self.decoded_entries();
+        decoded
+            .entries
+            .partition_point(|entry| &decoded.keys[entry.key.clone()] < key)
+    }
+
+    fn decoded_entries(&self) -> &BlockEntries {
+        self.entries
+            .get_or_init(|| self.decode_entries().expect("block entries must lie within the block"))
+    }
+
+    // entries must lie within the data, and their keys must be in order.
+    // versions of a key are stored under the same key, so keys may repeat
+    pub(crate) fn verify(&self) -> Result<(), String> {
+        let decoded = self
+            .decode_entries()
+            .ok_or_else(|| "block has an entry that runs past its data".to_string())?;
+        for (index, pair) in decoded.entries.windows(2).enumerate() {
+            let (key, next_key) = (&decoded.keys[pair[0].key.clone()], &decoded.keys[pair[1].key.clone()]);
+            if key > next_key {
+                return Err(format!("block entry {} has key {:?} after {:?}", index + 1, next_key, key));
+            }
         }
+        Ok(())
+    }
+
+    // None if an entry runs past the data
+    fn decode_entries(&self) -> Option<BlockEntries> {
+        let read_u16 = |offset: usize| -> Option<usize> {
+            let bytes = self.data.get(offset..offset.checked_add(2)?)?;
+            Some(u16::from_be_bytes([bytes[0], bytes[1]]) as usize)
+        };
+        let mut keys: Vec<u8> = Vec::new();
+        let mut entries: Vec<BlockEntry> = Vec::with_capacity(self.offsets.len());
+        let mut previous_key = 0..0;
+        for (index, offset) in self.offsets.iter().enumerate() {
+            let offset = *offset as usize;
+            let key_start = keys.len();
+            let value_len_offset = if index == 0 {
+                let key_len = read_u16(offset)?;
+                keys.extend_from_slice(self.data.get(offset + 2..offset + 2 + key_len)?);
+                offset + 2 + key_len
+            } else {
+                let key_overlap_len = read_u16(offset)?;
+                let rest_key_len = read_u16(offset + 2)?;
+                let overlap_start = match self.restart_interval {
+                    0 => 0,
+                    _ => previous_key.start,
+                };
+                if overlap_start + key_overlap_len > key_start {
+                    return None;
+                }
+                keys.extend_from_within(overlap_start..overlap_start + key_overlap_len);
+                keys.extend_from_slice(self.data.get(offset + 4..offset + 4 + rest_key_len)?);
+                offset + 4 + rest_key_len
+            };
+            let value_start = value_len_offset + 2;
+            let value_len = read_u16(value_len_offset)?;
+            if value_start + value_len > self.data.len() {
+                return None;
+            }
+            previous_key = key_start..keys.len();
+            entries.push(BlockEntry {
+                key: previous_key.clone(),
+                value: value_start..value_start + value_len,
+            });
+        }
+        Some(BlockEntries {
+            keys: Bytes::from(keys),
+            entries,
+        })
     }
 
     pub fn get_first_key(&self) -> Bytes {
@@ -87,9 +258,30 @@ mod tests {
         let actual = block.encode();
         assert_eq!(actual, expected);
 
-        let decoded_block = Block::decode(actual);
+        let decoded_block = Block::decode(actual, 0);
         assert_eq!(block, decoded_block);
 
         assert_eq!(block.get_first_key(), "k1".as_bytes());
     }
+
+    #[test]
+    fn test_verify() {
+        let mut data = vec![0,2];
+        data.extend("k2".as_bytes());
+        data.extend(vec![0,2]);
+        data.extend("v2".as_bytes());
+        data.extend(vec![0,0,0,2]);
+        data.extend("k1".as_bytes());
+        data.extend(vec![0,2]);
+        data.extend("v1".as_bytes());
+        let block = Block::new(data.clone(), vec![0, 8], 18);
+        assert!(block.verify().unwrap_err().contains("after"), "keys are out of order");
+        assert!(Block::new(data[..8].to_vec(), vec![0], 8).verify().is_ok());
+        // the second entry's value runs past the data
+        let block = Block::new(data[..16].to_vec(), vec![0, 8], 16);
+        assert!(block.verify().is_err());
+        // offsets that don't fit in the block
+        assert!(Block::try_decode(vec![0, 0, 0, 9], 0).is_none());
+        assert!(Block::try_decode(vec![0], 0).is_none());
+    }
 }
\ No newline at end of file
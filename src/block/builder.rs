@@ -1,126 +1,171 @@
 use anyhow::{anyhow, Result};
 
-use crate::kv::kv_pair::KeyValuePair;
+use super::{metadata::BlockStats, Block};
 
-use super::Block;
+// the hard limit on the size of a block. offsets and lengths are 2 bytes, so
+// no block can hold more entry data than this, whatever its block_size
+pub(crate) const MAX_BLOCK_DATA_BYTES: usize = u16::MAX as usize;
 
-pub struct BlockBuilder {
+pub(crate) struct BlockBuilder {
     data: Vec<u8>,
     offsets: Vec<u16>,
     current_offset: u16,
     block_size: usize,
     first_key: Vec<u8>,
+    last_key: Vec<u8>,
+    restart_interval: usize,
+    min_value_len: usize,
+    max_value_len: usize,
 }
 
 impl BlockBuilder {
+    #[cfg(test)]
     pub fn new(block_size: usize) -> Self {
+        Self::new_with_restart_interval(block_size, super::DEFAULT_RESTART_INTERVAL)
+    }
+
+    // see Block for what a restart interval of 0 means
+    pub(crate) fn new_with_restart_interval(block_size: usize, restart_interval: usize) -> Self {
         Self {
             data: Vec::new(),
             offsets: Vec::new(),
             current_offset: 0,
             block_size,
             first_key: Vec::new(),
+            last_key: Vec::new(),
+            restart_interval,
+            min_value_len: usize::MAX,
+            max_value_len: 0,
         }
     }
 
-    pub fn add(&mut self, kv_pair: KeyValuePair) -> Result<()> {
-        if !self.is_empty() && self.get_block_size_with_kv(&kv_pair) > self.block_size {
+    // block_size is a soft limit: a block holding entries takes no more once
+    // it would grow past it, but an empty block takes an entry of any size up
+    // to MAX_BLOCK_DATA_BYTES, which it then holds alone. key and value are
+    // copied straight into the block data
+    pub(crate) fn add(&mut self, key: &[u8], value: &[u8]) -> Result<()> {
+        if self.is_full_with(key, value) {
             return Err(anyhow!("max block size reached"));
         }
+        let entry_size = max_entry_size(key.len(), value.len());
+        if self.data.len() + entry_size > MAX_BLOCK_DATA_BYTES {
+            return Err(anyhow!(
+                "entry of {} bytes doesn't fit in a block of at most {} bytes",
+                entry_size,
+                MAX_BLOCK_DATA_BYTES
+            ));
+        }
 
-        let key_as_bytes: Vec<u8>;
-        if self.first_key.is_empty() {
-            self.first_key = kv_pair.key.get_key().to_vec();
-            let key_len_bytes = u16::try_from(kv_pair.key.get_key().len())?.to_be_bytes();
-            key_as_bytes = vec![key_len_bytes.to_vec(), kv_pair.key.get_key().to_vec()]
-                .into_iter()
-                .flatten()
-                .collect();
+        // lengths fit in 2 bytes, since the whole entry fits in the block
+        let entry_start = self.data.len();
+        if self.is_empty() {
+            self.first_key.extend_from_slice(key);
+            self.data.extend((key.len() as u16).to_be_bytes());
+            self.data.extend_from_slice(key);
         } else {
-            let key_overlap_len = kv_pair
-                .key
-                .get_key()
-                .iter()
-                .zip(self.first_key.clone())
-                .take_while(|(x, y)| *x == y)
-                .count();
-            let rest_key_len = kv_pair.key.get_key().len() - key_overlap_len;
-            key_as_bytes = vec![
-                u16::try_from(key_overlap_len)?.to_be_bytes().to_vec(),
-                u16::try_from(rest_key_len)?.to_be_bytes().to_vec(),
-                kv_pair.key.get_key()[key_overlap_len..].to_vec(),
-            ].into_iter()
-            .flatten()
-            .collect();
+            let key_overlap_len = match self.restart_interval {
+                0 => common_prefix_len(key, &self.first_key),
+                // restart points keep the whole key
+                restart_interval if self.offsets.len().is_multiple_of(restart_interval) => 0,
+                _ => common_prefix_len(key, &self.last_key),
+            };
+            self.data.extend((key_overlap_len as u16).to_be_bytes());
+            self.data.extend(((key.len() - key_overlap_len) as u16).to_be_bytes());
+            self.data.extend_from_slice(&key[key_overlap_len..]);
         }
-        let value_len_bytes = u16::try_from(kv_pair.value.len())?.to_be_bytes();
-        let kv_as_bytes: Vec<u8> = vec![
-            key_as_bytes,
-            value_len_bytes.to_vec(),
-            kv_pair.value.to_vec(),
-        ]
-        .into_iter()
-        .flatten()
-        .collect();
+        self.data.extend((value.len() as u16).to_be_bytes());
+        self.data.extend_from_slice(value);
 
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
         self.offsets.push(self.current_offset);
-        self.current_offset += u16::try_from(kv_as_bytes.len())?;
-        self.data.extend(kv_as_bytes);
+        self.current_offset += (self.data.len() - entry_start) as u16;
+        self.min_value_len = self.min_value_len.min(value.len());
+        self.max_value_len = self.max_value_len.max(value.len());
 
         Ok(())
     }
 
-    pub fn build(self) -> Block {
-        Block::new(self.data, self.offsets, self.current_offset)
+    pub(crate) fn build(self) -> Block {
+        Block::new_with_restart_interval(self.data, self.offsets, self.current_offset, self.restart_interval)
+    }
+
+    pub(crate) fn get_restart_interval(&self) -> usize {
+        self.restart_interval
+    }
+
+    pub(crate) fn get_stats(&self) -> BlockStats {
+        // value lengths are checked to fit in 2 bytes on add
+        BlockStats {
+            num_entries: self.offsets.len() as u32,
+            min_value_len: if self.offsets.is_empty() { 0 } else { self.min_value_len as u32 },
+            max_value_len: self.max_value_len as u32,
+            // which keys are live depends on the blocks before, so the SST
+            // builder fills it in
+            num_live_keys: None,
+        }
     }
 
-    pub fn is_empty(&self) -> bool {
+    pub(crate) fn is_empty(&self) -> bool {
         self.data.len() == 0
     }
 
-    pub fn get_block_size(&self) -> usize {
+    pub(crate) fn get_block_size(&self) -> usize {
         self.data.len() // data in bytes
         + 2 * self.offsets.len() // each offset is 2 bytes
         + 2 // end of data offset is 2 bytes
     }
 
-    pub fn get_block_size_with_kv(&self, kv: &KeyValuePair) -> usize {
+    // whether the block has to be finished before kv is added, see add
+    pub(crate) fn is_full_with(&self, key: &[u8], value: &[u8]) -> bool {
+        !self.is_empty() && self.get_block_size_with_kv(key, value) > self.block_size.min(MAX_BLOCK_DATA_BYTES)
+    }
+
+    pub(crate) fn get_block_size_with_kv(&self, key: &[u8], value: &[u8]) -> usize {
         let block_size = self.get_block_size();
-        if block_size == 0 {
-            2 + kv.key.get_key().len() + 2 + kv.value.len() + 2
+        if self.is_empty() {
+            block_size + 2 + key.len() + 2 + value.len() + 2
         } else {
             block_size
             + 4 // key_overlap + rest_key_len
-            + kv.key.get_key().len()
+            + key.len()
             + 2 // value length
-            + kv.value.len()
+            + value.len()
             + 2 // length of new offset
         }
     }
 }
 
+// the most data bytes an entry can take in a block, with its key not
+// compressed at all. an entry this size always fits in an empty block
+pub(crate) fn max_entry_size(key_len: usize, value_len: usize) -> usize {
+    4 + key_len + 2 + value_len
+}
+
+fn common_prefix_len(key: &[u8], other: &[u8]) -> usize {
+    key.iter().zip(other).take_while(|(x, y)| x == y).count()
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey};
+    use crate::block::DEFAULT_RESTART_INTERVAL;
 
-    use super::{Block, BlockBuilder};
+    use super::{Block, BlockBuilder, MAX_BLOCK_DATA_BYTES};
 
     #[test]
     fn test_blockbuilder_build() {
         let mut block_builder = BlockBuilder::new(32);
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k1".as_bytes().into()),
-                value: "v1".as_bytes().into()
-            })
+            .add("k1".as_bytes(), "v1".as_bytes())
             .is_ok());
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k2".as_bytes().into()),
-                value: "v2".as_bytes().into()
-            })
+            .add("k2".as_bytes(), "v2".as_bytes())
             .is_ok());
         let estimated_size = block_builder.get_block_size();
+        let stats = block_builder.get_stats();
+        assert_eq!(stats.num_entries, 2);
+        assert_eq!(stats.min_value_len, 2);
+        assert_eq!(stats.max_value_len, 2);
 
         let actual = block_builder.build();
 
@@ -132,7 +177,7 @@ mod tests {
         expected_data.extend("2".as_bytes());
         expected_data.extend(vec![0, 2]);
         expected_data.extend("v2".as_bytes());
-        let expected = Block::new(expected_data, vec![0, 8], 17);
+        let expected = Block::new_with_restart_interval(expected_data, vec![0, 8], 17, DEFAULT_RESTART_INTERVAL);
         assert_eq!(actual, expected);
 
         // check that our calculated size is correct
@@ -143,16 +188,56 @@ mod tests {
     fn test_blockbuilder_check_block_size() {
         let mut block_builder = BlockBuilder::new(12);
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k1".as_bytes().into()),
-                value: "v1".as_bytes().into()
-            })
+            .add("k1".as_bytes(), "v1".as_bytes())
             .is_ok());
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k2".as_bytes().into()),
-                value: "v2".as_bytes().into()
-            })
+            .add("k2".as_bytes(), "v2".as_bytes())
             .is_err());
     }
+
+    #[test]
+    fn test_blockbuilder_oversized_entry() {
+        // block_size is a soft limit, so an empty block takes a larger entry
+        let mut block_builder = BlockBuilder::new(12);
+        assert!(!block_builder.is_full_with(b"k1", &[0; 100]));
+        block_builder.add(b"k1", &[0; 100]).unwrap();
+        assert!(block_builder.get_block_size() > 12);
+        assert!(block_builder.is_full_with(b"k2", b""));
+        assert!(block_builder.add(b"k2", b"").is_err());
+        assert_eq!(block_builder.build().encode().len(), 2 + 2 + 2 + 2 + 100 + 2);
+
+        // but no block holds more than the format can address
+        let mut block_builder = BlockBuilder::new(12);
+        assert!(block_builder.add(b"k1", &vec![0; MAX_BLOCK_DATA_BYTES]).is_err());
+        assert!(block_builder.is_empty());
+        block_builder.add(b"k1", &vec![0; MAX_BLOCK_DATA_BYTES - 8]).unwrap();
+    }
+
+    #[test]
+    fn test_blockbuilder_restart_interval() {
+        let mut block_builder = BlockBuilder::new_with_restart_interval(64, 2);
+        for key in ["k1", "k12", "k2"] {
+            block_builder
+                .add(key.as_bytes(), "v".as_bytes())
+                .unwrap();
+        }
+        let actual = block_builder.build();
+
+        let mut expected_data = vec![0, 2];
+        expected_data.extend("k1".as_bytes());
+        expected_data.extend(vec![0, 1]);
+        expected_data.extend("v".as_bytes());
+        // compressed against the previous key
+        expected_data.extend(vec![0, 2, 0, 1]);
+        expected_data.extend("2".as_bytes());
+        expected_data.extend(vec![0, 1]);
+        expected_data.extend("v".as_bytes());
+        // restart point
+        expected_data.extend(vec![0, 0, 0, 2]);
+        expected_data.extend("k2".as_bytes());
+        expected_data.extend(vec![0, 1]);
+        expected_data.extend("v".as_bytes());
+        let expected = Block::new_with_restart_interval(expected_data, vec![0, 7, 15], 24, 2);
+        assert_eq!(actual, expected);
+    }
 }
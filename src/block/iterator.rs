@@ -1,144 +1,103 @@
-use std::{cmp::Ordering, sync::Arc};
-
-use bytes::Bytes;
+use std::sync::Arc;
 
 use crate::{
-    iterator::StorageIterator,
+    iterator::{IteratorStats, StorageIterator},
     kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
 };
 
 use super::Block;
 
-pub struct BlockIterator {
+pub(crate) struct BlockIterator {
     block: Arc<Block>,
     current_index: usize,
     current_kv: Option<KeyValuePair>,
-    first_key: Bytes,
+    // see keys_only
+    keys_only: bool,
 }
 
 impl BlockIterator {
-    pub fn create_and_seek_to_first(block: Arc<Block>) -> Self {
-        let first_key = block.get_first_key();
+    pub(crate) fn create_and_seek_to_first(block: Arc<Block>) -> Self {
         let mut res = Self {
             block,
             current_index: 0,
             current_kv: None,
-            first_key,
+            keys_only: false,
         };
-        res.current_kv = res.parse_current_kv();
+        res.seek_to_first();
         res
     }
 
-    pub fn create_and_seek_to_key(block: Arc<Block>, key: TimestampedKey) -> Self {
-        let first_key = block.get_first_key();
+    pub(crate) fn create_and_seek_to_key(block: Arc<Block>, key: TimestampedKey) -> Self {
         let mut res = Self {
             block,
             current_index: 0,
             current_kv: None,
-            first_key,
+            keys_only: false,
         };
         res.seek_to_key(key);
         res
     }
 
-    pub fn seek_to_first(&mut self) {
-        self.current_index = 0;
+    // leave out all of each value but the value type, for reads that only
+    // need keys, see Block::entry_without_value
+    pub(crate) fn keys_only(mut self, keys_only: bool) -> Self {
+        self.keys_only = keys_only;
+        self.current_kv = self.read_entry(self.current_index);
+        self
     }
 
-    pub fn seek_to_key(&mut self, key: TimestampedKey) {
-        // seek to first key greater than or equal to key
-        // binary search for the key in range 0..num_elements
-        let (mut lo, mut hi) = (0, self.block.offsets.len() - 1);
-        while lo < hi {
-            let mid = (lo + hi) / 2;
-            self.current_index = mid;
-            self.current_kv = self.parse_current_kv();
-            let raw_key = self
-                .current_kv
-                .clone()
-                .expect("mid is less than length of block offsets")
-                .key
-                .get_key();
-            match raw_key.cmp(&key.get_key()) {
-                Ordering::Less => lo = mid + 1,
-                Ordering::Greater => hi = mid,
-                Ordering::Equal => return,
-            }
+    fn read_entry(&self, index: usize) -> Option<KeyValuePair> {
+        match self.keys_only {
+            true => self.block.entry_without_value(index),
+            false => self.block.entry(index),
         }
-        let mid = (lo + hi) / 2;
-        self.current_index = mid;
-        self.current_kv = self.parse_current_kv();
     }
 
-    fn parse_current_kv(&self) -> Option<KeyValuePair> {
-        if self.current_index == self.block.offsets.len() {
-            return None;
-        }
+    pub(crate) fn seek_to_first(&mut self) {
+        self.current_index = 0;
+        self.current_kv = self.read_entry(0);
+    }
 
-        let current_offset = self.block.offsets[self.current_index];
-        // parse key
-        let key_contents_offset: usize;
-        let key_vec: Vec<u8>;
-        let value_contents_offset: usize;
-        if self.current_index == 0 {
-            key_contents_offset = current_offset as usize + 2;
-            let key_size = u16::from_be_bytes(
-                self.block.data[current_offset.into()..key_contents_offset]
-                    .try_into()
-                    .expect("chunk of size 2"),
-            ) as usize;
-            key_vec =
-                self.block.data[key_contents_offset..key_contents_offset + key_size].to_vec();
-            value_contents_offset = key_contents_offset + key_size + 2;
-        } else {
-            key_contents_offset = current_offset as usize + 4;
-            let key_overlap_len = u16::from_be_bytes(
-                self.block.data[current_offset as usize..current_offset as usize + 2]
-                    .try_into()
-                    .expect("chunk of size 2"),
-            ) as usize;
-            let key_overlap = &self.first_key[..key_overlap_len];
-            let rest_key_len = u16::from_be_bytes(
-                self.block.data[current_offset as usize + 2..key_contents_offset]
-                    .try_into()
-                    .expect("chunk of size 2"),
-            ) as usize;
-            let rest_key = &self.block.data[key_contents_offset..key_contents_offset + rest_key_len];
-            key_vec = [key_overlap, rest_key].concat().to_vec();
-            value_contents_offset = key_contents_offset + rest_key_len + 2;
-        }
-        // parse value
-        let value_size = u16::from_be_bytes(
-            self.block.data[(value_contents_offset - 2)..value_contents_offset]
-                .try_into()
-                .expect("chunk of size 2"),
-        ) as usize;
-        let value_slice = &self.block.data
-            [value_contents_offset..value_contents_offset + value_size];
-        Some(KeyValuePair {
-            key: TimestampedKey::new(Bytes::from(key_vec)),
-            value: Bytes::copy_from_slice(value_slice),
-        })
+    // seek to the newest version of the first key greater than or equal to key
+    pub(crate) fn seek_to_key(&mut self, key: TimestampedKey) {
+        self.current_index = self.block.find_key(&key.get_key());
+        self.current_kv = self.read_entry(self.current_index);
+    }
+
+    fn advance(&mut self) {
+        self.current_index += 1;
+        self.current_kv = self.read_entry(self.current_index);
     }
 }
 
 impl StorageIterator for BlockIterator {
-    fn peek(&mut self) -> Option<KeyValuePair> {
-        self.current_kv.clone()
+    fn peek(&self) -> Option<&KeyValuePair> {
+        self.current_kv.as_ref()
     }
 
     fn is_valid(&self) -> bool {
         true
     }
+
+    // entries are already in memory, so reading them can't fail
+    fn error(&self) -> Option<&anyhow::Error> {
+        None
+    }
+
+    fn stats(&self) -> IteratorStats {
+        IteratorStats {
+            num_pinned_blocks: self.current_kv.is_some().into(),
+            ..Default::default()
+        }
+    }
 }
 
 impl Iterator for BlockIterator {
     type Item = KeyValuePair;
     fn next(&mut self) -> Option<KeyValuePair> {
-        let res = self.current_kv.clone()?;
+        let res = self.current_kv.take()?;
         // update next item
-        self.current_index += 1;
-        self.current_kv = self.parse_current_kv();
+        self.advance();
 
         Some(res)
     }
@@ -151,31 +110,25 @@ mod tests {
     use bytes::Bytes;
 
     use crate::{
-        block::{builder::BlockBuilder, iterator::BlockIterator},
+        block::{builder::BlockBuilder, iterator::BlockIterator, Block},
         iterator::StorageIterator,
-        kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
+        kv::timestamped_key::TimestampedKey,
     };
 
     #[test]
     fn test_create_and_seek_to_first() {
         let mut block_builder = BlockBuilder::new(32);
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k1".as_bytes().into()),
-                value: "v1".as_bytes().into()
-            })
+            .add("k1".as_bytes(), "v1".as_bytes())
             .is_ok());
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k2".as_bytes().into()),
-                value: "v2".as_bytes().into()
-            })
+            .add("k2".as_bytes(), "v2".as_bytes())
             .is_ok());
 
         let block = Arc::new(block_builder.build());
 
-        let mut block_iterator = BlockIterator::create_and_seek_to_first(block);
-        assert_eq!(block_iterator.first_key, "k1".as_bytes());
+        let block_iterator = BlockIterator::create_and_seek_to_first(block);
+        assert_eq!(block_iterator.block.get_first_key(), "k1".as_bytes());
         assert!(block_iterator.peek().is_some());
         assert_eq!(
             block_iterator
@@ -195,22 +148,13 @@ mod tests {
     fn test_seek_to_key() {
         let mut block_builder = BlockBuilder::new(50);
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k1".as_bytes().into()),
-                value: "v1".as_bytes().into()
-            })
+            .add("k1".as_bytes(), "v1".as_bytes())
             .is_ok());
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k3".as_bytes().into()),
-                value: "v3".as_bytes().into()
-            })
+            .add("k3".as_bytes(), "v3".as_bytes())
             .is_ok());
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k4".as_bytes().into()),
-                value: "v4".as_bytes().into()
-            })
+            .add("k4".as_bytes(), "v4".as_bytes())
             .is_ok());
 
         let block = Arc::new(block_builder.build());
@@ -241,4 +185,100 @@ mod tests {
             "k3".as_bytes()
         );
     }
+
+    #[test]
+    fn test_values_share_block_buffer() {
+        let mut block_builder = BlockBuilder::new(32);
+        assert!(block_builder
+            .add("k1".as_bytes(), "v1".as_bytes())
+            .is_ok());
+        let block = Arc::new(block_builder.build());
+        let kv = BlockIterator::create_and_seek_to_first(block.clone())
+            .next()
+            .unwrap();
+        assert_eq!(kv.value, "v1".as_bytes());
+        let data_range = block.data.as_ptr_range();
+        assert!(data_range.contains(&kv.value.as_ptr()));
+    }
+
+    #[test]
+    fn test_keys_only() {
+        let mut block_builder = BlockBuilder::new(64);
+        for (key, value) in [("k1", "v1"), ("k2", ""), ("k3", "v3")] {
+            block_builder.add(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+        let block = Arc::new(block_builder.build());
+        let entries: Vec<(Bytes, Bytes)> = BlockIterator::create_and_seek_to_first(block.clone())
+            .keys_only(true)
+            .map(|kv| (kv.key.get_key(), kv.value))
+            .collect();
+        // only the first byte of each value is kept, which is where SSTs keep
+        // the value type
+        assert_eq!(
+            entries,
+            vec![
+                ("k1".into(), "v".into()),
+                ("k2".into(), Bytes::new()),
+                ("k3".into(), "v".into())
+            ]
+        );
+        let seeked = BlockIterator::create_and_seek_to_key(block, TimestampedKey::new("k3".as_bytes().into()))
+            .keys_only(true)
+            .next()
+            .unwrap();
+        assert_eq!(seeked.value, "v");
+    }
+
+    #[test]
+    fn test_block_decoded_once() {
+        let mut block_builder = BlockBuilder::new_with_restart_interval(4096, 2);
+        for key in ["key1", "key2", "key3"] {
+            block_builder
+                .add(key.as_bytes(), "v".as_bytes())
+                .unwrap();
+        }
+        let block = Arc::new(block_builder.build());
+        let first_scan: Vec<Bytes> = BlockIterator::create_and_seek_to_first(block.clone())
+            .map(|kv| kv.key.get_key())
+            .collect();
+        let second_scan: Vec<Bytes> = BlockIterator::create_and_seek_to_first(block.clone())
+            .map(|kv| kv.key.get_key())
+            .collect();
+        assert_eq!(first_scan, vec!["key1", "key2", "key3"]);
+        // both scans hand out slices of the same decoded keys
+        for (first_key, second_key) in first_scan.iter().zip(second_scan.iter()) {
+            assert_eq!(first_key.as_ptr(), second_key.as_ptr());
+        }
+        let seeked = BlockIterator::create_and_seek_to_key(block, TimestampedKey::new("key2".as_bytes().into()))
+            .next()
+            .unwrap();
+        assert_eq!(seeked.key.get_key().as_ptr(), first_scan[1].as_ptr());
+    }
+
+    #[test]
+    fn test_restart_intervals() {
+        let keys: Vec<String> = (0..10).map(|i| format!("key{:02}", i * 2)).collect();
+        for restart_interval in [0, 1, 3, 16] {
+            let mut block_builder = BlockBuilder::new_with_restart_interval(4096, restart_interval);
+            for key in keys.iter() {
+                block_builder
+                    .add(key.as_bytes(), "v".as_bytes())
+                    .unwrap();
+            }
+            let block = Arc::new(Block::decode(block_builder.build().encode(), restart_interval));
+
+            let scanned: Vec<_> = BlockIterator::create_and_seek_to_first(block.clone())
+                .map(|kv| kv.key.get_key())
+                .collect();
+            assert_eq!(scanned, keys.iter().map(|key| key.as_bytes()).collect::<Vec<_>>());
+            for i in 0..20 {
+                let target = TimestampedKey::new(format!("key{:02}", i).into());
+                let found = BlockIterator::create_and_seek_to_key(block.clone(), target)
+                    .peek()
+                    .map(|kv| kv.key.get_key());
+                let expected = keys.iter().find(|key| **key >= format!("key{:02}", i));
+                assert_eq!(found.as_deref(), expected.map(|key| key.as_bytes()));
+            }
+        }
+    }
 }
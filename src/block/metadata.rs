@@ -1,24 +1,58 @@
 use bytes::Bytes;
 
-use crate::kv::timestamped_key::TimestampedKey;
+use crate::{
+    kv::timestamped_key::TimestampedKey,
+    table::{SST_FORMAT_VERSION_LIVE_KEYS, SST_FORMAT_VERSION_RESTARTS, SST_FORMAT_VERSION_STATS},
+};
 
-#[derive(Debug, PartialEq)]
-pub struct BlockMetadata {
+// aggregate stats for the entries of one block, so callers can answer some
+// questions about a block without reading it
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct BlockStats {
+    pub num_entries: u32,
+    pub min_value_len: u32,
+    pub max_value_len: u32,
+    // user keys whose newest version in the SST is in this block and isn't a
+    // tombstone. not recorded before sst format version 8
+    pub num_live_keys: Option<u32>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct BlockMetadata {
     offset: u32,
     first_key: TimestampedKey,
     last_key: TimestampedKey,
+    // not recorded by sst format version 1
+    stats: Option<BlockStats>,
+    // 0 before sst format version 3, see Block
+    restart_interval: usize,
 }
 
 impl BlockMetadata {
-    pub fn new(offset: u32, first_key: TimestampedKey, last_key: TimestampedKey) -> Self {
+    pub(crate) fn new(
+        offset: u32,
+        first_key: TimestampedKey,
+        last_key: TimestampedKey,
+        stats: Option<BlockStats>,
+        restart_interval: usize,
+    ) -> Self {
         Self {
             offset,
             first_key,
             last_key,
+            stats,
+            restart_interval,
         }
     }
 
-    pub fn encode(&self) -> Vec<u8> {
+    // in memory, including the keys
+    pub(crate) fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.first_key.get_key().len() + self.last_key.get_key().len()
+    }
+
+    // always encodes the latest format version
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
         let mut encoded: Vec<u8> = Vec::new();
         encoded.extend(self.offset.to_be_bytes());
         // size of first key
@@ -39,108 +73,196 @@ impl BlockMetadata {
             .expect("size must fit in 2 bytes");
         encoded.extend(last_key_size.to_be_bytes());
         encoded.extend(&self.last_key.get_key());
+        let stats = self.stats.unwrap_or_default();
+        encoded.extend(stats.num_entries.to_be_bytes());
+        encoded.extend(stats.min_value_len.to_be_bytes());
+        encoded.extend(stats.max_value_len.to_be_bytes());
+        encoded.extend(stats.num_live_keys.unwrap_or_default().to_be_bytes());
+        let restart_interval: u32 = self
+            .restart_interval
+            .try_into()
+            .expect("restart interval must fit in 4 bytes");
+        encoded.extend(restart_interval.to_be_bytes());
         encoded
     }
 
-    pub fn decode(encoded_block_meta: &[u8], start_index: usize) -> (Self, usize) {
+    // None if encoded_block_meta ends part way through the metadata
+    pub(crate) fn decode(encoded_block_meta: &[u8], start_index: usize, format_version: u32) -> Option<(Self, usize)> {
         let mut current_index = start_index;
-        let offset: u32 = u32::from_be_bytes(
-            encoded_block_meta[current_index..current_index + 4]
-                .try_into()
-                .expect("chunk of size 4"),
-        );
-        current_index += 4;
-        let first_key_size: usize = u16::from_be_bytes(
-            encoded_block_meta[current_index..current_index + 2]
-                .try_into()
-                .expect("chunk of size 2"),
-        )
-        .into();
-        current_index += 2;
-        let first_key = Bytes::copy_from_slice(
-            &encoded_block_meta[current_index..current_index + first_key_size],
-        );
-        current_index += first_key_size;
-        let last_key_size: usize = u16::from_be_bytes(
-            encoded_block_meta[current_index..current_index + 2]
-                .try_into()
-                .expect("chunk of size 2"),
-        )
-        .into();
-        current_index += 2;
-        let last_key = Bytes::copy_from_slice(
-            &encoded_block_meta[current_index..current_index + last_key_size],
-        );
-        current_index += last_key_size;
+        let offset = read_u32(encoded_block_meta, &mut current_index)?;
+        let first_key_size: usize = read_u16(encoded_block_meta, &mut current_index)?.into();
+        let first_key = Bytes::copy_from_slice(take(encoded_block_meta, &mut current_index, first_key_size)?);
+        let last_key_size: usize = read_u16(encoded_block_meta, &mut current_index)?.into();
+        let last_key = Bytes::copy_from_slice(take(encoded_block_meta, &mut current_index, last_key_size)?);
+        let mut stats = if format_version >= SST_FORMAT_VERSION_STATS {
+            Some(BlockStats {
+                num_entries: read_u32(encoded_block_meta, &mut current_index)?,
+                min_value_len: read_u32(encoded_block_meta, &mut current_index)?,
+                max_value_len: read_u32(encoded_block_meta, &mut current_index)?,
+                num_live_keys: None,
+            })
+        } else {
+            None
+        };
+        if let (Some(stats), true) = (&mut stats, format_version >= SST_FORMAT_VERSION_LIVE_KEYS) {
+            stats.num_live_keys = Some(read_u32(encoded_block_meta, &mut current_index)?);
+        }
+        let restart_interval = if format_version >= SST_FORMAT_VERSION_RESTARTS {
+            read_u32(encoded_block_meta, &mut current_index)? as usize
+        } else {
+            0
+        };
 
         // return block meta and size of the encoded meta in bytes
-        (
+        Some((
             Self {
                 offset,
                 first_key: TimestampedKey::new(first_key),
                 last_key: TimestampedKey::new(last_key),
+                stats,
+                restart_interval,
             },
             current_index,
-        )
+        ))
     }
 
-    pub fn decode_to_list(encoded_block_meta: &[u8]) -> Vec<Self> {
+    // None if the last metadata is cut short
+    pub(crate) fn decode_to_list(encoded_block_meta: &[u8], format_version: u32) -> Option<Vec<Self>> {
         let mut current_index = 0;
         let mut res: Vec<Self> = Vec::new();
         let encoded_size = encoded_block_meta.len();
         while current_index < encoded_size {
-            let (decoded_block_meta, next_index) = Self::decode(encoded_block_meta, current_index);
+            let (decoded_block_meta, next_index) = Self::decode(encoded_block_meta, current_index, format_version)?;
             res.push(decoded_block_meta);
             current_index = next_index;
         }
-        res
+        Some(res)
     }
 
-    pub fn get_first_key(&self) -> TimestampedKey {
+    pub(crate) fn get_first_key(&self) -> TimestampedKey {
         self.first_key.clone()
     }
 
-    pub fn get_last_key(&self) -> TimestampedKey {
+    pub(crate) fn get_last_key(&self) -> TimestampedKey {
         self.last_key.clone()
     }
 
-    pub fn get_offset(&self) -> u32 {
+    pub(crate) fn get_offset(&self) -> u32 {
         self.offset
     }
+
+    pub(crate) fn get_stats(&self) -> Option<BlockStats> {
+        self.stats
+    }
+
+    pub(crate) fn get_restart_interval(&self) -> usize {
+        self.restart_interval
+    }
+}
+
+pub(crate) fn take<'a>(encoded: &'a [u8], index: &mut usize, len: usize) -> Option<&'a [u8]> {
+    let bytes = encoded.get(*index..index.checked_add(len)?)?;
+    *index += len;
+    Some(bytes)
+}
+
+pub(crate) fn read_u16(encoded: &[u8], index: &mut usize) -> Option<u16> {
+    Some(u16::from_be_bytes(take(encoded, index, 2)?.try_into().expect("chunk of size 2")))
+}
+
+pub(crate) fn read_u32(encoded: &[u8], index: &mut usize) -> Option<u32> {
+    Some(u32::from_be_bytes(take(encoded, index, 4)?.try_into().expect("chunk of size 4")))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::{block::metadata::BlockMetadata, kv::timestamped_key::TimestampedKey};
+    use crate::{
+        block::metadata::{BlockMetadata, BlockStats},
+        kv::timestamped_key::TimestampedKey,
+        table::{SST_FORMAT_VERSION, SST_FORMAT_VERSION_LEGACY, SST_FORMAT_VERSION_PARTITIONED_INDEX},
+    };
+
+    fn stats(num_entries: u32) -> Option<BlockStats> {
+        Some(BlockStats {
+            num_entries,
+            min_value_len: 1,
+            max_value_len: 3,
+            num_live_keys: Some(1),
+        })
+    }
 
     #[test]
     fn test_encode_decode() {
-        let block_meta = BlockMetadata::new(4, TimestampedKey::new("k1".as_bytes().into()), TimestampedKey::new("k2".as_bytes().into()));
+        let block_meta = BlockMetadata::new(4, TimestampedKey::new("k1".as_bytes().into()), TimestampedKey::new("k2".as_bytes().into()), stats(2), 16);
         let mut expected = vec![0, 0, 0, 4];
         expected.extend(vec![0, 2]);
         expected.extend("k1".as_bytes());
         expected.extend(vec![0, 2]);
         expected.extend("k2".as_bytes());
+        expected.extend(vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 3, 0, 0, 0, 1]);
+        expected.extend(vec![0, 0, 0, 16]);
 
         let actual = block_meta.encode();
         let encoded_size = actual.len();
         assert_eq!(actual, expected);
 
-        let (decoded_block_meta, block_meta_size) = BlockMetadata::decode(&actual, 0);
+        let (decoded_block_meta, block_meta_size) = BlockMetadata::decode(&actual, 0, SST_FORMAT_VERSION).unwrap();
         assert_eq!(block_meta, decoded_block_meta);
         assert_eq!(block_meta_size, encoded_size);
     }
 
+    #[test]
+    fn test_decode_legacy() {
+        // version 1 metadata ends after the last key
+        let mut encoded = vec![0, 0, 0, 4];
+        encoded.extend(vec![0, 2]);
+        encoded.extend("k1".as_bytes());
+        encoded.extend(vec![0, 2]);
+        encoded.extend("k2".as_bytes());
+
+        let (decoded_block_meta, block_meta_size) = BlockMetadata::decode(&encoded, 0, SST_FORMAT_VERSION_LEGACY).unwrap();
+        assert_eq!(block_meta_size, encoded.len());
+        assert_eq!(
+            decoded_block_meta,
+            BlockMetadata::new(4, TimestampedKey::new("k1".as_bytes().into()), TimestampedKey::new("k2".as_bytes().into()), None, 0)
+        );
+    }
+
+    #[test]
+    fn test_decode_without_live_keys() {
+        // version 7 stats end after the value lengths
+        let mut encoded = vec![0, 0, 0, 4];
+        encoded.extend(vec![0, 2]);
+        encoded.extend("k1".as_bytes());
+        encoded.extend(vec![0, 2]);
+        encoded.extend("k2".as_bytes());
+        encoded.extend(vec![0, 0, 0, 2, 0, 0, 0, 1, 0, 0, 0, 3]);
+        encoded.extend(vec![0, 0, 0, 16]);
+
+        let (decoded_block_meta, block_meta_size) =
+            BlockMetadata::decode(&encoded, 0, SST_FORMAT_VERSION_PARTITIONED_INDEX).unwrap();
+        assert_eq!(block_meta_size, encoded.len());
+        let expected_stats = BlockStats {
+            num_live_keys: None,
+            ..stats(2).unwrap()
+        };
+        assert_eq!(decoded_block_meta.get_stats(), Some(expected_stats));
+        assert_eq!(decoded_block_meta.get_restart_interval(), 16);
+    }
+
     #[test]
     fn test_decode_to_list() {
-        let block_meta_1 = BlockMetadata::new(4, TimestampedKey::new("k1".as_bytes().into()), TimestampedKey::new("k2".as_bytes().into()));
-        let block_meta_2 = BlockMetadata::new(4, TimestampedKey::new("k3".as_bytes().into()), TimestampedKey::new("k4".as_bytes().into()));
+        let block_meta_1 = BlockMetadata::new(4, TimestampedKey::new("k1".as_bytes().into()), TimestampedKey::new("k2".as_bytes().into()), stats(2), 16);
+        let block_meta_2 = BlockMetadata::new(4, TimestampedKey::new("k3".as_bytes().into()), TimestampedKey::new("k4".as_bytes().into()), stats(5), 16);
         let mut encoded = block_meta_1.encode();
         encoded.extend(block_meta_2.encode());
 
-        let decoded_list = BlockMetadata::decode_to_list(&encoded);
+        let decoded_list = BlockMetadata::decode_to_list(&encoded, SST_FORMAT_VERSION).unwrap();
         assert_eq!(decoded_list.len(), 2);
         assert_eq!(decoded_list[0], block_meta_1);
         assert_eq!(decoded_list[1], block_meta_2);
+
+        // cut off part way through the second block
+        assert!(BlockMetadata::decode_to_list(&encoded[..encoded.len() - 20], SST_FORMAT_VERSION).is_none());
     }
 }
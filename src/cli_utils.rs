@@ -1,8 +1,12 @@
 use anyhow::Result;
 
-pub fn readline() -> Result<String> {
+// None once stdin is closed
+pub fn readline() -> Result<Option<String>> {
     let mut buffer = String::new();
-    std::io::stdin()
+    let num_read = std::io::stdin()
         .read_line(&mut buffer)?;
-    Ok(buffer)
-}
\ No newline at end of file
+    if num_read == 0 {
+        return Ok(None);
+    }
+    Ok(Some(buffer))
+}
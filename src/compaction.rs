@@ -0,0 +1,271 @@
+use std::{
+    ops::Range,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+
+use crate::table::Sst;
+
+// how the background compaction task reorganizes SSTs
+#[derive(Clone, Copy, Debug, Default)]
+pub enum CompactionStyle {
+    // SSTs stay in l0 exactly as they were written
+    #[default]
+    None,
+    // for time-series data such as logs or metrics, see TimeWindowOptions
+    TimeWindow(TimeWindowOptions),
+    // never merges SSTs, and deletes the oldest ones whenever the store
+    // grows past StorageStateOptions::max_db_size_bytes, e.g. for a bounded
+    // on-disk cache
+    Fifo,
+}
+
+// how many of the oldest SSTs to delete to get back under max_size_bytes.
+// sst_sizes are those of the l0 SSTs, newest to oldest
+pub(crate) fn plan_fifo_eviction(sst_sizes: &[u64], max_size_bytes: u64) -> usize {
+    let mut total_size: u64 = sst_sizes.iter().sum();
+    sst_sizes
+        .iter()
+        .rev()
+        .take_while(|size| {
+            let over_limit = total_size > max_size_bytes;
+            total_size -= **size;
+            over_limit
+        })
+        .count()
+}
+
+// buckets SSTs by creation time into fixed windows. SSTs of the same window
+// are merged with each other but never with another window's, so once a
+// window is older than the ttl all of its data can be dropped by deleting
+// whole files
+#[derive(Clone, Copy, Debug)]
+pub struct TimeWindowOptions {
+    pub window: Duration,
+    // SSTs the current window collects before they are merged. windows that
+    // have closed are merged as soon as they hold two SSTs
+    pub min_merge_width: usize,
+    // delete a window once this long has passed since it closed. None keeps
+    // every window
+    pub ttl: Option<Duration>,
+}
+
+impl Default for TimeWindowOptions {
+    fn default() -> Self {
+        Self {
+            window: Duration::from_secs(3600),
+            min_merge_width: 4,
+            ttl: None,
+        }
+    }
+}
+
+// what one round of time-window compaction should do, as positions in l0
+#[derive(Debug, Default, PartialEq)]
+pub struct TimeWindowPlan {
+    // SSTs whose window has aged out
+    pub expired: Vec<usize>,
+    // newest to oldest SSTs to merge into one, all from the same window
+    pub merge: Option<Range<usize>>,
+}
+
+impl TimeWindowOptions {
+    // creation_times are those of the l0 SSTs, newest to oldest
+    pub fn plan(&self, creation_times: &[SystemTime], now: SystemTime) -> TimeWindowPlan {
+        let windows: Vec<u128> = creation_times.iter().map(|created| self.window_of(*created)).collect();
+        let current_window = self.window_of(now);
+        let mut plan = TimeWindowPlan::default();
+        if let Some(ttl) = self.ttl {
+            plan.expired = windows
+                .iter()
+                .enumerate()
+                .filter(|(_, window)| self.window_end(**window) + ttl <= now)
+                .map(|(position, _)| position)
+                .collect();
+        }
+        let mut start = 0;
+        while start < windows.len() {
+            let window = windows[start];
+            let end = start + windows[start..].iter().take_while(|other| **other == window).count();
+            // merging a window split up by another one would change which
+            // version of a key is the newest
+            let is_contiguous = !windows[end..].contains(&window);
+            let min_width = if window >= current_window { self.min_merge_width } else { 2 };
+            if is_contiguous && end - start >= min_width.max(2) && !plan.expired.contains(&start) {
+                plan.merge = Some(start..end);
+                break;
+            }
+            start = end;
+        }
+        plan
+    }
+
+    // windows count from the unix epoch. times before it go in the first one
+    fn window_of(&self, time: SystemTime) -> u128 {
+        let since_epoch = time.duration_since(SystemTime::UNIX_EPOCH).unwrap_or_default();
+        since_epoch.as_nanos() / self.window.as_nanos().max(1)
+    }
+
+    fn window_end(&self, window: u128) -> SystemTime {
+        let end_nanos = (window + 1) * self.window.as_nanos().max(1);
+        let end = Duration::new(
+            (end_nanos / 1_000_000_000) as u64,
+            (end_nanos % 1_000_000_000) as u32,
+        );
+        SystemTime::UNIX_EPOCH + end
+    }
+}
+
+// decides whether compaction may drop the tombstones of an input SST.
+// entries don't record when they were written, but a tombstone is at least as
+// old as the SST holding it, so the SST's creation time bounds its age from
+// below and the retention window is never cut short
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TombstoneRetention {
+    ttl: Option<Duration>,
+}
+
+impl TombstoneRetention {
+    pub(crate) fn new(ttl: Option<Duration>) -> Self {
+        Self { ttl }
+    }
+
+    // tombstones can only ever be dropped at the bottom level, where there is
+    // no older version left for them to hide
+    pub(crate) fn can_drop_tombstones(&self, sst: &Sst, is_bottom_level: bool, now: SystemTime) -> Result<bool> {
+        if !is_bottom_level {
+            return Ok(false);
+        }
+        match self.ttl {
+            None => Ok(true),
+            Some(ttl) => Ok(Self::is_expired(sst.get_creation_time()?, ttl, now)),
+        }
+    }
+
+    fn is_expired(created: SystemTime, ttl: Duration, now: SystemTime) -> bool {
+        // a creation time in the future (clock skew) counts as brand new
+        now.duration_since(created).is_ok_and(|age| age >= ttl)
+    }
+}
+
+// how many versions of each key a merge of SSTs keeps. flushes keep every
+// version a memtable holds, and reads only ever see the newest one unless they
+// ask for history, see LsmStore::get_versions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum VersionRetention {
+    // shadowed versions are dropped
+    #[default]
+    Latest,
+    // the newest n versions, n at least 1
+    Count(usize),
+    // every version for this long. entries don't record when they were
+    // written, so like TombstoneRetention this goes by SST creation times:
+    // all versions are kept until the newest input of a merge is this old
+    Age(Duration),
+}
+
+impl VersionRetention {
+    // versions of each key to keep in a merge whose newest input was created
+    // at newest_created
+    pub fn versions_to_keep(&self, newest_created: SystemTime, now: SystemTime) -> usize {
+        match *self {
+            VersionRetention::Latest => 1,
+            VersionRetention::Count(n) => n.max(1),
+            VersionRetention::Age(age) => match TombstoneRetention::is_expired(newest_created, age, now) {
+                true => 1,
+                false => usize::MAX,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::table::test_utils::build_sst;
+
+    use super::{plan_fifo_eviction, TimeWindowOptions, TimeWindowPlan, TombstoneRetention, VersionRetention};
+
+    #[test]
+    fn test_tombstone_retention() {
+        let sst = build_sst();
+        let created = sst.get_creation_time().unwrap();
+        let hour = Duration::from_secs(3600);
+
+        let no_ttl = TombstoneRetention::default();
+        assert!(no_ttl.can_drop_tombstones(&sst, true, created).unwrap());
+        assert!(!no_ttl.can_drop_tombstones(&sst, false, created).unwrap());
+
+        let retention = TombstoneRetention::new(Some(hour));
+        assert!(!retention.can_drop_tombstones(&sst, true, created).unwrap());
+        assert!(!retention.can_drop_tombstones(&sst, true, created + hour / 2).unwrap());
+        assert!(retention.can_drop_tombstones(&sst, true, created + hour).unwrap());
+        assert!(!retention.can_drop_tombstones(&sst, false, created + 2 * hour).unwrap());
+        assert!(!retention.can_drop_tombstones(&sst, true, SystemTime::UNIX_EPOCH).unwrap());
+    }
+
+    #[test]
+    fn test_time_window_plan() {
+        let hour = Duration::from_secs(3600);
+        let at = |hours: u32, minutes: u64| {
+            SystemTime::UNIX_EPOCH + hour * hours + Duration::from_secs(60 * minutes)
+        };
+        let options = TimeWindowOptions {
+            window: hour,
+            min_merge_width: 3,
+            ttl: Some(2 * hour),
+        };
+        let now = at(10, 30);
+
+        // the current window only merges once it has min_merge_width SSTs
+        let created = [at(10, 20), at(10, 10), at(9, 50), at(9, 40)];
+        assert_eq!(options.plan(&created, now), TimeWindowPlan { expired: vec![], merge: Some(2..4) });
+        let created = [at(10, 20), at(10, 10), at(10, 0), at(9, 40)];
+        assert_eq!(options.plan(&created, now), TimeWindowPlan { expired: vec![], merge: Some(0..3) });
+
+        // windows are never merged with each other
+        let created = [at(10, 20), at(9, 20), at(8, 20)];
+        assert_eq!(options.plan(&created, now), TimeWindowPlan::default());
+
+        // a window interrupted by another one is left alone
+        let created = [at(9, 50), at(8, 50), at(9, 10)];
+        assert_eq!(options.plan(&created, now), TimeWindowPlan::default());
+
+        // windows expire ttl after they close, and expired SSTs aren't merged
+        let created = [at(9, 0), at(8, 0), at(7, 30), at(7, 0)];
+        assert_eq!(
+            options.plan(&created, now),
+            TimeWindowPlan { expired: vec![2, 3], merge: None }
+        );
+        let expired = options.plan(&created, at(10, 0));
+        assert_eq!(expired, TimeWindowPlan { expired: vec![2, 3], merge: None });
+        let kept = options.plan(&created, at(9, 59));
+        assert_eq!(kept, TimeWindowPlan { expired: vec![], merge: Some(2..4) });
+    }
+
+    #[test]
+    fn test_fifo_eviction() {
+        assert_eq!(plan_fifo_eviction(&[], 0), 0);
+        assert_eq!(plan_fifo_eviction(&[10, 20, 30], 60), 0);
+        assert_eq!(plan_fifo_eviction(&[10, 20, 30], 59), 1);
+        assert_eq!(plan_fifo_eviction(&[10, 20, 30], 30), 1);
+        assert_eq!(plan_fifo_eviction(&[10, 20, 30], 29), 2);
+        assert_eq!(plan_fifo_eviction(&[10, 20, 30], 0), 3);
+    }
+
+    #[test]
+    fn test_version_retention() {
+        let now = SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        assert_eq!(VersionRetention::Latest.versions_to_keep(now, now), 1);
+        assert_eq!(VersionRetention::Count(3).versions_to_keep(now - hour, now), 3);
+        assert_eq!(VersionRetention::Count(0).versions_to_keep(now, now), 1);
+        let age = VersionRetention::Age(hour);
+        assert_eq!(age.versions_to_keep(now - hour / 2, now), usize::MAX);
+        assert_eq!(age.versions_to_keep(now - hour, now), 1);
+        // clock skew
+        assert_eq!(age.versions_to_keep(now + hour, now), usize::MAX);
+    }
+}
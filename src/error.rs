@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+use thiserror::Error;
+
+// errors callers may want to match on. they are returned wrapped in
+// anyhow::Error, so use `err.downcast_ref::<LsmError>()` to inspect them
+#[derive(Error, Debug, Clone, PartialEq)]
+pub enum LsmError {
+    #[error("store does not exist at {0:?} and create_if_missing is false")]
+    NotFound(PathBuf),
+    #[error("store already exists at {0:?} and error_if_exists is true")]
+    AlreadyExists(PathBuf),
+    #[error("corruption: {0}")]
+    Corruption(String),
+    #[error("invalid options: {0}")]
+    InvalidOptions(String),
+    // a read with ReadOptions::min_sequence on a store that hasn't caught up
+    // to it yet, e.g. a replica. retry once it has
+    #[error("read requires sequence {required}, but the store is at {current}")]
+    SequenceNotReached { required: u64, current: u64 },
+    // the store was closed, and only takes reads of its stats from then on
+    #[error("store is closed")]
+    Closed,
+    // a background task panicked, see LsmStore::background_error
+    #[error("background error: {0}")]
+    Background(String),
+}
@@ -0,0 +1,50 @@
+use anyhow::Result;
+
+// named points in multi-step operations where tests can make the operation
+// fail, as if the process had died there. armed per thread, so only calls
+// made on the test's own thread fail. compiled out of non-test builds
+
+// after a flush has written its SSTs, before they are in the manifest
+pub(crate) const FLUSH_SST_WRITTEN: &str = "flush_sst_written";
+// after the manifest records the flush, before its memtables are dropped
+pub(crate) const FLUSH_MANIFEST_UPDATED: &str = "flush_manifest_updated";
+
+#[cfg(test)]
+thread_local! {
+    static ARMED: std::cell::Cell<Option<&'static str>> = const { std::cell::Cell::new(None) };
+}
+
+// Err if name is armed. the failpoint disarms itself, so retries go through
+#[cfg(test)]
+pub(crate) fn check(name: &'static str) -> Result<()> {
+    if ARMED.with(|armed| armed.get()) == Some(name) {
+        ARMED.with(|armed| armed.set(None));
+        anyhow::bail!("failpoint {} hit", name);
+    }
+    Ok(())
+}
+
+#[cfg(not(test))]
+#[inline(always)]
+pub(crate) fn check(_name: &'static str) -> Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) fn arm(name: &'static str) {
+    ARMED.with(|armed| armed.set(Some(name)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{arm, check, FLUSH_MANIFEST_UPDATED, FLUSH_SST_WRITTEN};
+
+    #[test]
+    fn test_arm() {
+        assert!(check(FLUSH_SST_WRITTEN).is_ok());
+        arm(FLUSH_SST_WRITTEN);
+        assert!(check(FLUSH_MANIFEST_UPDATED).is_ok());
+        assert!(check(FLUSH_SST_WRITTEN).is_err());
+        assert!(check(FLUSH_SST_WRITTEN).is_ok());
+    }
+}
@@ -1,12 +1,124 @@
-use crate::kv::kv_pair::KeyValuePair;
+use std::{
+    iter::Sum,
+    ops::{Add, Sub},
+};
 
-pub mod merge_iterator;
-pub mod two_merge_iterator;
-pub mod bounded_iterator;
+use anyhow::{anyhow, Result};
+
+use crate::{error::LsmError, kv::kv_pair::KeyValuePair};
+
+pub(crate) mod merge_iterator;
+pub(crate) mod two_merge_iterator;
+pub(crate) mod bounded_iterator;
+pub(crate) mod keys_only_iterator;
+pub(crate) mod latest_iterator;
+pub(crate) mod limit_iterator;
+pub(crate) mod lsm_iterator;
+pub(crate) mod projection_iterator;
+pub(crate) mod scan_iterator;
+pub(crate) mod tracked_iterator;
 #[cfg(test)]
 pub mod test_iterator;
 
-pub trait StorageIterator: Iterator {
-    fn peek(&mut self) -> Option<KeyValuePair>;
+// resources held by an iterator and everything below it. a memtable or SST
+// iterator stops counting once it has no entries left
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct IteratorStats {
+    pub num_memtable_iterators: usize,
+    pub num_sst_iterators: usize,
+    // blocks kept in memory by the iterators, whether or not they are cached
+    pub num_pinned_blocks: usize,
+}
+
+impl Add for IteratorStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            num_memtable_iterators: self.num_memtable_iterators + other.num_memtable_iterators,
+            num_sst_iterators: self.num_sst_iterators + other.num_sst_iterators,
+            num_pinned_blocks: self.num_pinned_blocks + other.num_pinned_blocks,
+        }
+    }
+}
+
+impl Sub for IteratorStats {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            num_memtable_iterators: self.num_memtable_iterators - other.num_memtable_iterators,
+            num_sst_iterators: self.num_sst_iterators - other.num_sst_iterators,
+            num_pinned_blocks: self.num_pinned_blocks - other.num_pinned_blocks,
+        }
+    }
+}
+
+impl Sum for IteratorStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+pub(crate) trait StorageIterator: Iterator {
+    // borrowed so that merging can compare entries without copying them. a
+    // value kept in an SST's blob file is only read once next returns it, and
+    // is empty until then
+    fn peek(&self) -> Option<&KeyValuePair>;
     fn is_valid(&self) -> bool;
+    // the error that stopped iteration, if any. an iterator that returns None
+    // from next() has either run out of entries or failed, and only this tells
+    // the two apart
+    fn error(&self) -> Option<&anyhow::Error>;
+    fn stats(&self) -> IteratorStats;
+
+    // moves past the current entry like next, without reading its value
+    fn skip_current(&mut self) {
+        self.next();
+    }
+
+    // memtable and SST iterators that still have entries to return
+    fn num_active_iterators(&self) -> usize {
+        let stats = self.stats();
+        stats.num_memtable_iterators + stats.num_sst_iterators
+    }
+
+    // Err if iteration stopped because of an error. call after iterating
+    fn check_error(&self) -> Result<()> {
+        check_error(self.error())
+    }
+}
+
+impl<T: StorageIterator + ?Sized> StorageIterator for Box<T> {
+    fn peek(&self) -> Option<&KeyValuePair> {
+        (**self).peek()
+    }
+
+    fn is_valid(&self) -> bool {
+        (**self).is_valid()
+    }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        (**self).error()
+    }
+
+    fn stats(&self) -> IteratorStats {
+        (**self).stats()
+    }
+
+    fn skip_current(&mut self) {
+        (**self).skip_current()
+    }
+}
+
+// anyhow::Error can't be cloned, so errors are copied out by message. store
+// errors keep their type so callers can still match on them
+pub(crate) fn check_error(error: Option<&anyhow::Error>) -> Result<()> {
+    match error {
+        None => Ok(()),
+        Some(err) => match err.downcast_ref::<LsmError>() {
+            Some(lsm_err) => Err(lsm_err.clone().into()),
+            None => Err(anyhow!("scan failed: {:#}", err)),
+        },
+    }
 }
\ No newline at end of file
@@ -3,36 +3,31 @@ use std::ops::Bound;
 
 use bytes::Bytes;
 
-use crate::iterator::StorageIterator;
+use crate::iterator::{IteratorStats, StorageIterator};
 use crate::kv::kv_pair::KeyValuePair;
 use crate::kv::timestamped_key::TimestampedKey;
 
-pub struct BoundedIterator<T> {
+pub(crate) struct BoundedIterator<T> {
     sub_iterator: T,
     upper_bound: Bound<TimestampedKey>,
 }
 
 impl<T> BoundedIterator<T> where T: StorageIterator + Iterator<Item = KeyValuePair> {
-    pub fn new(sub_iterator: T, bound: Bound<&[u8]>) -> Self {
+    pub(crate) fn new(sub_iterator: T, bound: Bound<&[u8]>) -> Self {
         Self {
             sub_iterator,
             upper_bound: bound.map(|key| TimestampedKey::new(Bytes::copy_from_slice(key))),
         }
     }
-}
 
-impl<T> StorageIterator for BoundedIterator<T>
-where
-    T: StorageIterator + Iterator<Item = KeyValuePair>,
-{
-    fn peek(&mut self) -> Option<KeyValuePair> {
+    fn next_in_bounds(&mut self) -> Option<KeyValuePair> {
         match self.sub_iterator.peek() {
             Some(current_kv) => {
                 match &self.upper_bound {
                     Bound::Included(upper_key) => {
                         match current_kv.key.cmp(upper_key) {
                             Ordering::Less | Ordering::Equal => {
-                                Some(current_kv)
+                                self.sub_iterator.next()
                             },
                             Ordering::Greater => {
                                 None
@@ -42,39 +37,35 @@ where
                     Bound::Excluded(upper_key) => {
                         match current_kv.key.cmp(upper_key) {
                             Ordering::Less => {
-                                Some(current_kv)
+                                self.sub_iterator.next()
                             },
                             Ordering::Equal | Ordering::Greater => {
                                 None
                             },
                         }
                     },
-                    Bound::Unbounded => { Some(current_kv) },
+                    Bound::Unbounded => { 
+                        self.sub_iterator.next()
+                    },
                 }
             },
             None => { None },
         }
     }
-
-    fn is_valid(&self) -> bool {
-        self.sub_iterator.is_valid()
-    }
 }
 
-impl<T> Iterator for BoundedIterator<T>
+impl<T> StorageIterator for BoundedIterator<T>
 where
     T: StorageIterator + Iterator<Item = KeyValuePair>,
 {
-    type Item = KeyValuePair;
-
-    fn next(&mut self) -> Option<KeyValuePair> {
+    fn peek(&self) -> Option<&KeyValuePair> {
         match self.sub_iterator.peek() {
             Some(current_kv) => {
                 match &self.upper_bound {
                     Bound::Included(upper_key) => {
                         match current_kv.key.cmp(upper_key) {
                             Ordering::Less | Ordering::Equal => {
-                                self.sub_iterator.next()
+                                Some(current_kv)
                             },
                             Ordering::Greater => {
                                 None
@@ -84,21 +75,52 @@ where
                     Bound::Excluded(upper_key) => {
                         match current_kv.key.cmp(upper_key) {
                             Ordering::Less => {
-                                self.sub_iterator.next()
+                                Some(current_kv)
                             },
                             Ordering::Equal | Ordering::Greater => {
                                 None
                             },
                         }
                     },
-                    Bound::Unbounded => { 
-                        self.sub_iterator.next()
-                    },
+                    Bound::Unbounded => { Some(current_kv) },
                 }
             },
             None => { None },
         }
     }
+
+    fn is_valid(&self) -> bool {
+        self.sub_iterator.is_valid()
+    }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.sub_iterator.error()
+    }
+
+    // the sub-iterator may have more entries past the bound
+    fn stats(&self) -> IteratorStats {
+        if self.peek().is_none() {
+            return IteratorStats::default();
+        }
+        self.sub_iterator.stats()
+    }
+
+    fn skip_current(&mut self) {
+        if self.peek().is_some() {
+            self.sub_iterator.skip_current();
+        }
+    }
+}
+
+impl<T> Iterator for BoundedIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    type Item = KeyValuePair;
+
+    fn next(&mut self) -> Option<KeyValuePair> {
+        self.next_in_bounds()
+    }
 }
 
 #[cfg(test)]
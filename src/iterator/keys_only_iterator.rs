@@ -0,0 +1,85 @@
+use std::iter::FusedIterator;
+
+use bytes::Bytes;
+
+use crate::kv::kv_pair::KeyValuePair;
+
+use super::{latest_iterator::LatestIterator, IteratorStats, StorageIterator};
+
+// yields each live user key once
+pub(crate) struct KeysOnlyIterator<T: StorageIterator> {
+    sub_iterator: LatestIterator<T>,
+}
+
+impl<T> KeysOnlyIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    pub(crate) fn new(sub_iterator: T) -> Self {
+        Self {
+            sub_iterator: LatestIterator::new(sub_iterator),
+        }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.sub_iterator.is_valid()
+    }
+
+    pub(crate) fn error(&self) -> Option<&anyhow::Error> {
+        self.sub_iterator.error()
+    }
+
+    pub(crate) fn check_error(&self) -> anyhow::Result<()> {
+        self.sub_iterator.check_error()
+    }
+
+    pub(crate) fn stats(&self) -> IteratorStats {
+        self.sub_iterator.stats()
+    }
+}
+
+impl<T> Iterator for KeysOnlyIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        self.sub_iterator.next().map(|kv| kv.key.get_key())
+    }
+}
+
+impl<T> FusedIterator for KeysOnlyIterator<T> where T: StorageIterator + Iterator<Item = KeyValuePair> {}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::{
+        iterator::merge_iterator::MergeIterator,
+        memory::memtable::{iterator::MemTableIterator, MemTable},
+    };
+
+    use super::KeysOnlyIterator;
+
+    #[test]
+    fn test_keys_only() {
+        let newer = MemTable::new(0);
+        let _ = newer.delete("k1".as_bytes());
+        let _ = newer.put("k2".as_bytes(), "v2".as_bytes());
+        let older = MemTable::new(1);
+        let _ = older.put("k1".as_bytes(), "v1".as_bytes());
+        let _ = older.put("k2".as_bytes(), "old_v2".as_bytes());
+        let _ = older.put("k3".as_bytes(), "v3".as_bytes());
+
+        let merge_iterator = MergeIterator::new(vec![
+            MemTableIterator::new(&newer, Bound::Unbounded, Bound::Unbounded),
+            MemTableIterator::new(&older, Bound::Unbounded, Bound::Unbounded),
+        ]);
+        let keys_only_iterator = KeysOnlyIterator::new(merge_iterator);
+        assert!(keys_only_iterator.is_valid());
+        let keys: Vec<_> = keys_only_iterator.collect();
+        // k1 was deleted in the newer memtable
+        assert_eq!(keys, vec!["k2".as_bytes(), "k3".as_bytes()]);
+    }
+}
@@ -0,0 +1,152 @@
+use std::iter::FusedIterator;
+
+use bytes::Bytes;
+
+use crate::kv::kv_pair::KeyValuePair;
+
+use super::{IteratorStats, StorageIterator};
+
+// yields only the newest version of each key and hides deleted keys. expects
+// the sub-iterator to return equal keys newest first, as the merge iterators do
+pub(crate) struct LatestIterator<T: StorageIterator> {
+    // points at the next entry to return, so its value is only read by next
+    sub_iterator: T,
+    // the sub-iterator has returned None, and isn't read again
+    is_exhausted: bool,
+}
+
+impl<T> LatestIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    pub(crate) fn new(sub_iterator: T) -> Self {
+        let mut res = Self {
+            sub_iterator,
+            is_exhausted: false,
+        };
+        res.skip_deleted_keys();
+        res
+    }
+
+    // move past the versions of deleted keys, without reading their values
+    fn skip_deleted_keys(&mut self) {
+        while let Some(key) = self
+            .sub_iterator
+            .peek()
+            .filter(|kv| kv.is_tombstone())
+            .map(|kv| kv.key.get_key())
+        {
+            self.sub_iterator.skip_current();
+            self.skip_older_versions(&key);
+        }
+    }
+
+    fn skip_older_versions(&mut self, key: &Bytes) {
+        while self
+            .sub_iterator
+            .peek()
+            .is_some_and(|kv| kv.key.get_key() == key)
+        {
+            self.sub_iterator.skip_current();
+        }
+    }
+}
+
+impl<T> StorageIterator for LatestIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    fn peek(&self) -> Option<&KeyValuePair> {
+        if self.is_exhausted {
+            return None;
+        }
+        self.sub_iterator.peek()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.sub_iterator.is_valid()
+    }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.sub_iterator.error()
+    }
+
+    fn stats(&self) -> IteratorStats {
+        self.sub_iterator.stats()
+    }
+
+    fn skip_current(&mut self) {
+        let Some(key) = self.peek().map(|kv| kv.key.get_key()) else {
+            return;
+        };
+        self.sub_iterator.skip_current();
+        self.skip_older_versions(&key);
+        self.skip_deleted_keys();
+    }
+}
+
+impl<T> Iterator for LatestIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    type Item = KeyValuePair;
+
+    fn next(&mut self) -> Option<KeyValuePair> {
+        if self.is_exhausted {
+            return None;
+        }
+        let Some(res) = self.sub_iterator.next() else {
+            self.is_exhausted = true;
+            return None;
+        };
+        self.skip_older_versions(&res.key.get_key());
+        self.skip_deleted_keys();
+        Some(res)
+    }
+}
+
+impl<T> FusedIterator for LatestIterator<T> where T: StorageIterator + Iterator<Item = KeyValuePair> {}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::{
+        iterator::{merge_iterator::MergeIterator, StorageIterator},
+        memory::memtable::{iterator::MemTableIterator, MemTable},
+    };
+
+    use super::LatestIterator;
+
+    #[test]
+    fn test_latest_versions() {
+        let newer = MemTable::new(0);
+        let _ = newer.delete("k1".as_bytes());
+        let _ = newer.put("k2".as_bytes(), "v2".as_bytes());
+        let older = MemTable::new(1);
+        let _ = older.put("k1".as_bytes(), "v1".as_bytes());
+        let _ = older.put("k2".as_bytes(), "old_v2".as_bytes());
+        let _ = older.put("k3".as_bytes(), "v3".as_bytes());
+
+        let merge_iterator = MergeIterator::new(vec![
+            MemTableIterator::new(&newer, Bound::Unbounded, Bound::Unbounded),
+            MemTableIterator::new(&older, Bound::Unbounded, Bound::Unbounded),
+        ]);
+        let latest_iterator = LatestIterator::new(merge_iterator);
+        assert!(latest_iterator.is_valid());
+        assert!(latest_iterator
+            .peek()
+            .is_some_and(|kv| kv.key.get_key() == "k2".as_bytes()));
+        let items: Vec<_> = latest_iterator
+            .map(|kv| (kv.key.get_key(), kv.value))
+            .collect();
+        // k1 was deleted in the newer memtable
+        assert_eq!(
+            items,
+            vec![
+                ("k2".as_bytes().into(), "v2".as_bytes().into()),
+                ("k3".as_bytes().into(), "v3".as_bytes().into()),
+            ]
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use crate::kv::kv_pair::KeyValuePair;
+
+use super::{IteratorStats, StorageIterator};
+
+// stops after limit entries, see ReadOptions::limit
+pub(crate) struct LimitIterator<T> {
+    sub_iterator: T,
+    // None for no limit
+    remaining: Option<usize>,
+}
+
+impl<T> LimitIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    pub(crate) fn new(sub_iterator: T, limit: Option<usize>) -> Self {
+        Self {
+            sub_iterator,
+            remaining: limit,
+        }
+    }
+
+    fn reached_limit(&self) -> bool {
+        self.remaining == Some(0)
+    }
+}
+
+impl<T> StorageIterator for LimitIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    fn peek(&self) -> Option<&KeyValuePair> {
+        if self.reached_limit() {
+            return None;
+        }
+        self.sub_iterator.peek()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.sub_iterator.is_valid()
+    }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.sub_iterator.error()
+    }
+
+    fn stats(&self) -> IteratorStats {
+        if self.reached_limit() {
+            return IteratorStats::default();
+        }
+        self.sub_iterator.stats()
+    }
+
+    // a skipped entry counts towards the limit like a returned one
+    fn skip_current(&mut self) {
+        if self.peek().is_none() {
+            return;
+        }
+        self.sub_iterator.skip_current();
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+    }
+}
+
+impl<T> Iterator for LimitIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    type Item = KeyValuePair;
+
+    fn next(&mut self) -> Option<KeyValuePair> {
+        if self.reached_limit() {
+            return None;
+        }
+        let res = self.sub_iterator.next()?;
+        if let Some(remaining) = self.remaining.as_mut() {
+            *remaining -= 1;
+        }
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use crate::{iterator::StorageIterator, memory::memtable::MemTable};
+
+    use super::LimitIterator;
+
+    #[test]
+    fn test_limit_iterator() {
+        let memtable = MemTable::new(0);
+        for key in ["k1", "k2", "k3"] {
+            memtable.put(key.as_bytes(), "value".as_bytes()).unwrap();
+        }
+
+        let mut iterator = LimitIterator::new(memtable.scan(Bound::Unbounded, Bound::Unbounded), Some(2));
+        assert_eq!(iterator.next().unwrap().key.get_key(), "k1".as_bytes());
+        assert_eq!(iterator.peek().unwrap().key.get_key(), "k2".as_bytes());
+        assert_eq!(iterator.next().unwrap().key.get_key(), "k2".as_bytes());
+        assert!(iterator.peek().is_none());
+        assert!(iterator.next().is_none());
+        assert_eq!(iterator.num_active_iterators(), 0);
+
+        let iterator = LimitIterator::new(memtable.scan(Bound::Unbounded, Bound::Unbounded), None);
+        assert_eq!(iterator.count(), 3);
+    }
+}
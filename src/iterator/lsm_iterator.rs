@@ -0,0 +1,86 @@
+use std::iter::FusedIterator;
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+use crate::kv::kv_pair::KeyValuePair;
+
+use super::{check_error, IteratorStats, StorageIterator};
+
+// a scan as a nameable type, for embedders that need to keep one in a struct
+// or pass it around as a plain Iterator. yields the live key-value pairs in
+// range, and an error that stops the scan is yielded once as the last item
+pub struct LsmIterator {
+    sub_iterator: Box<dyn StorageIterator<Item = KeyValuePair>>,
+    // set once the error has been yielded
+    done: bool,
+}
+
+impl LsmIterator {
+    // sub_iterator should already hide tombstones and older versions, as
+    // LatestIterator does
+    pub(crate) fn new(sub_iterator: impl StorageIterator<Item = KeyValuePair> + 'static) -> Self {
+        Self {
+            sub_iterator: Box::new(sub_iterator),
+            done: false,
+        }
+    }
+
+    pub fn stats(&self) -> IteratorStats {
+        self.sub_iterator.stats()
+    }
+}
+
+impl Iterator for LsmIterator {
+    type Item = Result<(Bytes, Bytes)>;
+
+    fn next(&mut self) -> Option<Result<(Bytes, Bytes)>> {
+        if self.done {
+            return None;
+        }
+        match self.sub_iterator.next() {
+            Some(kv) => Some(Ok((kv.key.get_key(), kv.value))),
+            None => {
+                self.done = true;
+                check_error(self.sub_iterator.error()).err().map(Err)
+            }
+        }
+    }
+}
+
+impl FusedIterator for LsmIterator {}
+
+// an LsmIterator over UTF-8 keys and values, see LsmStore::scan_str
+pub struct LsmStrIterator {
+    sub_iterator: LsmIterator,
+}
+
+impl LsmStrIterator {
+    pub(crate) fn new(sub_iterator: LsmIterator) -> Self {
+        Self { sub_iterator }
+    }
+
+    pub fn stats(&self) -> IteratorStats {
+        self.sub_iterator.stats()
+    }
+}
+
+impl Iterator for LsmStrIterator {
+    type Item = Result<(String, String)>;
+
+    fn next(&mut self) -> Option<Result<(String, String)>> {
+        let (key, value) = match self.sub_iterator.next()? {
+            Ok(kv) => kv,
+            Err(err) => return Some(Err(err)),
+        };
+        let value = utf8_string(value, "value", &key);
+        Some(utf8_string(key.clone(), "key", &key).and_then(|key| Ok((key, value?))))
+    }
+}
+
+impl FusedIterator for LsmStrIterator {}
+
+// what names the bytes in the error, e.g. "value" of key
+pub(crate) fn utf8_string(bytes: Bytes, what: &str, key: &[u8]) -> Result<String> {
+    String::from_utf8(bytes.into()).map_err(|err| anyhow!("{} of {:?} is not valid UTF-8: {}", what, key, err))
+}
@@ -1,37 +1,127 @@
-use std::{cmp::Reverse, collections::BinaryHeap};
+use std::{
+    cmp::{Ordering, Reverse},
+    collections::BinaryHeap,
+};
 
-use crate::kv::kv_pair::KeyValuePair;
+use bytes::Bytes;
 
-use super::StorageIterator;
+use crate::kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey};
 
-pub struct MergeIterator<T: StorageIterator> {
-    // first value: key value pair; second value: index of source iterator
-    heap: BinaryHeap<Reverse<(KeyValuePair, usize)>>,
+use super::{IteratorStats, StorageIterator};
+
+// the current entry of each source iterator is left in the iterator, so that
+// values are only read for the entries that are returned
+struct HeapEntry {
+    key: TimestampedKey,
+    // index of source iterator
+    index: usize,
+}
+
+// order by key, then by source index so that equal keys come out of the
+// heap newest first (callers pass iterators ordered newest to oldest)
+impl Ord for HeapEntry {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key
+            .cmp(&other.key)
+            .then(self.index.cmp(&other.index))
+    }
+}
+
+impl PartialOrd for HeapEntry {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for HeapEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for HeapEntry {}
+
+// merges sorted iterators into one, yielding only the newest version of each
+// key. every other version, from any input, is skipped. tombstones are passed
+// on like any other value
+pub(crate) struct MergeIterator<T: StorageIterator> {
+    heap: BinaryHeap<Reverse<HeapEntry>>,
     iterators_to_merge: Vec<T>,
     is_valid: bool,
+    // yield older versions too, newest first, see new_with_all_versions
+    all_versions: bool,
 }
 
 impl<T> MergeIterator<T>
 where
     T: StorageIterator + Iterator<Item = KeyValuePair>,
 {
-    pub fn new(mut iterators_to_merge: Vec<T>) -> Self {
+    pub(crate) fn new(iterators_to_merge: Vec<T>) -> Self {
         let mut is_valid = true;
-        let mut heap: BinaryHeap<Reverse<(KeyValuePair, usize)>> = BinaryHeap::new();
-        for (index, iterator) in iterators_to_merge.iter_mut().enumerate() {
+        let mut heap: BinaryHeap<Reverse<HeapEntry>> = BinaryHeap::new();
+        for (index, iterator) in iterators_to_merge.iter().enumerate() {
             if !iterator.is_valid() {
                 is_valid = false;
                 break;
             }
-            let new_heap_kv = iterator.next();
-            if let Some(new_kv) = new_heap_kv {
-                heap.push(Reverse((new_kv, index)));
+            if let Some(kv) = iterator.peek() {
+                heap.push(Reverse(HeapEntry { key: kv.key.clone(), index }));
             }
         }
         Self {
             heap,
             iterators_to_merge,
             is_valid,
+            all_versions: false,
+        }
+    }
+
+    // like new, without skipping older versions. versions of a key come out
+    // newest first, so merges can keep some of them
+    pub(crate) fn new_with_all_versions(iterators_to_merge: Vec<T>) -> Self {
+        Self {
+            all_versions: true,
+            ..Self::new(iterators_to_merge)
+        }
+    }
+
+    // take the smallest entry, or skip it without reading its value, and
+    // refill the heap from its iterator. None once an input has become invalid
+    fn pop_and_advance(&mut self, skip: bool) -> Option<Option<KeyValuePair>> {
+        if !self.is_valid {
+            return None;
+        }
+        let Reverse(HeapEntry { index, .. }) = self.heap.pop()?;
+        let iterator = &mut self.iterators_to_merge[index];
+        let res = match skip {
+            true => {
+                iterator.skip_current();
+                None
+            }
+            false => iterator.next(),
+        };
+        if !iterator.is_valid() {
+            self.is_valid = false;
+        } else if let Some(kv) = iterator.peek() {
+            self.heap.push(Reverse(HeapEntry { key: kv.key.clone(), index }));
+        }
+        Some(res)
+    }
+
+    // move every input past the versions of key, unless all of them are
+    // returned
+    fn skip_older_versions(&mut self, key: &Bytes) {
+        if self.all_versions {
+            return;
+        }
+        while self
+            .heap
+            .peek()
+            .is_some_and(|Reverse(entry)| entry.key.get_key() == key)
+        {
+            if self.pop_and_advance(true).is_none() {
+                break;
+            }
         }
     }
 }
@@ -40,13 +130,34 @@ impl<T> StorageIterator for MergeIterator<T>
 where
     T: StorageIterator + Iterator<Item = KeyValuePair>,
 {
-    fn peek(&mut self) -> Option<KeyValuePair> {
-        self.heap.peek().map(|Reverse((res_kv, _))| res_kv.clone())
+    fn peek(&self) -> Option<&KeyValuePair> {
+        if !self.is_valid {
+            return None;
+        }
+        self.heap
+            .peek()
+            .and_then(|Reverse(entry)| self.iterators_to_merge[entry.index].peek())
     }
 
     fn is_valid(&self) -> bool {
         self.is_valid
     }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.iterators_to_merge.iter().find_map(|iterator| iterator.error())
+    }
+
+    fn stats(&self) -> IteratorStats {
+        self.iterators_to_merge.iter().map(|iterator| iterator.stats()).sum()
+    }
+
+    fn skip_current(&mut self) {
+        let Some(key) = self.peek().map(|kv| kv.key.get_key()) else {
+            return;
+        };
+        self.pop_and_advance(true);
+        self.skip_older_versions(&key);
+    }
 }
 
 impl<T> Iterator for MergeIterator<T>
@@ -55,23 +166,9 @@ where
 {
     type Item = KeyValuePair;
     fn next(&mut self) -> Option<KeyValuePair> {
-        if !self.is_valid {
-            return None;
-        }
-        let res = self.heap.pop();
-        match res {
-            None => None,
-            Some(Reverse((res_kv, index))) => {
-                if !self.iterators_to_merge[index].is_valid() {
-                    self.is_valid = false;
-                }
-                let new_heap_kv = self.iterators_to_merge[index].next();
-                if let Some(new_kv) = new_heap_kv {
-                    self.heap.push(Reverse((new_kv, index)));
-                }
-                Some(res_kv)
-            }
-        }
+        let res = self.pop_and_advance(false)??;
+        self.skip_older_versions(&res.key.get_key());
+        Some(res)
     }
 }
 
@@ -114,15 +211,61 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_equal_keys_newest_only() {
+        // iterators are passed newest to oldest
+        let newer = MemTable::new(0);
+        let _ = newer.put("k1".as_bytes(), "a".as_bytes());
+        let older = MemTable::new(1);
+        let _ = older.put("k1".as_bytes(), "z".as_bytes());
+
+        let merge_iterator = MergeIterator::new(vec![
+            MemTableIterator::new(&older, Bound::Unbounded, Bound::Unbounded),
+            MemTableIterator::new(&newer, Bound::Unbounded, Bound::Unbounded),
+        ]);
+        let values: Vec<_> = merge_iterator.map(|kv| kv.value).collect();
+        assert_eq!(values, vec!["z".as_bytes()]);
+
+        let merge_iterator = MergeIterator::new(vec![
+            MemTableIterator::new(&newer, Bound::Unbounded, Bound::Unbounded),
+            MemTableIterator::new(&older, Bound::Unbounded, Bound::Unbounded),
+        ]);
+        let values: Vec<_> = merge_iterator.map(|kv| kv.value).collect();
+        assert_eq!(values, vec!["a".as_bytes()]);
+    }
+
     #[test]
     fn test_not_valid() {
         let test_iter_1 = TestIterator::new(1, 2);
         let test_iter_2 = TestIterator::new(2, 1);
 
         let mut merge_iterator = MergeIterator::new(vec![test_iter_1, test_iter_2]);
-        assert_eq!(merge_iterator.next().unwrap().key.get_key(), "k1".as_bytes());
-        assert!(merge_iterator.is_valid());
+        // skipping the second k1 reads test_iter_1 past its last valid entry
         assert_eq!(merge_iterator.next().unwrap().key.get_key(), "k1".as_bytes());
         assert!(!merge_iterator.is_valid());
+        assert!(merge_iterator.next().is_none());
+    }
+
+    #[test]
+    fn test_skip_older_versions() {
+        let newer = MemTable::new(0);
+        let _ = newer.delete_with_timestamp("k1".as_bytes(), 4);
+        let _ = newer.put_with_timestamp("k2".as_bytes(), 3, "v2@3".as_bytes());
+        let older = MemTable::new(1);
+        let _ = older.put_with_timestamp("k1".as_bytes(), 2, "v1@2".as_bytes());
+        let _ = older.put_with_timestamp("k2".as_bytes(), 1, "v2@1".as_bytes());
+        let _ = older.put_with_timestamp("k2".as_bytes(), 2, "v2@2".as_bytes());
+        let _ = older.put_with_timestamp("k3".as_bytes(), 1, "v3@1".as_bytes());
+
+        let merge_iterator = MergeIterator::new(vec![
+            MemTableIterator::new(&newer, Bound::Unbounded, Bound::Unbounded),
+            MemTableIterator::new(&older, Bound::Unbounded, Bound::Unbounded),
+        ]);
+        // tombstones are kept, they still hide the key from older SSTs
+        let values: Vec<_> = merge_iterator.map(|kv| (kv.is_tombstone(), kv.value)).collect();
+        assert_eq!(
+            values,
+            vec![(true, "".into()), (false, "v2@3".into()), (false, "v3@1".into())]
+        );
     }
 }
@@ -0,0 +1,140 @@
+use bytes::Bytes;
+
+use crate::kv::kv_pair::KeyValuePair;
+
+use super::{IteratorStats, StorageIterator};
+
+pub(crate) type KeyValueFilter = Box<dyn Fn(&[u8], &[u8]) -> bool>;
+pub(crate) type ValueTransform = Box<dyn Fn(&[u8], Bytes) -> Bytes>;
+
+// what a ScanBuilder does to each entry
+#[derive(Default)]
+pub(crate) struct Projection {
+    // an entry is kept if every filter accepts it
+    pub filters: Vec<KeyValueFilter>,
+    // applied in order to the values of kept entries
+    pub value_transforms: Vec<ValueTransform>,
+    // values are dropped before any transform, which then sees them empty
+    pub keys_only: bool,
+}
+
+impl Projection {
+    fn apply(&self, mut kv: KeyValuePair) -> Option<KeyValuePair> {
+        let key = kv.key.get_key();
+        if !self.filters.iter().all(|filter| filter(&key, &kv.value)) {
+            return None;
+        }
+        if self.keys_only {
+            kv.value = Bytes::new();
+        }
+        for transform in self.value_transforms.iter() {
+            kv.value = transform(&key, kv.value);
+        }
+        Some(kv)
+    }
+}
+
+// filters and reshapes entries as they come out of the merge, so that entries
+// filtered out never count towards a limit or get handed to the caller
+pub(crate) struct ProjectionIterator<T> {
+    sub_iterator: T,
+    projection: Projection,
+    // the next entry the projection kept, already taken from sub_iterator
+    current_kv: Option<KeyValuePair>,
+}
+
+impl<T> ProjectionIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    pub(crate) fn new(sub_iterator: T, projection: Projection) -> Self {
+        let mut new = Self {
+            sub_iterator,
+            projection,
+            current_kv: None,
+        };
+        new.advance();
+        new
+    }
+
+    fn advance(&mut self) {
+        self.current_kv = None;
+        for kv in self.sub_iterator.by_ref() {
+            self.current_kv = self.projection.apply(kv);
+            if self.current_kv.is_some() {
+                return;
+            }
+        }
+    }
+}
+
+impl<T> StorageIterator for ProjectionIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    fn peek(&self) -> Option<&KeyValuePair> {
+        self.current_kv.as_ref()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.sub_iterator.is_valid()
+    }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.sub_iterator.error()
+    }
+
+    fn stats(&self) -> IteratorStats {
+        self.sub_iterator.stats()
+    }
+}
+
+impl<T> Iterator for ProjectionIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    type Item = KeyValuePair;
+
+    fn next(&mut self) -> Option<KeyValuePair> {
+        let res = self.current_kv.take()?;
+        self.advance();
+        Some(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use bytes::Bytes;
+
+    use crate::{iterator::StorageIterator, memory::memtable::MemTable};
+
+    use super::{Projection, ProjectionIterator};
+
+    #[test]
+    fn test_projection() {
+        let memtable = MemTable::new(0);
+        for (key, value) in [("k1", "a"), ("k2", "bb"), ("k3", "ccc")] {
+            memtable.put(key.as_bytes(), value.as_bytes()).unwrap();
+        }
+        let projection = Projection {
+            filters: vec![Box::new(|key, _| key != b"k2"), Box::new(|_, value| !value.is_empty())],
+            value_transforms: vec![Box::new(|key, value| Bytes::from([key, &value[..]].concat()))],
+            keys_only: false,
+        };
+        let mut iterator =
+            ProjectionIterator::new(memtable.scan(Bound::Unbounded, Bound::Unbounded), projection);
+        assert_eq!(iterator.peek().unwrap().value, "k1a");
+        let values: Vec<Bytes> = iterator.by_ref().map(|kv| kv.value).collect();
+        assert_eq!(values, vec!["k1a", "k3ccc"]);
+        assert!(iterator.peek().is_none());
+
+        let projection = Projection {
+            keys_only: true,
+            ..Default::default()
+        };
+        let iterator = ProjectionIterator::new(memtable.scan(Bound::Unbounded, Bound::Unbounded), projection);
+        assert!(iterator.map(|kv| kv.value).all(|value| value.is_empty()));
+    }
+}
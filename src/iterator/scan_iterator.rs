@@ -0,0 +1,56 @@
+use std::iter::FusedIterator;
+
+use anyhow::Result;
+
+use crate::kv::{entry::Entry, kv_pair::KeyValuePair};
+
+use super::{IteratorStats, StorageIterator};
+
+// a scan as LsmStore hands it out, yielding user keys instead of versioned
+// internal keys. like the iterators underneath, a scan that stops early
+// because of an error only says so through check_error
+pub(crate) struct ScanIterator<T: StorageIterator> {
+    sub_iterator: T,
+}
+
+impl<T> ScanIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    pub(crate) fn new(sub_iterator: T) -> Self {
+        Self { sub_iterator }
+    }
+
+    pub(crate) fn is_valid(&self) -> bool {
+        self.sub_iterator.is_valid()
+    }
+
+    pub(crate) fn error(&self) -> Option<&anyhow::Error> {
+        self.sub_iterator.error()
+    }
+
+    pub(crate) fn check_error(&self) -> Result<()> {
+        self.sub_iterator.check_error()
+    }
+
+    pub(crate) fn stats(&self) -> IteratorStats {
+        self.sub_iterator.stats()
+    }
+
+    pub(crate) fn num_active_iterators(&self) -> usize {
+        self.sub_iterator.num_active_iterators()
+    }
+}
+
+impl<T> Iterator for ScanIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        self.sub_iterator.next().map(Entry::from)
+    }
+}
+
+impl<T> FusedIterator for ScanIterator<T> where T: StorageIterator + FusedIterator<Item = KeyValuePair> {}
@@ -2,7 +2,7 @@ use bytes::Bytes;
 
 use crate::kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey};
 
-use super::StorageIterator;
+use super::{IteratorStats, StorageIterator};
 
 pub struct TestIterator {
     is_valid: bool,
@@ -14,7 +14,7 @@ impl TestIterator {
     pub fn new(id: usize, is_valid_count: usize) -> Self {
         let key = Bytes::copy_from_slice(format!("k{}", id).as_bytes());
         let value = Bytes::copy_from_slice(format!("v{}", id).as_bytes());
-        let kv = KeyValuePair { key: TimestampedKey::new(key), value };
+        let kv = KeyValuePair::new(TimestampedKey::new(key), value);
         Self {
             is_valid: is_valid_count > 0,
             is_valid_count,
@@ -24,13 +24,21 @@ impl TestIterator {
 }
 
 impl StorageIterator for TestIterator {
-    fn peek(&mut self) -> Option<KeyValuePair> {
-        Some(self.kv.clone())
+    fn peek(&self) -> Option<&KeyValuePair> {
+        Some(&self.kv)
     }
 
     fn is_valid(&self) -> bool {
         self.is_valid
     }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        None
+    }
+
+    fn stats(&self) -> IteratorStats {
+        IteratorStats::default()
+    }
 }
 
 impl Iterator for TestIterator {
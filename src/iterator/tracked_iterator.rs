@@ -0,0 +1,90 @@
+use std::{iter::FusedIterator, sync::Arc};
+
+use crate::{kv::kv_pair::KeyValuePair, platform::Instant, stats::ScanRegistry};
+
+use super::{IteratorStats, StorageIterator};
+
+// counts a scan in its store's LsmStats for as long as the scan is alive, and
+// times each next for the store's latency report
+pub(crate) struct TrackedIterator<T: StorageIterator> {
+    sub_iterator: T,
+    registry: Arc<ScanRegistry>,
+    // what this scan currently contributes to the registry
+    registered_stats: IteratorStats,
+    // next has returned None. memtables can grow after that, so the
+    // sub-iterator isn't asked again
+    is_exhausted: bool,
+}
+
+impl<T> TrackedIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    pub(crate) fn new(sub_iterator: T, registry: Arc<ScanRegistry>) -> Self {
+        let registered_stats = sub_iterator.stats();
+        registry.register(registered_stats);
+        Self {
+            sub_iterator,
+            registry,
+            registered_stats,
+            is_exhausted: false,
+        }
+    }
+}
+
+impl<T> StorageIterator for TrackedIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    fn peek(&self) -> Option<&KeyValuePair> {
+        self.sub_iterator.peek()
+    }
+
+    fn is_valid(&self) -> bool {
+        self.sub_iterator.is_valid()
+    }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.sub_iterator.error()
+    }
+
+    fn stats(&self) -> IteratorStats {
+        self.sub_iterator.stats()
+    }
+
+    fn skip_current(&mut self) {
+        self.sub_iterator.skip_current()
+    }
+}
+
+impl<T> Iterator for TrackedIterator<T>
+where
+    T: StorageIterator + Iterator<Item = KeyValuePair>,
+{
+    type Item = KeyValuePair;
+
+    fn next(&mut self) -> Option<KeyValuePair> {
+        if self.is_exhausted {
+            return None;
+        }
+        let start = Instant::now();
+        let res = self.sub_iterator.next();
+        self.registry.record_next(start.elapsed());
+        self.is_exhausted = res.is_none();
+        if res.is_none() && self.registered_stats != IteratorStats::default() {
+            // an exhausted scan no longer holds anything
+            let stats = self.sub_iterator.stats();
+            self.registry.update(self.registered_stats, stats);
+            self.registered_stats = stats;
+        }
+        res
+    }
+}
+
+impl<T> FusedIterator for TrackedIterator<T> where T: StorageIterator + Iterator<Item = KeyValuePair> {}
+
+impl<T: StorageIterator> Drop for TrackedIterator<T> {
+    fn drop(&mut self) {
+        self.registry.unregister(self.registered_stats);
+    }
+}
@@ -1,11 +1,11 @@
 use crate::kv::kv_pair::KeyValuePair;
 
-use super::StorageIterator;
+use super::{IteratorStats, StorageIterator};
 
-pub struct TwoMergeIterator<X: StorageIterator, Y: StorageIterator> {
+pub(crate) struct TwoMergeIterator<X: StorageIterator, Y: StorageIterator> {
     sub_iters: (X, Y),
-    current_kv: Option<KeyValuePair>,
-    current_iter_index: bool,
+    // which sub-iterator holds the current entry, None once both are done
+    current_iter_index: Option<bool>,
     is_valid: bool,
 }
 
@@ -14,35 +14,61 @@ where
     X: StorageIterator + Iterator<Item = KeyValuePair>,
     Y: StorageIterator + Iterator<Item = KeyValuePair>,
 {
-    pub fn new(sub_iter_1: X, sub_iter_2: Y) -> Self {
-        let mut sub_iters = (sub_iter_1, sub_iter_2);
+    pub(crate) fn new(sub_iter_1: X, sub_iter_2: Y) -> Self {
+        let sub_iters = (sub_iter_1, sub_iter_2);
         let is_valid = sub_iters.0.is_valid() && sub_iters.1.is_valid();
-        let (current_kv, current_iter_index) =
-            Self::get_current_kv_and_iter_index(&mut sub_iters, is_valid);
+        let current_iter_index = Self::get_current_iter_index(&sub_iters, is_valid);
         Self {
             sub_iters,
-            current_kv,
             current_iter_index,
             is_valid: true,
         }
     }
 
-    fn get_current_kv_and_iter_index(
-        sub_iters: &mut (X, Y),
-        is_valid: bool,
-    ) -> (Option<KeyValuePair>, bool) {
-        if !is_valid {
-            (None, false)
-        } else {
-            let peek = (sub_iters.0.peek(), sub_iters.1.peek());
-            match peek {
-                (Some(kv0), Some(kv1)) => {
-                    if kv0 < kv1 { (Some(kv0), false) } else { (Some(kv1), true) }
+    // take the entry from the iterator that holds it, or skip it
+    fn advance(&mut self, skip: bool) -> Option<KeyValuePair> {
+        let res = match self.current_iter_index? {
+            false => {
+                let res = match skip {
+                    true => {
+                        self.sub_iters.0.skip_current();
+                        None
+                    }
+                    false => self.sub_iters.0.next(),
+                };
+                if !self.sub_iters.0.is_valid() {
+                    self.is_valid = false;
+                }
+                res
+            }
+            true => {
+                let res = match skip {
+                    true => {
+                        self.sub_iters.1.skip_current();
+                        None
+                    }
+                    false => self.sub_iters.1.next(),
+                };
+                if !self.sub_iters.1.is_valid() {
+                    self.is_valid = false;
                 }
-                (Some(kv0), None) => { (Some(kv0), false) }
-                (None, Some(kv1)) => { (Some(kv1), true) }
-                (None, None) => { (None, false) }
+                res
             }
+        };
+        self.current_iter_index = Self::get_current_iter_index(&self.sub_iters, self.is_valid);
+        res
+    }
+
+    fn get_current_iter_index(sub_iters: &(X, Y), is_valid: bool) -> Option<bool> {
+        if !is_valid {
+            return None;
+        }
+        match (sub_iters.0.peek(), sub_iters.1.peek()) {
+            // on equal keys the first iterator holds the newer entry
+            (Some(kv0), Some(kv1)) => Some(kv0.key > kv1.key),
+            (Some(_), None) => Some(false),
+            (None, Some(_)) => Some(true),
+            (None, None) => None,
         }
     }
 }
@@ -52,13 +78,28 @@ where
     X: StorageIterator + Iterator<Item = KeyValuePair>,
     Y: StorageIterator + Iterator<Item = KeyValuePair>,
 {
-    fn peek(&mut self) -> Option<KeyValuePair> {
-        self.current_kv.clone()
+    fn peek(&self) -> Option<&KeyValuePair> {
+        match self.current_iter_index? {
+            false => self.sub_iters.0.peek(),
+            true => self.sub_iters.1.peek(),
+        }
     }
 
     fn is_valid(&self) -> bool {
         self.is_valid
     }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.sub_iters.0.error().or_else(|| self.sub_iters.1.error())
+    }
+
+    fn stats(&self) -> IteratorStats {
+        self.sub_iters.0.stats() + self.sub_iters.1.stats()
+    }
+
+    fn skip_current(&mut self) {
+        self.advance(true);
+    }
 }
 
 impl<X, Y> Iterator for TwoMergeIterator<X, Y>
@@ -69,25 +110,9 @@ where
     type Item = KeyValuePair;
 
     fn next(&mut self) -> Option<KeyValuePair> {
-        let res = self.current_kv.clone();
-        // increment the correct iterator
-        if !self.current_iter_index {  // int(self.current_iter_index) == 0
-            self.sub_iters.0.next();
-            if !self.sub_iters.0.is_valid() {
-                self.is_valid = false;
-            }
-        } else {  // int(self.current_iter_index) == 1
-            self.sub_iters.1.next();
-            if !self.sub_iters.1.is_valid() {
-                self.is_valid = false;
-            }
-        }
-        (self.current_kv, self.current_iter_index) =
-            Self::get_current_kv_and_iter_index(&mut self.sub_iters, self.is_valid);
-        res
+        self.advance(false)
     }
 }
-
 #[cfg(test)]
 mod tests {
     use std::ops::Bound;
@@ -121,6 +146,21 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_equal_keys_prefer_first() {
+        let newer = MemTable::new(0);
+        let _ = newer.put("k1".as_bytes(), "z".as_bytes());
+        let older = MemTable::new(1);
+        let _ = older.put("k1".as_bytes(), "a".as_bytes());
+
+        let two_merge_iterator = TwoMergeIterator::new(
+            MemTableIterator::new(&newer, Bound::Unbounded, Bound::Unbounded),
+            MemTableIterator::new(&older, Bound::Unbounded, Bound::Unbounded),
+        );
+        let values: Vec<_> = two_merge_iterator.map(|kv| kv.value).collect();
+        assert_eq!(values, vec!["z".as_bytes(), "a".as_bytes()]);
+    }
+
     #[test]
     fn test_not_valid() {
         let test_iter_1 = TestIterator::new(1, 2);
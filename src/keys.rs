@@ -0,0 +1,236 @@
+use anyhow::{anyhow, Result};
+
+// order-preserving key encodings: a < b exactly when encode(a) < encode(b)
+// bytewise, which is how the store orders keys. numbers are fixed width, and
+// byte strings inside a composite key use the memcomparable format, so a
+// shorter string can't compare past the component after it
+
+// byte strings are cut into groups of this many bytes, each followed by a
+// marker byte
+const GROUP_SIZE: usize = 8;
+// the marker of a full group with more groups after it. the last group is
+// padded with zeros and marked with the number of bytes it holds, so "ab"
+// sorts before "ab\0" sorts before "abc"
+const FULL_GROUP_MARKER: u8 = GROUP_SIZE as u8 + 1;
+
+// big-endian, so byte order is numeric order
+pub fn encode_u64(value: u64) -> [u8; 8] {
+    value.to_be_bytes()
+}
+
+pub fn decode_u64(bytes: [u8; 8]) -> u64 {
+    u64::from_be_bytes(bytes)
+}
+
+// like u64 with the sign bit flipped, so negative numbers sort first
+pub fn encode_i64(value: i64) -> [u8; 8] {
+    encode_u64((value as u64) ^ (1 << 63))
+}
+
+pub fn decode_i64(bytes: [u8; 8]) -> i64 {
+    (decode_u64(bytes) ^ (1 << 63)) as i64
+}
+
+// positive numbers get their sign bit set, negative ones have every bit
+// flipped so that larger magnitudes sort first. -0.0 sorts just before 0.0
+// and NaNs sort past the infinities, positive or negative by their sign bit
+pub fn encode_f64(value: f64) -> [u8; 8] {
+    let bits = value.to_bits();
+    match bits >> 63 {
+        0 => encode_u64(bits | (1 << 63)),
+        _ => encode_u64(!bits),
+    }
+}
+
+pub fn decode_f64(bytes: [u8; 8]) -> f64 {
+    let bits = decode_u64(bytes);
+    match bits >> 63 {
+        1 => f64::from_bits(bits & !(1 << 63)),
+        _ => f64::from_bits(!bits),
+    }
+}
+
+// memcomparable, see GROUP_SIZE
+pub fn encode_bytes(value: &[u8], out: &mut Vec<u8>) {
+    let mut remaining = value;
+    while remaining.len() > GROUP_SIZE {
+        let (group, rest) = remaining.split_at(GROUP_SIZE);
+        out.extend_from_slice(group);
+        out.push(FULL_GROUP_MARKER);
+        remaining = rest;
+    }
+    out.extend_from_slice(remaining);
+    out.extend(std::iter::repeat_n(0, GROUP_SIZE - remaining.len()));
+    out.push(remaining.len() as u8);
+}
+
+// decodes a byte string from the front of input and advances it past it
+pub fn decode_bytes(input: &mut &[u8]) -> Result<Vec<u8>> {
+    let mut value = Vec::new();
+    loop {
+        if input.len() < GROUP_SIZE + 1 {
+            return Err(anyhow!("key ends in the middle of a byte string"));
+        }
+        let (group, rest) = input.split_at(GROUP_SIZE);
+        let marker = rest[0];
+        *input = &rest[1..];
+        match marker {
+            FULL_GROUP_MARKER => value.extend_from_slice(group),
+            len if (len as usize) <= GROUP_SIZE => {
+                let len = len as usize;
+                if group[len..].iter().any(|byte| *byte != 0) {
+                    return Err(anyhow!("byte string has non-zero padding"));
+                }
+                value.extend_from_slice(&group[..len]);
+                return Ok(value);
+            }
+            marker => return Err(anyhow!("invalid byte string group marker {}", marker)),
+        }
+    }
+}
+
+// one component of a composite key. tuples of parts are parts too, so
+// encode((user_id, timestamp)) gives keys that sort by user, then by time
+pub trait KeyPart: Sized {
+    fn encode_into(&self, out: &mut Vec<u8>);
+    // decodes the part from the front of input and advances it past it
+    fn decode_from(input: &mut &[u8]) -> Result<Self>;
+}
+
+pub fn encode<T: KeyPart>(key: &T) -> Vec<u8> {
+    let mut out = Vec::new();
+    key.encode_into(&mut out);
+    out
+}
+
+// bytes must hold exactly one encoded key
+pub fn decode<T: KeyPart>(bytes: &[u8]) -> Result<T> {
+    let mut input = bytes;
+    let key = T::decode_from(&mut input)?;
+    if !input.is_empty() {
+        return Err(anyhow!("{} bytes left over after decoding key", input.len()));
+    }
+    Ok(key)
+}
+
+fn take_fixed(input: &mut &[u8]) -> Result<[u8; 8]> {
+    if input.len() < 8 {
+        return Err(anyhow!("key ends in the middle of a number"));
+    }
+    let (bytes, rest) = input.split_at(8);
+    *input = rest;
+    Ok(bytes.try_into().expect("split at 8"))
+}
+
+impl KeyPart for u64 {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend(encode_u64(*self));
+    }
+
+    fn decode_from(input: &mut &[u8]) -> Result<Self> {
+        Ok(decode_u64(take_fixed(input)?))
+    }
+}
+
+impl KeyPart for i64 {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend(encode_i64(*self));
+    }
+
+    fn decode_from(input: &mut &[u8]) -> Result<Self> {
+        Ok(decode_i64(take_fixed(input)?))
+    }
+}
+
+impl KeyPart for f64 {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        out.extend(encode_f64(*self));
+    }
+
+    fn decode_from(input: &mut &[u8]) -> Result<Self> {
+        Ok(decode_f64(take_fixed(input)?))
+    }
+}
+
+impl KeyPart for Vec<u8> {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self, out);
+    }
+
+    fn decode_from(input: &mut &[u8]) -> Result<Self> {
+        decode_bytes(input)
+    }
+}
+
+impl KeyPart for String {
+    fn encode_into(&self, out: &mut Vec<u8>) {
+        encode_bytes(self.as_bytes(), out);
+    }
+
+    fn decode_from(input: &mut &[u8]) -> Result<Self> {
+        String::from_utf8(decode_bytes(input)?).map_err(|err| anyhow!("key part is not valid UTF-8: {}", err))
+    }
+}
+
+macro_rules! impl_key_part_for_tuple {
+    ($($part:ident),+) => {
+        impl<$($part: KeyPart),+> KeyPart for ($($part,)+) {
+            #[allow(non_snake_case)]
+            fn encode_into(&self, out: &mut Vec<u8>) {
+                let ($($part,)+) = self;
+                $($part.encode_into(out);)+
+            }
+
+            fn decode_from(input: &mut &[u8]) -> Result<Self> {
+                Ok(($($part::decode_from(input)?,)+))
+            }
+        }
+    };
+}
+
+impl_key_part_for_tuple!(A, B);
+impl_key_part_for_tuple!(A, B, C);
+impl_key_part_for_tuple!(A, B, C, D);
+
+#[cfg(test)]
+mod tests {
+    use super::{decode, decode_f64, encode, encode_f64, encode_i64};
+
+    fn assert_sorted<T: std::fmt::Debug>(values: &[T], encode: impl Fn(&T) -> Vec<u8>) {
+        for pair in values.windows(2) {
+            assert!(encode(&pair[0]) < encode(&pair[1]), "{:?} should sort before {:?}", pair[0], pair[1]);
+        }
+    }
+
+    #[test]
+    fn test_numbers() {
+        assert_sorted(&[i64::MIN, -256, -1, 0, 1, 256, i64::MAX], |value| encode_i64(*value).to_vec());
+        let floats = [f64::NEG_INFINITY, -1e300, -1.5, -f64::MIN_POSITIVE, -0.0, 0.0, 1e-300, 2.5, f64::INFINITY];
+        assert_sorted(&floats, |value| encode_f64(*value).to_vec());
+        for value in floats {
+            assert_eq!(decode_f64(encode_f64(value)).to_bits(), value.to_bits());
+        }
+        assert!(decode_f64(encode_f64(f64::NAN)).is_nan());
+    }
+
+    #[test]
+    fn test_composite() {
+        let strings: Vec<String> = ["", "\0", "a", "ab", "ab\0", "abc", "abcdefgh", "abcdefgh\0", "abcdefghi", "b"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        // a string sorts before its extensions whatever follows it
+        let keys: Vec<(String, u64)> = strings.iter().map(|s| (s.clone(), u64::MAX)).collect();
+        assert_sorted(&keys, encode);
+        for key in keys.iter() {
+            assert_eq!(decode::<(String, u64)>(&encode(key)).unwrap(), *key);
+        }
+
+        let key = (-3i64, "user".to_string(), 0.5f64, vec![0u8, 255]);
+        let encoded = encode(&key);
+        assert_eq!(decode::<(i64, String, f64, Vec<u8>)>(&encoded).unwrap(), key);
+        assert!(decode::<(i64, String)>(&encoded).is_err());
+        assert!(decode::<(i64, String, f64, Vec<u8>)>(&encoded[..encoded.len() - 1]).is_err());
+        assert_sorted(&[(1u64, -1.0f64), (1, 0.0), (2, -1.0)], encode);
+    }
+}
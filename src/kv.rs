@@ -1,2 +1,4 @@
-pub mod kv_pair;
-pub mod timestamped_key;
\ No newline at end of file
+pub(crate) mod entry;
+pub(crate) mod key_range;
+pub(crate) mod kv_pair;
+pub(crate) mod timestamped_key;
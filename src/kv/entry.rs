@@ -0,0 +1,95 @@
+use bytes::Bytes;
+
+use super::{
+    kv_pair::{KeyValuePair, ValueType},
+    timestamped_key::TimestampedKey,
+};
+
+// a key-value pair as LsmStore hands it out and takes it in, keyed by the
+// user key alone. the internal key format, with its version timestamps, stays
+// inside the crate so that it can change without breaking callers
+#[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Debug)]
+pub struct Entry {
+    pub key: Bytes,
+    pub value: Bytes,
+    // only raw scans, see ReadOptions::ignore_tombstones, yield deletes
+    pub value_type: ValueType,
+}
+
+// one version of a key, see LsmStore::get_versions
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Version {
+    // the sequence of the write. SST entries don't record theirs, so this is
+    // None for versions read back from an SST, unless every entry in it has
+    // the same sequence, as in bulk-loaded SSTs. versions without one are
+    // still in order, newest first
+    pub sequence: Option<u64>,
+    // None for a delete
+    pub value: Option<Bytes>,
+}
+
+impl Entry {
+    pub fn new(key: impl Into<Bytes>, value: impl Into<Bytes>) -> Self {
+        Self {
+            key: key.into(),
+            value: value.into(),
+            value_type: ValueType::Put,
+        }
+    }
+
+    pub fn tombstone(key: impl Into<Bytes>) -> Self {
+        Self {
+            key: key.into(),
+            value: Bytes::new(),
+            value_type: ValueType::Delete,
+        }
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.value_type == ValueType::Delete
+    }
+}
+
+impl From<KeyValuePair> for Entry {
+    fn from(kv: KeyValuePair) -> Self {
+        Self {
+            key: kv.key.get_key(),
+            value: kv.value,
+            value_type: kv.value_type,
+        }
+    }
+}
+
+// the entry is stamped like a write read back from an SST
+impl From<Entry> for KeyValuePair {
+    fn from(entry: Entry) -> Self {
+        Self {
+            key: TimestampedKey::new(entry.key),
+            value: entry.value,
+            value_type: entry.value_type,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey};
+
+    use super::Entry;
+
+    #[test]
+    fn test_conversions() {
+        let kv = KeyValuePair::new(TimestampedKey::new_with_timestamp("k1".into(), 7), "v1".into());
+        let entry = Entry::from(kv);
+        assert_eq!(entry, Entry::new("k1", "v1"));
+        assert!(!entry.is_tombstone());
+
+        let tombstone = KeyValuePair::from(Entry::tombstone("k2"));
+        assert!(tombstone.is_tombstone());
+        assert_eq!(tombstone.key.get_key(), Bytes::from("k2"));
+        assert_eq!(tombstone.key.get_timestamp(), 0);
+        assert!(Entry::from(tombstone).is_tombstone());
+    }
+}
@@ -0,0 +1,81 @@
+use std::ops::{Bound, Range, RangeFrom, RangeFull, RangeInclusive, RangeTo, RangeToInclusive};
+
+// a range of keys to scan, e.g. `b"a"..b"m"`, `"a"..="z"`, `..` or a pair of
+// Bounds. std's RangeBounds<[u8]> isn't implemented for ranges of slices, and
+// RangeBounds<K> leaves K ambiguous for `..` and byte string literals, so
+// every std range type over AsRef<[u8]> keys implements this instead
+pub trait KeyRange {
+    fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>);
+}
+
+fn as_bytes<K: AsRef<[u8]>>(bound: &Bound<K>) -> Bound<&[u8]> {
+    match bound {
+        Bound::Included(key) => Bound::Included(key.as_ref()),
+        Bound::Excluded(key) => Bound::Excluded(key.as_ref()),
+        Bound::Unbounded => Bound::Unbounded,
+    }
+}
+
+impl KeyRange for RangeFull {
+    fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        (Bound::Unbounded, Bound::Unbounded)
+    }
+}
+
+impl<K: AsRef<[u8]>> KeyRange for Range<K> {
+    fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        (Bound::Included(self.start.as_ref()), Bound::Excluded(self.end.as_ref()))
+    }
+}
+
+impl<K: AsRef<[u8]>> KeyRange for RangeInclusive<K> {
+    fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        (Bound::Included(self.start().as_ref()), Bound::Included(self.end().as_ref()))
+    }
+}
+
+impl<K: AsRef<[u8]>> KeyRange for RangeFrom<K> {
+    fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        (Bound::Included(self.start.as_ref()), Bound::Unbounded)
+    }
+}
+
+impl<K: AsRef<[u8]>> KeyRange for RangeTo<K> {
+    fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        (Bound::Unbounded, Bound::Excluded(self.end.as_ref()))
+    }
+}
+
+impl<K: AsRef<[u8]>> KeyRange for RangeToInclusive<K> {
+    fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        (Bound::Unbounded, Bound::Included(self.end.as_ref()))
+    }
+}
+
+impl<K: AsRef<[u8]>> KeyRange for (Bound<K>, Bound<K>) {
+    fn bounds(&self) -> (Bound<&[u8]>, Bound<&[u8]>) {
+        (as_bytes(&self.0), as_bytes(&self.1))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use super::KeyRange;
+
+    #[test]
+    fn test_bounds() {
+        let (a, m): (&[u8], &[u8]) = (b"a", b"m");
+        assert_eq!((..).bounds(), (Bound::Unbounded, Bound::Unbounded));
+        assert_eq!((b"a"..b"m").bounds(), (Bound::Included(a), Bound::Excluded(m)));
+        assert_eq!(("a"..="m").bounds(), (Bound::Included(a), Bound::Included(m)));
+        assert_eq!((a.to_vec()..).bounds(), (Bound::Included(a), Bound::Unbounded));
+        assert_eq!((.."m".to_string()).bounds(), (Bound::Unbounded, Bound::Excluded(m)));
+        assert_eq!((..=m).bounds(), (Bound::Unbounded, Bound::Included(m)));
+        assert_eq!(
+            (Bound::Excluded(a), Bound::Unbounded).bounds(),
+            (Bound::Excluded(a), Bound::Unbounded)
+        );
+    }
+}
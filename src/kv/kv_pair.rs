@@ -2,8 +2,41 @@ use bytes::Bytes;
 
 use super::timestamped_key::TimestampedKey;
 
+// whether an entry writes its value or deletes the key. kept apart from the
+// value, so that empty values can be stored like any other
+#[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Copy, Debug, Default)]
+pub enum ValueType {
+    #[default]
+    Put,
+    // a tombstone. its value is always empty
+    Delete,
+}
+
 #[derive(Eq, Ord, PartialEq, PartialOrd, Clone, Debug)]
 pub struct KeyValuePair {
     pub key: TimestampedKey,
     pub value: Bytes,
-}
\ No newline at end of file
+    pub value_type: ValueType,
+}
+
+impl KeyValuePair {
+    pub fn new(key: TimestampedKey, value: Bytes) -> Self {
+        Self {
+            key,
+            value,
+            value_type: ValueType::Put,
+        }
+    }
+
+    pub fn tombstone(key: TimestampedKey) -> Self {
+        Self {
+            key,
+            value: Bytes::new(),
+            value_type: ValueType::Delete,
+        }
+    }
+
+    pub fn is_tombstone(&self) -> bool {
+        self.value_type == ValueType::Delete
+    }
+}
@@ -3,20 +3,28 @@ use bytes::Bytes;
 #[derive(Eq, PartialEq, Clone, Debug)]
 pub struct TimestampedKey {
     key: Bytes,
-    timestamp_ms: usize,
+    // orders versions of the same key, newer writes get larger timestamps.
+    // writes are stamped with the store's sequence number, but SSTs don't
+    // record it, so entries read back from SSTs are all at 0
+    timestamp: u64,
 }
 
 impl TimestampedKey {
     pub fn new(key: Bytes) -> Self {
-        TimestampedKey {
-            key,
-            timestamp_ms: 0, // TODO: set timestamp later
-        }
+        Self::new_with_timestamp(key, 0)
+    }
+
+    pub fn new_with_timestamp(key: Bytes, timestamp: u64) -> Self {
+        TimestampedKey { key, timestamp }
     }
 
     pub fn get_key(&self) -> Bytes {
         self.key.clone()
     }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
 }
 
 impl Ord for TimestampedKey {
@@ -25,7 +33,7 @@ impl Ord for TimestampedKey {
         // if two keys are equal, then latest timestamp is smaller
         self.key
             .cmp(&other.key)
-            .then(other.timestamp_ms.cmp(&self.timestamp_ms))
+            .then(other.timestamp.cmp(&self.timestamp))
     }
 }
 
@@ -41,9 +49,9 @@ mod tests {
 
     #[test]
     fn test_ord() {
-        let tk1 = TimestampedKey{key: "k1".into(), timestamp_ms: 100};
-        let tk2 = TimestampedKey{key: "k1".into(), timestamp_ms: 0};
-        let tk3 = TimestampedKey{key: "k2".into(), timestamp_ms: 100};
+        let tk1 = TimestampedKey{key: "k1".into(), timestamp: 100};
+        let tk2 = TimestampedKey{key: "k1".into(), timestamp: 0};
+        let tk3 = TimestampedKey{key: "k2".into(), timestamp: 100};
 
         assert!(tk1 < tk2);
         assert!(tk1 < tk3);
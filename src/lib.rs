@@ -1,8 +1,74 @@
-pub mod memory;
-pub mod state;
-pub mod iterator;
-pub mod kv;
-pub mod block;
-pub mod table;
-pub mod store;
-pub mod utils;
+// the modules are internal, and the crate's public surface is what is
+// re-exported below, so that internals can change without breaking users
+mod batch;
+mod compaction;
+mod memory;
+mod state;
+mod iterator;
+mod kv;
+mod listener;
+mod manifest;
+mod failpoint;
+mod platform;
+mod block;
+mod error;
+mod table;
+mod scheduler;
+mod stats;
+mod store;
+mod typed;
+mod utils;
+
+// order-preserving encodings for composite keys
+pub mod keys;
+// replays a write workload through the compaction planners alone
+pub mod simulator;
+
+pub use batch::WriteBatchWithIndex as WriteBatch;
+pub use error::LsmError as Error;
+pub use iterator::lsm_iterator::LsmIterator as Iterator;
+pub use state::storage_state_options::StorageStateOptions as DbOptions;
+pub use store::LsmStore as Db;
+
+// what the above take and return
+pub use compaction::{CompactionStyle, TimeWindowOptions, VersionRetention};
+pub use batch::{BatchIter, BatchScan};
+pub use iterator::{lsm_iterator::LsmStrIterator, IteratorStats};
+pub use kv::{
+    entry::{Entry, Version},
+    key_range::KeyRange,
+};
+pub use listener::{
+    CompactionJobInfo, CorruptionInfo, EventListener, FlushJobInfo, SstDeletionInfo, WriteStallInfo, WriteStallReason,
+};
+pub use memory::{limiter::MemoryLimiter, memtable::rep::MemTableRepType};
+pub use state::{
+    backup::BackupInfo,
+    read_options::ReadOptions,
+    repair::RepairReport,
+    scrub::ScrubOptions,
+    snapshot::Snapshot,
+    storage_state_options::{FlushTrigger, StorageStateOptionsBuilder as DbOptionsBuilder},
+    update_log::{WriteEvent, WriteRecord},
+    write_options::{WriteOptions, WriteToken},
+};
+pub use stats::{
+    description::{LevelDescription, MemTableDescription, ShardDescription, SstDescription, StoreDescription},
+    explain::{GetExplanation, GetStep, ProbeOutcome, ScanExplanation, ShardScanPlan, SstScanPlan},
+    histogram::LatencySummary,
+    BackgroundStatus, LatencyReport, LsmStats, MemoryUsage, MergeProgress, PrefixStats,
+};
+pub use store::{KeyScan, Scan, ScanBuilder};
+pub use table::{
+    persistent_cache::PersistentCacheOptions,
+    sst_path::{FlatSstPathProvider, LeveledSstPathProvider, SstPathProvider},
+};
+pub use typed::{KeyCodec, TypedIterator, TypedStore, ValueCodec, Versioned};
+
+// values of any serde type
+#[cfg(feature = "serde")]
+pub use typed::serde_codec::{Bincode, Json};
+
+// converts between SSTs and RocksDB/LevelDB block-based table files
+#[cfg(feature = "rocksdb-sst")]
+pub use table::rocksdb;
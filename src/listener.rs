@@ -0,0 +1,67 @@
+use std::{path::PathBuf, time::Duration};
+
+pub struct FlushJobInfo {
+    // memtables are flushed to an SST with the same id. an oversized flush
+    // that is split into several SSTs reports the first one's id and path
+    pub sst_id: usize,
+    pub path: PathBuf,
+    // of all the SSTs written. only known once the flush has completed
+    pub file_size: Option<u64>,
+}
+
+pub struct CompactionJobInfo {
+    pub input_sst_ids: Vec<usize>,
+    pub output_sst_ids: Vec<usize>,
+}
+
+pub struct SstDeletionInfo {
+    pub sst_id: usize,
+    pub path: PathBuf,
+}
+
+// found by the scrubber, see StorageStateOptions::scrub
+pub struct CorruptionInfo {
+    pub sst_id: usize,
+    pub path: PathBuf,
+    pub message: String,
+    // whether the SST was taken out of the store, see ScrubOptions::quarantine
+    pub quarantined: bool,
+}
+
+// why a write had to wait on a flush or compaction
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WriteStallReason {
+    // num_memtables_limit frozen memtables were waiting to be flushed. only
+    // low priority writes wait for them, see WriteOptions::low_priority
+    MemtableLimit,
+    // a synced write waited for its memtable and every older one to be
+    // flushed, see WriteOptions::sync
+    Sync,
+    // l0 held l0_stall_num_ssts SSTs, so the write ran compaction before
+    // going into the memtable
+    L0Limit,
+}
+
+pub struct WriteStallInfo {
+    pub reason: WriteStallReason,
+    // how long the write waited
+    pub duration: Duration,
+}
+
+// hooks into store lifecycle events, registered through
+// StorageStateOptions::listeners. callbacks run on the thread doing the work,
+// outside of the state lock, so they should return quickly
+pub trait EventListener: Send + Sync {
+    fn on_flush_begin(&self, _info: &FlushJobInfo) {}
+
+    fn on_flush_completed(&self, _info: &FlushJobInfo) {}
+
+    fn on_compaction_completed(&self, _info: &CompactionJobInfo) {}
+
+    fn on_sst_deleted(&self, _info: &SstDeletionInfo) {}
+
+    fn on_corruption_detected(&self, _info: &CorruptionInfo) {}
+
+    // sent once the stalled write is done waiting
+    fn on_write_stall(&self, _info: &WriteStallInfo) {}
+}
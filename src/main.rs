@@ -1,10 +1,17 @@
 mod cli_utils;
 
-use std::{io::Write, ops::Bound, str::from_utf8};
+use std::{
+    io::{IsTerminal, Write},
+    ops::Bound,
+    process::ExitCode,
+    str::from_utf8,
+    time::Instant,
+};
 
-use clap::{Parser, Subcommand};
+use anyhow::Result;
+use clap::{error::ErrorKind, Parser, Subcommand};
 
-use mini_lsm::{state::storage_state_options::StorageStateOptions, store::LsmStore};
+use mini_lsm::{Db, DbOptions};
 
 #[derive(Parser)]
 #[clap(name = "", no_binary_name = true)]
@@ -18,6 +25,9 @@ enum Command {
     Get {
         key: String,
     },
+    Exists {
+        key: String,
+    },
     Put {
         key: String,
         value: String,
@@ -25,72 +35,215 @@ enum Command {
     Delete {
         key: String,
     },
+    // add delta (default 1) to a little-endian i64 counter and print the sum
+    Incr {
+        key: String,
+        #[clap(allow_negative_numbers = true, default_value_t = 1)]
+        delta: i64,
+    },
     Scan {
         lower: Option<String>,
         upper: Option<String>,
+        // print the number of keys in range instead of the pairs
+        #[clap(long)]
+        count_only: bool,
     },
     Fill {
         lower: u64,
         upper: u64,
     },
+    // memtables and SSTs of the store, as tables
+    Describe,
+    // estimated entries and bytes under a key prefix
+    Stats {
+        prefix: String,
+    },
+    // background flushes, merges and scrubbing
+    Bg {
+        #[clap(subcommand)]
+        action: BgCommand,
+    },
+    // what a read looks at, step by step
+    Explain {
+        #[clap(subcommand)]
+        what: ExplainCommand,
+    },
     Quit,
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let options = StorageStateOptions::new_with_defaults()?;
-    let lsm = LsmStore::open(options)?;
+#[derive(Debug, Subcommand)]
+enum BgCommand {
+    // queued and running tasks, pending flushes and merge progress
+    Status,
+    // start no new background tasks until resumed
+    Pause,
+    Resume,
+}
+
+#[derive(Debug, Subcommand)]
+enum ExplainCommand {
+    Get { key: String },
+    Scan { lower: Option<String>, upper: Option<String> },
+}
+
+// command line flags of the binary itself, as opposed to the REPL commands
+#[derive(Parser)]
+struct Args {
+    // print how long each command took
+    #[clap(long)]
+    verbose: bool,
+}
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
+    let args = Args::parse();
+    // commands piped in stop at the first failure, with a non-zero exit code
+    let interactive = std::io::stdin().is_terminal();
+    let options = DbOptions::new_with_defaults()?;
+    let lsm = Db::open(options)?;
     loop {
-        print!("$ ");
-        std::io::stdout().flush()?;
+        if interactive {
+            print!("$ ");
+            std::io::stdout().flush()?;
+        }
 
-        let line = cli_utils::readline()?;
-        let args = shlex::split(&line).unwrap_or_default();
-        let parsed = Cli::try_parse_from(args);
-        if parsed.is_err() {
-            parsed.err().unwrap().print()?;
-            continue
+        let Some(line) = cli_utils::readline()? else {
+            lsm.close()?;
+            return Ok(ExitCode::SUCCESS);
+        };
+        let args_line = shlex::split(&line).unwrap_or_default();
+        if args_line.is_empty() {
+            continue;
+        }
+        let command = match Cli::try_parse_from(args_line) {
+            Ok(cli) => cli.command,
+            Err(err) if matches!(err.kind(), ErrorKind::DisplayHelp | ErrorKind::DisplayVersion) => {
+                err.print()?;
+                continue;
+            }
+            Err(err) => {
+                // just the first line, without clap's usage hint
+                let message = err.to_string();
+                let message = message.lines().next().unwrap_or_default();
+                println!("ERROR: {}", message.trim_start_matches("error: "));
+                if !interactive {
+                    lsm.close()?;
+                    return Ok(ExitCode::FAILURE);
+                }
+                continue;
+            }
+        };
+        let started = Instant::now();
+        let res = run_command(&lsm, command);
+        if args.verbose {
+            println!("({:?})", started.elapsed());
         }
-        match parsed.unwrap().command {
-            Command::Get { key } => {
-                let value = lsm.get(key.as_bytes())?;
-                if let Some(res) = value {
-                    println!("{}={}", key, from_utf8(&res)?);
+        match res {
+            Ok(Outcome::Continue) => {}
+            Ok(Outcome::Quit) => return Ok(ExitCode::SUCCESS),
+            Err(err) => {
+                println!("ERROR: {:#}", err);
+                if !interactive {
+                    lsm.close()?;
+                    return Ok(ExitCode::FAILURE);
                 }
             }
-            Command::Put { key, value } => {
-                lsm.put(key.as_bytes(), value.as_bytes())?;
+        }
+    }
+}
+
+enum Outcome {
+    Continue,
+    Quit,
+}
+
+// prints the command's output, or OK for commands that have none
+fn run_command(lsm: &Db, command: Command) -> Result<Outcome> {
+    match command {
+        Command::Get { key } => {
+            match lsm.get_str(&key)? {
+                Some(res) => println!("{}={}", key, res),
+                None => println!("{} not found", key),
             }
-            Command::Delete { key } => {
-                lsm.delete(key.as_bytes())?;
+        }
+        Command::Exists { key } => {
+            if lsm.exists(&key)? {
+                println!("{} exists", key);
+            } else {
+                println!("{} not found", key);
             }
-            Command::Scan { lower, upper } => {
-                let lb = lower
-                    .as_ref()
-                    .map_or(Bound::Unbounded, |v| Bound::Included(v.as_bytes()));
-                let ub = upper
-                    .as_ref()
-                    .map_or(Bound::Unbounded, |v| Bound::Included(v.as_bytes()));
-                let iter = lsm.scan(lb, ub)?;
-                for kv in iter {
-                    println!(
-                        "{}={}",
-                        from_utf8(&kv.key.get_key())?,
-                        from_utf8(&kv.value)?
-                    );
-                }
+        }
+        Command::Put { key, value } => {
+            lsm.put(&key, &value)?;
+            println!("OK");
+        }
+        Command::Delete { key } => {
+            lsm.delete(&key)?;
+            println!("OK");
+        }
+        Command::Incr { key, delta } => {
+            println!("{}", lsm.increment(&key, delta)?);
+        }
+        Command::Scan { lower, upper, count_only } => {
+            let range = (
+                lower.as_ref().map_or(Bound::Unbounded, Bound::Included),
+                upper.as_ref().map_or(Bound::Unbounded, Bound::Included),
+            );
+            if count_only {
+                println!("{}", lsm.count(range)?);
+                return Ok(Outcome::Continue);
             }
-            Command::Fill { lower, upper } => {
-                for i in lower..upper + 1 {
-                    lsm.put(
-                        format!("{:?}", i).as_bytes(),
-                        format!("value@{:?}", i).as_bytes(),
-                    )?;
-                }
+            let mut iter = lsm.scan(range)?;
+            for kv in iter.by_ref() {
+                println!(
+                    "{}={}",
+                    from_utf8(&kv.key)?,
+                    from_utf8(&kv.value)?
+                );
+            }
+            iter.check_error()?;
+        }
+        Command::Fill { lower, upper } => {
+            for i in lower..upper + 1 {
+                lsm.put(format!("{:?}", i), format!("value@{:?}", i))?;
+            }
+            println!("OK");
+        }
+        Command::Describe => {
+            print!("{}", lsm.describe()?);
+        }
+        Command::Stats { prefix } => {
+            let stats = lsm.prefix_stats(&prefix)?;
+            println!(
+                "{} entries, {} bytes ({} blocks sampled)",
+                stats.num_entries, stats.size_bytes, stats.num_sampled_blocks
+            );
+        }
+        Command::Bg { action } => match action {
+            BgCommand::Status => print!("{}", lsm.background_status()?),
+            BgCommand::Pause => {
+                lsm.pause_background_work()?;
+                println!("OK");
+            }
+            BgCommand::Resume => {
+                lsm.resume_background_work()?;
+                println!("OK");
             }
-            Command::Quit => {
-                lsm.close()?;
-                return Ok(());
+        },
+        Command::Explain { what } => match what {
+            ExplainCommand::Get { key } => print!("{}", lsm.explain_get(&key)?),
+            ExplainCommand::Scan { lower, upper } => {
+                let range = (
+                    lower.as_ref().map_or(Bound::Unbounded, Bound::Included),
+                    upper.as_ref().map_or(Bound::Unbounded, Bound::Included),
+                );
+                print!("{}", lsm.explain_scan(range)?);
             }
+        },
+        Command::Quit => {
+            lsm.close()?;
+            println!("OK");
+            return Ok(Outcome::Quit);
         }
     }
+    Ok(Outcome::Continue)
 }
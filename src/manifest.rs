@@ -0,0 +1,707 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use anyhow::Result;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    error::LsmError,
+    platform,
+    table::{
+        sst_path::{FlatSstPathProvider, SstPathProvider},
+        SST_FORMAT_VERSION,
+    },
+};
+
+const CURRENT_FILE_NAME: &str = "CURRENT";
+const MANIFEST_FILE_PREFIX: &str = "MANIFEST-";
+
+// records written before sst paths were recorded. their ssts use the flat layout
+const LEGACY_FLUSH_RECORD_TAG: u8 = 0;
+const LEGACY_SNAPSHOT_RECORD_TAG: u8 = 1;
+const FLUSH_RECORD_TAG: u8 = 2;
+const SNAPSHOT_RECORD_TAG: u8 = 3;
+const CONFIG_RECORD_TAG: u8 = 4;
+
+// the only key order and block encoding there are so far. recorded anyway,
+// so that a store written with another can't be misread
+pub(crate) const BYTEWISE_COMPARATOR: &str = "bytewise";
+pub(crate) const NO_COMPRESSION: &str = "none";
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct SstFile {
+    pub id: usize,
+    // relative to the store directory
+    pub path: PathBuf,
+}
+
+impl SstFile {
+    // each sst is laid out as: id (8 bytes) | path length (2 bytes) | utf-8 path
+    fn encode(&self, encoded: &mut Vec<u8>) {
+        let path = self.path.to_str().expect("sst paths must be valid utf-8");
+        encoded.extend((self.id as u64).to_be_bytes());
+        encoded.extend(
+            u16::try_from(path.len())
+                .expect("sst path must fit in 2 bytes")
+                .to_be_bytes(),
+        );
+        encoded.extend(path.as_bytes());
+    }
+
+    // returns the decoded sst and the rest of the buffer
+    fn decode(encoded: &[u8]) -> Option<(Self, &[u8])> {
+        if encoded.len() < 10 {
+            return None;
+        }
+        let id = u64::from_be_bytes(encoded[..8].try_into().expect("chunk of size 8")) as usize;
+        let path_len = u16::from_be_bytes(encoded[8..10].try_into().expect("chunk of size 2")) as usize;
+        let rest = &encoded[10..];
+        if rest.len() < path_len {
+            return None;
+        }
+        let path = std::str::from_utf8(&rest[..path_len]).ok()?;
+        Some((
+            Self {
+                id,
+                path: PathBuf::from(path),
+            },
+            &rest[path_len..],
+        ))
+    }
+
+    fn legacy(id: usize) -> Self {
+        Self {
+            id,
+            path: FlatSstPathProvider.sst_path(id, 0),
+        }
+    }
+}
+
+// settings a store is created with that decide how its files are read. kept
+// at the start of every manifest, and checked on each open
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) struct StoreConfig {
+    // newest SST format version the store may contain
+    pub format_version: u32,
+    pub comparator: String,
+    pub compression: String,
+    // informational. SSTs record their own block boundaries, so the option
+    // may change between opens
+    pub block_max_size_bytes: u64,
+}
+
+impl StoreConfig {
+    // what this build writes
+    pub(crate) fn new(block_max_size_bytes: usize) -> Self {
+        Self {
+            format_version: SST_FORMAT_VERSION,
+            comparator: BYTEWISE_COMPARATOR.to_string(),
+            compression: NO_COMPRESSION.to_string(),
+            block_max_size_bytes: block_max_size_bytes as u64,
+        }
+    }
+
+    // whether a store recorded with this config can be opened by a build
+    // that writes current
+    pub(crate) fn check_compatible(&self, current: &StoreConfig) -> Result<()> {
+        let incompatible = |setting: &str, recorded: &dyn std::fmt::Debug, supported: &dyn std::fmt::Debug| {
+            Err(LsmError::InvalidOptions(format!(
+                "store was created with {} {:?}, but only {:?} is supported",
+                setting, recorded, supported
+            ))
+            .into())
+        };
+        if self.comparator != current.comparator {
+            return incompatible("comparator", &self.comparator, &current.comparator);
+        }
+        if self.compression != current.compression {
+            return incompatible("compression", &self.compression, &current.compression);
+        }
+        if self.format_version > current.format_version {
+            return incompatible("format version", &self.format_version, &format!("up to {}", current.format_version));
+        }
+        Ok(())
+    }
+
+    // laid out as: format version (4 bytes) | block max size (8 bytes) |
+    // comparator length (2 bytes) | comparator | compression length (2 bytes) | compression
+    fn encode(&self, encoded: &mut Vec<u8>) {
+        encoded.extend(self.format_version.to_be_bytes());
+        encoded.extend(self.block_max_size_bytes.to_be_bytes());
+        for name in [&self.comparator, &self.compression] {
+            encoded.extend(u16::try_from(name.len()).expect("name must fit in 2 bytes").to_be_bytes());
+            encoded.extend(name.as_bytes());
+        }
+    }
+
+    fn decode(encoded: &[u8]) -> Option<Self> {
+        if encoded.len() < 12 {
+            return None;
+        }
+        let format_version = u32::from_be_bytes(encoded[..4].try_into().expect("chunk of size 4"));
+        let block_max_size_bytes = u64::from_be_bytes(encoded[4..12].try_into().expect("chunk of size 8"));
+        let mut rest = &encoded[12..];
+        let mut read_name = || {
+            if rest.len() < 2 {
+                return None;
+            }
+            let len = u16::from_be_bytes(rest[..2].try_into().expect("chunk of size 2")) as usize;
+            let name = std::str::from_utf8(rest.get(2..2 + len)?).ok()?.to_string();
+            rest = &rest[2 + len..];
+            Some(name)
+        };
+        let comparator = read_name()?;
+        let compression = read_name()?;
+        rest.is_empty().then_some(Self {
+            format_version,
+            comparator,
+            compression,
+            block_max_size_bytes,
+        })
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub(crate) enum ManifestRecord {
+    // memtable was flushed to a new l0 sst with the same id
+    Flush(SstFile),
+    // full list of l0 ssts, newest to oldest
+    Snapshot(Vec<SstFile>),
+    // see StoreConfig
+    Config(StoreConfig),
+}
+
+impl ManifestRecord {
+    // each record is laid out as: payload length (4 bytes) | payload | checksum (4 bytes)
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut payload: Vec<u8> = Vec::new();
+        match self {
+            ManifestRecord::Flush(sst_file) => {
+                payload.push(FLUSH_RECORD_TAG);
+                sst_file.encode(&mut payload);
+            }
+            ManifestRecord::Snapshot(sst_files) => {
+                payload.push(SNAPSHOT_RECORD_TAG);
+                payload.extend(
+                    u32::try_from(sst_files.len())
+                        .expect("number of ssts must fit in 4 bytes")
+                        .to_be_bytes(),
+                );
+                for sst_file in sst_files {
+                    sst_file.encode(&mut payload);
+                }
+            }
+            ManifestRecord::Config(config) => {
+                payload.push(CONFIG_RECORD_TAG);
+                config.encode(&mut payload);
+            }
+        }
+        let mut encoded: Vec<u8> = Vec::new();
+        encoded.extend(
+            u32::try_from(payload.len())
+                .expect("record size must fit in 4 bytes")
+                .to_be_bytes(),
+        );
+        encoded.extend(&payload);
+        encoded.extend((xxh3_64(&payload) as u32).to_be_bytes());
+        encoded
+    }
+
+    // decode all complete records in the buffer. decoding stops at the first
+    // truncated or corrupt record, which is what a crash mid-append leaves behind
+    #[cfg(test)]
+    pub fn decode_to_list(encoded: &[u8]) -> Vec<Self> {
+        Self::decode_prefix(encoded).0
+    }
+
+    // like decode_to_list, but also return the number of bytes decoded
+    fn decode_prefix(encoded: &[u8]) -> (Vec<Self>, usize) {
+        let mut records = Vec::new();
+        let mut current_index = 0;
+        while current_index + 4 <= encoded.len() {
+            let payload_len = u32::from_be_bytes(
+                encoded[current_index..current_index + 4]
+                    .try_into()
+                    .expect("chunk of size 4"),
+            ) as usize;
+            let payload_start = current_index + 4;
+            let checksum_start = payload_start + payload_len;
+            if checksum_start + 4 > encoded.len() {
+                break;
+            }
+            let payload = &encoded[payload_start..checksum_start];
+            let checksum = u32::from_be_bytes(
+                encoded[checksum_start..checksum_start + 4]
+                    .try_into()
+                    .expect("chunk of size 4"),
+            );
+            if checksum != xxh3_64(payload) as u32 {
+                break;
+            }
+            match Self::decode_payload(payload) {
+                Some(record) => records.push(record),
+                None => break,
+            }
+            current_index = checksum_start + 4;
+        }
+        (records, current_index)
+    }
+
+    fn decode_payload(payload: &[u8]) -> Option<Self> {
+        let (tag, rest) = payload.split_first()?;
+        let read_id = |chunk: &[u8]| {
+            u64::from_be_bytes(chunk.try_into().expect("chunk of size 8")) as usize
+        };
+        match *tag {
+            LEGACY_FLUSH_RECORD_TAG if rest.len() == 8 => {
+                Some(ManifestRecord::Flush(SstFile::legacy(read_id(rest))))
+            }
+            LEGACY_SNAPSHOT_RECORD_TAG if rest.len() >= 4 => {
+                let num_ids = u32::from_be_bytes(rest[..4].try_into().expect("chunk of size 4"));
+                let ids_bytes = &rest[4..];
+                if ids_bytes.len() != 8 * num_ids as usize {
+                    return None;
+                }
+                Some(ManifestRecord::Snapshot(
+                    ids_bytes.chunks_exact(8).map(read_id).map(SstFile::legacy).collect(),
+                ))
+            }
+            FLUSH_RECORD_TAG => match SstFile::decode(rest)? {
+                (sst_file, []) => Some(ManifestRecord::Flush(sst_file)),
+                _ => None,
+            },
+            SNAPSHOT_RECORD_TAG if rest.len() >= 4 => {
+                let num_ssts = u32::from_be_bytes(rest[..4].try_into().expect("chunk of size 4"));
+                let mut rest = &rest[4..];
+                let mut sst_files = Vec::new();
+                for _ in 0..num_ssts {
+                    let (sst_file, next) = SstFile::decode(rest)?;
+                    sst_files.push(sst_file);
+                    rest = next;
+                }
+                rest.is_empty().then_some(ManifestRecord::Snapshot(sst_files))
+            }
+            CONFIG_RECORD_TAG => StoreConfig::decode(rest).map(ManifestRecord::Config),
+            _ => None,
+        }
+    }
+}
+
+struct ManifestFile {
+    file: File,
+    id: usize,
+    num_records: usize,
+}
+
+// append-only log of edits to the set of ssts. the CURRENT file names the
+// active manifest, which lets rotation swap in a compacted manifest atomically
+pub(crate) struct Manifest {
+    dir: PathBuf,
+    current: Mutex<ManifestFile>,
+    max_records: usize,
+    // the last Config record, carried over into rotated manifests
+    config: Mutex<Option<StoreConfig>>,
+}
+
+impl Manifest {
+    pub(crate) fn exists(dir: impl AsRef<Path>) -> bool {
+        dir.as_ref().join(CURRENT_FILE_NAME).exists()
+    }
+
+    // open the manifest in dir (creating one if none exists) and return the
+    // records it contains. with paranoid_checks, a torn or corrupt record is
+    // an error instead of being dropped
+    pub(crate) fn open(
+        dir: impl AsRef<Path>,
+        max_records: usize,
+        paranoid_checks: bool,
+    ) -> Result<(Self, Vec<ManifestRecord>)> {
+        let dir = dir.as_ref().to_owned();
+        let current_path = dir.join(CURRENT_FILE_NAME);
+        let (id, records) = if current_path.exists() {
+            let manifest_name = std::fs::read_to_string(&current_path)?;
+            let id = Self::parse_manifest_id(manifest_name.trim())
+                .ok_or_else(|| {
+                    LsmError::Corruption(format!("CURRENT points to invalid manifest {:?}", manifest_name))
+                })?;
+            let mut encoded: Vec<u8> = Vec::new();
+            File::open(Self::get_manifest_path(&dir, id))?.read_to_end(&mut encoded)?;
+            let (records, decoded_len) = ManifestRecord::decode_prefix(&encoded);
+            if paranoid_checks && decoded_len != encoded.len() {
+                return Err(LsmError::Corruption(format!(
+                    "manifest {} has {} undecodable trailing bytes",
+                    manifest_name.trim(),
+                    encoded.len() - decoded_len
+                ))
+                .into());
+            }
+            (id, records)
+        } else {
+            Self::create_manifest_file(&dir, 0, &[])?;
+            Self::set_current(&dir, 0)?;
+            (0, Vec::new())
+        };
+        // a crash during rotation can leave behind manifests CURRENT doesn't point to
+        Self::remove_stale_manifests(&dir, id)?;
+
+        // rewrite the records that were read back so a torn tail is dropped
+        let file = Self::create_manifest_file(&dir, id, &records)?;
+        let config = records.iter().rev().find_map(|record| match record {
+            ManifestRecord::Config(config) => Some(config.clone()),
+            _ => None,
+        });
+        let manifest = Self {
+            dir,
+            current: Mutex::new(ManifestFile {
+                file,
+                id,
+                num_records: records.len(),
+            }),
+            max_records,
+            config: Mutex::new(config),
+        };
+        Ok((manifest, records))
+    }
+
+    pub(crate) fn add_record(&self, record: &ManifestRecord) -> Result<()> {
+        let mut current = self.current.lock().unwrap();
+        current.file.write_all(&record.encode())?;
+        current.file.sync_all()?;
+        current.num_records += 1;
+        if let ManifestRecord::Config(config) = record {
+            *self.config.lock().unwrap() = Some(config.clone());
+        }
+        Ok(())
+    }
+
+    // None for stores created before configs were recorded
+    pub(crate) fn config(&self) -> Option<StoreConfig> {
+        self.config.lock().unwrap().clone()
+    }
+
+    pub(crate) fn should_rotate(&self) -> bool {
+        self.current.lock().unwrap().num_records > self.max_records
+    }
+
+    // replace the edit log with a single snapshot record, after the config if
+    // there is one: write and fsync the new manifest, atomically point CURRENT
+    // at it, then delete the old one
+    pub(crate) fn rotate(&self, snapshot: &ManifestRecord) -> Result<()> {
+        let mut current = self.current.lock().unwrap();
+        let old_id = current.id;
+        let new_id = old_id + 1;
+        let mut records: Vec<ManifestRecord> = self.config().map(ManifestRecord::Config).into_iter().collect();
+        records.push(snapshot.clone());
+        let file = Self::create_manifest_file(&self.dir, new_id, &records)?;
+        Self::set_current(&self.dir, new_id)?;
+        // the config isn't an edit, so it doesn't count towards the next rotation
+        *current = ManifestFile {
+            file,
+            id: new_id,
+            num_records: 1,
+        };
+        std::fs::remove_file(Self::get_manifest_path(&self.dir, old_id))?;
+        Ok(())
+    }
+
+    // remove CURRENT and every manifest file in dir
+    pub(crate) fn destroy(dir: impl AsRef<Path>) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_manifest_file = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| {
+                    name.starts_with(MANIFEST_FILE_PREFIX) || name.starts_with(CURRENT_FILE_NAME)
+                });
+            if is_manifest_file {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn create_manifest_file(dir: &Path, id: usize, records: &[ManifestRecord]) -> Result<File> {
+        let path = Self::get_manifest_path(dir, id);
+        let tmp_path = path.with_extension("tmp");
+        let mut file = File::create(&tmp_path)?;
+        for record in records {
+            file.write_all(&record.encode())?;
+        }
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, &path)?;
+        Self::sync_dir(dir)?;
+        Ok(OpenOptions::new().append(true).open(path)?)
+    }
+
+    fn set_current(dir: &Path, id: usize) -> Result<()> {
+        let tmp_path = dir.join(format!("{}.tmp", CURRENT_FILE_NAME));
+        let mut file = File::create(&tmp_path)?;
+        file.write_all(format!("{}{:05}\n", MANIFEST_FILE_PREFIX, id).as_bytes())?;
+        file.sync_all()?;
+        std::fs::rename(&tmp_path, dir.join(CURRENT_FILE_NAME))?;
+        Self::sync_dir(dir)
+    }
+
+    fn remove_stale_manifests(dir: &Path, current_id: usize) -> Result<()> {
+        for entry in std::fs::read_dir(dir)? {
+            let path = entry?.path();
+            let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+                continue;
+            };
+            let is_stale = match Self::parse_manifest_id(file_name) {
+                Some(id) => id != current_id,
+                // leftovers from an interrupted create_manifest_file or set_current
+                None => {
+                    file_name.ends_with(".tmp")
+                        && (file_name.starts_with(MANIFEST_FILE_PREFIX)
+                            || file_name.starts_with(CURRENT_FILE_NAME))
+                }
+            };
+            if is_stale {
+                std::fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn sync_dir(dir: &Path) -> Result<()> {
+        Ok(platform::sync_dir(dir)?)
+    }
+
+    fn parse_manifest_id(file_name: &str) -> Option<usize> {
+        file_name.strip_prefix(MANIFEST_FILE_PREFIX)?.parse().ok()
+    }
+
+    fn get_manifest_path(dir: &Path, id: usize) -> PathBuf {
+        dir.join(format!("{}{:05}", MANIFEST_FILE_PREFIX, id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, path::PathBuf};
+
+    use tempfile::tempdir;
+
+    use crate::error::LsmError;
+
+    use super::{Manifest, ManifestRecord, SstFile, StoreConfig, LEGACY_FLUSH_RECORD_TAG, LEGACY_SNAPSHOT_RECORD_TAG};
+
+    fn flush(sst_id: usize) -> ManifestRecord {
+        ManifestRecord::Flush(SstFile::legacy(sst_id))
+    }
+
+    fn snapshot(sst_ids: &[usize]) -> ManifestRecord {
+        ManifestRecord::Snapshot(sst_ids.iter().copied().map(SstFile::legacy).collect())
+    }
+
+    // frame a payload the way ManifestRecord::encode does
+    fn frame(payload: &[u8]) -> Vec<u8> {
+        let mut encoded = (payload.len() as u32).to_be_bytes().to_vec();
+        encoded.extend(payload);
+        encoded.extend((xxhash_rust::xxh3::xxh3_64(payload) as u32).to_be_bytes());
+        encoded
+    }
+
+    #[test]
+    fn test_encode_decode_paths() {
+        let records = vec![
+            ManifestRecord::Flush(SstFile {
+                id: 7,
+                path: PathBuf::from("L0/00007.sst"),
+            }),
+            ManifestRecord::Snapshot(vec![
+                SstFile {
+                    id: 7,
+                    path: PathBuf::from("L0/00007.sst"),
+                },
+                SstFile::legacy(3),
+            ]),
+        ];
+        let mut encoded: Vec<u8> = Vec::new();
+        for record in &records {
+            encoded.extend(record.encode());
+        }
+        assert_eq!(ManifestRecord::decode_to_list(&encoded), records);
+    }
+
+    #[test]
+    fn test_decode_legacy_records() {
+        let mut flush_payload = vec![LEGACY_FLUSH_RECORD_TAG];
+        flush_payload.extend(3_u64.to_be_bytes());
+        let mut snapshot_payload = vec![LEGACY_SNAPSHOT_RECORD_TAG];
+        snapshot_payload.extend(2_u32.to_be_bytes());
+        snapshot_payload.extend(1_u64.to_be_bytes());
+        snapshot_payload.extend(0_u64.to_be_bytes());
+        let mut encoded = frame(&flush_payload);
+        encoded.extend(frame(&snapshot_payload));
+
+        // ssts from before paths were recorded are in the store directory
+        assert_eq!(
+            ManifestRecord::decode_to_list(&encoded),
+            vec![
+                ManifestRecord::Flush(SstFile {
+                    id: 3,
+                    path: PathBuf::from("00003.sst"),
+                }),
+                snapshot(&[1, 0]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let records = vec![
+            flush(3),
+            snapshot(&[2, 1, 0]),
+            snapshot(&[]),
+        ];
+        let mut encoded: Vec<u8> = Vec::new();
+        for record in &records {
+            encoded.extend(record.encode());
+        }
+        assert_eq!(ManifestRecord::decode_to_list(&encoded), records);
+
+        // truncated trailing record is dropped
+        let truncated = &encoded[..encoded.len() - 1];
+        assert_eq!(ManifestRecord::decode_to_list(truncated), records[..2]);
+
+        // corrupt checksum stops decoding
+        let mut corrupted = encoded.clone();
+        corrupted[5] ^= 0xff;
+        assert!(ManifestRecord::decode_to_list(&corrupted).is_empty());
+    }
+
+    #[test]
+    fn test_open_add_records() {
+        let dir = tempdir().unwrap();
+        {
+            let (manifest, records) = Manifest::open(dir.path(), 100, false).unwrap();
+            assert!(records.is_empty());
+            manifest.add_record(&flush(0)).unwrap();
+            manifest.add_record(&flush(1)).unwrap();
+        }
+        let (_, records) = Manifest::open(dir.path(), 100, false).unwrap();
+        assert_eq!(records, vec![flush(0), flush(1)]);
+    }
+
+    #[test]
+    fn test_open_drops_torn_record() {
+        let dir = tempdir().unwrap();
+        {
+            let (manifest, _) = Manifest::open(dir.path(), 100, false).unwrap();
+            manifest.add_record(&flush(0)).unwrap();
+            // simulate crash in the middle of appending a record
+            let torn = flush(1).encode();
+            let mut current = manifest.current.lock().unwrap();
+            current.file.write_all(&torn[..torn.len() - 2]).unwrap();
+        }
+        let err = Manifest::open(dir.path(), 100, true).err().unwrap();
+        assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::Corruption(_))));
+        let (manifest, records) = Manifest::open(dir.path(), 100, false).unwrap();
+        assert_eq!(records, vec![flush(0)]);
+        manifest.add_record(&flush(2)).unwrap();
+        drop(manifest);
+
+        let (_, records) = Manifest::open(dir.path(), 100, false).unwrap();
+        assert_eq!(records, vec![flush(0), flush(2)]);
+    }
+
+    #[test]
+    fn test_rotate() {
+        let dir = tempdir().unwrap();
+        {
+            let (manifest, _) = Manifest::open(dir.path(), 2, false).unwrap();
+            manifest.add_record(&flush(0)).unwrap();
+            manifest.add_record(&flush(1)).unwrap();
+            assert!(!manifest.should_rotate());
+            manifest.add_record(&flush(2)).unwrap();
+            assert!(manifest.should_rotate());
+
+            manifest.rotate(&snapshot(&[2, 1, 0])).unwrap();
+            assert!(!manifest.should_rotate());
+            manifest.add_record(&flush(3)).unwrap();
+        }
+        // old manifest is deleted and CURRENT points at the new one
+        assert!(!dir.path().join("MANIFEST-00000").exists());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join("CURRENT")).unwrap(),
+            "MANIFEST-00001\n"
+        );
+        let (_, records) = Manifest::open(dir.path(), 2, false).unwrap();
+        assert_eq!(
+            records,
+            vec![snapshot(&[2, 1, 0]), flush(3)]
+        );
+    }
+
+    #[test]
+    fn test_config() {
+        let config = StoreConfig::new(4096);
+        assert!(config.check_compatible(&config).is_ok());
+        let encoded = ManifestRecord::Config(config.clone()).encode();
+        assert_eq!(ManifestRecord::decode_to_list(&encoded), vec![ManifestRecord::Config(config.clone())]);
+
+        let incompatible = [
+            StoreConfig { comparator: "reverse".to_string(), ..config.clone() },
+            StoreConfig { compression: "zstd".to_string(), ..config.clone() },
+            StoreConfig { format_version: config.format_version + 1, ..config.clone() },
+        ];
+        for recorded in incompatible {
+            let err = recorded.check_compatible(&config).err().unwrap();
+            assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::InvalidOptions(_))), "{}", err);
+        }
+        // older formats and other block sizes are fine
+        let older = StoreConfig { format_version: 1, block_max_size_bytes: 1, ..config.clone() };
+        assert!(older.check_compatible(&config).is_ok());
+
+        // the config is kept across rotations
+        let dir = tempdir().unwrap();
+        {
+            let (manifest, _) = Manifest::open(dir.path(), 1, false).unwrap();
+            assert_eq!(manifest.config(), None);
+            manifest.add_record(&ManifestRecord::Config(config.clone())).unwrap();
+            manifest.add_record(&flush(0)).unwrap();
+            manifest.rotate(&snapshot(&[0])).unwrap();
+            assert!(!manifest.should_rotate());
+        }
+        let (manifest, records) = Manifest::open(dir.path(), 1, false).unwrap();
+        assert_eq!(records, vec![ManifestRecord::Config(config.clone()), snapshot(&[0])]);
+        assert_eq!(manifest.config(), Some(config));
+    }
+
+    #[test]
+    fn test_open_removes_stale_manifest() {
+        let dir = tempdir().unwrap();
+        {
+            let (manifest, _) = Manifest::open(dir.path(), 100, false).unwrap();
+            manifest.add_record(&flush(0)).unwrap();
+        }
+        // simulate crash after writing the new manifest but before swapping CURRENT
+        std::fs::write(dir.path().join("MANIFEST-00001"), snapshot(&[]).encode())
+            .unwrap();
+        let (_, records) = Manifest::open(dir.path(), 100, false).unwrap();
+        assert_eq!(records, vec![flush(0)]);
+        assert!(!dir.path().join("MANIFEST-00001").exists());
+    }
+
+    #[test]
+    fn test_destroy() {
+        let dir = tempdir().unwrap();
+        {
+            let (manifest, _) = Manifest::open(dir.path(), 100, false).unwrap();
+            manifest.add_record(&flush(0)).unwrap();
+        }
+        std::fs::write(dir.path().join("00000.sst"), "data").unwrap();
+        Manifest::destroy(dir.path()).unwrap();
+        assert!(!dir.path().join("CURRENT").exists());
+        assert!(!dir.path().join("MANIFEST-00000").exists());
+        // files that don't belong to the manifest are left alone
+        assert!(dir.path().join("00000.sst").exists());
+    }
+}
@@ -1,2 +1,4 @@
-pub mod memtable;
-pub mod skiplist;
\ No newline at end of file
+pub(crate) mod accountant;
+pub(crate) mod limiter;
+pub(crate) mod memtable;
+pub(crate) mod skiplist;
\ No newline at end of file
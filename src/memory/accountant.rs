@@ -0,0 +1,26 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// tracks the bytes held by every memtable of a store, active and frozen,
+// across all shards
+#[derive(Default)]
+pub(crate) struct MemoryAccountant {
+    used_bytes: AtomicUsize,
+}
+
+impl MemoryAccountant {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn allocate(&self, bytes: usize) {
+        self.used_bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    pub(crate) fn release(&self, bytes: usize) {
+        self.used_bytes.fetch_sub(bytes, Ordering::SeqCst);
+    }
+
+    pub(crate) fn used_bytes(&self) -> usize {
+        self.used_bytes.load(Ordering::SeqCst)
+    }
+}
@@ -0,0 +1,168 @@
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc, Mutex, Weak,
+};
+
+use crate::table::block_cache::BlockCache;
+
+// process-wide memory budget shared by any number of stores through
+// StorageStateOptions::memory_limiter. memtables and in-flight build buffers
+// are counted as they are allocated, and registered block caches are measured
+// when the budget is enforced. enforcement runs on the background flush tick:
+// it evicts cached blocks first, since they can be read back, and asks for
+// memtables to be flushed if that is not enough
+pub struct MemoryLimiter {
+    limit_bytes: usize,
+    memtable_bytes: AtomicUsize,
+    // SSTs being built by flushes and compactions
+    buffer_bytes: AtomicUsize,
+    // caches of closed stores drop out on their own
+    caches: Mutex<Vec<Weak<BlockCache>>>,
+}
+
+// releases its bytes from the limiter when dropped
+pub struct BufferReservation {
+    limiter: Arc<MemoryLimiter>,
+    bytes: usize,
+}
+
+impl Drop for BufferReservation {
+    fn drop(&mut self) {
+        self.limiter.buffer_bytes.fetch_sub(self.bytes, Ordering::SeqCst);
+    }
+}
+
+impl MemoryLimiter {
+    pub fn new(limit_bytes: usize) -> Self {
+        Self {
+            limit_bytes,
+            memtable_bytes: AtomicUsize::new(0),
+            buffer_bytes: AtomicUsize::new(0),
+            caches: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub fn limit_bytes(&self) -> usize {
+        self.limit_bytes
+    }
+
+    pub fn allocate_memtable(&self, bytes: usize) {
+        self.memtable_bytes.fetch_add(bytes, Ordering::SeqCst);
+    }
+
+    pub fn release_memtable(&self, bytes: usize) {
+        self.memtable_bytes.fetch_sub(bytes, Ordering::SeqCst);
+    }
+
+    pub fn reserve_buffer(self: &Arc<Self>, bytes: usize) -> BufferReservation {
+        self.buffer_bytes.fetch_add(bytes, Ordering::SeqCst);
+        BufferReservation {
+            limiter: self.clone(),
+            bytes,
+        }
+    }
+
+    pub fn register_cache(&self, cache: &Arc<BlockCache>) {
+        self.caches.lock().unwrap().push(Arc::downgrade(cache));
+    }
+
+    pub fn memtable_bytes(&self) -> usize {
+        self.memtable_bytes.load(Ordering::SeqCst)
+    }
+
+    pub fn buffer_bytes(&self) -> usize {
+        self.buffer_bytes.load(Ordering::SeqCst)
+    }
+
+    // walks every cached block, so only call this off the hot path
+    pub fn cache_bytes(&self) -> usize {
+        self.live_caches()
+            .iter()
+            .map(|cache| {
+                cache.run_pending_tasks();
+                cache.iter().map(|(_, block)| block.size_bytes()).sum::<usize>()
+            })
+            .sum()
+    }
+
+    pub fn usage_bytes(&self) -> usize {
+        self.memtable_bytes() + self.buffer_bytes() + self.cache_bytes()
+    }
+
+    // evict cached blocks until usage is back under the limit. returns true if
+    // usage is still over the limit once the caches are empty, meaning
+    // memtables have to be flushed
+    pub fn enforce(&self) -> bool {
+        let mut usage = self.usage_bytes();
+        if usage <= self.limit_bytes {
+            return false;
+        }
+        for cache in self.live_caches() {
+            for (key, block) in cache.iter() {
+                if usage <= self.limit_bytes {
+                    break;
+                }
+                cache.invalidate(&key);
+                usage = usage.saturating_sub(block.size_bytes());
+            }
+            cache.run_pending_tasks();
+        }
+        usage > self.limit_bytes
+    }
+
+    fn live_caches(&self) -> Vec<Arc<BlockCache>> {
+        let mut caches = self.caches.lock().unwrap();
+        caches.retain(|cache| cache.strong_count() > 0);
+        caches.iter().filter_map(Weak::upgrade).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{block::Block, table::block_cache::new_block_cache};
+
+    use super::MemoryLimiter;
+
+    #[test]
+    fn test_buffer_reservation() {
+        let limiter = Arc::new(MemoryLimiter::new(100));
+        {
+            let _reservation = limiter.reserve_buffer(60);
+            assert_eq!(limiter.buffer_bytes(), 60);
+            limiter.allocate_memtable(50);
+            assert_eq!(limiter.usage_bytes(), 110);
+        }
+        assert_eq!(limiter.buffer_bytes(), 0);
+        assert_eq!(limiter.usage_bytes(), 50);
+    }
+
+    #[test]
+    fn test_enforce_evicts_cache_first() {
+        let limiter = MemoryLimiter::new(100);
+        let cache = Arc::new(new_block_cache(1 << 10, 1));
+        limiter.register_cache(&cache);
+        for i in 0..4 {
+            // 20 bytes of data, 2 bytes of offsets and 2 for the end of data offset
+            cache.insert((0, i), Arc::new(Block::new(vec![0; 20], vec![0], 20)));
+        }
+        assert_eq!(limiter.cache_bytes(), 4 * 24);
+        assert!(!limiter.enforce());
+
+        // evicting blocks is enough to get back under the limit
+        limiter.allocate_memtable(40);
+        assert!(!limiter.enforce());
+        assert!(limiter.usage_bytes() <= 100);
+        assert!(limiter.cache_bytes() > 0);
+
+        // memtables alone are over the limit
+        limiter.allocate_memtable(80);
+        assert!(limiter.enforce());
+        assert_eq!(limiter.cache_bytes(), 0);
+
+        // caches of dropped stores are forgotten
+        drop(cache);
+        assert_eq!(limiter.cache_bytes(), 0);
+    }
+}
@@ -1,4 +1,5 @@
-pub mod iterator;
+pub(crate) mod iterator;
+pub(crate) mod rep;
 
 use std::{ops::Bound, sync::{
     atomic::{AtomicBool, AtomicUsize, Ordering},
@@ -8,15 +9,18 @@ use std::{ops::Bound, sync::{
 use anyhow::{anyhow, Ok, Result};
 use bytes::Bytes;
 
-use crossbeam_skiplist::SkipMap;
 use iterator::MemTableIterator;
+use rep::{MemTableRep, MemTableRepType};
 
-use crate::table::builder::SSTBuilder;
+use crate::kv::{kv_pair::{KeyValuePair, ValueType}, timestamped_key::TimestampedKey};
 
-pub struct MemTable {
+pub(crate) struct MemTable {
     id: usize,
-    pub(super) entries: Arc<SkipMap<Bytes, Bytes>>,
+    pub(super) entries: Arc<dyn MemTableRep>,
     size_bytes: AtomicUsize,
+    // writes, counting every version. a write that replaces a version with
+    // the same timestamp is counted again, so this is an upper bound
+    num_entries: AtomicUsize,
     mutable: AtomicBool,
 }
 
@@ -26,50 +30,118 @@ impl Clone for MemTable {
             id: self.id,
             entries: self.entries.clone(),
             size_bytes: AtomicUsize::new(self.size_bytes.load(Ordering::SeqCst)),
+            num_entries: AtomicUsize::new(self.num_entries.load(Ordering::SeqCst)),
             mutable: AtomicBool::new(self.mutable.load(Ordering::SeqCst)),
         }
     }
 }
 
 impl MemTable {
-    pub fn new(id: usize) -> Self {
-        let entries: SkipMap<Bytes, Bytes> = SkipMap::new();
+    pub(crate) fn new(id: usize) -> Self {
+        Self::new_with_rep(id, MemTableRepType::default())
+    }
+
+    pub(crate) fn new_with_rep(id: usize, rep_type: MemTableRepType) -> Self {
         Self {
             id,
-            entries: Arc::new(entries),
+            entries: rep_type.create(),
             size_bytes: AtomicUsize::new(0),
+            num_entries: AtomicUsize::new(0),
             mutable: AtomicBool::new(true),
         }
     }
 
-    pub fn get(&self, key: &[u8]) -> Option<Bytes> {
-        self.entries.get(key).map(|entry| entry.value().clone())
+    // the newest version of key. Some(None) if it is a delete
+    pub(crate) fn get(&self, key: &[u8]) -> Option<Option<Bytes>> {
+        self.entries.get(key).map(|entry| decode_value(entry).1)
+    }
+
+    // the newest version at or before timestamp, like get
+    pub(crate) fn get_as_of(&self, key: &[u8], timestamp: u64) -> Option<Option<Bytes>> {
+        self.scan_as_of(Bound::Included(key), Bound::Included(key), timestamp)
+            .next()
+            .map(|kv| (!kv.is_tombstone()).then_some(kv.value))
+    }
+
+    pub(crate) fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.put_with_timestamp(key, 0, value)
     }
 
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+    pub(crate) fn delete(&self, key: &[u8]) -> Result<()> {
+        self.delete_with_timestamp(key, 0)
+    }
+
+    pub(crate) fn put_with_timestamp(&self, key: &[u8], timestamp: u64, value: &[u8]) -> Result<()> {
+        self.write_with_timestamp(key, timestamp, ValueType::Put, value)
+    }
+
+    pub(crate) fn delete_with_timestamp(&self, key: &[u8], timestamp: u64) -> Result<()> {
+        self.write_with_timestamp(key, timestamp, ValueType::Delete, &[])
+    }
+
+    // adds a version of key. other versions are kept, except one with the same
+    // timestamp, which is replaced
+    pub(crate) fn write_with_timestamp(&self, key: &[u8], timestamp: u64, value_type: ValueType, value: &[u8]) -> Result<()> {
         if !self.mutable.load(Ordering::SeqCst) {
             return Err(anyhow!("cannot modify immutable table"));
         }
-        self.entries
-            .insert(Bytes::copy_from_slice(key), Bytes::copy_from_slice(value));
+        self.entries.insert(
+            TimestampedKey::new_with_timestamp(Bytes::copy_from_slice(key), timestamp),
+            encode_value(value_type, value),
+        );
         self.size_bytes
             .fetch_add(key.len() + value.len(), Ordering::SeqCst);
+        self.num_entries.fetch_add(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    // writes a batch of strictly increasing keys, all at timestamp, through
+    // MemTableRep::insert_sorted
+    pub(crate) fn write_sorted_with_timestamp(&self, batch: &[KeyValuePair], timestamp: u64) -> Result<()> {
+        if !self.mutable.load(Ordering::SeqCst) {
+            return Err(anyhow!("cannot modify immutable table"));
+        }
+        let entries = batch
+            .iter()
+            .map(|kv| {
+                let key = TimestampedKey::new_with_timestamp(kv.key.get_key(), timestamp);
+                (key, encode_value(kv.value_type, &kv.value))
+            })
+            .collect();
+        self.entries.insert_sorted(entries);
+        let size_bytes: usize = batch.iter().map(|kv| kv.key.get_key().len() + kv.value.len()).sum();
+        self.size_bytes.fetch_add(size_bytes, Ordering::SeqCst);
+        self.num_entries.fetch_add(batch.len(), Ordering::SeqCst);
         Ok(())
     }
 
-    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableIterator {
+    pub(crate) fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableIterator {
         MemTableIterator::new(self, lower, upper)
     }
 
-    pub fn get_id(&self) -> usize {
+    // the versions at or before timestamp
+    pub(crate) fn scan_as_of(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>, timestamp: u64) -> MemTableIterator {
+        MemTableIterator::new_as_of(self, lower, upper, timestamp)
+    }
+
+    pub(crate) fn get_id(&self) -> usize {
         self.id
     }
 
-    pub fn get_size_bytes(&self) -> usize {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub(crate) fn get_size_bytes(&self) -> usize {
         self.size_bytes.load(Ordering::SeqCst)
     }
 
-    pub fn freeze(&self) -> Result<()> {
+    // at least the number of entries a flush of the memtable writes
+    pub(crate) fn get_num_entries(&self) -> usize {
+        self.num_entries.load(Ordering::SeqCst)
+    }
+
+    pub(crate) fn freeze(&self) -> Result<()> {
         let res = self
             .mutable
             .compare_exchange(true, false, Ordering::SeqCst, Ordering::SeqCst);
@@ -79,15 +151,40 @@ impl MemTable {
         Ok(())
     }
 
-    pub fn flush(&self, sst_builder: &mut SSTBuilder) -> Result<()> {
+    // versions of a key are written newest first, and SSTBuilder::add rejects
+    // anything out of order
+    #[cfg(test)]
+    pub fn flush(&self, sst_builder: &mut crate::table::builder::SSTBuilder) -> Result<()> {
         let iterator = MemTableIterator::new(self, Bound::Unbounded, Bound::Unbounded);
         for kv in iterator {
-            sst_builder.add(kv)?;
+            sst_builder.add(&kv)?;
         }
         Ok(())
     }
 }
 
+// entries are stored as | value type (u8) | value |, so that reps only ever
+// deal in plain bytes
+const VALUE_TYPE_PUT: u8 = 0;
+const VALUE_TYPE_DELETE: u8 = 1;
+
+fn encode_value(value_type: ValueType, value: &[u8]) -> Bytes {
+    let mut encoded = Vec::with_capacity(1 + value.len());
+    encoded.push(match value_type {
+        ValueType::Put => VALUE_TYPE_PUT,
+        ValueType::Delete => VALUE_TYPE_DELETE,
+    });
+    encoded.extend_from_slice(value);
+    encoded.into()
+}
+
+pub(super) fn decode_value(encoded: Bytes) -> (ValueType, Option<Bytes>) {
+    match encoded.first() {
+        Some(&VALUE_TYPE_PUT) => (ValueType::Put, Some(encoded.slice(1..))),
+        _ => (ValueType::Delete, None),
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::{ops::Bound, sync::{atomic::Ordering, Arc}};
@@ -96,6 +193,7 @@ mod tests {
     use tempfile::tempdir;
 
     use crate::{
+        iterator::{latest_iterator::LatestIterator, StorageIterator},
         kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
         memory::memtable::MemTable,
         table::{builder::SSTBuilder, iterator::SSTIterator},
@@ -110,8 +208,14 @@ mod tests {
 
         assert_eq!(
             memtable.get("hello".as_bytes()).unwrap(),
-            Bytes::from("world".as_bytes())
+            Some(Bytes::from("world".as_bytes()))
         );
+        // deletes are kept apart from empty values
+        memtable.put("empty".as_bytes(), &[]).unwrap();
+        assert_eq!(memtable.get("empty".as_bytes()), Some(Some(Bytes::new())));
+        memtable.delete("empty".as_bytes()).unwrap();
+        assert_eq!(memtable.get("empty".as_bytes()), Some(None));
+        assert_eq!(memtable.get("missing".as_bytes()), None);
 
         assert!(memtable.freeze().is_ok());
         assert!(!memtable.mutable.load(Ordering::SeqCst));
@@ -148,10 +252,43 @@ mod tests {
         let mut sst_iterator = SSTIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
         assert_eq!(
             sst_iterator.next().unwrap(),
-            KeyValuePair {
-                key: TimestampedKey::new("hello".as_bytes().into()),
-                value: "world".as_bytes().into()
-            }
+            KeyValuePair::new(
+                TimestampedKey::new("hello".as_bytes().into()),
+                "world".as_bytes().into(),
+            )
         );
     }
+
+    #[test]
+    fn test_flush_versions() {
+        let memtable = MemTable::new(0);
+        for (key, timestamp) in [("k2", 1), ("k1", 0), ("k2", 3), ("k3", 0), ("k2", 2)] {
+            memtable
+                .put_with_timestamp(key.as_bytes(), timestamp, format!("{}@{}", key, timestamp).as_bytes())
+                .unwrap();
+        }
+
+        // small blocks, so the versions of k2 span blocks
+        let mut sst_builder = SSTBuilder::new(20);
+        memtable.flush(&mut sst_builder).unwrap();
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test_memtable_flush.sst");
+        let sst = Arc::new(sst_builder.build(0, path, None).unwrap());
+        assert!(sst.metadata().unwrap().num_blocks() > 2);
+
+        // newest first within each key
+        let values: Vec<Bytes> = SSTIterator::create_and_seek_to_first(sst.clone())
+            .unwrap()
+            .map(|kv| kv.value)
+            .collect();
+        assert_eq!(values, vec!["k1@0", "k2@3", "k2@2", "k2@1", "k3@0"]);
+        // seeks land on the newest version
+        let sst_iterator =
+            SSTIterator::create_and_seek_to_key(sst.clone(), TimestampedKey::new("k2".as_bytes().into())).unwrap();
+        assert_eq!(sst_iterator.peek().unwrap().value, "k2@3".as_bytes());
+        let values: Vec<Bytes> = LatestIterator::new(SSTIterator::create_and_seek_to_first(sst).unwrap())
+            .map(|kv| kv.value)
+            .collect();
+        assert_eq!(values, vec!["k1@0", "k2@3", "k3@0"]);
+    }
 }
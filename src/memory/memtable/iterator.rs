@@ -1,77 +1,94 @@
-use std::{iter::Peekable, ops::Bound};
-use std::sync::Arc;
+use std::ops::Bound;
 
 use bytes::Bytes;
-use crossbeam_skiplist::{map::Range, SkipMap};
-use ouroboros::self_referencing;
 
-use crate::{iterator::StorageIterator, kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey}};
+use crate::{iterator::{IteratorStats, StorageIterator}, kv::kv_pair::KeyValuePair};
 
-use super::MemTable;
+use super::{decode_value, rep::MemTableRange, MemTable};
 
-type BytesBound = (Bound<Bytes>, Bound<Bytes>);
-
-pub struct MemTableIterator {
-    internal: MemTableIteratorInternal,
-    current_kv: Option<KeyValuePair>
+pub(crate) struct MemTableIterator {
+    sub_iterator: MemTableRange,
+    // the entry after the ones returned so far, already taken from
+    // sub_iterator
+    current_kv: Option<KeyValuePair>,
+    // see keys_only
+    keys_only: bool,
 }
 
 impl MemTableIterator {
-    pub fn new(memtable: &MemTable, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Self {
-        let bound = (
-            lower.map(Bytes::copy_from_slice),
-            upper.map(Bytes::copy_from_slice),
-        );
+    pub(crate) fn new(memtable: &MemTable, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Self {
+        Self::from_range(memtable.entries.scan(lower, upper))
+    }
+
+    // skips versions newer than timestamp, including any written while the
+    // iterator is open
+    pub(crate) fn new_as_of(memtable: &MemTable, lower: Bound<&[u8]>, upper: Bound<&[u8]>, timestamp: u64) -> Self {
+        let range = memtable.entries.scan(lower, upper);
+        Self::from_range(Box::new(range.filter(move |(key, _)| key.get_timestamp() <= timestamp)))
+    }
+
+    fn from_range(range: MemTableRange) -> Self {
         let mut new = Self {
-            internal: MemTableIteratorInternal::new(memtable.entries.clone(), |map| map.range(bound).peekable()),
-            current_kv: None
+            sub_iterator: range,
+            current_kv: None,
+            keys_only: false,
         };
-        new.set_current_kv();
+        new.advance();
         new
     }
 
-    fn set_current_kv(&mut self) {
-        let new_entry = self.internal.with_sub_iterator_mut(
-            |iterator| iterator.peek().map(|entry| KeyValuePair {
-                key: TimestampedKey::new(entry.key().clone()), value: entry.value().clone()})
-        );
-        self.current_kv = new_entry;
+    // leave values out, for reads that only need keys
+    pub(crate) fn keys_only(mut self, keys_only: bool) -> Self {
+        self.keys_only = keys_only;
+        if let Some(kv) = self.current_kv.as_mut().filter(|_| keys_only) {
+            kv.value = Bytes::new();
+        }
+        self
+    }
+
+    fn advance(&mut self) {
+        self.current_kv = self.sub_iterator.next().map(|(key, entry)| {
+            let (value_type, value) = decode_value(entry);
+            KeyValuePair {
+                key,
+                value: value.filter(|_| !self.keys_only).unwrap_or_default(),
+                value_type,
+            }
+        });
     }
 }
 
 impl StorageIterator for MemTableIterator {
-    fn peek(&mut self) -> Option<KeyValuePair> {
-        self.current_kv.clone()
+    fn peek(&self) -> Option<&KeyValuePair> {
+        self.current_kv.as_ref()
     }
 
     fn is_valid(&self) -> bool {
         true
     }
+
+    // entries are already in memory, so reading them can't fail
+    fn error(&self) -> Option<&anyhow::Error> {
+        None
+    }
+
+    fn stats(&self) -> IteratorStats {
+        IteratorStats {
+            num_memtable_iterators: self.current_kv.is_some().into(),
+            ..Default::default()
+        }
+    }
 }
 
 impl Iterator for MemTableIterator {
     type Item = KeyValuePair;
     fn next(&mut self) -> Option<KeyValuePair> {
-        let next = self.internal.with_sub_iterator_mut(|iter| iter.next());
-        let res = next.map(
-            |entry| KeyValuePair {
-                key: TimestampedKey::new(entry.key().clone()),
-                value: entry.value().clone(),
-            }
-        );
-        self.set_current_kv();
+        let res = self.current_kv.take();
+        self.advance();
         res
     }
 }
 
-#[self_referencing]
-pub struct MemTableIteratorInternal {
-    map: Arc<SkipMap<Bytes, Bytes>>,
-    #[borrows(map)]
-    #[not_covariant]
-    sub_iterator: Peekable<Range<'this, Bytes, BytesBound, Bytes, Bytes>>,
-}
-
 #[cfg(test)]
 mod tests {
     use std::ops::Bound;
@@ -87,8 +104,8 @@ mod tests {
 
         let mut iterator: MemTableIterator = MemTableIterator::new(&memtable, Bound::Unbounded, Bound::Unbounded);
         
-        let expected_item = KeyValuePair { key: TimestampedKey::new("hello".as_bytes().into()), value: "world".as_bytes().into() };
-        assert!(iterator.peek().is_some_and(|kv| kv == expected_item));
+        let expected_item = KeyValuePair::new(TimestampedKey::new("hello".as_bytes().into()), "world".as_bytes().into());
+        assert!(iterator.peek().is_some_and(|kv| *kv == expected_item));
 
         assert!(iterator.next().is_some_and(|kv| kv == expected_item));
         assert!(iterator.next().is_none());
@@ -0,0 +1,532 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap},
+    iter::Peekable,
+    ops::{Bound, RangeBounds},
+    sync::{Arc, Mutex, RwLock},
+};
+
+use bytes::Bytes;
+use crossbeam_skiplist::{map::Range, SkipMap};
+use ouroboros::self_referencing;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::kv::timestamped_key::TimestampedKey;
+
+type TimestampedKeyBound = (Bound<TimestampedKey>, Bound<TimestampedKey>);
+
+pub(crate) type MemTableRange = Box<dyn Iterator<Item = (TimestampedKey, Bytes)>>;
+
+// in-memory structure holding the entries of a memtable, one per version of
+// a key. implementations must be safe to write from several threads at once,
+// and scan must return entries in TimestampedKey order, so the versions of a
+// key come out newest first
+pub trait MemTableRep: Send + Sync {
+    // newest version of key
+    fn get(&self, key: &[u8]) -> Option<Bytes>;
+
+    // replaces any existing value for the same key and timestamp
+    fn insert(&self, key: TimestampedKey, value: Bytes);
+
+    // entries in strictly increasing TimestampedKey order, none of them
+    // already in the rep, e.g. an append_sorted batch. reps that can make use
+    // of the order override this
+    fn insert_sorted(&self, entries: Vec<(TimestampedKey, Bytes)>) {
+        for (key, value) in entries {
+            self.insert(key, value);
+        }
+    }
+
+    fn is_empty(&self) -> bool;
+
+    // every version of the keys in range
+    fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableRange;
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum MemTableRepType {
+    // lock-free skiplist. scans read the live table without copying, and
+    // append_sorted batches of ascending keys are added whole
+    #[default]
+    SkipList,
+    // BTreeMap behind a read-write lock. scans copy the range out under the lock
+    BTreeMap,
+    // hash maps sharded by key. puts only lock one shard, but scans have to
+    // gather and sort the whole range, which makes them the slowest
+    HashSharded,
+}
+
+impl MemTableRepType {
+    pub fn create(self) -> Arc<dyn MemTableRep> {
+        match self {
+            MemTableRepType::SkipList => Arc::new(SkipListRep::default()),
+            MemTableRepType::BTreeMap => Arc::new(BTreeMapRep::default()),
+            MemTableRepType::HashSharded => Arc::new(HashShardedRep::default()),
+        }
+    }
+}
+
+// BTreeMap::range panics on these, so they are answered before reaching it
+fn is_empty_range(lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> bool {
+    match (lower, upper) {
+        (Bound::Included(lower), Bound::Included(upper)) => lower > upper,
+        (Bound::Included(lower), Bound::Excluded(upper))
+        | (Bound::Excluded(lower), Bound::Included(upper))
+        | (Bound::Excluded(lower), Bound::Excluded(upper)) => lower >= upper,
+        _ => false,
+    }
+}
+
+// bounds on user keys that take in or leave out every version of the key
+fn timestamped_key_bounds(lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> TimestampedKeyBound {
+    let version = |key: &[u8], timestamp| TimestampedKey::new_with_timestamp(Bytes::copy_from_slice(key), timestamp);
+    let lower = match lower {
+        Bound::Included(key) => Bound::Included(version(key, u64::MAX)),
+        Bound::Excluded(key) => Bound::Excluded(version(key, 0)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    let upper = match upper {
+        Bound::Included(key) => Bound::Included(version(key, 0)),
+        Bound::Excluded(key) => Bound::Excluded(version(key, u64::MAX)),
+        Bound::Unbounded => Bound::Unbounded,
+    };
+    (lower, upper)
+}
+
+type SortedChunk = Arc<[(TimestampedKey, Bytes)]>;
+
+pub(crate) struct SkipListRep {
+    entries: Arc<SkipMap<TimestampedKey, Bytes>>,
+    // sorted batches that came after every earlier one, each kept whole and
+    // keyed by its last entry, so that appending ascending keys takes one
+    // skiplist insert per batch rather than per key. reads merge them with
+    // the entries
+    chunks: SkipMap<TimestampedKey, SortedChunk>,
+    // held while checking that a batch comes after the last chunk and
+    // appending it
+    chunks_lock: Mutex<()>,
+}
+
+impl Default for SkipListRep {
+    fn default() -> Self {
+        Self {
+            entries: Arc::default(),
+            chunks: SkipMap::new(),
+            chunks_lock: Mutex::new(()),
+        }
+    }
+}
+
+impl MemTableRep for SkipListRep {
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        let newest = TimestampedKey::new_with_timestamp(Bytes::copy_from_slice(key), u64::MAX);
+        let entry = self
+            .entries
+            .lower_bound(Bound::Included(&newest))
+            .map(|entry| (entry.key().clone(), entry.value().clone()));
+        // the first chunk ending at or after newest holds the first entry
+        // from it on
+        let chunk_entry = self.chunks.lower_bound(Bound::Included(&newest)).map(|chunk| {
+            let chunk = chunk.value();
+            chunk[chunk.partition_point(|(chunk_key, _)| *chunk_key < newest)].clone()
+        });
+        [entry, chunk_entry]
+            .into_iter()
+            .flatten()
+            .filter(|(entry_key, _)| entry_key.get_key() == key)
+            .min_by(|a, b| a.0.cmp(&b.0))
+            .map(|(_, value)| value)
+    }
+
+    fn insert(&self, key: TimestampedKey, value: Bytes) {
+        self.entries.insert(key, value);
+    }
+
+    // a batch past the last chunk is appended as a chunk of its own without
+    // searching for any of its keys. any other goes in one entry at a time
+    fn insert_sorted(&self, entries: Vec<(TimestampedKey, Bytes)>) {
+        let Some((last_key, _)) = entries.last() else {
+            return;
+        };
+        let last_key = last_key.clone();
+        {
+            let _chunks_guard = self.chunks_lock.lock().unwrap();
+            let is_after_chunks = self.chunks.back().is_none_or(|chunk| *chunk.key() < entries[0].0);
+            if is_after_chunks {
+                self.chunks.insert(last_key, entries.into());
+                return;
+            }
+        }
+        for (key, value) in entries {
+            self.entries.insert(key, value);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty() && self.chunks.is_empty()
+    }
+
+    fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableRange {
+        let bound = timestamped_key_bounds(lower, upper);
+        // chunks from the first that ends in range, up to the first that
+        // starts past it
+        let chunks: Vec<MemTableRange> = self
+            .chunks
+            .range((bound.0.as_ref(), Bound::Unbounded))
+            .map(|chunk| chunk.value().clone())
+            .take_while(|chunk| match &bound.1 {
+                Bound::Included(upper) => chunk[0].0 <= *upper,
+                Bound::Excluded(upper) => chunk[0].0 < *upper,
+                Bound::Unbounded => true,
+            })
+            .map(|chunk| chunk_range(chunk, &bound))
+            .collect();
+        let entries: MemTableRange = Box::new(SkipMapRange::new(self.entries.clone(), |map| map.range(bound)));
+        if chunks.is_empty() {
+            return entries;
+        }
+        let chunks: MemTableRange = Box::new(chunks.into_iter().flatten());
+        Box::new(MergedRange {
+            entries: entries.peekable(),
+            chunks: chunks.peekable(),
+        })
+    }
+}
+
+// the entries of a chunk within the bounds
+fn chunk_range(chunk: SortedChunk, (lower, upper): &TimestampedKeyBound) -> MemTableRange {
+    let start = match lower {
+        Bound::Included(lower) => chunk.partition_point(|(key, _)| key < lower),
+        Bound::Excluded(lower) => chunk.partition_point(|(key, _)| key <= lower),
+        Bound::Unbounded => 0,
+    };
+    let end = match upper {
+        Bound::Included(upper) => chunk.partition_point(|(key, _)| key <= upper),
+        Bound::Excluded(upper) => chunk.partition_point(|(key, _)| key < upper),
+        Bound::Unbounded => chunk.len(),
+    };
+    Box::new((start..end.max(start)).map(move |index| chunk[index].clone()))
+}
+
+#[self_referencing]
+struct SkipMapRange {
+    map: Arc<SkipMap<TimestampedKey, Bytes>>,
+    #[borrows(map)]
+    #[not_covariant]
+    range: Range<'this, TimestampedKey, TimestampedKeyBound, TimestampedKey, Bytes>,
+}
+
+impl Iterator for SkipMapRange {
+    type Item = (TimestampedKey, Bytes);
+
+    fn next(&mut self) -> Option<(TimestampedKey, Bytes)> {
+        self.with_range_mut(|range| {
+            range
+                .next()
+                .map(|entry| (entry.key().clone(), entry.value().clone()))
+        })
+    }
+}
+
+// the entries and the chunks of a SkipListRep as one, in TimestampedKey
+// order
+struct MergedRange {
+    entries: Peekable<MemTableRange>,
+    chunks: Peekable<MemTableRange>,
+}
+
+impl Iterator for MergedRange {
+    type Item = (TimestampedKey, Bytes);
+
+    fn next(&mut self) -> Option<(TimestampedKey, Bytes)> {
+        match (self.entries.peek(), self.chunks.peek()) {
+            (Some((entry_key, _)), Some((chunk_key, _))) if chunk_key < entry_key => self.chunks.next(),
+            (Some(_), _) => self.entries.next(),
+            (None, _) => self.chunks.next(),
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct BTreeMapRep {
+    entries: RwLock<BTreeMap<TimestampedKey, Bytes>>,
+}
+
+impl MemTableRep for BTreeMapRep {
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.entries
+            .read()
+            .unwrap()
+            .range(timestamped_key_bounds(Bound::Included(key), Bound::Included(key)))
+            .next()
+            .map(|(_, value)| value.clone())
+    }
+
+    fn insert(&self, key: TimestampedKey, value: Bytes) {
+        self.entries.write().unwrap().insert(key, value);
+    }
+
+    // the lock is taken once for the whole batch
+    fn insert_sorted(&self, entries: Vec<(TimestampedKey, Bytes)>) {
+        let mut map = self.entries.write().unwrap();
+        for (key, value) in entries {
+            map.insert(key, value);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.entries.read().unwrap().is_empty()
+    }
+
+    fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableRange {
+        if is_empty_range(lower, upper) {
+            return Box::new(std::iter::empty());
+        }
+        let entries: Vec<(TimestampedKey, Bytes)> = self
+            .entries
+            .read()
+            .unwrap()
+            .range(timestamped_key_bounds(lower, upper))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect();
+        Box::new(entries.into_iter())
+    }
+}
+
+const NUM_HASH_SHARDS: usize = 16;
+
+// versions of each key, newest first
+type KeyVersions = BTreeMap<Reverse<u64>, Bytes>;
+
+pub(crate) struct HashShardedRep {
+    shards: Vec<Mutex<HashMap<Bytes, KeyVersions>>>,
+}
+
+impl Default for HashShardedRep {
+    fn default() -> Self {
+        Self {
+            shards: (0..NUM_HASH_SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+        }
+    }
+}
+
+impl HashShardedRep {
+    fn shard(&self, key: &[u8]) -> &Mutex<HashMap<Bytes, KeyVersions>> {
+        &self.shards[self.shard_index(key)]
+    }
+
+    fn shard_index(&self, key: &[u8]) -> usize {
+        xxh3_64(key) as usize % self.shards.len()
+    }
+}
+
+impl MemTableRep for HashShardedRep {
+    fn get(&self, key: &[u8]) -> Option<Bytes> {
+        self.shard(key)
+            .lock()
+            .unwrap()
+            .get(key)
+            .and_then(|versions| versions.values().next().cloned())
+    }
+
+    fn insert(&self, key: TimestampedKey, value: Bytes) {
+        self.shard(&key.get_key())
+            .lock()
+            .unwrap()
+            .entry(key.get_key())
+            .or_default()
+            .insert(Reverse(key.get_timestamp()), value);
+    }
+
+    // each shard is locked once, for all of its keys in the batch
+    fn insert_sorted(&self, entries: Vec<(TimestampedKey, Bytes)>) {
+        let mut shard_entries: Vec<Vec<(TimestampedKey, Bytes)>> = vec![Vec::new(); self.shards.len()];
+        for (key, value) in entries {
+            shard_entries[self.shard_index(&key.get_key())].push((key, value));
+        }
+        for (shard, entries) in self.shards.iter().zip(shard_entries) {
+            if entries.is_empty() {
+                continue;
+            }
+            let mut shard = shard.lock().unwrap();
+            for (key, value) in entries {
+                shard
+                    .entry(key.get_key())
+                    .or_default()
+                    .insert(Reverse(key.get_timestamp()), value);
+            }
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.shards.iter().all(|shard| shard.lock().unwrap().is_empty())
+    }
+
+    fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> MemTableRange {
+        let mut entries: Vec<(TimestampedKey, Bytes)> = Vec::new();
+        for shard in self.shards.iter() {
+            for (key, versions) in shard.lock().unwrap().iter() {
+                if !RangeBounds::<[u8]>::contains(&(lower, upper), key.as_ref()) {
+                    continue;
+                }
+                entries.extend(versions.iter().map(|(Reverse(timestamp), value)| {
+                    (TimestampedKey::new_with_timestamp(key.clone(), *timestamp), value.clone())
+                }));
+            }
+        }
+        entries.sort_unstable_by(|a, b| a.0.cmp(&b.0));
+        Box::new(entries.into_iter())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use bytes::Bytes;
+
+    use crate::kv::timestamped_key::TimestampedKey;
+
+    use super::{MemTableRep, MemTableRepType, SkipListRep};
+
+    #[test]
+    fn test_reps() {
+        for rep_type in [
+            MemTableRepType::SkipList,
+            MemTableRepType::BTreeMap,
+            MemTableRepType::HashSharded,
+        ] {
+            let rep = rep_type.create();
+            assert!(rep.is_empty());
+            for key in ["k3", "k1", "k4", "k2"] {
+                rep.insert(TimestampedKey::new(Bytes::from(key)), Bytes::from(format!("v{}", &key[1..])));
+            }
+            rep.insert(TimestampedKey::new(Bytes::from("k1")), Bytes::from("new_v1"));
+            assert!(!rep.is_empty());
+            assert_eq!(rep.get("k1".as_bytes()).unwrap(), "new_v1".as_bytes());
+            assert!(rep.get("k5".as_bytes()).is_none());
+
+            let keys: Vec<Bytes> = rep
+                .scan(Bound::Unbounded, Bound::Unbounded)
+                .map(|(key, _)| key.get_key())
+                .collect();
+            assert_eq!(keys, vec!["k1", "k2", "k3", "k4"], "{:?}", rep_type);
+            let keys: Vec<Bytes> = rep
+                .scan(Bound::Excluded("k1".as_bytes()), Bound::Included("k3".as_bytes()))
+                .map(|(key, _)| key.get_key())
+                .collect();
+            assert_eq!(keys, vec!["k2", "k3"], "{:?}", rep_type);
+            // empty and inverted ranges don't panic
+            assert!(rep
+                .scan(Bound::Excluded("k2".as_bytes()), Bound::Excluded("k2".as_bytes()))
+                .next()
+                .is_none());
+            assert!(rep
+                .scan(Bound::Included("k3".as_bytes()), Bound::Included("k1".as_bytes()))
+                .next()
+                .is_none());
+        }
+    }
+
+    #[test]
+    fn test_rep_versions() {
+        for rep_type in [
+            MemTableRepType::SkipList,
+            MemTableRepType::BTreeMap,
+            MemTableRepType::HashSharded,
+        ] {
+            let rep = rep_type.create();
+            for (key, timestamp) in [("k2", 1), ("k1", 1), ("k2", 3), ("k3", 0), ("k2", 2)] {
+                rep.insert(
+                    TimestampedKey::new_with_timestamp(Bytes::from(key), timestamp),
+                    Bytes::from(format!("{}@{}", key, timestamp)),
+                );
+            }
+            assert_eq!(rep.get("k2".as_bytes()).unwrap(), "k2@3".as_bytes(), "{:?}", rep_type);
+            let versions: Vec<Bytes> = rep
+                .scan(Bound::Unbounded, Bound::Unbounded)
+                .map(|(_, value)| value)
+                .collect();
+            assert_eq!(versions, vec!["k1@1", "k2@3", "k2@2", "k2@1", "k3@0"], "{:?}", rep_type);
+            // bounds take in or leave out every version of a key
+            let versions: Vec<Bytes> = rep
+                .scan(Bound::Included("k2".as_bytes()), Bound::Included("k2".as_bytes()))
+                .map(|(_, value)| value)
+                .collect();
+            assert_eq!(versions, vec!["k2@3", "k2@2", "k2@1"], "{:?}", rep_type);
+            let versions: Vec<Bytes> = rep
+                .scan(Bound::Excluded("k1".as_bytes()), Bound::Excluded("k2".as_bytes()))
+                .map(|(_, value)| value)
+                .collect();
+            assert!(versions.is_empty(), "{:?}", rep_type);
+        }
+    }
+
+    #[test]
+    fn test_insert_sorted() {
+        for rep_type in [
+            MemTableRepType::SkipList,
+            MemTableRepType::BTreeMap,
+            MemTableRepType::HashSharded,
+        ] {
+            let rep = rep_type.create();
+            rep.insert(TimestampedKey::new_with_timestamp(Bytes::from("k2"), 1), Bytes::from("old"));
+            let entries = ["k1", "k2", "k3"]
+                .into_iter()
+                .map(|key| (TimestampedKey::new_with_timestamp(Bytes::from(key), 2), Bytes::from(key)))
+                .collect();
+            rep.insert_sorted(entries);
+            assert_eq!(rep.get("k2".as_bytes()).unwrap(), "k2".as_bytes(), "{:?}", rep_type);
+            let versions: Vec<Bytes> = rep
+                .scan(Bound::Unbounded, Bound::Unbounded)
+                .map(|(_, value)| value)
+                .collect();
+            assert_eq!(versions, vec!["k1", "k2", "old", "k3"], "{:?}", rep_type);
+        }
+    }
+
+    #[test]
+    fn test_sorted_chunks() {
+        let rep = SkipListRep::default();
+        rep.insert_sorted(Vec::new());
+        assert!(rep.is_empty());
+        let version = |key: &str, timestamp| {
+            (
+                TimestampedKey::new_with_timestamp(Bytes::from(key.to_string()), timestamp),
+                Bytes::from(format!("{}@{}", key, timestamp)),
+            )
+        };
+        let (key, value) = version("k2", 1);
+        rep.insert(key, value);
+        rep.insert_sorted(vec![version("k1", 2), version("k3", 2)]);
+        rep.insert_sorted(vec![version("k4", 3), version("k5", 3)]);
+        // goes back before the last chunk, so it is inserted entry by entry
+        rep.insert_sorted(vec![version("k2", 4), version("k4", 4)]);
+        rep.insert_sorted(vec![version("k6", 5)]);
+        assert_eq!(rep.chunks.len(), 3);
+        assert_eq!(rep.entries.len(), 3);
+
+        for (key, expected) in [("k1", "k1@2"), ("k2", "k2@4"), ("k3", "k3@2"), ("k4", "k4@4"), ("k6", "k6@5")] {
+            assert_eq!(rep.get(key.as_bytes()).unwrap(), expected.as_bytes());
+        }
+        for key in ["k0", "k35", "k7"] {
+            assert!(rep.get(key.as_bytes()).is_none());
+        }
+
+        let versions = |lower, upper| rep.scan(lower, upper).map(|(_, value)| value).collect::<Vec<Bytes>>();
+        assert_eq!(
+            versions(Bound::Unbounded, Bound::Unbounded),
+            vec!["k1@2", "k2@4", "k2@1", "k3@2", "k4@4", "k4@3", "k5@3", "k6@5"]
+        );
+        assert_eq!(
+            versions(Bound::Excluded("k1".as_bytes()), Bound::Included("k4".as_bytes())),
+            vec!["k2@4", "k2@1", "k3@2", "k4@4", "k4@3"]
+        );
+        assert_eq!(
+            versions(Bound::Included("k5".as_bytes()), Bound::Excluded("k6".as_bytes())),
+            vec!["k5@3"]
+        );
+        assert!(versions(Bound::Excluded("k3".as_bytes()), Bound::Excluded("k4".as_bytes())).is_empty());
+        assert!(versions(Bound::Included("k5".as_bytes()), Bound::Included("k4".as_bytes())).is_empty());
+    }
+}
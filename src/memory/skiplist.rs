@@ -5,13 +5,13 @@ use anyhow::Result;
 
 type Link<T> = Option<NonNull<T>>;
 
-pub struct SkipList<K, V> {
+pub(crate) struct SkipList<K, V> {
     head: NonNull<Head<K, V>>,
     max_level: usize
 }
 
 impl<K, V> SkipList<K, V> {
-    pub fn new(max_level: usize) -> Self {
+    pub(crate) fn new(max_level: usize) -> Self {
         Self {
             head: NonNull::new(&mut Head::new(max_level)).expect("head pointer is null"),
             max_level
@@ -19,33 +19,33 @@ impl<K, V> SkipList<K, V> {
     }
 }
 
-pub struct Head<K, V> {
+pub(crate) struct Head<K, V> {
     forward: Vec<Link<SkipNode<K, V>>>
 }
 
 impl<K, V> Head<K, V> {
-    pub fn new(max_level: usize) -> Self {
+    pub(crate) fn new(max_level: usize) -> Self {
         let forward: Vec<Link<SkipNode<K, V>>> = vec![None; max_level];
         Head { forward }
     }
 
-    pub fn get(self, _key: K) -> Option<V> {
+    pub(crate) fn get(self, _key: K) -> Option<V> {
         todo!()
     }
 
-    pub fn insert(self, _key: K, _value: V) -> Result<()> {
+    pub(crate) fn insert(self, _key: K, _value: V) -> Result<()> {
         todo!()
     }
 }
 
-pub struct SkipNode<K, V> {
+pub(crate) struct SkipNode<K, V> {
     key: K,
     value: V,
     forward: Vec<Link<SkipNode<K, V>>>
 }
 
 impl<K, V> SkipNode<K, V> {
-    pub fn new(key: K, value: V) -> Self {
+    pub(crate) fn new(key: K, value: V) -> Self {
         SkipNode { key, value, forward: Vec::new() }
     }
 }
\ No newline at end of file
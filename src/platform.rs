@@ -0,0 +1,137 @@
+use std::{fs::File, io, path::Path, time::SystemTime};
+
+// the few file, clock and thread operations that differ between platforms.
+// everything else goes through std directly
+
+// read exactly buffer.len() bytes at offset without moving a shared cursor,
+// so concurrent readers can share one handle
+#[cfg(unix)]
+pub(crate) fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+
+// seek_read moves the handle's cursor, but nothing here relies on it
+#[cfg(windows)]
+pub(crate) fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    read_exact_with(|buffer, offset| std::os::windows::fs::FileExt::seek_read(file, buffer, offset), buffer, offset)
+}
+
+// e.g. wasm, where only in-memory stores work
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn read_exact_at(_file: &File, _buffer: &mut [u8], _offset: u64) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "sst files can't be read on this platform, use an in-memory store",
+    ))
+}
+
+// fill buffer from a positional read that may return fewer bytes than asked
+#[cfg_attr(not(windows), allow(dead_code))]
+fn read_exact_with(
+    mut read_at: impl FnMut(&mut [u8], u64) -> io::Result<usize>,
+    mut buffer: &mut [u8],
+    mut offset: u64,
+) -> io::Result<()> {
+    while !buffer.is_empty() {
+        match read_at(buffer, offset) {
+            Ok(0) => return Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+            Ok(num_read) => {
+                buffer = &mut buffer[num_read..];
+                offset += num_read as u64;
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(())
+}
+
+// make a rename or new file in dir durable
+#[cfg(unix)]
+pub(crate) fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+// directories can't be opened like files on windows, and NTFS journals
+// renames on its own
+#[cfg(not(unix))]
+pub(crate) fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+// wasm32-unknown-unknown has no clock, and Instant::now and SystemTime::now
+// panic there. time stands still instead: every duration measured is zero,
+// and every file is as old as the epoch
+pub(crate) const HAS_CLOCK: bool = !cfg!(all(target_arch = "wasm32", target_os = "unknown"));
+
+// wasm can't spawn threads, so background work runs on the threads that
+// trigger it, see BackgroundScheduler::new
+pub(crate) const CAN_SPAWN_THREADS: bool = !cfg!(target_arch = "wasm32");
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+pub(crate) use std::time::Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct Instant;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl Instant {
+    pub(crate) fn now() -> Self {
+        Instant
+    }
+
+    pub(crate) fn elapsed(&self) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+
+    pub(crate) fn saturating_duration_since(&self, _earlier: Instant) -> std::time::Duration {
+        std::time::Duration::ZERO
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+impl std::ops::Add<std::time::Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, _duration: std::time::Duration) -> Instant {
+        self
+    }
+}
+
+pub(crate) fn system_time_now() -> SystemTime {
+    match HAS_CLOCK {
+        true => SystemTime::now(),
+        false => SystemTime::UNIX_EPOCH,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::read_exact_with;
+
+    #[test]
+    fn test_read_exact_with_short_reads() {
+        let data: Vec<u8> = (0..10).collect();
+        // at most 3 bytes per read, with an interruption on the first one
+        let mut interrupted = false;
+        let mut read_at = |buffer: &mut [u8], offset: u64| {
+            if !interrupted {
+                interrupted = true;
+                return Err(io::Error::from(io::ErrorKind::Interrupted));
+            }
+            let start = (offset as usize).min(data.len());
+            let len = buffer.len().min(3).min(data.len() - start);
+            buffer[..len].copy_from_slice(&data[start..start + len]);
+            Ok(len)
+        };
+        let mut buffer = [0; 7];
+        read_exact_with(&mut read_at, &mut buffer, 2).unwrap();
+        assert_eq!(buffer, [2, 3, 4, 5, 6, 7, 8]);
+
+        let err = read_exact_with(&mut read_at, &mut buffer, 5).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,643 @@
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    panic::{self, AssertUnwindSafe},
+    sync::{Arc, Condvar, Mutex, MutexGuard, Weak},
+    thread,
+    time::Duration,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::{
+    error::LsmError,
+    platform::{Instant, CAN_SPAWN_THREADS},
+};
+
+// queued tasks with a higher priority always run first
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TaskPriority {
+    // background verification, see StorageStateOptions::scrub
+    Scrub,
+    Compaction,
+    Flush,
+}
+
+type Job = Arc<dyn Fn() -> Result<()> + Send + Sync>;
+
+struct Task {
+    priority: TaskPriority,
+    // submission order, so tasks of equal priority run first-in first-out
+    seq: u64,
+    job: Job,
+    // index into SchedulerState::periodic_tasks for periodic tasks
+    periodic_index: Option<usize>,
+}
+
+impl Ord for Task {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.priority
+            .cmp(&other.priority)
+            .then(other.seq.cmp(&self.seq))
+    }
+}
+
+impl PartialOrd for Task {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Task {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Task {}
+
+struct PeriodicTask {
+    priority: TaskPriority,
+    interval: Duration,
+    next_run: Instant,
+    job: Job,
+    // a periodic task is never queued or run more than once at a time
+    in_flight: bool,
+    // run again as soon as the current run finishes
+    triggered: bool,
+}
+
+struct SchedulerState {
+    queue: BinaryHeap<Task>,
+    periodic_tasks: Vec<PeriodicTask>,
+    next_seq: u64,
+    is_shutdown: bool,
+    // no task is started while paused, see BackgroundScheduler::pause
+    is_paused: bool,
+    // no worker threads, see BackgroundScheduler::new
+    is_inline: bool,
+    // one entry per task being run
+    running: Vec<TaskPriority>,
+    // the first panic of a task, see BackgroundScheduler::background_error
+    background_error: Option<LsmError>,
+}
+
+// what the scheduler is doing, see BackgroundScheduler::status
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct SchedulerStatus {
+    pub is_paused: bool,
+    // one entry per task waiting for a worker, highest priority first
+    pub queued: Vec<TaskPriority>,
+    pub running: Vec<TaskPriority>,
+}
+
+impl SchedulerState {
+    fn push(&mut self, priority: TaskPriority, job: Job, periodic_index: Option<usize>) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        self.queue.push(Task {
+            priority,
+            seq,
+            job,
+            periodic_index,
+        });
+    }
+
+    // queue every periodic task that is due and return when the next one will be
+    fn enqueue_due_periodic_tasks(&mut self, now: Instant) -> Option<Instant> {
+        let mut due = vec![];
+        for (index, task) in self.periodic_tasks.iter_mut().enumerate() {
+            if !task.in_flight && task.next_run <= now {
+                task.in_flight = true;
+                due.push((task.priority, task.job.clone(), index));
+            }
+        }
+        for (priority, job, index) in due {
+            self.push(priority, job, Some(index));
+        }
+        self.periodic_tasks
+            .iter()
+            .filter(|task| !task.in_flight)
+            .map(|task| task.next_run)
+            .min()
+    }
+
+    fn finish_task(&mut self, priority: TaskPriority, panic_message: Option<String>) {
+        if let Some(message) = panic_message {
+            let error = LsmError::Background(format!("{:?} task panicked: {}", priority, message));
+            self.background_error.get_or_insert(error);
+        }
+        let position = self.running.iter().position(|running| *running == priority);
+        self.running.swap_remove(position.expect("running task is recorded"));
+    }
+}
+
+// the panic message if job panicked. errors are only logged
+fn run_job(priority: TaskPriority, job: &Job) -> Option<String> {
+    match panic::catch_unwind(AssertUnwindSafe(|| job())) {
+        Ok(Ok(())) => None,
+        Ok(Err(e)) => {
+            eprintln!("error during background {:?} task: {}", priority, e);
+            None
+        }
+        Err(payload) => Some(match payload.downcast_ref::<&str>() {
+            Some(message) => message.to_string(),
+            None => payload.downcast_ref::<String>().cloned().unwrap_or_default(),
+        }),
+    }
+}
+
+type Shared = (Mutex<SchedulerState>, Condvar);
+
+// runs a periodic task ahead of its schedule, see
+// BackgroundScheduler::submit_periodic
+#[derive(Clone)]
+pub(crate) struct PeriodicTaskHandle {
+    // doesn't keep the scheduler alive
+    shared: Weak<Shared>,
+    index: usize,
+}
+
+impl PeriodicTaskHandle {
+    // run the task now, or straight after the current run if it is running.
+    // triggers close together may be served by a single run. without worker
+    // threads the task runs on this thread before trigger returns, unless
+    // the scheduler is paused
+    pub(crate) fn trigger(&self) {
+        let Some(shared) = self.shared.upgrade() else {
+            return;
+        };
+        let (lock, condvar) = &*shared;
+        let mut state = lock.lock().unwrap();
+        if state.is_shutdown {
+            return;
+        }
+        let is_inline = state.is_inline;
+        let task = &mut state.periodic_tasks[self.index];
+        match task.in_flight {
+            true => task.triggered = true,
+            false if is_inline => {
+                if !state.is_paused {
+                    Self::run_inline(lock, state, self.index);
+                }
+                return;
+            }
+            false => task.next_run = Instant::now(),
+        }
+        condvar.notify_one();
+    }
+
+    // runs the task until no trigger came in during the last run, e.g. from
+    // another thread or from the task itself
+    fn run_inline<'a>(lock: &'a Mutex<SchedulerState>, mut state: MutexGuard<'a, SchedulerState>, index: usize) {
+        let task = &mut state.periodic_tasks[index];
+        task.in_flight = true;
+        let (priority, job) = (task.priority, task.job.clone());
+        loop {
+            state.running.push(priority);
+            drop(state);
+            let panic_message = run_job(priority, &job);
+            state = lock.lock().unwrap();
+            state.finish_task(priority, panic_message);
+            let task = &mut state.periodic_tasks[index];
+            if !std::mem::take(&mut task.triggered) {
+                task.in_flight = false;
+                return;
+            }
+        }
+    }
+}
+
+// small pool of worker threads shared by all background work
+pub(crate) struct BackgroundScheduler {
+    shared: Arc<Shared>,
+    workers: Mutex<Vec<thread::JoinHandle<()>>>,
+}
+
+impl BackgroundScheduler {
+    // with no threads, as always on wasm, nothing runs on a schedule: a
+    // periodic task only runs when triggered, on the triggering thread
+    pub(crate) fn new(num_threads: usize) -> Result<Self> {
+        let num_threads = if CAN_SPAWN_THREADS { num_threads } else { 0 };
+        let shared = Arc::new((
+            Mutex::new(SchedulerState {
+                queue: BinaryHeap::new(),
+                periodic_tasks: Vec::new(),
+                next_seq: 0,
+                is_shutdown: false,
+                is_paused: false,
+                is_inline: num_threads == 0,
+                running: Vec::new(),
+                background_error: None,
+            }),
+            Condvar::new(),
+        ));
+        let workers = (0..num_threads)
+            .map(|_| {
+                let shared = shared.clone();
+                thread::spawn(move || Self::run_worker(&shared))
+            })
+            .collect();
+        Ok(Self {
+            shared,
+            workers: Mutex::new(workers),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn submit(
+        &self,
+        priority: TaskPriority,
+        job: impl Fn() -> Result<()> + Send + Sync + 'static,
+    ) -> Result<()> {
+        let (lock, condvar) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+        if state.is_shutdown {
+            return Err(anyhow!("scheduler is shut down"));
+        }
+        state.push(priority, Arc::new(job), None);
+        condvar.notify_one();
+        Ok(())
+    }
+
+    // run job every interval until shutdown, and whenever the returned handle
+    // is triggered. runs never overlap: if a run takes longer than interval,
+    // the next run starts as soon as it finishes
+    pub(crate) fn submit_periodic(
+        &self,
+        priority: TaskPriority,
+        interval: Duration,
+        job: impl Fn() -> Result<()> + Send + Sync + 'static,
+    ) -> Result<PeriodicTaskHandle> {
+        let (lock, condvar) = &*self.shared;
+        let mut state = lock.lock().unwrap();
+        if state.is_shutdown {
+            return Err(anyhow!("scheduler is shut down"));
+        }
+        state.periodic_tasks.push(PeriodicTask {
+            priority,
+            interval,
+            next_run: Instant::now() + interval,
+            job: Arc::new(job),
+            in_flight: false,
+            triggered: false,
+        });
+        // wake a worker so it picks up the new deadline
+        condvar.notify_one();
+        Ok(PeriodicTaskHandle {
+            shared: Arc::downgrade(&self.shared),
+            index: state.periodic_tasks.len() - 1,
+        })
+    }
+
+    // stop accepting tasks, drop queued ones and wait for running tasks to finish
+    pub(crate) fn shutdown(&self) -> Result<()> {
+        {
+            let (lock, condvar) = &*self.shared;
+            let mut state = lock.lock().map_err(|e| anyhow!("{:?}", e))?;
+            state.is_shutdown = true;
+            state.queue.clear();
+            condvar.notify_all();
+        }
+        // join all threads to avoid unexpected behavior
+        // https://matklad.github.io/2019/08/23/join-your-threads.html
+        let workers = std::mem::take(&mut *self.workers.lock().map_err(|e| anyhow!("{:?}", e))?);
+        for worker in workers {
+            worker.join().map_err(|e| anyhow!("{:?}", e))?;
+        }
+        Ok(())
+    }
+
+    // stop starting tasks until resume, e.g. to look at a store while nothing
+    // changes under it. running tasks finish, and tasks submitted meanwhile
+    // are queued. flushes are paused too, so once num_memtables_limit frozen
+    // memtables pile up writes flush them themselves
+    pub(crate) fn pause(&self) {
+        self.shared.0.lock().unwrap().is_paused = true;
+    }
+
+    pub(crate) fn resume(&self) {
+        let (lock, condvar) = &*self.shared;
+        lock.lock().unwrap().is_paused = false;
+        condvar.notify_all();
+    }
+
+    // no worker threads, so tasks only run when triggered
+    pub(crate) fn is_inline(&self) -> bool {
+        self.shared.0.lock().unwrap().is_inline
+    }
+
+    pub(crate) fn status(&self) -> SchedulerStatus {
+        let state = self.shared.0.lock().unwrap();
+        let mut queued: Vec<TaskPriority> = state.queue.iter().map(|task| task.priority).collect();
+        queued.sort_by(|a, b| b.cmp(a));
+        SchedulerStatus {
+            is_paused: state.is_paused,
+            queued,
+            running: state.running.clone(),
+        }
+    }
+
+    // the first panic of a task since the scheduler started or the error was
+    // cleared. the panicking task's worker carries on, and a periodic task
+    // still runs on schedule, but e.g. a flush that panics again every time
+    // leaves frozen memtables to pile up
+    pub(crate) fn background_error(&self) -> Option<LsmError> {
+        self.shared.0.lock().unwrap().background_error.clone()
+    }
+
+    pub(crate) fn clear_background_error(&self) {
+        self.shared.0.lock().unwrap().background_error = None;
+    }
+
+    // number of worker threads that have not been joined yet
+    #[cfg(test)]
+    pub fn num_workers(&self) -> usize {
+        self.workers.lock().unwrap().len()
+    }
+
+    #[cfg(test)]
+    pub fn is_shutdown(&self) -> bool {
+        self.shared.0.lock().unwrap().is_shutdown
+    }
+
+    fn run_worker(shared: &Shared) {
+        let (lock, condvar) = shared;
+        let mut state = lock.lock().unwrap();
+        loop {
+            if state.is_shutdown {
+                return;
+            }
+            if state.is_paused {
+                state = condvar.wait(state).unwrap();
+                continue;
+            }
+            let next_deadline = state.enqueue_due_periodic_tasks(Instant::now());
+            if let Some(task) = state.queue.pop() {
+                state.running.push(task.priority);
+                drop(state);
+                let panic_message = run_job(task.priority, &task.job);
+                state = lock.lock().unwrap();
+                state.finish_task(task.priority, panic_message);
+                if let Some(index) = task.periodic_index {
+                    let periodic_task = &mut state.periodic_tasks[index];
+                    periodic_task.in_flight = false;
+                    periodic_task.next_run = match std::mem::take(&mut periodic_task.triggered) {
+                        true => Instant::now(),
+                        false => Instant::now() + periodic_task.interval,
+                    };
+                }
+                continue;
+            }
+            state = match next_deadline {
+                Some(deadline) => {
+                    let timeout = deadline.saturating_duration_since(Instant::now());
+                    condvar.wait_timeout(state, timeout).unwrap().0
+                }
+                None => condvar.wait(state).unwrap(),
+            };
+        }
+    }
+}
+
+impl Drop for BackgroundScheduler {
+    fn drop(&mut self) {
+        self.shutdown().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use crate::error::LsmError;
+
+    use super::{BackgroundScheduler, SchedulerStatus, TaskPriority};
+
+    #[test]
+    fn test_submit() {
+        let scheduler = BackgroundScheduler::new(2).unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        for _ in 0..10 {
+            let counter = counter.clone();
+            let sender = sender.clone();
+            scheduler
+                .submit(TaskPriority::Flush, move || {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    sender.send(()).unwrap();
+                    Ok(())
+                })
+                .unwrap();
+        }
+        for _ in 0..10 {
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(counter.load(Ordering::SeqCst), 10);
+
+        assert_eq!(scheduler.num_workers(), 2);
+        scheduler.shutdown().unwrap();
+        assert!(scheduler.is_shutdown());
+        assert_eq!(scheduler.num_workers(), 0);
+        assert!(scheduler.submit(TaskPriority::Flush, || Ok(())).is_err());
+        // shutting down twice is fine
+        scheduler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let scheduler = BackgroundScheduler::new(1).unwrap();
+        let order = Arc::new(Mutex::new(vec![]));
+        // block the only worker so the remaining tasks queue up
+        let (unblock_sender, unblock_receiver) = crossbeam_channel::bounded::<()>(0);
+        scheduler
+            .submit(TaskPriority::Compaction, move || {
+                unblock_receiver.recv().unwrap();
+                Ok(())
+            })
+            .unwrap();
+        let (done_sender, done_receiver) = crossbeam_channel::unbounded();
+        for (name, priority) in [
+            ("compaction_1", TaskPriority::Compaction),
+            ("flush_1", TaskPriority::Flush),
+            ("compaction_2", TaskPriority::Compaction),
+            ("flush_2", TaskPriority::Flush),
+        ] {
+            let order = order.clone();
+            let done_sender = done_sender.clone();
+            scheduler
+                .submit(priority, move || {
+                    order.lock().unwrap().push(name);
+                    done_sender.send(()).unwrap();
+                    Ok(())
+                })
+                .unwrap();
+        }
+        unblock_sender.send(()).unwrap();
+        for _ in 0..4 {
+            done_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(
+            *order.lock().unwrap(),
+            vec!["flush_1", "flush_2", "compaction_1", "compaction_2"]
+        );
+    }
+
+    #[test]
+    fn test_periodic_does_not_overlap() {
+        let scheduler = BackgroundScheduler::new(4).unwrap();
+        let running = Arc::new(AtomicUsize::new(0));
+        let runs = Arc::new(AtomicUsize::new(0));
+        {
+            let running = running.clone();
+            let runs = runs.clone();
+            scheduler
+                .submit_periodic(TaskPriority::Flush, Duration::from_millis(1), move || {
+                    assert_eq!(running.fetch_add(1, Ordering::SeqCst), 0);
+                    thread::sleep(Duration::from_millis(5));
+                    running.fetch_sub(1, Ordering::SeqCst);
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+                .unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        scheduler.shutdown().unwrap();
+        assert!(runs.load(Ordering::SeqCst) > 1);
+        assert_eq!(running.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_trigger_periodic() {
+        let scheduler = BackgroundScheduler::new(1).unwrap();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let handle = scheduler
+            .submit_periodic(TaskPriority::Flush, Duration::from_secs(3600), move || {
+                sender.send(()).unwrap();
+                Ok(())
+            })
+            .unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        for _ in 0..3 {
+            handle.trigger();
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+
+        // does nothing once the scheduler is gone
+        drop(scheduler);
+        handle.trigger();
+    }
+
+    #[test]
+    fn test_inline() {
+        let scheduler = BackgroundScheduler::new(0).unwrap();
+        assert!(scheduler.is_inline());
+        assert_eq!(scheduler.num_workers(), 0);
+        let runs = Arc::new(AtomicUsize::new(0));
+        let handle = {
+            let runs = runs.clone();
+            scheduler
+                .submit_periodic(TaskPriority::Flush, Duration::from_millis(1), move || {
+                    runs.fetch_add(1, Ordering::SeqCst);
+                    Ok(())
+                })
+                .unwrap()
+        };
+        // nothing runs on schedule, only when triggered, before trigger returns
+        thread::sleep(Duration::from_millis(10));
+        assert_eq!(runs.load(Ordering::SeqCst), 0);
+        handle.trigger();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+
+        scheduler.pause();
+        handle.trigger();
+        assert_eq!(runs.load(Ordering::SeqCst), 1);
+        scheduler.resume();
+        handle.trigger();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+        scheduler.shutdown().unwrap();
+        handle.trigger();
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn test_pause() {
+        let scheduler = BackgroundScheduler::new(1).unwrap();
+        let (unblock_sender, unblock_receiver) = crossbeam_channel::bounded::<()>(0);
+        scheduler
+            .submit(TaskPriority::Compaction, move || {
+                unblock_receiver.recv().unwrap();
+                Ok(())
+            })
+            .unwrap();
+        // wait for the worker to pick up the blocking task
+        while scheduler.status().running.is_empty() {
+            thread::yield_now();
+        }
+        scheduler.pause();
+        let (done_sender, done_receiver) = crossbeam_channel::unbounded();
+        scheduler
+            .submit(TaskPriority::Flush, move || {
+                done_sender.send(()).unwrap();
+                Ok(())
+            })
+            .unwrap();
+        assert_eq!(
+            scheduler.status(),
+            SchedulerStatus {
+                is_paused: true,
+                queued: vec![TaskPriority::Flush],
+                running: vec![TaskPriority::Compaction],
+            }
+        );
+        // the running task finishes, and the queued one waits
+        unblock_sender.send(()).unwrap();
+        assert!(done_receiver.recv_timeout(Duration::from_millis(50)).is_err());
+        assert_eq!(scheduler.status().queued, vec![TaskPriority::Flush]);
+
+        scheduler.resume();
+        done_receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(!scheduler.status().is_paused);
+        // a paused scheduler still shuts down
+        scheduler.pause();
+        scheduler.shutdown().unwrap();
+    }
+
+    #[test]
+    fn test_panicking_task() {
+        let scheduler = BackgroundScheduler::new(1).unwrap();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        let runs = Arc::new(AtomicUsize::new(0));
+        {
+            let runs = runs.clone();
+            scheduler
+                .submit_periodic(TaskPriority::Flush, Duration::from_millis(1), move || {
+                    sender.send(()).unwrap();
+                    if runs.fetch_add(1, Ordering::SeqCst) == 0 {
+                        panic!("flush failed");
+                    }
+                    Ok(())
+                })
+                .unwrap();
+        }
+        // the periodic task keeps running on the same worker after panicking
+        for _ in 0..3 {
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+        }
+        assert_eq!(
+            scheduler.background_error(),
+            Some(LsmError::Background("Flush task panicked: flush failed".to_string()))
+        );
+        assert_eq!(scheduler.num_workers(), 1);
+        scheduler.clear_background_error();
+        assert_eq!(scheduler.background_error(), None);
+        scheduler.shutdown().unwrap();
+    }
+}
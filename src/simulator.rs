@@ -0,0 +1,186 @@
+use std::time::{Duration, SystemTime};
+
+use crate::compaction::{plan_fifo_eviction, CompactionStyle};
+
+// replays a synthetic write workload through the compaction planners alone,
+// with no memtables, files or I/O, to see what a compaction configuration
+// would do before deploying it. memtables are flushed whenever they fill up
+// and a compaction round runs after every flush, as the background tasks
+// would at worst. merges are assumed to shrink nothing, so that write
+// amplification comes out as an upper bound for workloads that overwrite keys
+#[derive(Clone, Copy, Debug)]
+pub struct SimulationOptions {
+    pub compaction_style: CompactionStyle,
+    // bytes written between flushes, StorageStateOptions::sst_max_size_bytes
+    pub memtable_size_bytes: u64,
+    // for CompactionStyle::Fifo, like StorageStateOptions::max_db_size_bytes
+    pub max_db_size_bytes: Option<u64>,
+}
+
+// written at a steady rate for duration
+#[derive(Clone, Copy, Debug)]
+pub struct Workload {
+    pub write_bytes_per_second: u64,
+    pub duration: Duration,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SimulationReport {
+    // written by the workload
+    pub user_bytes: u64,
+    // written to SSTs by flushes and by merges
+    pub flushed_bytes: u64,
+    pub compacted_bytes: u64,
+    // bytes written to SSTs per byte written by the workload
+    pub write_amplification: f64,
+    pub num_flushes: usize,
+    pub num_merges: usize,
+    // by time-window expiry or fifo eviction
+    pub num_deleted_ssts: usize,
+    // l0 is the only level, so these are the store's file counts
+    pub num_l0_ssts: usize,
+    pub max_num_l0_ssts: usize,
+    pub db_size_bytes: u64,
+}
+
+struct SimulatedSst {
+    id: usize,
+    size_bytes: u64,
+    created: SystemTime,
+}
+
+pub fn simulate(options: &SimulationOptions, workload: &Workload) -> SimulationReport {
+    let mut report = SimulationReport::default();
+    let memtable_size_bytes = options.memtable_size_bytes.max(1);
+    let rate = workload.write_bytes_per_second.max(1);
+    let total_bytes = (workload.duration.as_secs_f64() * rate as f64) as u64;
+    let start = SystemTime::UNIX_EPOCH;
+    // newest first, like l0
+    let mut l0: Vec<SimulatedSst> = Vec::new();
+    let mut next_id = 0;
+    while report.user_bytes < total_bytes {
+        let size_bytes = memtable_size_bytes.min(total_bytes - report.user_bytes);
+        report.user_bytes += size_bytes;
+        let now = start + Duration::from_secs_f64(report.user_bytes as f64 / rate as f64);
+        l0.insert(
+            0,
+            SimulatedSst {
+                id: next_id,
+                size_bytes,
+                created: now,
+            },
+        );
+        next_id += 1;
+        report.flushed_bytes += size_bytes;
+        report.num_flushes += 1;
+        run_compaction_round(options, &mut l0, &mut next_id, now, &mut report);
+        report.max_num_l0_ssts = report.max_num_l0_ssts.max(l0.len());
+    }
+    report.write_amplification = match report.user_bytes {
+        0 => 0.0,
+        user_bytes => (report.flushed_bytes + report.compacted_bytes) as f64 / user_bytes as f64,
+    };
+    report.num_l0_ssts = l0.len();
+    report.db_size_bytes = l0.iter().map(|sst| sst.size_bytes).sum();
+    report
+}
+
+// what StorageState::trigger_compaction would do
+fn run_compaction_round(
+    options: &SimulationOptions,
+    l0: &mut Vec<SimulatedSst>,
+    next_id: &mut usize,
+    now: SystemTime,
+    report: &mut SimulationReport,
+) {
+    let time_window = match options.compaction_style {
+        CompactionStyle::None => return,
+        CompactionStyle::Fifo => {
+            if let Some(max_db_size_bytes) = options.max_db_size_bytes {
+                let sst_sizes: Vec<u64> = l0.iter().map(|sst| sst.size_bytes).collect();
+                let num_evicted = plan_fifo_eviction(&sst_sizes, max_db_size_bytes);
+                l0.truncate(l0.len() - num_evicted);
+                report.num_deleted_ssts += num_evicted;
+            }
+            return;
+        }
+        CompactionStyle::TimeWindow(time_window) => time_window,
+    };
+    let creation_times: Vec<SystemTime> = l0.iter().map(|sst| sst.created).collect();
+    let plan = time_window.plan(&creation_times, now);
+    let expired_ids: Vec<usize> = plan.expired.iter().map(|position| l0[*position].id).collect();
+    let merge_ids: Vec<usize> = plan
+        .merge
+        .map(|range| l0[range].iter().map(|sst| sst.id).collect())
+        .unwrap_or_default();
+    l0.retain(|sst| !expired_ids.contains(&sst.id));
+    report.num_deleted_ssts += expired_ids.len();
+    // like merge_ssts, the merge is given up if one of its inputs is gone
+    let Some(start) = l0.iter().position(|sst| merge_ids.first() == Some(&sst.id)) else {
+        return;
+    };
+    if !l0[start..].iter().map(|sst| sst.id).take(merge_ids.len()).eq(merge_ids.iter().copied()) {
+        return;
+    }
+    let inputs: Vec<SimulatedSst> = l0.splice(start..start + merge_ids.len(), []).collect();
+    let merged = SimulatedSst {
+        id: *next_id,
+        size_bytes: inputs.iter().map(|sst| sst.size_bytes).sum(),
+        // the newest input's, as merge_ssts keeps it
+        created: inputs.iter().map(|sst| sst.created).max().unwrap_or(now),
+    };
+    *next_id += 1;
+    report.compacted_bytes += merged.size_bytes;
+    report.num_merges += 1;
+    l0.insert(start, merged);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::compaction::{CompactionStyle, TimeWindowOptions};
+
+    use super::{simulate, SimulationOptions, Workload};
+
+    const MB: u64 = 1 << 20;
+
+    #[test]
+    fn test_simulate() {
+        let workload = Workload {
+            write_bytes_per_second: MB,
+            duration: Duration::from_secs(3600),
+        };
+        let options = |compaction_style| SimulationOptions {
+            compaction_style,
+            memtable_size_bytes: 60 * MB,
+            max_db_size_bytes: Some(600 * MB),
+        };
+
+        let none = simulate(&options(CompactionStyle::None), &workload);
+        assert_eq!(none.user_bytes, 3600 * MB);
+        assert_eq!(none.num_flushes, 60);
+        assert_eq!((none.num_l0_ssts, none.write_amplification), (60, 1.0));
+
+        let fifo = simulate(&options(CompactionStyle::Fifo), &workload);
+        assert_eq!(fifo.write_amplification, 1.0);
+        assert_eq!((fifo.num_l0_ssts, fifo.num_deleted_ssts), (10, 50));
+        assert_eq!(fifo.db_size_bytes, 600 * MB);
+
+        // ten-minute windows of ten flushes, merged four at a time
+        let time_window = TimeWindowOptions {
+            window: Duration::from_secs(600),
+            min_merge_width: 4,
+            ttl: Some(Duration::from_secs(1200)),
+        };
+        let windowed = simulate(&options(CompactionStyle::TimeWindow(time_window)), &workload);
+        assert!(windowed.write_amplification > 1.0);
+        assert!(windowed.compacted_bytes > 0);
+        assert!(windowed.num_deleted_ssts > 0);
+        assert!(windowed.max_num_l0_ssts < none.num_l0_ssts);
+        assert_eq!(
+            windowed.flushed_bytes + windowed.compacted_bytes,
+            (windowed.write_amplification * windowed.user_bytes as f64) as u64
+        );
+    }
+}
@@ -1,176 +1,911 @@
 use std::{
     collections::VecDeque,
-    fs::create_dir_all,
+    fs::{create_dir_all, read_dir, remove_dir, remove_file},
     iter,
     ops::Bound,
-    path::PathBuf,
+    path::{Path, PathBuf},
     sync::{
-        atomic::{AtomicUsize, Ordering},
-        Arc, RwLock,
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Mutex, OnceLock, RwLock, RwLockWriteGuard,
     },
     thread,
-    time::Duration,
+    time::{Duration, SystemTime},
 };
 
 use anyhow::{anyhow, Ok, Result};
 use bytes::Bytes;
-use storage_state_options::StorageStateOptions;
+use crossbeam_channel::Sender;
+use arc_cell::ArcCell;
+use bulk_load::BulkLoader;
+use range_lock::RangeLockTable;
+use read_options::{ReadOptions, ReadOptionsIterator};
+use scrub::ScrubCursor;
+use snapshot::ShardSnapshot;
+use update_log::{UpdateLog, WriteEvent, WriteRecord};
+use storage_state_options::{FlushTrigger, StorageStateOptions};
+use write_options::{WriteOptions, WriteToken};
 
 use crate::{
+    compaction::{plan_fifo_eviction, CompactionStyle, TombstoneRetention},
     iterator::{
-        bounded_iterator::BoundedIterator, merge_iterator::MergeIterator,
+        bounded_iterator::BoundedIterator, keys_only_iterator::KeysOnlyIterator,
+        latest_iterator::LatestIterator, merge_iterator::MergeIterator,
         two_merge_iterator::TwoMergeIterator, StorageIterator,
     },
-    kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
-    memory::memtable::MemTable,
-    table::{block_cache::BlockCache, builder::SSTBuilder, iterator::SSTIterator, Sst},
-    utils::range_overlap,
+    error::LsmError,
+    failpoint,
+    kv::{entry::{Entry, Version}, kv_pair::{KeyValuePair, ValueType}, timestamped_key::TimestampedKey},
+    listener::{CompactionJobInfo, FlushJobInfo, SstDeletionInfo, WriteStallInfo, WriteStallReason},
+    manifest::{Manifest, ManifestRecord, SstFile, StoreConfig},
+    memory::{accountant::MemoryAccountant, memtable::MemTable},
+    platform::{system_time_now, Instant},
+    scheduler::{BackgroundScheduler, PeriodicTaskHandle, TaskPriority},
+    stats::{
+        description::{LevelDescription, MemTableDescription, ShardDescription, SstDescription},
+        explain::{GetExplanation, GetStep, ProbeOutcome, ShardScanPlan, SstScanPlan},
+        histogram::LatencyHistogram,
+        LsmStats, MemoryUsage, MergeProgress, PrefixStats,
+    },
+    table::{
+        blob::remove_blob_file,
+        block_cache::{new_block_cache, BlockCache},
+        builder::SSTBuilder,
+        iterator::SSTIterator,
+        metadata_cache::MetadataCache, persistent_cache::PersistentBlockCache, table_cache::TableCache, Sst,
+    },
+    utils::{prefix_upper_bound, range_overlap},
 };
 
-const TOMBSTONE: &[u8] = &[];
+// rotate the manifest once it holds this many records
+const MANIFEST_MAX_RECORDS: usize = 1000;
+const COMPACTION_INTERVAL: Duration = Duration::from_secs(1);
+const FLUSH_TICK_INTERVAL: Duration = Duration::from_millis(50);
+// FlushTrigger::Event still checks now and then, e.g. for a memory limiter
+// that is over budget because of another store
+const FLUSH_FALLBACK_INTERVAL: Duration = Duration::from_secs(1);
 
-pub mod storage_state_options;
+mod arc_cell;
+pub(crate) mod backup;
+pub(crate) mod bulk_load;
+mod range_lock;
+pub(crate) mod read_options;
+pub(crate) mod repair;
+pub(crate) mod scrub;
+pub(crate) mod sharded_state;
+pub(crate) mod snapshot;
+pub(crate) mod storage_state_options;
+pub(crate) mod update_log;
+pub(crate) mod write_options;
 
 #[derive(Clone)]
 struct StorageStateProtected {
     current_memtable: Arc<MemTable>,
     frozen_memtables: VecDeque<Arc<MemTable>>,
-    l0_sst_ids: VecDeque<usize>,
+    l0_sst_files: VecDeque<SstFile>,
     ssts: VecDeque<Arc<Sst>>,
 }
 
-pub struct StorageState {
+impl StorageStateProtected {
+    fn l0_sst_ids(&self) -> Vec<usize> {
+        self.l0_sst_files.iter().map(|sst_file| sst_file.id).collect()
+    }
+}
+
+pub(crate) struct StorageState {
     block_cache: Arc<BlockCache>,
+    // None when SSTs keep their metadata resident
+    metadata_cache: Option<Arc<MetadataCache>>,
+    // None when every SST keeps its file open
+    table_cache: Option<Arc<TableCache>>,
+    persistent_cache: Option<Arc<PersistentBlockCache>>,
+    // None for in-memory stores
+    manifest: Option<Manifest>,
+    // taken by writers. readers never take it
     state_lock: Arc<RwLock<Arc<StorageStateProtected>>>,
+    // the state last installed under state_lock, for readers
+    published_state: ArcCell<StorageStateProtected>,
     sst_counter: AtomicUsize,
+    // timestamp of the newest write. shared with the other shards, so
+    // timestamps double as store-wide sequence numbers
+    last_timestamp: Arc<AtomicU64>,
+    // writes of this shard take a ticket along with their timestamp, see
+    // stamp, and publish in ticket order once they are in the memtable.
+    // published_timestamp is the newest published write's, so every write of
+    // the shard at or below it is fully in the memtable, see snapshot
+    stamp_lock: Mutex<()>,
+    next_ticket: AtomicU64,
+    published_ticket: AtomicU64,
+    published_timestamp: AtomicU64,
+    // writes kept for consumers of the store's updates, see
+    // set_sequence_floor
+    update_log: UpdateLog,
+    // shared with the other shards of the store
+    memory_accountant: Arc<MemoryAccountant>,
+    // compactions are not installed while a BulkLoader is open, see
+    // merge_ssts, and flushes take one memtable at a time
+    bulk_loads_in_progress: AtomicUsize,
+    // set with FlushTrigger::Event, so that freezing can start a flush
+    flush_task: OnceLock<PeriodicTaskHandle>,
+    // flushes and compaction rounds run one at a time, whether they are
+    // started in the background or by a caller
+    flush_lock: Mutex<()>,
+    compaction_lock: Mutex<()>,
+    // key ranges of the flushes, merges and bulk loads installing SSTs
+    range_locks: RangeLockTable,
+    // set when the store is closing. a merge in progress gives up at its
+    // next block and no further compaction rounds start
+    compaction_cancelled: AtomicBool,
+    // the merge running now, if any. merges run one at a time under
+    // compaction_lock
+    merge_progress: Mutex<Option<MergeProgress>>,
+    // where the scrubber left off, see ScrubOptions
+    scrub_cursor: Mutex<ScrubCursor>,
+    // of flushes and merging compactions that finished
+    flush_latency: LatencyHistogram,
+    compaction_latency: LatencyHistogram,
+    num_write_stalls: AtomicU64,
+    write_stall_nanos: AtomicU64,
     options: StorageStateOptions,
 }
 
+// a write's timestamp, see StorageState::stamp
+struct Stamp<'a> {
+    storage_state: &'a StorageState,
+    ticket: u64,
+    timestamp: u64,
+}
+
+impl Drop for Stamp<'_> {
+    // publish once the writes stamped before are, so that the published
+    // timestamp never covers a write that is still going into the memtable.
+    // those writes hold the state lock too, so they are only moments away
+    fn drop(&mut self) {
+        let storage_state = self.storage_state;
+        while storage_state.published_ticket.load(Ordering::SeqCst) != self.ticket {
+            thread::yield_now();
+        }
+        storage_state.published_timestamp.store(self.timestamp, Ordering::SeqCst);
+        storage_state.published_ticket.store(self.ticket + 1, Ordering::SeqCst);
+    }
+}
+
 impl StorageState {
-    pub fn open(options: StorageStateOptions) -> Result<Self> {
+    pub(crate) fn open(options: StorageStateOptions) -> Result<Self> {
+        Self::open_shard(options, Arc::new(MemoryAccountant::new()), Arc::new(AtomicU64::new(0)))
+    }
+
+    // shards of one store share their memtable budget and timestamps
+    pub(crate) fn open_shard(
+        options: StorageStateOptions,
+        memory_accountant: Arc<MemoryAccountant>,
+        last_timestamp: Arc<AtomicU64>,
+    ) -> Result<Self> {
+        options.validate()?;
+        let exists = !options.in_memory && Manifest::exists(&options.path);
+        if exists && options.error_if_exists {
+            return Err(LsmError::AlreadyExists(options.path.clone()).into());
+        }
+        if !exists && !options.create_if_missing {
+            return Err(LsmError::NotFound(options.path.clone()).into());
+        }
         // initialize directory if it doesn't exist
-        create_dir_all(&options.path)?;
+        if !options.in_memory {
+            create_dir_all(&options.path)?;
+        }
 
-        let sst_counter: AtomicUsize = AtomicUsize::new(0);
-        let current_memtable = Arc::new(MemTable::new(sst_counter.fetch_add(1, Ordering::SeqCst)));
+        let block_cache = Arc::new(new_block_cache(options.block_cache_size_bytes, options.block_cache_shards));
+        if let Some(limiter) = &options.memory_limiter {
+            limiter.register_cache(&block_cache);
+        }
+        let metadata_cache = options
+            .metadata_cache_capacity
+            .map(|capacity| Arc::new(MetadataCache::new(capacity)));
+        let table_cache = options
+            .max_open_files
+            .map(|max_open_files| Arc::new(TableCache::new(max_open_files)));
+        let persistent_cache = options
+            .persistent_cache
+            .as_ref()
+            .map(|persistent_cache| {
+                PersistentBlockCache::new(options.path.join(&persistent_cache.path), persistent_cache.capacity_bytes)
+                    .map(Arc::new)
+            })
+            .transpose()?;
+
+        // rebuild the SST list by replaying the manifest
+        let (manifest, records) = match options.in_memory {
+            true => (None, Vec::new()),
+            false => {
+                let (manifest, records) =
+                    Manifest::open(&options.path, MANIFEST_MAX_RECORDS, options.paranoid_checks)?;
+                // refuse stores whose files this build would misread. stores
+                // from before configs were recorded only ever used this one
+                let config = StoreConfig::new(options.block_max_size_bytes);
+                match manifest.config() {
+                    Some(recorded) => recorded.check_compatible(&config)?,
+                    None => manifest.add_record(&ManifestRecord::Config(config))?,
+                }
+                (Some(manifest), records)
+            }
+        };
+        let l0_sst_files = Self::replay_sst_files(records);
+        let mut ssts: VecDeque<Arc<Sst>> = VecDeque::new();
+        for sst_file in &l0_sst_files {
+            let path = options.path.join(&sst_file.path);
+            let mut sst = match &metadata_cache {
+                Some(metadata_cache) => {
+                    Sst::open_with_metadata_cache(sst_file.id, path, Some(block_cache.clone()), metadata_cache.clone())?
+                }
+                None if options.lazy_bloom_filters => {
+                    Sst::open_with_lazy_bloom_filter(sst_file.id, path, Some(block_cache.clone()))?
+                }
+                None => Sst::open(sst_file.id, path, Some(block_cache.clone()))?,
+            };
+            if options.paranoid_checks {
+                sst.validate()?;
+            }
+            if let Some(table_cache) = &table_cache {
+                sst.set_table_cache(table_cache.clone());
+            }
+            if let Some(persistent_cache) = &persistent_cache {
+                sst.set_persistent_cache(persistent_cache.clone());
+            }
+            ssts.push_back(Arc::new(sst));
+        }
+        // entries read back from SSTs are at timestamp 0, but the footers
+        // record the sequences they were written at. resume after the newest
+        if let Some(max_sequence) = ssts.iter().map(|sst| sst.get_sequence_range().1).max() {
+            last_timestamp.fetch_max(max_sequence, Ordering::SeqCst);
+        }
+
+        // ids are shared by memtables and SSTs, so resume after the newest SST
+        let next_sst_id = l0_sst_files
+            .iter()
+            .map(|sst_file| sst_file.id)
+            .max()
+            .map_or(0, |max_id| max_id + 1);
+        let sst_counter: AtomicUsize = AtomicUsize::new(next_sst_id);
+        let current_memtable = Arc::new(MemTable::new_with_rep(
+            sst_counter.fetch_add(1, Ordering::SeqCst),
+            options.memtable_rep,
+        ));
         // newest to oldest frozen memtables
         let frozen_memtables: VecDeque<Arc<MemTable>> = VecDeque::new();
-        // newest to oldest l0 SSTs
-        let l0_sst_ids: VecDeque<usize> = VecDeque::new();
-        let ssts: VecDeque<Arc<Sst>> = VecDeque::new();
-
-        let block_cache = Arc::new(BlockCache::new(options.block_cache_size_bytes));
 
         let protected_state = StorageStateProtected {
             current_memtable,
             frozen_memtables,
-            l0_sst_ids,
+            l0_sst_files,
             ssts,
         };
 
+        let protected_state = Arc::new(protected_state);
         Ok(Self {
             block_cache,
-            state_lock: Arc::new(RwLock::new(Arc::new(protected_state))),
+            metadata_cache,
+            table_cache,
+            persistent_cache,
+            manifest,
+            state_lock: Arc::new(RwLock::new(protected_state.clone())),
+            published_state: ArcCell::new(protected_state),
             sst_counter,
+            stamp_lock: Mutex::new(()),
+            next_ticket: AtomicU64::new(0),
+            published_ticket: AtomicU64::new(0),
+            published_timestamp: AtomicU64::new(last_timestamp.load(Ordering::SeqCst)),
+            last_timestamp,
+            update_log: UpdateLog::default(),
+            memory_accountant,
+            bulk_loads_in_progress: AtomicUsize::new(0),
+            num_write_stalls: AtomicU64::new(0),
+            write_stall_nanos: AtomicU64::new(0),
+            flush_task: OnceLock::new(),
+            flush_lock: Mutex::new(()),
+            compaction_lock: Mutex::new(()),
+            range_locks: RangeLockTable::default(),
+            compaction_cancelled: AtomicBool::new(false),
+            merge_progress: Mutex::new(None),
+            scrub_cursor: Mutex::new(ScrubCursor::default()),
+            flush_latency: LatencyHistogram::default(),
+            compaction_latency: LatencyHistogram::default(),
             options,
         })
     }
-    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        let ro_snapshot = self.state_lock.read().unwrap();
+    pub(crate) fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.get_with_options(key, &ReadOptions::default())
+    }
 
-        // look up value in memtables
-        let mut res = ro_snapshot.current_memtable.get(key);
-        if res.is_none() {
-            for memtable in &ro_snapshot.frozen_memtables {
-                res = memtable.get(key);
-                if res.is_some() {
-                    break;
-                }
+    // only snapshot, fill_cache and keys_only apply to gets
+    pub(crate) fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
+        match &options.snapshot {
+            Some(snapshot) => {
+                let shard_snapshot = snapshot.for_shard(&self.state_lock)?;
+                Self::check_min_sequence(options, shard_snapshot.sequence)?;
+                Self::get_from_snapshot(
+                    &shard_snapshot.state,
+                    key,
+                    Some(shard_snapshot.timestamp),
+                    options,
+                )
+            }
+            None => {
+                Self::check_min_sequence(options, self.latest_sequence())?;
+                Self::get_from_snapshot(&self.published_state.load(), key, None, options)
             }
         }
-        if let Some(val) = &res {
-            if val == TOMBSTONE {
-                return Ok(None);
+    }
+
+    // reads with ReadOptions::min_sequence must see at least that sequence
+    fn check_min_sequence(options: &ReadOptions, read_sequence: u64) -> Result<()> {
+        match options.min_sequence {
+            Some(token) if token.sequence > read_sequence => Err(LsmError::SequenceNotReached {
+                required: token.sequence,
+                current: read_sequence,
             }
-            return Ok(res);
+            .into()),
+            _ => Ok(()),
         }
+    }
 
-        // if not found in memtable, look up in SSTs
+    // read_timestamp None reads the newest versions. layers are searched
+    // newest first: the current memtable, the frozen ones, then l0. every
+    // layer only holds versions newer than the layers after it, so the first
+    // one with a version of key decides the result, even if it is a tombstone.
+    // scans resolve versions the same way, see scan_entries
+    fn get_from_snapshot(
+        ro_snapshot: &StorageStateProtected,
+        key: &[u8],
+        read_timestamp: Option<u64>,
+        options: &ReadOptions,
+    ) -> Result<Option<Bytes>> {
+        let memtables = iter::once(&ro_snapshot.current_memtable).chain(ro_snapshot.frozen_memtables.iter());
+        for memtable in memtables {
+            let found = match read_timestamp {
+                Some(timestamp) => memtable.get_as_of(key, timestamp),
+                None => memtable.get(key),
+            };
+            if let Some(value) = found {
+                return Ok(value.map(|value| if options.keys_only { Bytes::new() } else { value }));
+            }
+        }
         for sst in &ro_snapshot.ssts {
-            if sst.maybe_contains_key(key) {
-                let found_kv = SSTIterator::create_and_seek_to_key(
-                    sst.clone(),
-                    TimestampedKey::new(Bytes::copy_from_slice(key)),
-                )?
-                .peek();
-                if found_kv.as_ref().is_some_and(|kv| kv.key.get_key() == key) {
-                    let val = found_kv.unwrap().value;
-                    if val == TOMBSTONE {
-                        return Ok(None);
-                    }
-                    return Ok(Some(val));
-                }
+            if let Some(value) = Self::get_from_sst(sst, key, options)? {
+                return Ok(value);
             }
         }
         Ok(None)
     }
 
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+    // the versions of key still held, newest first, at most limit of them.
+    // memtables hold every version written to them and flushes keep them
+    // all, but merges keep only the newest version of each key
+    pub(crate) fn get_versions(&self, key: &[u8], limit: usize) -> Result<Vec<Version>> {
+        let ro_snapshot = self.published_state.load();
+        let mut versions = Vec::new();
+        let memtables = iter::once(&ro_snapshot.current_memtable).chain(ro_snapshot.frozen_memtables.iter());
+        for memtable in memtables {
+            for kv in memtable.scan(Bound::Included(key), Bound::Included(key)) {
+                if versions.len() == limit {
+                    return Ok(versions);
+                }
+                versions.push(Version {
+                    sequence: Some(kv.key.get_timestamp()),
+                    value: (!kv.is_tombstone()).then_some(kv.value),
+                });
+            }
+        }
+        for sst in ro_snapshot.ssts.iter() {
+            if versions.len() == limit {
+                break;
+            }
+            if !sst.maybe_contains_key(key)? {
+                continue;
+            }
+            let mut sst_iterator = SSTIterator::create_and_seek_to_key_with_options(
+                sst.clone(),
+                TimestampedKey::new(Bytes::copy_from_slice(key)),
+                &ReadOptions {
+                    fill_cache: false,
+                    ..Default::default()
+                },
+            )?;
+            // entries don't record their sequences, so it is only known when
+            // the SST's range pins it down. legacy SSTs report (0, 0)
+            let sequence = match sst.get_sequence_range() {
+                (min_sequence, max_sequence) if min_sequence == max_sequence && max_sequence > 0 => Some(max_sequence),
+                _ => None,
+            };
+            while versions.len() < limit {
+                match sst_iterator.next() {
+                    Some(kv) if kv.key.get_key() == key => versions.push(Version {
+                        sequence,
+                        value: (!kv.is_tombstone()).then_some(kv.value),
+                    }),
+                    _ => break,
+                }
+            }
+            sst_iterator.check_error()?;
+        }
+        Ok(versions)
+    }
+
+    // like MemTable::get, Some(None) if the SST holds a tombstone for key.
+    // entries come back from SSTs without timestamps, so every version in one
+    // is visible to every snapshot that can see the SST. snapshots pin the
+    // state they were taken from, and every SST in it has a sequence range at
+    // or below the snapshot's timestamp, so there are no newer files to skip
+    fn get_from_sst(sst: &Arc<Sst>, key: &[u8], options: &ReadOptions) -> Result<Option<Option<Bytes>>> {
+        if !sst.maybe_contains_key(key)? {
+            return Ok(None);
+        }
+        let sst_iterator = SSTIterator::create_and_seek_to_key_with_options(
+            sst.clone(),
+            TimestampedKey::new(Bytes::copy_from_slice(key)),
+            options,
+        )?;
+        if sst_iterator.peek().is_none_or(|kv| kv.key.get_key() != key) {
+            return Ok(None);
+        }
+        Ok(sst_iterator
+            .read_current()?
+            .map(|kv| (!kv.is_tombstone()).then_some(kv.value)))
+    }
+
+    pub(crate) fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.write(key, ValueType::Put, value)?;
+        Ok(())
+    }
+
+    // returns the write's sequence
+    fn write(&self, key: &[u8], value_type: ValueType, value: &[u8]) -> Result<u64> {
+        self.maybe_wait_for_l0()?;
+        self.maybe_freeze_memtable(key.len() + value.len())?;
+        let timestamp = {
+            let ro_snapshot = self.state_lock.read().unwrap();
+            let stamp = self.stamp();
+            let timestamp = stamp.timestamp;
+            ro_snapshot.current_memtable.write_with_timestamp(key, timestamp, value_type, value)?;
+            self.log_write(timestamp, key, value_type, value);
+            timestamp
+        };
+        self.allocate_memtable_bytes(key.len() + value.len());
+        Ok(timestamp)
+    }
+
+    #[cfg(test)]
+    pub fn write_batch(&self, batch: &[KeyValuePair]) -> Result<()> {
+        self.apply_batch(batch)?;
+        Ok(())
+    }
+
+    // returns the batch's sequence
+    fn apply_batch(&self, batch: &[KeyValuePair]) -> Result<u64> {
+        let batch_size_bytes = batch
+            .iter()
+            .map(|kv| kv.key.get_key().len() + kv.value.len())
+            .sum();
+        self.maybe_wait_for_l0()?;
+        self.maybe_freeze_memtable(batch_size_bytes)?;
+        {
+            // hold the read lock for the whole batch so that the memtable
+            // cannot be frozen halfway through and split the batch
+            let ro_snapshot = self.state_lock.read().unwrap();
+            // one timestamp for the whole batch, so that scans see all of it
+            // or none. later writes of a key in the batch replace earlier ones
+            let stamp = self.stamp();
+            let timestamp = stamp.timestamp;
+            for kv in batch {
+                ro_snapshot
+                    .current_memtable
+                    .write_with_timestamp(&kv.key.get_key(), timestamp, kv.value_type, &kv.value)?;
+                self.allocate_memtable_bytes(kv.key.get_key().len() + kv.value.len());
+            }
+            self.update_log
+                .append(timestamp, || batch.iter().cloned().map(Entry::from).collect());
+            Ok(timestamp)
+        }
+    }
+
+    fn log_write(&self, timestamp: u64, key: &[u8], value_type: ValueType, value: &[u8]) {
+        self.update_log.append(timestamp, || {
+            vec![Entry {
+                key: Bytes::copy_from_slice(key),
+                value: Bytes::copy_from_slice(value),
+                value_type,
+            }]
+        });
+    }
+
+    // write new (None deletes) only if the key currently holds expected
+    // (None meaning absent). on a mismatch nothing is written and the current
+    // value is returned, like AtomicU64::compare_exchange. the state write
+    // lock is held from the read to the write, which keeps out puts and
+    // freezes for that long
+    pub(crate) fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<std::result::Result<(), Option<Bytes>>> {
+        let (value_type, value) = match new {
+            Some(value) => (ValueType::Put, value),
+            None => (ValueType::Delete, &[][..]),
+        };
+        self.maybe_wait_for_l0()?;
+        self.maybe_freeze_memtable(key.len() + value.len())?;
+        {
+            let rw_guard = self.state_lock.write().unwrap();
+            let current = Self::get_from_snapshot(&rw_guard, key, None, &ReadOptions::default())?;
+            if current.as_deref() != expected {
+                return Ok(Err(current));
+            }
+            if current.is_none() && new.is_none() {
+                return Ok(std::result::Result::Ok(()));
+            }
+            let stamp = self.stamp();
+            let timestamp = stamp.timestamp;
+            rw_guard.current_memtable.write_with_timestamp(key, timestamp, value_type, value)?;
+            self.log_write(timestamp, key, value_type, value);
+        }
+        self.allocate_memtable_bytes(key.len() + value.len());
+        Ok(std::result::Result::Ok(()))
+    }
+
+    pub(crate) fn put_with_options(&self, key: &[u8], value: &[u8], options: &WriteOptions) -> Result<WriteToken> {
+        self.write_with_options(options, || self.write(key, ValueType::Put, value))
+    }
+
+    pub(crate) fn delete_with_options(&self, key: &[u8], options: &WriteOptions) -> Result<WriteToken> {
+        self.write_with_options(options, || self.delete_existing(key))
+    }
+
+    pub(crate) fn write_batch_with_options(&self, batch: &[KeyValuePair], options: &WriteOptions) -> Result<WriteToken> {
+        self.write_with_options(options, || self.apply_batch(batch))
+    }
+
+    fn write_with_options(&self, options: &WriteOptions, write: impl FnOnce() -> Result<u64>) -> Result<WriteToken> {
+        if options.low_priority {
+            let num_frozen_memtables = self.state_lock.read().unwrap().frozen_memtables.len();
+            if num_frozen_memtables >= self.options.num_memtables_limit {
+                let started = Instant::now();
+                self.wait_for_flush()?;
+                self.record_write_stall(WriteStallReason::MemtableLimit, started.elapsed());
+            }
+        }
+        let sequence = write()?;
+        if options.sync && !self.options.in_memory {
+            let current_memtable_is_empty = self.state_lock.read().unwrap().current_memtable.is_empty();
+            // another write may have frozen the memtable already, in which
+            // case waiting for the frozen ones covers it
+            let started = Instant::now();
+            if !current_memtable_is_empty {
+                self.freeze_memtable()?;
+            }
+            self.wait_for_flush()?;
+            self.record_write_stall(WriteStallReason::Sync, started.elapsed());
+        }
+        Ok(WriteToken { sequence })
+    }
+
+    fn record_write_stall(&self, reason: WriteStallReason, duration: Duration) {
+        self.num_write_stalls.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.write_stall_nanos.fetch_add(nanos, Ordering::Relaxed);
+        let stall_info = WriteStallInfo { reason, duration };
+        for listener in self.options.listeners.iter() {
+            listener.on_write_stall(&stall_info);
+        }
+    }
+
+    // the next timestamp, for a write of this shard. take it while holding
+    // the state lock, and keep the stamp until the write is in the memtable:
+    // dropping it publishes the write, see snapshot
+    fn stamp(&self) -> Stamp<'_> {
+        let _stamp_guard = self.stamp_lock.lock().unwrap();
+        let ticket = self.next_ticket.fetch_add(1, Ordering::SeqCst);
+        let timestamp = self.last_timestamp.fetch_add(1, Ordering::SeqCst) + 1;
+        Stamp {
+            storage_state: self,
+            ticket,
+            timestamp,
+        }
+    }
+
+    fn allocate_memtable_bytes(&self, bytes: usize) {
+        self.memory_accountant.allocate(bytes);
+        if let Some(limiter) = &self.options.memory_limiter {
+            limiter.allocate_memtable(bytes);
+        }
+    }
+
+    // with l0_stall_num_ssts, hold the write back until compaction has
+    // brought l0 under it. concurrent writers take turns compacting, and the
+    // later ones find nothing left to do
+    fn maybe_wait_for_l0(&self) -> Result<()> {
+        let Some(l0_stall_num_ssts) = self.options.l0_stall_num_ssts else {
+            return Ok(());
+        };
+        if matches!(self.options.compaction_style, CompactionStyle::None) {
+            return Ok(());
+        }
+        let num_l0_ssts = self.state_lock.read().unwrap().l0_sst_files.len();
+        if num_l0_ssts < l0_stall_num_ssts {
+            return Ok(());
+        }
+        let started = Instant::now();
+        self.wait_for_compaction()?;
+        self.record_write_stall(WriteStallReason::L0Limit, started.elapsed());
+        Ok(())
+    }
+
+    fn maybe_freeze_memtable(&self, incoming_size_bytes: usize) -> Result<()> {
         let current_memtable_size = {
             let ro_snapshot = self.state_lock.read().unwrap();
             ro_snapshot.current_memtable.get_size_bytes()
         };
         if current_memtable_size > 0
-            && current_memtable_size + key.len() + value.len() > self.options.sst_max_size_bytes
+            && current_memtable_size + incoming_size_bytes > self.memtable_size_limit(current_memtable_size)
         {
             self.freeze_memtable()?;
         }
-        {
-            let ro_snapshot = self.state_lock.read().unwrap();
-            ro_snapshot.current_memtable.put(key, value)
-        }
+        Ok(())
+    }
+
+    // how large the active memtable may grow before it is frozen
+    fn memtable_size_limit(&self, current_memtable_size: usize) -> usize {
+        let Some(budget) = self.options.memtable_memory_budget_bytes else {
+            return self.options.sst_max_size_bytes;
+        };
+        // every memtable that can be alive at once, across all shards
+        let max_memtables = (self.options.num_memtables_limit + 1) * self.options.num_shards.max(1);
+        let min_share = budget / max_memtables;
+        let used_elsewhere = self
+            .memory_accountant
+            .used_bytes()
+            .saturating_sub(current_memtable_size);
+        budget.saturating_sub(used_elsewhere).max(min_share)
     }
 
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
+    pub(crate) fn delete(&self, key: &[u8]) -> Result<()> {
+        self.delete_existing(key)?;
+        Ok(())
+    }
+
+    // returns the delete's sequence
+    fn delete_existing(&self, key: &[u8]) -> Result<u64> {
         if self.get(key)?.is_none() {
             return Err(anyhow!("key cannot be deleted because it does not exist"));
         }
-        self.put(key, TOMBSTONE)
+        self.write(key, ValueType::Delete, &[])
     }
 
     fn freeze_memtable(&self) -> Result<()> {
-        let new_memtable = MemTable::new(self.get_next_sst_id());
-
-        let mut rw_guard = self.state_lock.write().unwrap();
-        let mut rw_snapshot = rw_guard.as_ref().clone();
-        rw_snapshot.current_memtable.freeze()?;
-        rw_snapshot
-            .frozen_memtables
-            .push_front(rw_snapshot.current_memtable.clone());
-        rw_snapshot.current_memtable = Arc::new(new_memtable);
-        *rw_guard = Arc::new(rw_snapshot);
+        // the new state is built before taking the write lock, which writers
+        // would otherwise queue behind for the length of the copy. if another
+        // change got installed in the meantime, start over from that one. the
+        // new memtable's id is taken after loading the state, so it is newer
+        // than every memtable in it
+        let num_frozen_memtables = loop {
+            let ro_snapshot = self.published_state.load();
+            let new_memtable = MemTable::new_with_rep(self.get_next_sst_id(), self.options.memtable_rep);
+            let mut new_snapshot = ro_snapshot.as_ref().clone();
+            new_snapshot
+                .frozen_memtables
+                .push_front(ro_snapshot.current_memtable.clone());
+            new_snapshot.current_memtable = Arc::new(new_memtable);
+            let mut rw_guard = self.state_lock.write().unwrap();
+            if !Arc::ptr_eq(&rw_guard, &ro_snapshot) {
+                continue;
+            }
+            ro_snapshot.current_memtable.freeze()?;
+            let num_frozen_memtables = new_snapshot.frozen_memtables.len();
+            self.install(&mut rw_guard, new_snapshot);
+            break num_frozen_memtables;
+        };
 
+        if num_frozen_memtables >= self.options.num_memtables_limit {
+            if let Some(flush_task) = self.flush_task.get() {
+                flush_task.trigger();
+            }
+        }
         Ok(())
     }
 
+    fn new_sst_builder(&self) -> SSTBuilder {
+        let mut sst_builder =
+            SSTBuilder::new_with_restart_interval(self.options.block_max_size_bytes, self.options.block_restart_interval);
+        sst_builder.set_blob_threshold(self.options.blob_threshold_bytes);
+        sst_builder.set_bloom_false_positive_rate(self.options.bloom_false_positive_rate);
+        sst_builder.set_index_partition_num_blocks(self.options.index_partition_num_blocks);
+        sst_builder
+    }
+
+    fn build_sst(&self, sst_builder: SSTBuilder, sst_id: usize, path: &Path) -> Result<Sst> {
+        let block_cache = Some(self.block_cache.clone());
+        let mut sst = match self.options.in_memory {
+            true => sst_builder.build_in_memory(sst_id, path, block_cache)?,
+            false => {
+                if let Some(parent) = path.parent() {
+                    create_dir_all(parent)?;
+                }
+                sst_builder.build(sst_id, path, block_cache)?
+            }
+        };
+        if let Some(metadata_cache) = &self.metadata_cache {
+            sst.set_metadata_cache(metadata_cache.clone());
+        }
+        if let Some(table_cache) = &self.table_cache {
+            sst.set_table_cache(table_cache.clone());
+        }
+        if let Some(persistent_cache) = &self.persistent_cache {
+            sst.set_persistent_cache(persistent_cache.clone());
+        }
+        Ok(sst)
+    }
+
+    // where a new SST for level goes. compaction outputs must use this too, so
+    // that they land in the data directory of the level they are written to
+    fn new_sst_file(&self, sst_id: usize, level: usize) -> SstFile {
+        let sst_path = self.options.sst_path_provider.sst_path(sst_id, level);
+        let level_path = self
+            .options
+            .level_paths
+            .get(level)
+            .or(self.options.level_paths.last());
+        SstFile {
+            id: sst_id,
+            // recorded relative to the store directory unless the level's
+            // directory is absolute
+            path: match level_path {
+                Some(level_path) => level_path.join(sst_path),
+                None => sst_path,
+            },
+        }
+    }
+
+    // newest to oldest l0 SSTs
+    fn replay_sst_files(records: Vec<ManifestRecord>) -> VecDeque<SstFile> {
+        let mut l0_sst_files: VecDeque<SstFile> = VecDeque::new();
+        for record in records {
+            match record {
+                ManifestRecord::Flush(sst_file) => l0_sst_files.push_front(sst_file),
+                ManifestRecord::Snapshot(sst_files) => l0_sst_files = sst_files.into(),
+                ManifestRecord::Config(_) => {}
+            }
+        }
+        l0_sst_files
+    }
+
+    // replace the state while holding the write lock, and publish it to the
+    // readers that don't take the lock
+    fn install(&self, rw_guard: &mut RwLockWriteGuard<'_, Arc<StorageStateProtected>>, state: StorageStateProtected) {
+        let state = Arc::new(state);
+        self.published_state.store(state.clone());
+        **rw_guard = state;
+    }
+
     fn get_next_sst_id(&self) -> usize {
         self.sst_counter.fetch_add(1, Ordering::SeqCst)
     }
 
-    pub fn scan(
+    pub(crate) fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<ReadOptionsIterator> {
+        self.scan_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    pub(crate) fn scan_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<ReadOptionsIterator> {
+        // the merge drops the versions the memtables shadow in SSTs, as the
+        // merge across shards does for a store
+        Ok(options.apply(MergeIterator::new(vec![self.scan_entries(lower, upper, options)?])))
+    }
+
+    // the state and the last write a read can see right now, without
+    // waiting for writers. the snapshot holds every write of the shard that
+    // finished before it, and none that was still going into the memtable
+    pub(crate) fn snapshot(&self) -> ShardSnapshot {
+        loop {
+            let state = self.published_state.load();
+            let published_ticket = self.published_ticket.load(Ordering::SeqCst);
+            let published_timestamp = self.published_timestamp.load(Ordering::SeqCst);
+            let sequence = self.last_timestamp.load(Ordering::SeqCst);
+            // a write stamped at or below sequence took its ticket before it,
+            // so with no ticket unpublished every one of them is in place
+            let is_writing = self.next_ticket.load(Ordering::SeqCst) != published_ticket;
+            // the memtables holding those writes were installed before they
+            // were written, so if the state is still the same it has them all.
+            // SSTs only hold published writes, as memtables are frozen under
+            // the write lock
+            if !Arc::ptr_eq(&state, &self.published_state.load()) {
+                continue;
+            }
+            return ShardSnapshot {
+                state_lock: Arc::downgrade(&self.state_lock),
+                state,
+                timestamp: if is_writing { published_timestamp } else { sequence },
+                sequence,
+            };
+        }
+    }
+
+    // sequence number of the newest write, shared by every shard of the store
+    pub(crate) fn latest_sequence(&self) -> u64 {
+        self.last_timestamp.load(Ordering::SeqCst)
+    }
+
+    // the sequence a consumer of the store's updates has caught up to.
+    // writes after it are kept for get_updates, and the ones up to it are
+    // released, so the floor only moves forward
+    pub(crate) fn set_sequence_floor(&self, sequence: u64) -> Result<()> {
+        let latest_sequence = self.latest_sequence();
+        if sequence > latest_sequence {
+            return Err(anyhow!("sequence {} is newer than the latest sequence {}", sequence, latest_sequence));
+        }
+        self.update_log.set_floor(sequence)
+    }
+
+    #[cfg(test)]
+    pub fn get_sequence_floor(&self) -> Option<u64> {
+        self.update_log.get_floor()
+    }
+
+    // send every later write to a key under prefix to sender
+    pub(crate) fn watch_prefix(&self, prefix: &[u8], sender: Sender<WriteEvent>) {
+        self.update_log.watch(Bytes::copy_from_slice(prefix), sender);
+    }
+
+    // every write in (since, until]. until must be at most completed_sequence,
+    // or writes still in progress could be missed
+    pub(crate) fn get_updates(&self, since: u64, until: u64) -> Result<Vec<WriteRecord>> {
+        self.update_log.get_updates(since, until)
+    }
+
+    // every write of the shard at or below this sequence has finished,
+    // including its logging, see snapshot
+    pub(crate) fn completed_sequence(&self) -> u64 {
+        self.snapshot().timestamp
+    }
+
+    // the newest version or tombstone of each key in the memtables, merged
+    // with the newest of each key in the SSTs, so a key in both comes out
+    // twice, newest first. without ignore_tombstones or limit applied
+    pub(crate) fn scan_entries(
         &self,
         lower: Bound<&[u8]>,
         upper: Bound<&[u8]>,
-    ) -> Result<impl StorageIterator<Item = KeyValuePair>> {
-        let ro_snapshot = {
-            let guard = self.state_lock.read().unwrap();
-            Arc::clone(&guard)
+        options: &ReadOptions,
+    ) -> Result<impl StorageIterator<Item = KeyValuePair> + 'static> {
+        let (ro_snapshot, read_timestamp, read_sequence) = match &options.snapshot {
+            Some(snapshot) => {
+                let shard_snapshot = snapshot.for_shard(&self.state_lock)?;
+                (Arc::clone(&shard_snapshot.state), shard_snapshot.timestamp, shard_snapshot.sequence)
+            }
+            None => {
+                let shard_snapshot = self.snapshot();
+                (shard_snapshot.state, shard_snapshot.timestamp, shard_snapshot.sequence)
+            }
         };
+        Self::check_min_sequence(options, read_sequence)?;
+        Self::scan_entries_as_of(ro_snapshot, read_timestamp, lower, upper, options)
+    }
+
+    // the entries of the memtables and SSTs in state, memtable versions up to
+    // read_timestamp
+    fn scan_entries_as_of(
+        ro_snapshot: Arc<StorageStateProtected>,
+        read_timestamp: u64,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<impl StorageIterator<Item = KeyValuePair> + 'static> {
         // build memtable iterator
         let memtables_snapshot = iter::once(ro_snapshot.current_memtable.clone())
             .chain(ro_snapshot.frozen_memtables.clone());
         let memtable_iterators = memtables_snapshot
-            .map(|memtable| memtable.scan(lower, upper))
+            .map(|memtable| memtable.scan_as_of(lower, upper, read_timestamp).keys_only(options.keys_only))
             .collect();
         let memtable_merge_iterator = MergeIterator::new(memtable_iterators);
         // build l0 sst iterator
@@ -183,17 +918,20 @@ impl StorageState {
             let mut sst_iterator: SSTIterator;
             match lower {
                 Bound::Included(lower_key) => {
-                    sst_iterator = SSTIterator::create_and_seek_to_key(
+                    sst_iterator = SSTIterator::create_and_seek_to_key_with_options(
                         sst,
                         TimestampedKey::new(Bytes::copy_from_slice(lower_key)),
+                        options,
                     )?;
                 }
                 Bound::Excluded(lower_key) => {
-                    sst_iterator = SSTIterator::create_and_seek_to_key(
+                    sst_iterator = SSTIterator::create_and_seek_to_key_with_options(
                         sst,
                         TimestampedKey::new(Bytes::copy_from_slice(lower_key)),
+                        options,
                     )?;
-                    if sst_iterator.is_valid()
+                    // every version of the key
+                    while sst_iterator.is_valid()
                         && sst_iterator
                             .peek()
                             .is_some_and(|kv| kv.key.get_key() == lower_key)
@@ -202,7 +940,7 @@ impl StorageState {
                     }
                 }
                 Bound::Unbounded => {
-                    sst_iterator = SSTIterator::create_and_seek_to_first(sst)?;
+                    sst_iterator = SSTIterator::create_and_seek_to_first_with_options(sst, options)?;
                 }
             }
 
@@ -214,115 +952,1075 @@ impl StorageState {
         Ok(two_merge_iterator)
     }
 
-    pub fn flush_next_memtable_to_l0(&self) -> Result<()> {
-        let memtable_to_flush: Arc<MemTable>;
-        {
-            // acquire read lock to get last memtable
-            let ro_snapshot = self.state_lock.read().unwrap();
-            let earliest_frozen_memtable = ro_snapshot.frozen_memtables.back();
-            match earliest_frozen_memtable {
-                Some(memtable) => memtable_to_flush = memtable.clone(),
-                _ => return Ok(()),
+    // live keys in range, without tombstones or shadowed versions
+    #[cfg(test)]
+    pub fn scan_keys(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<KeysOnlyIterator<impl StorageIterator<Item = KeyValuePair>>> {
+        let options = ReadOptions {
+            keys_only: true,
+            ..Default::default()
+        };
+        Ok(KeysOnlyIterator::new(self.scan_with_options(lower, upper, &options)?))
+    }
+
+    // number of live keys in range. an SST block whose keys no memtable or
+    // other SST has is counted from its stats, see Sst::block_key_counts,
+    // and only the keys between such blocks are merged
+    pub(crate) fn count(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<usize> {
+        let snapshot = self.snapshot();
+        let state = &snapshot.state;
+        // inclusive key spans with the number of live keys in each
+        let mut spans: Vec<(Bytes, Bytes, u64)> = Vec::new();
+        for (position, sst) in state.ssts.iter().enumerate() {
+            let blocks = sst.block_key_counts(lower, upper)?;
+            // nothing lies between the blocks of a run in this SST, so if no
+            // other source has keys in the run it is taken whole
+            for run in blocks.chunk_by(|block, next| block.block_index + 1 == next.block_index) {
+                let (first_key, last_key) = (&run[0].first_key, &run[run.len() - 1].last_key);
+                if Self::is_only_source(state, position, first_key, last_key)? {
+                    let num_live_keys = run.iter().map(|block| block.num_live_keys).sum();
+                    spans.push((first_key.clone(), last_key.clone(), num_live_keys));
+                    continue;
+                }
+                for block in run {
+                    if Self::is_only_source(state, position, &block.first_key, &block.last_key)? {
+                        spans.push((block.first_key.clone(), block.last_key.clone(), block.num_live_keys));
+                    }
+                }
             }
         }
-        // add to SST builder outside of lock
-        let mut sst_builder: SSTBuilder = SSTBuilder::new(self.options.block_max_size_bytes);
-        memtable_to_flush.flush(&mut sst_builder)?;
-        {
-            // acquire write
-            let mut rw_guard = self.state_lock.write().unwrap();
-            let mut rw_snapshot = rw_guard.as_ref().clone();
-            // build the SST
-            let sst_id = memtable_to_flush.get_id();
-            let sst = sst_builder.build(
-                sst_id,
-                self.get_sst_path(sst_id),
-                Some(self.block_cache.clone()),
-            )?;
-            // add to L0 and remove from memtables
-            rw_snapshot.l0_sst_ids.push_front(sst.get_id());
-            rw_snapshot.ssts.push_front(Arc::new(sst));
-            rw_snapshot.frozen_memtables.pop_back();
-            *rw_guard = Arc::new(rw_snapshot);
+        // spans of different SSTs hold none of each other's keys, so they
+        // don't overlap
+        spans.sort_unstable();
+        let mut count = spans.iter().map(|(_, _, num_live_keys)| *num_live_keys as usize).sum();
+        let mut gap_lower = lower;
+        for (first_key, last_key, _) in spans.iter() {
+            count += Self::count_keys_as_of(&snapshot, gap_lower, Bound::Excluded(first_key))?;
+            gap_lower = Bound::Excluded(last_key);
         }
-        Ok(())
+        count += Self::count_keys_as_of(&snapshot, gap_lower, upper)?;
+        Ok(count)
     }
 
-    pub fn flush_all_memtables(&self) -> Result<()> {
-        self.freeze_memtable()?;
-        loop {
-            let num_memtables = {
-                let ro_snapshot = self.state_lock.read().unwrap();
-                ro_snapshot.frozen_memtables.len()
-            };
-            if num_memtables == 0 { break; }
-            self.flush_next_memtable_to_l0()?;
+    // whether the SST at sst_position is the only memtable or SST with keys
+    // in first_key..=last_key. memtable versions newer than the snapshot
+    // count too, which only means merging more than needed
+    fn is_only_source(
+        state: &StorageStateProtected,
+        sst_position: usize,
+        first_key: &[u8],
+        last_key: &[u8],
+    ) -> Result<bool> {
+        let (lower, upper) = (Bound::Included(first_key), Bound::Included(last_key));
+        let memtables = iter::once(&state.current_memtable).chain(state.frozen_memtables.iter());
+        for memtable in memtables {
+            if memtable.scan(lower, upper).next().is_some() {
+                return Ok(false);
+            }
         }
-        Ok(())
+        for (position, sst) in state.ssts.iter().enumerate() {
+            if position == sst_position || !range_overlap(lower, upper, sst.get_first_key(), sst.get_last_key()) {
+                continue;
+            }
+            if !sst.blocks_in_range(lower, upper)?.is_empty() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
     }
 
-    pub fn trigger_flush(&self) -> Result<()> {
-        let should_trigger_flush = {
-            let ro_snapshot = self.state_lock.read().unwrap();
-            ro_snapshot.frozen_memtables.len() >= self.options.num_memtables_limit
+    // live keys in range as of the snapshot, merged from every entry
+    fn count_keys_as_of(snapshot: &ShardSnapshot, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<usize> {
+        let options = ReadOptions {
+            keys_only: true,
+            ..Default::default()
         };
-        if should_trigger_flush {
-            self.flush_next_memtable_to_l0()
-        } else {
-            Ok(())
-        }
+        let entries = Self::scan_entries_as_of(snapshot.state.clone(), snapshot.timestamp, lower, upper, &options)?;
+        let mut keys = KeysOnlyIterator::new(options.apply(MergeIterator::new(vec![entries])));
+        let count = keys.by_ref().count();
+        keys.check_error()?;
+        Ok(count)
     }
 
-    pub fn spawn_flush_thread(
-        self: &Arc<Self>,
-        end_flush: crossbeam_channel::Receiver<()>,
-    ) -> Result<Option<thread::JoinHandle<()>>> {
-        let this = self.clone();
-        let handle = thread::spawn(move || {
-            let ticker = crossbeam_channel::tick(Duration::from_millis(50));
-            loop {
-                crossbeam_channel::select! {
-                    recv(ticker) -> _ => if let Err(e) = this.trigger_flush() {
-                        eprintln!("error during background flush: {}", e);
-                    },
-                    recv(end_flush) -> _ => return
-                }
-            }
-        });
-        Ok(Some(handle))
+    // sum of the live values in range, each read as a little-endian u64
+    pub(crate) fn sum_values_as_u64(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        let mut sum: u64 = 0;
+        let mut latest_iterator = LatestIterator::new(self.scan(lower, upper)?);
+        for kv in latest_iterator.by_ref() {
+            let value: [u8; 8] = kv.value.as_ref().try_into().map_err(|_| {
+                anyhow!(
+                    "value for key {:?} is {} bytes, expected an 8-byte u64",
+                    kv.key.get_key(),
+                    kv.value.len()
+                )
+            })?;
+            sum = sum
+                .checked_add(u64::from_le_bytes(value))
+                .ok_or_else(|| anyhow!("sum of values overflows u64"))?;
+        }
+        latest_iterator.check_error()?;
+        Ok(sum)
     }
 
-    fn get_sst_path(&self, sst_id: usize) -> PathBuf {
-        self.options.path.join(format!("{:05}.sst", sst_id))
+    // upper bound on the number of entries in range, counting every version and
+    // tombstone. sst entries come from block stats, so no data blocks are read
+    pub(crate) fn estimate_count(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        let ro_snapshot = self.published_state.load();
+        let mut estimate = iter::once(&ro_snapshot.current_memtable)
+            .chain(ro_snapshot.frozen_memtables.iter())
+            .map(|memtable| memtable.scan(lower, upper).count() as u64)
+            .sum();
+        for sst in ro_snapshot.ssts.iter() {
+            estimate += sst.estimate_num_entries(lower, upper)?;
+        }
+        Ok(estimate)
     }
 
-    #[cfg(test)]
-    fn get_snapshot(&self) -> Arc<StorageStateProtected> {
-        let ro_snapshot = self.state_lock.read().unwrap();
-
-        let res = ro_snapshot.as_ref().clone();
-        Arc::new(res)
+    // see PrefixStats. memtable entries are counted exactly
+    pub(crate) fn prefix_stats(&self, prefix: &[u8]) -> Result<PrefixStats> {
+        let upper = prefix_upper_bound(prefix);
+        let upper = upper.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        let ro_snapshot = self.published_state.load();
+        let mut stats = PrefixStats::default();
+        let memtables = iter::once(&ro_snapshot.current_memtable).chain(ro_snapshot.frozen_memtables.iter());
+        for memtable in memtables {
+            for kv in memtable.scan(Bound::Included(prefix), upper) {
+                stats.num_entries += 1;
+                stats.size_bytes += (kv.key.get_key().len() + kv.value.len()) as u64;
+            }
+        }
+        for sst in ro_snapshot.ssts.iter() {
+            stats = stats + sst.prefix_stats(prefix)?;
+        }
+        Ok(stats)
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use std::ops::Bound;
 
-    use bytes::Bytes;
-    use tempfile::tempdir;
+    // the oldest frozen memtable, and with max_memtables_per_flush above 1 the
+    // ones after it for as long as they fit in one SST together. oldest first
+    fn pick_memtables_to_flush(&self, frozen_memtables: &VecDeque<Arc<MemTable>>) -> Vec<Arc<MemTable>> {
+        // an SST with writes from both before and after a bulk load started
+        // couldn't go either side of it, see BulkLoader
+        let max_memtables = match self.bulk_loads_in_progress.load(Ordering::SeqCst) {
+            0 => self.options.max_memtables_per_flush,
+            _ => 1,
+        };
+        let mut picked: Vec<Arc<MemTable>> = Vec::new();
+        let mut size_bytes = 0;
+        for memtable in frozen_memtables.iter().rev() {
+            size_bytes += memtable.get_size_bytes();
+            let is_full = picked.len() >= max_memtables
+                || size_bytes > self.options.sst_max_size_bytes;
+            if !picked.is_empty() && is_full {
+                break;
+            }
+            picked.push(memtable.clone());
+        }
+        picked
+    }
 
-    use crate::state::{storage_state_options::StorageStateOptions, StorageState};
+    // a memtable can grow past sst_max_size_bytes under a memtable memory
+    // budget, or when a single write is larger than that. its SST is then
+    // split into parts of about equal size, each within the limit where the
+    // values allow it. versions of a key always stay in one part.
+    // num_entries bounds the number of kvs, which sizes the bloom filter of
+    // an SST that isn't split
+    fn split_into_sst_builders(
+        &self,
+        kvs: impl Iterator<Item = KeyValuePair>,
+        size_bytes: usize,
+        num_entries: usize,
+    ) -> Result<Vec<SSTBuilder>> {
+        let num_parts = size_bytes.div_ceil(self.options.sst_max_size_bytes.max(1)).max(1);
+        let part_size_bytes = size_bytes.div_ceil(num_parts);
+        let mut sst_builders = Vec::new();
+        let mut sst_builder = self.new_sst_builder();
+        if num_parts == 1 {
+            sst_builder.set_expected_num_keys(num_entries);
+        }
+        let mut part_bytes = 0;
+        let mut last_key: Option<Bytes> = None;
+        for kv in kvs {
+            let key = kv.key.get_key();
+            if part_bytes >= part_size_bytes && last_key.as_ref() != Some(&key) {
+                sst_builders.push(std::mem::replace(&mut sst_builder, self.new_sst_builder()));
+                part_bytes = 0;
+            }
+            part_bytes += key.len() + kv.value.len();
+            last_key = Some(key);
+            sst_builder.add(&kv)?;
+        }
+        sst_builders.push(sst_builder);
+        Ok(sst_builders)
+    }
 
-    #[test]
-    fn test_storage_state_get_put() {
-        let dir = tempdir().unwrap();
+    pub(crate) fn flush_next_memtable_to_l0(&self) -> Result<()> {
+        let _flush_guard = self.flush_lock.lock().unwrap();
+        // oldest first
+        let memtables_to_flush = {
+            let ro_snapshot = self.state_lock.read().unwrap();
+            self.pick_memtables_to_flush(&ro_snapshot.frozen_memtables)
+        };
+        let Some(newest_memtable) = memtables_to_flush.last() else {
+            return Ok(());
+        };
+        let flushed_size_bytes: usize = memtables_to_flush.iter().map(|memtable| memtable.get_size_bytes()).sum();
+        let started = Instant::now();
+        // the SST takes the place of the memtables, so it has to be newer than
+        // all of them and older than the memtables left
+        let sst_id = newest_memtable.get_id();
+        let sst_file = self.new_sst_file(sst_id, 0);
+        let mut flush_info = FlushJobInfo {
+            sst_id,
+            path: self.options.path.join(&sst_file.path),
+            file_size: None,
+        };
+        for listener in self.options.listeners.iter() {
+            listener.on_flush_begin(&flush_info);
+        }
+        // the SST is built in memory before it is written out
+        let _buffer_reservation = self
+            .options
+            .memory_limiter
+            .as_ref()
+            .map(|limiter| limiter.reserve_buffer(flushed_size_bytes));
+        let kvs: Box<dyn Iterator<Item = KeyValuePair>> = match memtables_to_flush.as_slice() {
+            [memtable] => Box::new(memtable.scan(Bound::Unbounded, Bound::Unbounded)),
+            _ => {
+                // newest first, so the versions of each key come out newest
+                // first. flushes keep every version, like a single memtable's
+                // flush does, and leave dropping them to merges
+                let memtable_iterators = memtables_to_flush
+                    .iter()
+                    .rev()
+                    .map(|memtable| memtable.scan(Bound::Unbounded, Bound::Unbounded))
+                    .collect();
+                Box::new(MergeIterator::new_with_all_versions(memtable_iterators))
+            }
+        };
+        // add to SST builders outside of lock
+        let num_entries = memtables_to_flush.iter().map(|memtable| memtable.get_num_entries()).sum();
+        let sst_builders = self.split_into_sst_builders(kvs, flushed_size_bytes, num_entries)?;
+        // the builders are in key order
+        let key_range = sst_builders.first().zip(sst_builders.last()).and_then(|(first, last)| {
+            first.get_key_range().zip(last.get_key_range()).map(|((first_key, _), (_, last_key))| (first_key, last_key))
+        });
+        let _range_guard = key_range.map(|(first_key, last_key)| self.range_locks.lock(first_key, last_key));
+        {
+            // acquire write
+            let mut rw_guard = self.state_lock.write().unwrap();
+            let mut rw_snapshot = rw_guard.as_ref().clone();
+            // build the SSTs. the first one takes the place of the memtables,
+            // the others only hold keys after it and take fresh ids. those
+            // are newer than the ids of memtables still live, which is fine
+            // as l0 is ordered by position, and by sequence on repair
+            let mut file_size = 0;
+            let num_ssts = sst_builders.len();
+            for (index, sst_builder) in sst_builders.into_iter().enumerate() {
+                let (sst_id, sst_file) = match index {
+                    0 => (sst_id, sst_file.clone()),
+                    _ => {
+                        let sst_id = self.get_next_sst_id();
+                        (sst_id, self.new_sst_file(sst_id, 0))
+                    }
+                };
+                let sst = self.build_sst(sst_builder, sst_id, &self.options.path.join(&sst_file.path))?;
+                file_size += sst.get_file_size();
+                // add to L0. the SSTs don't overlap, so their order among
+                // themselves doesn't matter
+                rw_snapshot.l0_sst_files.push_front(sst_file);
+                rw_snapshot.ssts.push_front(Arc::new(sst));
+            }
+            flush_info.file_size = Some(file_size);
+            failpoint::check(failpoint::FLUSH_SST_WRITTEN)?;
+            // record the flush once the SSTs are durable, all at once when
+            // there are several
+            if let Some(manifest) = &self.manifest {
+                match num_ssts {
+                    1 => manifest.add_record(&ManifestRecord::Flush(sst_file))?,
+                    _ => self.record_snapshot(&rw_snapshot.l0_sst_files)?,
+                }
+            }
+            failpoint::check(failpoint::FLUSH_MANIFEST_UPDATED)?;
+            // remove from memtables
+            let num_frozen_memtables = rw_snapshot.frozen_memtables.len();
+            rw_snapshot.frozen_memtables.truncate(num_frozen_memtables - memtables_to_flush.len());
+            self.memory_accountant.release(flushed_size_bytes);
+            if let Some(limiter) = &self.options.memory_limiter {
+                limiter.release_memtable(flushed_size_bytes);
+            }
+            if let Some(manifest) = self.manifest.as_ref().filter(|manifest| manifest.should_rotate()) {
+                let snapshot = ManifestRecord::Snapshot(rw_snapshot.l0_sst_files.clone().into());
+                manifest.rotate(&snapshot)?;
+            }
+            self.install(&mut rw_guard, rw_snapshot);
+        }
+        self.flush_latency.record(started.elapsed());
+        for listener in self.options.listeners.iter() {
+            listener.on_flush_completed(&flush_info);
+        }
+        Ok(())
+    }
+
+    // load pre-sorted key-value pairs without going through the memtable, see
+    // BulkLoader. returns the ids of the new SSTs
+    pub(crate) fn bulk_load(&self, kvs: impl IntoIterator<Item = KeyValuePair>) -> Result<Vec<usize>> {
+        let mut loader = self.bulk_loader()?;
+        for kv in kvs {
+            loader.add(kv)?;
+        }
+        loader.finish()
+    }
+
+    // writes a batch of strictly increasing keys. the batch goes into the
+    // active memtable in order, see MemTable::write_sorted_with_timestamp. a
+    // batch larger than all the memtables that may be frozen at once would
+    // stall on flushes, so it is bulk loaded into new SSTs instead
+    pub(crate) fn append_sorted(&self, batch: &[KeyValuePair]) -> Result<()> {
+        check_sorted(batch)?;
+        let batch_size_bytes: usize = batch.iter().map(|kv| kv.key.get_key().len() + kv.value.len()).sum();
+        if batch_size_bytes > self.options.sst_max_size_bytes * self.options.num_memtables_limit.max(1) {
+            self.bulk_load(batch.iter().cloned())?;
+            return Ok(());
+        }
+        self.maybe_wait_for_l0()?;
+        self.maybe_freeze_memtable(batch_size_bytes)?;
+        // like apply_batch, under the read lock and at one timestamp
+        let ro_snapshot = self.state_lock.read().unwrap();
+        let stamp = self.stamp();
+        let timestamp = stamp.timestamp;
+        ro_snapshot.current_memtable.write_sorted_with_timestamp(batch, timestamp)?;
+        self.allocate_memtable_bytes(batch_size_bytes);
+        self.update_log
+            .append(timestamp, || batch.iter().cloned().map(Entry::from).collect());
+        Ok(())
+    }
+
+    pub(crate) fn bulk_loader(&self) -> Result<BulkLoader<'_>> {
+        BulkLoader::new(self)
+    }
+
+    // memtable and sst counts. scans are tracked by the store, see LsmStats
+    pub(crate) fn stats(&self) -> LsmStats {
+        let (num_range_lock_waits, range_lock_wait_duration) = self.range_locks.contention();
+        let ro_snapshot = self.published_state.load();
+        let memtables = iter::once(&ro_snapshot.current_memtable).chain(ro_snapshot.frozen_memtables.iter());
+        LsmStats {
+            num_memtables: 1 + ro_snapshot.frozen_memtables.len(),
+            memtable_bytes: memtables.map(|memtable| memtable.get_size_bytes()).sum(),
+            num_l0_ssts: ro_snapshot.ssts.len(),
+            num_write_stalls: self.num_write_stalls.load(Ordering::Relaxed),
+            write_stall_duration: Duration::from_nanos(self.write_stall_nanos.load(Ordering::Relaxed)),
+            num_range_lock_waits,
+            range_lock_wait_duration,
+            ..Default::default()
+        }
+    }
+
+    // reads the metadata of SSTs that don't keep it in memory, and of legacy
+    // SSTs without block stats the data blocks
+    pub(crate) fn describe(&self) -> Result<ShardDescription> {
+        let ro_snapshot = self.published_state.load();
+        let memtables = iter::once(&ro_snapshot.current_memtable)
+            .chain(ro_snapshot.frozen_memtables.iter())
+            .map(|memtable| MemTableDescription {
+                id: memtable.get_id(),
+                frozen: !Arc::ptr_eq(memtable, &ro_snapshot.current_memtable),
+                size_bytes: memtable.get_size_bytes(),
+            })
+            .collect();
+        let mut ssts = Vec::new();
+        for sst in ro_snapshot.ssts.iter() {
+            ssts.push(SstDescription {
+                id: sst.get_id(),
+                first_key: sst.get_first_key().get_key(),
+                last_key: sst.get_last_key().get_key(),
+                file_size_bytes: sst.get_file_size(),
+                num_blocks: sst.metadata()?.num_blocks(),
+                num_entries: sst.estimate_num_entries(Bound::Unbounded, Bound::Unbounded)?,
+            });
+        }
+        let levels = match ssts.is_empty() {
+            true => Vec::new(),
+            false => vec![LevelDescription { level: 0, ssts }],
+        };
+        Ok(ShardDescription { memtables, levels })
+    }
+
+    // like get, recording each memtable and SST looked at and why, see
+    // GetExplanation. blocks read aren't added to the block cache, so
+    // explaining a get doesn't change what the next one finds cached
+    pub(crate) fn explain_get(&self, key: &[u8]) -> Result<GetExplanation> {
+        let outcome = |value: &Option<Bytes>| match value {
+            Some(_) => ProbeOutcome::Found,
+            None => ProbeOutcome::Deleted,
+        };
+        let ro_snapshot = self.published_state.load();
+        let mut explanation = GetExplanation {
+            key: Bytes::copy_from_slice(key),
+            shard: 0,
+            num_shards: 1,
+            steps: Vec::new(),
+            value: None,
+        };
+        let memtables = iter::once(&ro_snapshot.current_memtable).chain(ro_snapshot.frozen_memtables.iter());
+        for memtable in memtables {
+            let found = memtable.get(key);
+            explanation.steps.push(GetStep::Memtable {
+                id: memtable.get_id(),
+                frozen: !Arc::ptr_eq(memtable, &ro_snapshot.current_memtable),
+                outcome: found.as_ref().map_or(ProbeOutcome::NotFound, outcome),
+            });
+            if let Some(value) = found {
+                explanation.value = value;
+                return Ok(explanation);
+            }
+        }
+        for sst in ro_snapshot.ssts.iter() {
+            let (first_key, last_key) = (sst.get_first_key().get_key(), sst.get_last_key().get_key());
+            if key < &first_key[..] || &last_key[..] < key {
+                explanation.steps.push(GetStep::SstOutsideKeyRange {
+                    id: sst.get_id(),
+                    first_key,
+                    last_key,
+                });
+                continue;
+            }
+            if !sst.maybe_contains_key(key)? {
+                explanation.steps.push(GetStep::SstFilteredByBloom { id: sst.get_id() });
+                continue;
+            }
+            let block_index = sst.get_block_index_for_key(&TimestampedKey::new(Bytes::copy_from_slice(key)))?;
+            let cached = sst.is_block_cached(block_index);
+            let options = ReadOptions {
+                fill_cache: false,
+                ..Default::default()
+            };
+            let found = Self::get_from_sst(sst, key, &options)?;
+            explanation.steps.push(GetStep::SstBlock {
+                id: sst.get_id(),
+                block_index,
+                cached,
+                outcome: found.as_ref().map_or(ProbeOutcome::NotFound, outcome),
+            });
+            if let Some(value) = found {
+                explanation.value = value;
+                return Ok(explanation);
+            }
+        }
+        Ok(explanation)
+    }
+
+    // what a scan of the range would read now, see ScanExplanation. reads the
+    // metadata of SSTs that don't keep it in memory, but no data blocks
+    pub(crate) fn explain_scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<ShardScanPlan> {
+        let ro_snapshot = self.published_state.load();
+        let mut plan = ShardScanPlan {
+            num_memtables: 1 + ro_snapshot.frozen_memtables.len(),
+            ..Default::default()
+        };
+        for sst in ro_snapshot.ssts.iter() {
+            if !range_overlap(lower, upper, sst.get_first_key(), sst.get_last_key()) {
+                plan.num_ssts_outside_range += 1;
+                continue;
+            }
+            let blocks = sst.blocks_in_range(lower, upper)?;
+            plan.ssts.push(SstScanPlan {
+                id: sst.get_id(),
+                num_blocks_read: blocks.len(),
+                num_blocks: sst.metadata()?.num_blocks(),
+                num_cached_blocks: blocks.iter().filter(|block_index| sst.is_block_cached(**block_index)).count(),
+            });
+        }
+        Ok(plan)
+    }
+
+    pub(crate) fn flush_latency(&self) -> &LatencyHistogram {
+        &self.flush_latency
+    }
+
+    pub(crate) fn compaction_latency(&self) -> &LatencyHistogram {
+        &self.compaction_latency
+    }
+
+    // walks the block and metadata caches, so only call this off the hot path
+    pub(crate) fn approximate_memory_usage(&self) -> MemoryUsage {
+        let (mut usage, ssts) = {
+            let ro_snapshot = self.published_state.load();
+            let mut usage = MemoryUsage {
+                active_memtable_bytes: ro_snapshot.current_memtable.get_size_bytes(),
+                frozen_memtable_bytes: ro_snapshot
+                    .frozen_memtables
+                    .iter()
+                    .map(|memtable| memtable.get_size_bytes())
+                    .sum(),
+                ..Default::default()
+            };
+            self.block_cache.run_pending_tasks();
+            usage.block_cache_bytes = self.block_cache.iter().map(|(_, block)| block.size_bytes()).sum();
+            (usage, ro_snapshot.ssts.clone())
+        };
+        let resident_metadata = ssts.iter().filter_map(|sst| sst.resident_metadata().cloned());
+        let cached_metadata = self
+            .metadata_cache
+            .iter()
+            .flat_map(|metadata_cache| metadata_cache.iter().map(|(_, metadata)| metadata));
+        for metadata in resident_metadata.chain(cached_metadata) {
+            usage.bloom_filter_bytes += metadata.bloom_filter_size_bytes();
+            usage.sst_metadata_bytes += metadata.index_size_bytes();
+        }
+        usage
+    }
+
+    pub(crate) fn flush_all_memtables(&self) -> Result<()> {
+        let current_memtable_is_empty = {
+            let ro_snapshot = self.state_lock.read().unwrap();
+            ro_snapshot.current_memtable.is_empty()
+        };
+        // an empty memtable would produce an SST without any keys
+        if !current_memtable_is_empty {
+            self.freeze_memtable()?;
+        }
+        loop {
+            let num_memtables = {
+                let ro_snapshot = self.state_lock.read().unwrap();
+                ro_snapshot.frozen_memtables.len()
+            };
+            if num_memtables == 0 { break; }
+            self.flush_next_memtable_to_l0()?;
+        }
+        Ok(())
+    }
+
+    // returns once the memtables frozen before the call are in l0, flushing
+    // them on this thread if the background hasn't got to them yet. the
+    // active memtable is left alone, unlike flush_all_memtables
+    pub(crate) fn wait_for_flush(&self) -> Result<()> {
+        let frozen_ids: Vec<usize> = {
+            let ro_snapshot = self.state_lock.read().unwrap();
+            ro_snapshot.frozen_memtables.iter().map(|memtable| memtable.get_id()).collect()
+        };
+        // memtables are flushed oldest first, so the newest one goes last
+        let Some(newest_id) = frozen_ids.first().copied() else {
+            return Ok(());
+        };
+        loop {
+            let is_frozen = {
+                let ro_snapshot = self.state_lock.read().unwrap();
+                ro_snapshot
+                    .frozen_memtables
+                    .iter()
+                    .any(|memtable| memtable.get_id() == newest_id)
+            };
+            if !is_frozen {
+                return Ok(());
+            }
+            self.flush_next_memtable_to_l0()?;
+        }
+    }
+
+    pub(crate) fn trigger_flush(&self) -> Result<()> {
+        let over_memory_limit = self
+            .options
+            .memory_limiter
+            .as_ref()
+            .is_some_and(|limiter| limiter.enforce());
+        let (num_frozen_memtables, current_memtable_is_empty) = {
+            let ro_snapshot = self.state_lock.read().unwrap();
+            (ro_snapshot.frozen_memtables.len(), ro_snapshot.current_memtable.is_empty())
+        };
+        if over_memory_limit && num_frozen_memtables == 0 && !current_memtable_is_empty {
+            // nothing frozen to flush yet, so free the active memtable's memory
+            self.freeze_memtable()?;
+            return self.flush_next_memtable_to_l0();
+        }
+        if over_memory_limit || num_frozen_memtables >= self.options.num_memtables_limit {
+            self.flush_next_memtable_to_l0()
+        } else {
+            Ok(())
+        }
+    }
+
+    pub(crate) fn schedule_flush(self: &Arc<Self>, scheduler: &BackgroundScheduler) -> Result<()> {
+        let interval = match self.options.flush_trigger {
+            FlushTrigger::Tick => FLUSH_TICK_INTERVAL,
+            FlushTrigger::Event => FLUSH_FALLBACK_INTERVAL,
+        };
+        let this = self.clone();
+        let handle = scheduler.submit_periodic(TaskPriority::Flush, interval, move || this.trigger_flush())?;
+        // nothing ticks without background threads, so the writer that
+        // freezes the last memtable allowed flushes the oldest one
+        if self.options.flush_trigger == FlushTrigger::Event || scheduler.is_inline() {
+            // a store is only scheduled once
+            let _ = self.flush_task.set(handle);
+        }
+        Ok(())
+    }
+
+    // one round of background compaction, see CompactionStyle
+    pub(crate) fn trigger_compaction(&self) -> Result<()> {
+        let _compaction_guard = self.compaction_lock.lock().unwrap();
+        if self.compaction_cancelled.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        let ssts = {
+            let ro_snapshot = self.state_lock.read().unwrap();
+            ro_snapshot.ssts.clone()
+        };
+        let time_window = match self.options.compaction_style {
+            CompactionStyle::None => return Ok(()),
+            CompactionStyle::Fifo => {
+                let Some(max_db_size_bytes) = self.options.max_db_size_bytes else {
+                    return Ok(());
+                };
+                let sst_sizes: Vec<u64> =
+                    ssts.iter().map(|sst| sst.get_file_size() + sst.get_blob_file_size()).collect();
+                let num_evicted = plan_fifo_eviction(&sst_sizes, max_db_size_bytes);
+                let evicted_ids: Vec<usize> = ssts
+                    .iter()
+                    .rev()
+                    .take(num_evicted)
+                    .map(|sst| sst.get_id())
+                    .collect();
+                return self.delete_ssts(&evicted_ids);
+            }
+            CompactionStyle::TimeWindow(time_window) => time_window,
+        };
+        let creation_times = ssts
+            .iter()
+            .map(|sst| sst.get_creation_time())
+            .collect::<Result<Vec<SystemTime>>>()?;
+        let plan = time_window.plan(&creation_times, system_time_now());
+        let expired_ids: Vec<usize> = plan.expired.iter().map(|position| ssts[*position].get_id()).collect();
+        self.delete_ssts(&expired_ids)?;
+        if let Some(range) = plan.merge {
+            let is_bottom_level = range.end == ssts.len();
+            self.merge_ssts(ssts.range(range).cloned().collect(), is_bottom_level)?;
+        }
+        Ok(())
+    }
+
+    // runs compaction rounds on this thread until one leaves l0 unchanged, so
+    // that nothing the compaction style would do right now is left pending
+    pub(crate) fn wait_for_compaction(&self) -> Result<()> {
+        if matches!(self.options.compaction_style, CompactionStyle::None) {
+            return Ok(());
+        }
+        loop {
+            let l0_sst_ids = || self.state_lock.read().unwrap().l0_sst_ids();
+            let l0_sst_ids_before = l0_sst_ids();
+            self.trigger_compaction()?;
+            if l0_sst_ids() == l0_sst_ids_before {
+                return Ok(());
+            }
+        }
+    }
+
+    // stop compacting for good, e.g. before shutting down the scheduler so
+    // that it doesn't wait on a long merge. returns without waiting for the
+    // merge in progress, whose output is thrown away
+    pub(crate) fn cancel_compaction(&self) {
+        self.compaction_cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub(crate) fn schedule_compaction(self: &Arc<Self>, scheduler: &BackgroundScheduler) -> Result<()> {
+        if matches!(self.options.compaction_style, CompactionStyle::None) {
+            return Ok(());
+        }
+        let this = self.clone();
+        scheduler.submit_periodic(TaskPriority::Compaction, COMPACTION_INTERVAL, move || {
+            this.trigger_compaction()
+        })?;
+        Ok(())
+    }
+
+    // merge a run of adjacent l0 SSTs, newest to oldest, into one SST that
+    // takes their place. the output gets a new id, so l0 is no longer ordered
+    // by id once it is installed, but its sequence range covers its inputs'
+    // and nothing else's, which is the order repair goes by. the output is
+    // thrown away if a bulk load is in progress, see BulkLoader
+    fn merge_ssts(&self, inputs: Vec<Arc<Sst>>, is_bottom_level: bool) -> Result<()> {
+        let res = self.merge_ssts_with_progress(inputs, is_bottom_level);
+        *self.merge_progress.lock().unwrap() = None;
+        res
+    }
+
+    // the merge running now, see MergeProgress
+    pub(crate) fn merge_progress(&self) -> Option<MergeProgress> {
+        *self.merge_progress.lock().unwrap()
+    }
+
+    // frozen memtables waiting to be flushed
+    pub(crate) fn num_pending_flushes(&self) -> usize {
+        self.published_state.load().frozen_memtables.len()
+    }
+
+    fn merge_ssts_with_progress(&self, inputs: Vec<Arc<Sst>>, is_bottom_level: bool) -> Result<()> {
+        if self.bulk_loads_in_progress.load(Ordering::SeqCst) > 0 {
+            return Ok(());
+        }
+        let started = Instant::now();
+        // flushes and bulk loads of keys the inputs cover wait for the merge
+        let first_key = inputs.iter().map(|sst| sst.get_first_key().get_key()).min();
+        let last_key = inputs.iter().map(|sst| sst.get_last_key().get_key()).max();
+        let _range_guard = first_key.zip(last_key).map(|(first_key, last_key)| self.range_locks.lock(first_key, last_key));
+        let now = system_time_now();
+        let retention = TombstoneRetention::new(self.options.tombstone_ttl);
+        let mut drop_tombstones = true;
+        for sst in inputs.iter() {
+            drop_tombstones &= retention.can_drop_tombstones(sst, is_bottom_level, now)?;
+        }
+        // the output keeps the creation time of its newest input, so that it
+        // stays in the same time window
+        let mut created = SystemTime::UNIX_EPOCH;
+        for sst in inputs.iter() {
+            created = created.max(sst.get_creation_time()?);
+        }
+        let _buffer_reservation = self
+            .options
+            .memory_limiter
+            .as_ref()
+            .map(|limiter| limiter.reserve_buffer(inputs.iter().map(|sst| sst.get_file_size() as usize).sum()));
+
+        let mut sst_builder = self.new_sst_builder();
+        // the merge drops shadowed versions and maybe tombstones, so the
+        // inputs' entries bound the output's
+        let mut num_entries = 0;
+        for sst in inputs.iter() {
+            num_entries += sst.estimate_num_entries(Bound::Unbounded, Bound::Unbounded)?;
+        }
+        sst_builder.set_expected_num_keys(num_entries as usize);
+        let mut progress = MergeProgress {
+            num_inputs: inputs.len(),
+            entries_merged: 0,
+            num_entries,
+        };
+        *self.merge_progress.lock().unwrap() = Some(progress);
+        // the entries come back at timestamp 0, so take the sequence range
+        // from the inputs
+        let sequence_ranges = inputs.iter().map(|sst| sst.get_sequence_range());
+        if let (Some(min_sequence), Some(max_sequence)) = (
+            sequence_ranges.clone().map(|(min, _)| min).min(),
+            sequence_ranges.map(|(_, max)| max).max(),
+        ) {
+            sst_builder.set_sequence_range(min_sequence, max_sequence);
+        }
+        let mut is_empty = true;
+        let sst_iterators = inputs
+            .iter()
+            .map(|sst| SSTIterator::create_and_seek_to_first(sst.clone()))
+            .collect::<Result<Vec<SSTIterator>>>()?;
+        let versions_to_keep = self.options.version_retention.versions_to_keep(created, now);
+        let mut merge_iterator = match versions_to_keep {
+            1 => MergeIterator::new(sst_iterators),
+            _ => MergeIterator::new_with_all_versions(sst_iterators),
+        };
+        let mut num_blocks = 0;
+        let mut current_key: Option<Bytes> = None;
+        let (mut num_versions, mut skip_key) = (0, false);
+        // versions of each key come out of the merge newest first
+        for kv in merge_iterator.by_ref() {
+            progress.entries_merged += 1;
+            let key = kv.key.get_key();
+            if current_key.as_ref() != Some(&key) {
+                num_versions = 0;
+                // the versions a dropped tombstone shadows go with it, or
+                // they would come back
+                skip_key = drop_tombstones && kv.is_tombstone();
+                current_key = Some(key.clone());
+            }
+            if skip_key || num_versions == versions_to_keep {
+                continue;
+            }
+            // entries come back from SSTs at timestamp 0, but the builder
+            // wants the versions of a key in decreasing timestamp order
+            let value = (!kv.is_tombstone()).then_some(&kv.value[..]);
+            sst_builder.add_entry(&key, u64::MAX - num_versions as u64, value)?;
+            num_versions += 1;
+            is_empty = false;
+            if sst_builder.num_blocks() > num_blocks {
+                num_blocks = sst_builder.num_blocks();
+                *self.merge_progress.lock().unwrap() = Some(progress);
+                if self.compaction_cancelled.load(Ordering::SeqCst) {
+                    return Ok(());
+                }
+            }
+        }
+        merge_iterator.check_error()?;
+        if self.compaction_cancelled.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+
+        // every key may have been a dropped tombstone
+        let output = match is_empty {
+            true => None,
+            false => {
+                let sst_id = self.get_next_sst_id();
+                let sst_file = self.new_sst_file(sst_id, 0);
+                let sst = self.build_sst(sst_builder, sst_id, &self.options.path.join(&sst_file.path))?;
+                sst.set_creation_time(created)?;
+                Some((sst_file, sst))
+            }
+        };
+
+        let input_ids: Vec<usize> = inputs.iter().map(|sst| sst.get_id()).collect();
+        let (removed, output_sst_ids) = {
+            let mut rw_guard = self.state_lock.write().unwrap();
+            let mut rw_snapshot = rw_guard.as_ref().clone();
+            let start = rw_snapshot
+                .l0_sst_files
+                .iter()
+                .position(|sst_file| sst_file.id == input_ids[0]);
+            let inputs_installed = start.is_some_and(|start| {
+                rw_snapshot
+                    .l0_sst_files
+                    .range(start..)
+                    .map(|sst_file| sst_file.id)
+                    .take(input_ids.len())
+                    .eq(input_ids.iter().copied())
+            });
+            if !inputs_installed
+                || self.bulk_loads_in_progress.load(Ordering::SeqCst) > 0
+                || self.compaction_cancelled.load(Ordering::SeqCst)
+            {
+                drop(rw_guard);
+                if let Some((sst_file, _)) = output {
+                    self.remove_sst_file(&sst_file)?;
+                }
+                return Ok(());
+            }
+            let start = start.unwrap();
+            let removed: Vec<SstFile> = rw_snapshot
+                .l0_sst_files
+                .drain(start..start + input_ids.len())
+                .collect();
+            rw_snapshot.ssts.drain(start..start + input_ids.len());
+            let mut output_sst_ids = Vec::new();
+            if let Some((sst_file, sst)) = output {
+                output_sst_ids.push(sst_file.id);
+                rw_snapshot.l0_sst_files.insert(start, sst_file);
+                rw_snapshot.ssts.insert(start, Arc::new(sst));
+            }
+            self.record_snapshot(&rw_snapshot.l0_sst_files)?;
+            self.install(&mut rw_guard, rw_snapshot);
+            (removed, output_sst_ids)
+        };
+        self.remove_sst_files(removed)?;
+        self.compaction_latency.record(started.elapsed());
+        let compaction_info = CompactionJobInfo {
+            input_sst_ids: input_ids,
+            output_sst_ids,
+        };
+        for listener in self.options.listeners.iter() {
+            listener.on_compaction_completed(&compaction_info);
+        }
+        Ok(())
+    }
+
+    // drop SSTs from l0 and delete their files. scans that already hold them
+    // keep reading from their open handles
+    fn delete_ssts(&self, sst_ids: &[usize]) -> Result<()> {
+        if sst_ids.is_empty() {
+            return Ok(());
+        }
+        let removed = {
+            let mut rw_guard = self.state_lock.write().unwrap();
+            let mut rw_snapshot = rw_guard.as_ref().clone();
+            let (removed, kept): (Vec<SstFile>, Vec<SstFile>) = rw_snapshot
+                .l0_sst_files
+                .iter()
+                .cloned()
+                .partition(|sst_file| sst_ids.contains(&sst_file.id));
+            rw_snapshot.l0_sst_files = kept.into();
+            rw_snapshot.ssts.retain(|sst| !sst_ids.contains(&sst.get_id()));
+            self.record_snapshot(&rw_snapshot.l0_sst_files)?;
+            self.install(&mut rw_guard, rw_snapshot);
+            removed
+        };
+        self.remove_sst_files(removed)
+    }
+
+    // record l0 as a whole, for changes that can't be replayed as flushes
+    fn record_snapshot(&self, l0_sst_files: &VecDeque<SstFile>) -> Result<()> {
+        let Some(manifest) = &self.manifest else {
+            return Ok(());
+        };
+        let snapshot = ManifestRecord::Snapshot(l0_sst_files.clone().into());
+        if manifest.should_rotate() {
+            manifest.rotate(&snapshot)
+        } else {
+            manifest.add_record(&snapshot)
+        }
+    }
+
+    // delete the files of SSTs that are no longer in the manifest
+    fn remove_sst_files(&self, sst_files: Vec<SstFile>) -> Result<()> {
+        for sst_file in sst_files {
+            if let Some(metadata_cache) = &self.metadata_cache {
+                metadata_cache.invalidate(&sst_file.id);
+            }
+            // a cached handle would keep the deleted file's space in use
+            if let Some(table_cache) = &self.table_cache {
+                table_cache.invalidate(&sst_file.id);
+            }
+            self.remove_sst_file(&sst_file)?;
+            let deletion_info = SstDeletionInfo {
+                sst_id: sst_file.id,
+                path: self.options.path.join(&sst_file.path),
+            };
+            for listener in self.options.listeners.iter() {
+                listener.on_sst_deleted(&deletion_info);
+            }
+        }
+        Ok(())
+    }
+
+    // a file on disk may share the path of an in-memory SST, so only on-disk
+    // stores delete anything
+    fn remove_sst_file(&self, sst_file: &SstFile) -> Result<()> {
+        if !self.options.in_memory {
+            let path = self.options.path.join(&sst_file.path);
+            remove_file(&path)?;
+            remove_blob_file(&path)?;
+        }
+        Ok(())
+    }
+
+    // remove every file belonging to the store at path. files the store
+    // doesn't recognize are left in place, and the directory is only removed
+    // once it is empty. SSTs in level_paths outside the store directory are
+    // found through the manifest. must not be called while the store is open
+    pub(crate) fn destroy(path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        if !path.exists() {
+            return Ok(());
+        }
+        if Manifest::exists(path) {
+            let (_, records) = Manifest::open(path, MANIFEST_MAX_RECORDS, false)?;
+            for sst_file in Self::replay_sst_files(records) {
+                let sst_path = path.join(&sst_file.path);
+                if !sst_path.starts_with(path) && sst_path.exists() {
+                    remove_file(&sst_path)?;
+                    remove_blob_file(&sst_path)?;
+                }
+            }
+        }
+        for (_, sst_path) in Self::list_sst_files(path)? {
+            remove_file(&sst_path)?;
+            remove_blob_file(&sst_path)?;
+            // clean up subdirectories created by the sst path provider
+            for dir in sst_path.ancestors().skip(1).take_while(|dir| *dir != path) {
+                if read_dir(dir)?.next().is_some() {
+                    break;
+                }
+                remove_dir(dir)?;
+            }
+        }
+        Manifest::destroy(path)?;
+        if read_dir(path)?.next().is_none() {
+            remove_dir(path)?;
+        }
+        Ok(())
+    }
+
+    // every `<id>.sst` file in dir or any of its subdirectories
+    fn list_sst_files(dir: &Path) -> Result<Vec<(usize, PathBuf)>> {
+        let mut sst_files = Vec::new();
+        let mut dirs_to_visit = vec![dir.to_owned()];
+        while let Some(dir) = dirs_to_visit.pop() {
+            for entry in read_dir(dir)? {
+                let entry = entry?;
+                let path = entry.path();
+                if entry.file_type()?.is_dir() {
+                    dirs_to_visit.push(path);
+                    continue;
+                }
+                if path.extension().is_none_or(|ext| ext != "sst") {
+                    continue;
+                }
+                let sst_id = path
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .and_then(|stem| stem.parse::<usize>().ok());
+                if let Some(sst_id) = sst_id {
+                    sst_files.push((sst_id, path));
+                }
+            }
+        }
+        Ok(sst_files)
+    }
+
+    #[cfg(test)]
+    fn get_snapshot(&self) -> Arc<StorageStateProtected> {
+        let ro_snapshot = self.state_lock.read().unwrap();
+
+        let res = ro_snapshot.as_ref().clone();
+        Arc::new(res)
+    }
+}
+
+// keys must be strictly increasing, checked before anything is written
+pub(crate) fn check_sorted(batch: &[KeyValuePair]) -> Result<()> {
+    for pair in batch.windows(2) {
+        let (previous, key) = (pair[0].key.get_key(), pair[1].key.get_key());
+        if previous >= key {
+            return Err(anyhow!("appended keys must be strictly increasing, got {:?} after {:?}", key, previous));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::File,
+        iter,
+        ops::Bound,
+        path::PathBuf,
+        sync::{Arc, Mutex},
+        thread,
+        time::{Duration, SystemTime},
+    };
+
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use crate::{
+        compaction::{CompactionStyle, TimeWindowOptions, VersionRetention},
+        error::LsmError,
+        failpoint,
+        iterator::StorageIterator,
+        kv::{
+            kv_pair::{KeyValuePair, ValueType},
+            timestamped_key::TimestampedKey,
+        },
+        listener::{CompactionJobInfo, EventListener, FlushJobInfo, SstDeletionInfo, WriteStallInfo, WriteStallReason},
+        manifest::{Manifest, ManifestRecord, StoreConfig},
+        memory::{limiter::MemoryLimiter, memtable::rep::MemTableRepType},
+        state::{
+            read_options::ReadOptions, snapshot::Snapshot, storage_state_options::StorageStateOptions,
+            write_options::WriteOptions, StorageState, MANIFEST_MAX_RECORDS,
+        },
+        stats::MemoryUsage,
+        table::{
+            blob::blob_path,
+            persistent_cache::PersistentCacheOptions,
+            sst_path::{FlatSstPathProvider, LeveledSstPathProvider, SstPathProvider},
+        },
+    };
+
+    #[test]
+    fn test_storage_state_get_put() {
+        let dir = tempdir().unwrap();
         let options = StorageStateOptions {
             sst_max_size_bytes: 128,
-            block_max_size_bytes: 0,
+            block_max_size_bytes: 1,
             block_cache_size_bytes: 0,
             path: dir.path().to_owned(),
             num_memtables_limit: 5,
+            ..Default::default()
         };
         let storage_state = StorageState::open(options).unwrap();
         storage_state
@@ -338,15 +2036,52 @@ mod tests {
         assert_eq!(storage_state.get("hello".as_bytes()).unwrap(), None);
     }
 
+    #[test]
+    fn test_empty_values_are_not_tombstones() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            sst_max_size_bytes: 1024,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        let empty = Some(Bytes::new());
+        {
+            let storage_state = StorageState::open(options()).unwrap();
+            storage_state.put("empty".as_bytes(), "".as_bytes()).unwrap();
+            storage_state.put("deleted".as_bytes(), "v".as_bytes()).unwrap();
+            storage_state.delete("deleted".as_bytes()).unwrap();
+            assert_eq!(storage_state.get("empty".as_bytes()).unwrap(), empty);
+            assert_eq!(storage_state.get("deleted".as_bytes()).unwrap(), None);
+
+            storage_state.flush_all_memtables().unwrap();
+            assert_eq!(storage_state.get("empty".as_bytes()).unwrap(), empty);
+            assert_eq!(storage_state.get("deleted".as_bytes()).unwrap(), None);
+        }
+        let storage_state = StorageState::open(options()).unwrap();
+        assert_eq!(storage_state.get("empty".as_bytes()).unwrap(), empty);
+        assert_eq!(storage_state.get("deleted".as_bytes()).unwrap(), None);
+        // raw scans still tell the two apart
+        let entries: Vec<(Bytes, bool)> = storage_state
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .unwrap()
+            .map(|kv| (kv.key.get_key(), kv.is_tombstone()))
+            .collect();
+        assert_eq!(entries, vec![(Bytes::from("deleted"), true), (Bytes::from("empty"), false)]);
+    }
+
     #[test]
     fn test_storage_state_freeze() {
         let dir = tempdir().unwrap();
         let options = StorageStateOptions {
             sst_max_size_bytes: 9,
-            block_max_size_bytes: 0,
+            block_max_size_bytes: 1,
             block_cache_size_bytes: 0,
             path: dir.path().to_owned(),
             num_memtables_limit: 5,
+            ..Default::default()
         };
         let storage_state = StorageState::open(options).unwrap();
         storage_state
@@ -391,10 +2126,11 @@ mod tests {
         let dir = tempdir().unwrap();
         let options = StorageStateOptions {
             sst_max_size_bytes: 4,
-            block_max_size_bytes: 0,
+            block_max_size_bytes: 1,
             block_cache_size_bytes: 0,
             path: dir.path().to_owned(),
             num_memtables_limit: 5,
+            ..Default::default()
         };
         let storage_state = StorageState::open(options).unwrap();
         storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
@@ -411,24 +2147,182 @@ mod tests {
     }
 
     #[test]
-    fn test_get_scan_with_l0_ssts() {
+    fn test_memtable_memory_budget() {
         let dir = tempdir().unwrap();
         let options = StorageStateOptions {
             sst_max_size_bytes: 4,
-            block_max_size_bytes: 4,
+            block_max_size_bytes: 1,
             block_cache_size_bytes: 0,
             path: dir.path().to_owned(),
-            num_memtables_limit: 5,
+            num_memtables_limit: 3,
+            memtable_memory_budget_bytes: Some(40),
+            ..Default::default()
         };
         let storage_state = StorageState::open(options).unwrap();
-        storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
-        // new kv entry can't fit in current memtable, so the memtable should be frozen
-        storage_state.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
-        assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 1);
+        let put = |i: usize| {
+            storage_state
+                .put(format!("k{}", i % 10).as_bytes(), "vv".as_bytes())
+                .unwrap()
+        };
+        // with nothing else in memory, the active memtable gets the whole
+        // budget instead of being capped at sst_max_size_bytes
+        for i in 0..10 {
+            put(i);
+        }
+        assert!(storage_state.get_snapshot().frozen_memtables.is_empty());
+        put(10);
+        assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 1);
+
+        // the frozen memtable uses the budget up, so the active memtable only
+        // gets its minimum share of 40 / (3 + 1) bytes
+        put(11);
+        put(12);
+        put(13);
+        let snapshot = storage_state.get_snapshot();
+        assert_eq!(snapshot.frozen_memtables.len(), 2);
+        assert_eq!(snapshot.frozen_memtables[0].get_size_bytes(), 8);
+        assert_eq!(storage_state.memory_accountant.used_bytes(), 40 + 8 + 8);
+
+        // flushing hands the memory back
+        storage_state.flush_next_memtable_to_l0().unwrap();
+        assert_eq!(storage_state.memory_accountant.used_bytes(), 8 + 8);
+        assert_eq!(storage_state.memtable_size_limit(8), 40 - 8);
+    }
+
+    #[test]
+    fn test_memory_limiter_flushes() {
+        let dir = tempdir().unwrap();
+        let limiter = Arc::new(MemoryLimiter::new(2));
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 128,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 1024,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            memory_limiter: Some(limiter.clone()),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        // reading the SST pulls its block into the cache
+        storage_state.get("k1".as_bytes()).unwrap();
+        assert!(limiter.cache_bytes() > 0);
+        storage_state.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        assert_eq!(limiter.memtable_bytes(), 4);
+
+        // evicting the cache isn't enough, so the active memtable is flushed
+        storage_state.trigger_flush().unwrap();
+        assert_eq!(limiter.cache_bytes(), 0);
+        assert_eq!(limiter.memtable_bytes(), 0);
+        assert_eq!(limiter.buffer_bytes(), 0);
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), vec![1, 0]);
+    }
+
+    #[test]
+    fn test_memtable_reps() {
+        for memtable_rep in [
+            MemTableRepType::SkipList,
+            MemTableRepType::BTreeMap,
+            MemTableRepType::HashSharded,
+        ] {
+            let dir = tempdir().unwrap();
+            let options = StorageStateOptions {
+                sst_max_size_bytes: 8,
+                block_max_size_bytes: 1,
+                block_cache_size_bytes: 0,
+                path: dir.path().to_owned(),
+                num_memtables_limit: 5,
+                memtable_rep,
+                ..Default::default()
+            };
+            let storage_state = StorageState::open(options).unwrap();
+            for key in ["k3", "k1", "k2"] {
+                storage_state.put(key.as_bytes(), "v".as_bytes()).unwrap();
+            }
+            storage_state.flush_all_memtables().unwrap();
+            storage_state.put("k0".as_bytes(), "v".as_bytes()).unwrap();
+            storage_state.delete("k2".as_bytes()).unwrap();
+
+            let keys: Vec<Bytes> = storage_state
+                .scan_keys(Bound::Unbounded, Bound::Unbounded)
+                .unwrap()
+                .collect();
+            assert_eq!(keys, vec!["k0", "k1", "k3"], "{:?}", memtable_rep);
+        }
+    }
+
+    #[test]
+    fn test_scan_snapshot_isolation() {
+        for memtable_rep in [
+            MemTableRepType::SkipList,
+            MemTableRepType::BTreeMap,
+            MemTableRepType::HashSharded,
+        ] {
+            let dir = tempdir().unwrap();
+            let options = StorageStateOptions {
+                sst_max_size_bytes: 1024,
+                block_max_size_bytes: 1,
+                block_cache_size_bytes: 0,
+                path: dir.path().to_owned(),
+                num_memtables_limit: 5,
+                memtable_rep,
+                ..Default::default()
+            };
+            let storage_state = StorageState::open(options).unwrap();
+            for key in ["k1", "k3", "k5"] {
+                storage_state.put(key.as_bytes(), "old".as_bytes()).unwrap();
+            }
+            let iterator = storage_state.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+
+            // overwrite, add and delete keys, flushing all of it before the
+            // scan is read
+            storage_state.put("k2".as_bytes(), "new".as_bytes()).unwrap();
+            storage_state.put("k3".as_bytes(), "new".as_bytes()).unwrap();
+            storage_state.delete("k5".as_bytes()).unwrap();
+            storage_state
+                .write_batch(&[KeyValuePair::new(
+                    TimestampedKey::new("k4".as_bytes().into()),
+                    "new".as_bytes().into(),
+                )])
+                .unwrap();
+            storage_state.flush_all_memtables().unwrap();
+            let pairs: Vec<(Bytes, Bytes)> = iterator.map(|kv| (kv.key.get_key(), kv.value)).collect();
+            let old: Vec<(Bytes, Bytes)> = ["k1", "k3", "k5"]
+                .into_iter()
+                .map(|key| (key.as_bytes().into(), "old".as_bytes().into()))
+                .collect();
+            assert_eq!(pairs, old, "{:?}", memtable_rep);
+
+            // a new scan sees everything
+            let keys: Vec<Bytes> = storage_state
+                .scan_keys(Bound::Unbounded, Bound::Unbounded)
+                .unwrap()
+                .collect();
+            assert_eq!(keys, vec!["k1", "k2", "k3", "k4"], "{:?}", memtable_rep);
+        }
+    }
+
+    #[test]
+    fn test_get_scan_with_l0_ssts() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 4,
+            block_max_size_bytes: 4,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        // new kv entry can't fit in current memtable, so the memtable should be frozen
+        storage_state.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 1);
         // flush to sst
         storage_state.flush_next_memtable_to_l0().unwrap();
         assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 0);
-        assert_eq!(storage_state.get_snapshot().l0_sst_ids.len(), 1);
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids().len(), 1);
         // new kv entry can't fit in current memtable, so the memtable should be frozen
         storage_state.put("k3".as_bytes(), "v3".as_bytes()).unwrap();
         assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 1);
@@ -466,16 +2360,232 @@ mod tests {
         assert!(bounded_iter.next().is_none());
     }
 
+    #[test]
+    fn test_snapshot_during_write() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        storage_state.put(b"k1", b"v1").unwrap();
+        let read = |snapshot, key: &[u8]| {
+            let options = ReadOptions {
+                snapshot: Some(Snapshot::new(vec![snapshot])),
+                ..Default::default()
+            };
+            storage_state.get_with_options(key, &options).unwrap()
+        };
+
+        // a write halfway into the memtable, under the lock that freezes wait
+        // for. snapshots don't take it, and leave the write out
+        let ro_snapshot = storage_state.state_lock.read().unwrap();
+        let stamp = storage_state.stamp();
+        ro_snapshot
+            .current_memtable
+            .write_with_timestamp(b"k2", stamp.timestamp, ValueType::Put, b"v2")
+            .unwrap();
+        let snapshot = storage_state.snapshot();
+        assert_eq!((snapshot.timestamp, snapshot.sequence), (1, 2));
+        assert_eq!(read(snapshot, b"k2"), None);
+        assert_eq!(read(storage_state.snapshot(), b"k1").unwrap(), "v1".as_bytes());
+
+        drop(stamp);
+        drop(ro_snapshot);
+        let snapshot = storage_state.snapshot();
+        assert_eq!((snapshot.timestamp, snapshot.sequence), (2, 2));
+        assert_eq!(read(snapshot, b"k2").unwrap(), "v2".as_bytes());
+    }
+
+    #[test]
+    fn test_snapshots_with_concurrent_writes() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 256,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 100,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        thread::scope(|scope| {
+            for writer in 0..4 {
+                let storage_state = &storage_state;
+                scope.spawn(move || {
+                    for i in 0..200 {
+                        let key = format!("w{}-{:03}", writer, i);
+                        storage_state.put(key.as_bytes(), b"v").unwrap();
+                    }
+                });
+            }
+            // a snapshot reads the same whatever is written after it
+            for _ in 0..20 {
+                let options = ReadOptions {
+                    snapshot: Some(Snapshot::new(vec![storage_state.snapshot()])),
+                    ..Default::default()
+                };
+                let scan = || {
+                    storage_state
+                        .scan_with_options(Bound::Unbounded, Bound::Unbounded, &options)
+                        .unwrap()
+                        .map(|kv| kv.key.get_key())
+                        .collect::<Vec<_>>()
+                };
+                let keys = scan();
+                thread::yield_now();
+                assert_eq!(scan(), keys);
+            }
+        });
+    }
+
+    #[test]
+    fn test_get_layer_precedence() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 10,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        let write = |key: &str, value: Option<&str>| match value {
+            Some(value) => storage_state.put(key.as_bytes(), value.as_bytes()).unwrap(),
+            None => storage_state.delete(key.as_bytes()).unwrap(),
+        };
+        // oldest to newest layer: two SSTs, two frozen memtables and the
+        // current memtable
+        for key in ["a", "b", "c", "d"] {
+            write(key, Some("sst_old"));
+        }
+        storage_state.flush_all_memtables().unwrap();
+        write("b", None);
+        write("c", Some("sst_new"));
+        storage_state.flush_all_memtables().unwrap();
+        write("a", None);
+        write("e", Some("frozen_old"));
+        write("f", Some("frozen_old"));
+        storage_state.freeze_memtable().unwrap();
+        write("a", Some("frozen_new"));
+        write("e", None);
+        storage_state.freeze_memtable().unwrap();
+        let snapshot = Snapshot::new(vec![storage_state.snapshot()]);
+        write("f", None);
+        write("g", Some("current"));
+        write("d", Some("current"));
+        let ro_snapshot = storage_state.get_snapshot();
+        assert_eq!(ro_snapshot.l0_sst_files.len(), 2);
+        assert_eq!(ro_snapshot.frozen_memtables.len(), 2);
+
+        // the newest layer holding a version of a key decides, tombstones
+        // included
+        let expected = [
+            ("a", Some("frozen_new")),
+            ("b", None),
+            ("c", Some("sst_new")),
+            ("d", Some("current")),
+            ("e", None),
+            ("f", None),
+            ("g", Some("current")),
+        ];
+        for (key, value) in expected {
+            assert_eq!(storage_state.get(key.as_bytes()).unwrap(), value.map(Bytes::from), "{}", key);
+        }
+        // and scans agree
+        let keys: Vec<Bytes> = storage_state.scan_keys(Bound::Unbounded, Bound::Unbounded).unwrap().collect();
+        let live_keys: Vec<&str> = expected
+            .iter()
+            .filter(|(_, value)| value.is_some())
+            .map(|(key, _)| *key)
+            .collect();
+        assert_eq!(keys, live_keys);
+
+        // a snapshot skips the versions written after it and falls through to
+        // the older layers
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot),
+            ..Default::default()
+        };
+        for (key, value) in [("d", Some("sst_old")), ("f", Some("frozen_old")), ("g", None)] {
+            let found = storage_state.get_with_options(key.as_bytes(), &at_snapshot).unwrap();
+            assert_eq!(found, value.map(Bytes::from), "{}", key);
+        }
+    }
+
+    #[test]
+    fn test_get_does_not_wait_for_writers() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let storage_state = Arc::new(StorageState::open(options).unwrap());
+        storage_state.put(b"k1", b"v1").unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        storage_state.put(b"k2", b"v2").unwrap();
+
+        // e.g. a flush installing its SST
+        let rw_guard = storage_state.state_lock.write().unwrap();
+        let reader = {
+            let storage_state = storage_state.clone();
+            thread::spawn(move || (storage_state.get(b"k1").unwrap(), storage_state.get(b"k2").unwrap()))
+        };
+        assert_eq!(reader.join().unwrap(), (Some(Bytes::from("v1")), Some(Bytes::from("v2"))));
+        drop(rw_guard);
+    }
+
+    #[test]
+    fn test_concurrent_freeze() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            num_memtables_limit: 1000,
+            ..Default::default()
+        };
+        let storage_state = Arc::new(StorageState::open(options).unwrap());
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let storage_state = storage_state.clone();
+                thread::spawn(move || {
+                    for i in 0..50 {
+                        storage_state.put(format!("k{}_{}", writer, i).as_bytes(), b"v").unwrap();
+                        if i % 5 == 0 {
+                            storage_state.freeze_memtable().unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.join().unwrap();
+        }
+
+        // every freeze took effect once, and no write went into a memtable
+        // after it was frozen
+        let ro_snapshot = storage_state.get_snapshot();
+        assert_eq!(ro_snapshot.frozen_memtables.len(), 40);
+        let ids: Vec<usize> = iter::once(&ro_snapshot.current_memtable)
+            .chain(ro_snapshot.frozen_memtables.iter())
+            .map(|memtable| memtable.get_id())
+            .collect();
+        assert!(ids.windows(2).all(|pair| pair[0] > pair[1]));
+        for writer in 0..4 {
+            for i in 0..50 {
+                let key = format!("k{}_{}", writer, i);
+                assert_eq!(storage_state.get(key.as_bytes()).unwrap(), Some(Bytes::from("v")));
+            }
+        }
+    }
+
     #[test]
     fn test_memtable_flush() {
         // set up storage state
         let dir = tempdir().unwrap();
         let options = StorageStateOptions {
             sst_max_size_bytes: 10,
-            block_max_size_bytes: 0,
+            block_max_size_bytes: 1,
             block_cache_size_bytes: 0,
             path: dir.path().to_owned(),
             num_memtables_limit: 5,
+            ..Default::default()
         };
         let storage_state = StorageState::open(options).unwrap();
         storage_state
@@ -483,14 +2593,14 @@ mod tests {
             .unwrap();
         storage_state.freeze_memtable().unwrap();
         assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 1);
-        assert!(storage_state.get_snapshot().l0_sst_ids.is_empty());
+        assert!(storage_state.get_snapshot().l0_sst_ids().is_empty());
 
         // flush the memtable
         let res = storage_state.flush_next_memtable_to_l0();
         assert!(res.is_ok());
 
         // assert sst created
-        assert_eq!(storage_state.get_snapshot().l0_sst_ids.len(), 1);
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids().len(), 1);
         assert!(storage_state.get_snapshot().frozen_memtables.is_empty());
     }
 
@@ -500,10 +2610,11 @@ mod tests {
         let dir = tempdir().unwrap();
         let options = StorageStateOptions {
             sst_max_size_bytes: 10,
-            block_max_size_bytes: 0,
+            block_max_size_bytes: 1,
             block_cache_size_bytes: 0,
             path: dir.path().to_owned(),
             num_memtables_limit: 5,
+            ..Default::default()
         };
         let storage_state = StorageState::open(options).unwrap();
         storage_state
@@ -520,7 +2631,1510 @@ mod tests {
         assert!(res.is_ok());
 
         // assert sst created
-        assert_eq!(storage_state.get_snapshot().l0_sst_ids.len(), 2);
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids().len(), 2);
         assert!(storage_state.get_snapshot().frozen_memtables.is_empty());
     }
+
+    #[test]
+    fn test_merge_memtables_on_flush() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 10,
+            max_memtables_per_flush: 3,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        let writes: [&[(&str, &str)]; 5] = [
+            &[("k1", "v1")],
+            &[("k1", "v2"), ("k2", "v1")],
+            &[("k3", "v1")],
+            &[("k4", "v1")],
+            &[("k5", "v1")],
+        ];
+        for kvs in writes {
+            for (key, value) in kvs {
+                storage_state.put(key.as_bytes(), value.as_bytes()).unwrap();
+            }
+            storage_state.freeze_memtable().unwrap();
+        }
+        let frozen_ids: Vec<usize> =
+            storage_state.get_snapshot().frozen_memtables.iter().map(|memtable| memtable.get_id()).collect();
+
+        // the three oldest memtables go into one SST, under the newest one's id
+        storage_state.flush_next_memtable_to_l0().unwrap();
+        let snapshot = storage_state.get_snapshot();
+        assert_eq!(snapshot.l0_sst_ids(), vec![frozen_ids[2]]);
+        assert_eq!(snapshot.frozen_memtables.len(), 2);
+        // both versions of k1 are kept
+        assert_eq!(snapshot.ssts[0].estimate_num_entries(Bound::Unbounded, Bound::Unbounded).unwrap(), 4);
+        assert_eq!(storage_state.get("k1".as_bytes()).unwrap().unwrap(), "v2".as_bytes());
+        let values: Vec<_> = storage_state
+            .get_versions("k1".as_bytes(), 10)
+            .unwrap()
+            .into_iter()
+            .map(|version| version.value)
+            .collect();
+        assert_eq!(values, vec![Some("v2".into()), Some("v1".into())]);
+        storage_state.flush_all_memtables().unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), vec![frozen_ids[0], frozen_ids[2]]);
+        drop(storage_state);
+
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 5);
+        drop(storage_state);
+
+        // memtables that don't fit in one SST together are flushed apart
+        let storage_state = StorageState::open(StorageStateOptions {
+            sst_max_size_bytes: 6,
+            ..options
+        })
+        .unwrap();
+        for key in ["k6", "k7"] {
+            storage_state.put(key.as_bytes(), "v1".as_bytes()).unwrap();
+            storage_state.freeze_memtable().unwrap();
+        }
+        storage_state.flush_next_memtable_to_l0().unwrap();
+        assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 1);
+    }
+
+    #[test]
+    fn test_split_oversized_flush() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            block_cache_size_bytes: 0,
+            block_max_size_bytes: 64,
+            // lets the memtable grow well past the SST size
+            memtable_memory_budget_bytes: Some(1 << 20),
+            path: dir.path().to_owned(),
+            sst_max_size_bytes: 1000,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        let value = vec![b'v'; 500];
+        for index in 0..8 {
+            storage_state.put(format!("key{}", index).as_bytes(), &value).unwrap();
+        }
+        storage_state.put("key0".as_bytes(), "new".as_bytes()).unwrap();
+        let memtable_id = storage_state.get_snapshot().current_memtable.get_id();
+        storage_state.flush_all_memtables().unwrap();
+
+        // the first part keeps the memtable's id, the others don't overlap it
+        let snapshot = storage_state.get_snapshot();
+        let sst_ids = snapshot.l0_sst_ids();
+        assert!(sst_ids.len() >= 4, "{:?}", sst_ids);
+        assert_eq!(*sst_ids.last().unwrap(), memtable_id);
+        let mut ranges: Vec<_> = snapshot
+            .ssts
+            .iter()
+            .map(|sst| (sst.get_first_key().get_key(), sst.get_last_key().get_key()))
+            .collect();
+        ranges.sort();
+        for pair in ranges.windows(2) {
+            assert!(pair[0].1 < pair[1].0, "{:?}", ranges);
+        }
+        for sst in snapshot.ssts.iter() {
+            assert!(sst.get_file_size() < 2 * options.sst_max_size_bytes as u64);
+        }
+        drop(snapshot);
+        assert_eq!(storage_state.get("key0".as_bytes()).unwrap().unwrap(), "new".as_bytes());
+        assert_eq!(storage_state.get("key7".as_bytes()).unwrap().unwrap(), value);
+        drop(storage_state);
+
+        let storage_state = StorageState::open(options).unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), sst_ids);
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 8);
+        assert_eq!(storage_state.get("key0".as_bytes()).unwrap().unwrap(), "new".as_bytes());
+    }
+
+    #[test]
+    fn test_repair_after_split_flush() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            sst_max_size_bytes: 16,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        let batch: Vec<KeyValuePair> = ["a", "b", "c"]
+            .iter()
+            .map(|key| KeyValuePair::new(TimestampedKey::new(Bytes::from(*key)), Bytes::from("old value")))
+            .collect();
+        storage_state.write_batch(&batch).unwrap();
+        // freezes the batch's memtable, whose flush is split into parts with
+        // ids newer than this write's memtable
+        storage_state.put("b".as_bytes(), "new".as_bytes()).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        let sst_ids = storage_state.get_snapshot().l0_sst_ids();
+        assert!(sst_ids.len() > 2, "{:?}", sst_ids);
+        drop(storage_state);
+
+        std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+        assert_eq!(StorageState::repair(dir.path()).unwrap().sst_ids, sst_ids);
+        let storage_state = StorageState::open(options).unwrap();
+        assert_eq!(storage_state.get("b".as_bytes()).unwrap().unwrap(), "new".as_bytes());
+        assert_eq!(storage_state.get("c".as_bytes()).unwrap().unwrap(), "old value".as_bytes());
+    }
+
+    #[test]
+    fn test_refuse_incompatible_config() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        drop(storage_state);
+        // the config is recorded once, and other block sizes still open
+        let storage_state = StorageState::open(StorageStateOptions {
+            block_max_size_bytes: 128,
+            ..options.clone()
+        })
+        .unwrap();
+        let recorded = storage_state.manifest.as_ref().unwrap().config().unwrap();
+        assert_eq!(recorded, StoreConfig::new(options.block_max_size_bytes));
+        drop(storage_state);
+
+        // e.g. a store written by a build with another key order
+        {
+            let (manifest, _) = Manifest::open(dir.path(), MANIFEST_MAX_RECORDS, false).unwrap();
+            manifest
+                .add_record(&ManifestRecord::Config(StoreConfig {
+                    comparator: "reverse".to_string(),
+                    ..recorded
+                }))
+                .unwrap();
+        }
+        let err = StorageState::open(options.clone()).err().unwrap();
+        assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::InvalidOptions(_))), "{}", err);
+        // repair keeps the config, so the store stays refused
+        StorageState::repair(dir.path()).unwrap();
+        assert!(StorageState::open(options).is_err());
+    }
+
+    #[test]
+    fn test_reopen_recovers_flushed_ssts() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            sst_max_size_bytes: 10,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        {
+            let storage_state = StorageState::open(options()).unwrap();
+            storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+            storage_state.freeze_memtable().unwrap();
+            storage_state.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+        }
+        let storage_state = StorageState::open(options()).unwrap();
+        let snapshot = storage_state.get_snapshot();
+        assert_eq!(snapshot.l0_sst_ids(), vec![1, 0]);
+        assert_eq!(snapshot.ssts.len(), 2);
+        // new memtable must not reuse an id that belongs to an existing SST
+        assert_eq!(snapshot.current_memtable.get_id(), 2);
+        assert_eq!(
+            storage_state.get("k1".as_bytes()).unwrap().unwrap(),
+            "v1".as_bytes()
+        );
+        assert_eq!(
+            storage_state.get("k2".as_bytes()).unwrap().unwrap(),
+            "v2".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_flush_crash_recovery() {
+        // where the crash happens, and whether the flush it interrupts has
+        // been recorded by then
+        let crash_points = [
+            (failpoint::FLUSH_SST_WRITTEN, false),
+            (failpoint::FLUSH_MANIFEST_UPDATED, true),
+        ];
+        for (crash_point, is_recorded) in crash_points {
+            let dir = tempdir().unwrap();
+            let options = || StorageStateOptions {
+                sst_max_size_bytes: 16,
+                block_max_size_bytes: 1,
+                path: dir.path().to_owned(),
+                num_memtables_limit: 5,
+                ..Default::default()
+            };
+            let sync = WriteOptions {
+                sync: true,
+                ..Default::default()
+            };
+            {
+                let storage_state = StorageState::open(options()).unwrap();
+                // acknowledged as durable
+                storage_state.put(b"k1", b"v1").unwrap();
+                storage_state.put_with_options(b"k2", b"v2", &sync).unwrap();
+                storage_state.put_with_options(b"k3", b"v3", &sync).unwrap();
+                storage_state.delete_with_options(b"k1", &sync).unwrap();
+                // large enough to be split into two SSTs, so the crash comes
+                // between writing the first one and recording them
+                let batch = [
+                    KeyValuePair::new(TimestampedKey::new("k2".into()), "new_value2".into()),
+                    KeyValuePair::tombstone(TimestampedKey::new("k3".into())),
+                    KeyValuePair::new(TimestampedKey::new("k4".into()), "v4".into()),
+                ];
+                storage_state.write_batch(&batch).unwrap();
+                failpoint::arm(crash_point);
+                assert!(storage_state.flush_all_memtables().is_err());
+            }
+
+            let storage_state = StorageState::open(options()).unwrap();
+            let num_l0_ssts = storage_state.get_snapshot().l0_sst_files.len();
+            assert_eq!(num_l0_ssts, if is_recorded { 5 } else { 3 });
+            // deletes that were acknowledged stay deleted
+            assert_eq!(storage_state.get(b"k1").unwrap(), None);
+            // the batch is recovered whole or not at all
+            let (k2, k3, k4) = match is_recorded {
+                true => (Some("new_value2"), None, Some("v4")),
+                false => (Some("v2"), Some("v3"), None),
+            };
+            assert_eq!(storage_state.get(b"k2").unwrap(), k2.map(Bytes::from));
+            assert_eq!(storage_state.get(b"k3").unwrap(), k3.map(Bytes::from));
+            assert_eq!(storage_state.get(b"k4").unwrap(), k4.map(Bytes::from));
+
+            // files the crash left behind don't get in the way of new flushes
+            storage_state.put(b"k5", b"v5").unwrap();
+            storage_state.flush_all_memtables().unwrap();
+            drop(storage_state);
+            let storage_state = StorageState::open(options()).unwrap();
+            assert_eq!(storage_state.get(b"k1").unwrap(), None);
+            assert_eq!(storage_state.get(b"k2").unwrap(), k2.map(Bytes::from));
+            assert_eq!(storage_state.get(b"k5").unwrap(), Some(Bytes::from("v5")));
+        }
+    }
+
+    #[test]
+    fn test_metadata_cache() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            sst_max_size_bytes: 4,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            metadata_cache_capacity: Some(1),
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        {
+            let storage_state = StorageState::open(options()).unwrap();
+            for key in ["k1", "k2", "k3"] {
+                storage_state.put(key.as_bytes(), "v".as_bytes()).unwrap();
+            }
+            storage_state.flush_all_memtables().unwrap();
+            // only one of the new SSTs keeps its metadata
+            let metadata_cache = storage_state.metadata_cache.as_ref().unwrap();
+            metadata_cache.run_pending_tasks();
+            assert_eq!(metadata_cache.entry_count(), 1);
+        }
+        let storage_state = StorageState::open(options()).unwrap();
+        let metadata_cache = storage_state.metadata_cache.as_ref().unwrap();
+        assert_eq!(metadata_cache.entry_count(), 0);
+        // out of every SST's key range, so nothing is loaded
+        assert_eq!(storage_state.get("k0".as_bytes()).unwrap(), None);
+        metadata_cache.run_pending_tasks();
+        assert_eq!(metadata_cache.entry_count(), 0);
+        for key in ["k1", "k2", "k3"] {
+            assert_eq!(storage_state.get(key.as_bytes()).unwrap().unwrap(), "v".as_bytes());
+        }
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 3);
+        metadata_cache.run_pending_tasks();
+        assert_eq!(metadata_cache.entry_count(), 1);
+    }
+
+    #[test]
+    fn test_max_open_files() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            sst_max_size_bytes: 4,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            max_open_files: Some(1),
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        {
+            let storage_state = StorageState::open(options()).unwrap();
+            for key in ["k1", "k2", "k3"] {
+                storage_state.put(key.as_bytes(), "v".as_bytes()).unwrap();
+            }
+            storage_state.flush_all_memtables().unwrap();
+        }
+        let storage_state = StorageState::open(options()).unwrap();
+        let table_cache = storage_state.table_cache.as_ref().unwrap();
+        for key in ["k1", "k2", "k3"] {
+            assert_eq!(storage_state.get(key.as_bytes()).unwrap().unwrap(), "v".as_bytes());
+            table_cache.run_pending_tasks();
+            assert_eq!(table_cache.entry_count(), 1);
+        }
+        // files closed by the cache are reopened to be read
+        table_cache.invalidate_all();
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 3);
+    }
+
+    #[test]
+    fn test_persistent_cache() {
+        let dir = tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        let options = StorageStateOptions {
+            block_cache_size_bytes: 0,
+            persistent_cache: Some(PersistentCacheOptions {
+                path: PathBuf::from("cache"),
+                capacity_bytes: 1 << 20,
+            }),
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let num_cached_blocks = || std::fs::read_dir(&cache_dir).unwrap().count();
+        {
+            let storage_state = StorageState::open(options.clone()).unwrap();
+            storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+            assert_eq!(num_cached_blocks(), 0);
+            assert_eq!(storage_state.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+            assert_eq!(num_cached_blocks(), 1);
+        }
+
+        // the cache starts out empty on every open
+        let storage_state = StorageState::open(options).unwrap();
+        assert_eq!(num_cached_blocks(), 0);
+        assert_eq!(storage_state.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+        assert_eq!(num_cached_blocks(), 1);
+        // once cached, the block isn't read from the SST again
+        let sst_path = dir.path().join(&storage_state.get_snapshot().l0_sst_files[0].path);
+        let sst_size = std::fs::metadata(&sst_path).unwrap().len();
+        std::fs::write(&sst_path, vec![0; sst_size as usize]).unwrap();
+        assert_eq!(storage_state.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 64,
+            block_max_size_bytes: 32,
+            path: path.clone(),
+            in_memory: true,
+            compaction_style: CompactionStyle::Fifo,
+            max_db_size_bytes: Some(0),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        for i in 0..20 {
+            storage_state.put(format!("k{:02}", i).as_bytes(), "v".as_bytes()).unwrap();
+        }
+        storage_state.flush_all_memtables().unwrap();
+        let loaded = KeyValuePair::new(
+            TimestampedKey::new("loaded".into()),
+            "v".into(),
+        );
+        storage_state.bulk_load([loaded]).unwrap();
+        assert!(storage_state.get_snapshot().ssts.len() > 1);
+        assert_eq!(storage_state.get("k05".as_bytes()).unwrap().unwrap(), "v".as_bytes());
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 21);
+        // nothing was written to disk
+        assert!(!path.exists());
+
+        // deleting SSTs only drops them from memory
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("00000.sst"), "not ours").unwrap();
+        storage_state.trigger_compaction().unwrap();
+        assert!(storage_state.get_snapshot().ssts.is_empty());
+        assert!(path.join("00000.sst").exists());
+        drop(storage_state);
+
+        let storage_state = StorageState::open(options).unwrap();
+        assert!(storage_state.get("k05".as_bytes()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_approximate_memory_usage() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            block_max_size_bytes: 1,
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        let usage = storage_state.approximate_memory_usage();
+        assert_eq!(usage, MemoryUsage { active_memtable_bytes: 4, ..Default::default() });
+
+        storage_state.freeze_memtable().unwrap();
+        storage_state.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        storage_state.put("k3".as_bytes(), "v3".as_bytes()).unwrap();
+        let usage = storage_state.approximate_memory_usage();
+        assert_eq!((usage.active_memtable_bytes, usage.frozen_memtable_bytes), (8, 4));
+
+        storage_state.flush_all_memtables().unwrap();
+        let usage = storage_state.approximate_memory_usage();
+        assert_eq!(usage.active_memtable_bytes + usage.frozen_memtable_bytes, 0);
+        assert_eq!(usage.block_cache_bytes, 0);
+        assert!(usage.bloom_filter_bytes > 0);
+        assert!(usage.sst_metadata_bytes > 0);
+        storage_state.get("k1".as_bytes()).unwrap();
+        assert!(storage_state.approximate_memory_usage().block_cache_bytes > 0);
+        drop(storage_state);
+
+        // metadata that isn't cached yet doesn't take up memory
+        let storage_state = StorageState::open(StorageStateOptions {
+            metadata_cache_capacity: Some(10),
+            ..options
+        })
+        .unwrap();
+        let usage = storage_state.approximate_memory_usage();
+        assert_eq!(usage.bloom_filter_bytes + usage.sst_metadata_bytes, 0);
+        storage_state.get("k1".as_bytes()).unwrap();
+        storage_state.metadata_cache.as_ref().unwrap().run_pending_tasks();
+        let usage = storage_state.approximate_memory_usage();
+        assert!(usage.bloom_filter_bytes > 0);
+        assert_eq!(usage.total_bytes(), usage.block_cache_bytes + usage.bloom_filter_bytes + usage.sst_metadata_bytes);
+    }
+
+    #[test]
+    fn test_destroy() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 10,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: path.clone(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        {
+            let storage_state = StorageState::open(options).unwrap();
+            storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+        }
+        StorageState::destroy(&path).unwrap();
+        assert!(!path.exists());
+        // destroying a store that doesn't exist is a no-op
+        StorageState::destroy(&path).unwrap();
+
+        // unrecognized files are kept along with the directory
+        std::fs::create_dir_all(&path).unwrap();
+        std::fs::write(path.join("notes.txt"), "keep me").unwrap();
+        StorageState::destroy(&path).unwrap();
+        assert!(path.join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_repair() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            sst_max_size_bytes: 10,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        {
+            let storage_state = StorageState::open(options()).unwrap();
+            storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+            storage_state.freeze_memtable().unwrap();
+            storage_state.put("k1".as_bytes(), "v2".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+        }
+        // lose the manifest and leave behind a corrupt SST
+        std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+        std::fs::write(dir.path().join("00009.sst"), "garbage").unwrap();
+
+        let report = StorageState::repair(dir.path()).unwrap();
+        assert_eq!(report.sst_ids, vec![1, 0]);
+        assert_eq!(report.skipped_sst_ids, vec![9]);
+        let storage_state = StorageState::open(options()).unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), vec![1, 0]);
+        // newer SST still shadows the older one
+        assert_eq!(
+            storage_state.get("k1".as_bytes()).unwrap().unwrap(),
+            "v2".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_count_reports_read_errors() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 1024,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        for i in 0..10 {
+            storage_state
+                .put(format!("k{}", i).as_bytes(), &[b'v'; 64])
+                .unwrap();
+        }
+        storage_state.flush_all_memtables().unwrap();
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 10);
+
+        // values make up most of the file, so this loses the later blocks
+        let sst_path = dir.path().join("00000.sst");
+        let file = std::fs::OpenOptions::new().write(true).open(&sst_path).unwrap();
+        file.set_len(file.metadata().unwrap().len() / 2).unwrap();
+        // the SST is the only source, so its blocks are counted from their
+        // stats without being read
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 10);
+        // the memtable's k9 has to be merged with the lost block holding it.
+        // a short count would look like missing keys, so it has to fail instead
+        storage_state.put("k9".as_bytes(), "new".as_bytes()).unwrap();
+        assert!(storage_state.count(Bound::Unbounded, Bound::Unbounded).is_err());
+    }
+
+    #[test]
+    fn test_leveled_sst_paths() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = |sst_path_provider: Arc<dyn SstPathProvider>| StorageStateOptions {
+            sst_max_size_bytes: 10,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: path.clone(),
+            num_memtables_limit: 5,
+            sst_path_provider,
+            ..Default::default()
+        };
+        {
+            let storage_state = StorageState::open(options(Arc::new(LeveledSstPathProvider))).unwrap();
+            storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+        }
+        assert!(path.join("L0").join("00000.sst").exists());
+        {
+            // the manifest remembers where existing SSTs are after switching layouts
+            let storage_state = StorageState::open(options(Arc::new(FlatSstPathProvider))).unwrap();
+            assert_eq!(storage_state.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+            storage_state.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+        }
+        assert!(path.join("00001.sst").exists());
+
+        assert_eq!(StorageState::repair(&path).unwrap().sst_ids, vec![1, 0]);
+        {
+            let storage_state = StorageState::open(options(Arc::new(FlatSstPathProvider))).unwrap();
+            assert_eq!(storage_state.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+            assert_eq!(storage_state.get("k2".as_bytes()).unwrap().unwrap(), "v2".as_bytes());
+        }
+
+        StorageState::destroy(&path).unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_bulk_load() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 64,
+            block_max_size_bytes: 32,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        let kvs = |range: std::ops::Range<usize>, value: &'static str| {
+            range.map(move |i| KeyValuePair::new(
+                TimestampedKey::new(format!("k{:03}", i).into()),
+                value.as_bytes().into(),
+            ))
+        };
+        {
+            let storage_state = StorageState::open(options.clone()).unwrap();
+            storage_state.put("k000".as_bytes(), "old".as_bytes()).unwrap();
+            storage_state.put("zzz".as_bytes(), "old".as_bytes()).unwrap();
+            let ids = storage_state.bulk_load(kvs(0..50, "loaded")).unwrap();
+            assert!(ids.len() > 1);
+            // loaded keys shadow earlier writes, and later writes shadow them
+            storage_state.put("k001".as_bytes(), "new".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+            assert_eq!(storage_state.get("k000".as_bytes()).unwrap().unwrap(), "loaded".as_bytes());
+            assert_eq!(storage_state.get("k001".as_bytes()).unwrap().unwrap(), "new".as_bytes());
+            assert_eq!(storage_state.get("zzz".as_bytes()).unwrap().unwrap(), "old".as_bytes());
+            assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 51);
+
+            // unsorted input fails and leaves nothing behind
+            let num_files = std::fs::read_dir(dir.path()).unwrap().count();
+            let unsorted = kvs(100..150, "v").chain(kvs(120..121, "v"));
+            assert!(storage_state.bulk_load(unsorted).is_err());
+            assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), num_files);
+        }
+
+        let reopened = StorageState::open(options).unwrap();
+        assert_eq!(reopened.get("k000".as_bytes()).unwrap().unwrap(), "loaded".as_bytes());
+        assert_eq!(reopened.get("k001".as_bytes()).unwrap().unwrap(), "new".as_bytes());
+        assert!(reopened.get("k100".as_bytes()).unwrap().is_none());
+        // the SSTs on disk alone give the order the manifest recorded
+        let l0_ids = reopened.get_snapshot().l0_sst_ids();
+        drop(reopened);
+        assert_eq!(StorageState::repair(dir.path()).unwrap().sst_ids, l0_ids);
+    }
+
+    #[test]
+    fn test_bulk_load_with_concurrent_writes() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 64,
+            block_max_size_bytes: 32,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            max_memtables_per_flush: 3,
+            ..Default::default()
+        };
+        let kvs = |range: std::ops::Range<usize>| {
+            range.map(|i| KeyValuePair::new(
+                TimestampedKey::new(format!("k{:03}", i).into()),
+                "loaded".as_bytes().into(),
+            ))
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        for i in 0..40 {
+            storage_state.put(format!("k{:03}", i).as_bytes(), "before".as_bytes()).unwrap();
+        }
+        let mut loader = storage_state.bulk_loader().unwrap();
+        for (i, kv) in kvs(0..40).enumerate() {
+            loader.add(kv).unwrap();
+            // writes made while the load is open freeze and flush memtables
+            // whose ids are interleaved with the loaded SSTs'
+            if i % 2 == 0 {
+                storage_state.put(format!("k{:03}", i).as_bytes(), "during".as_bytes()).unwrap();
+            }
+            if i % 8 == 0 {
+                storage_state.freeze_memtable().unwrap();
+                storage_state.flush_next_memtable_to_l0().unwrap();
+            }
+        }
+        loader.finish().unwrap();
+
+        let check = |storage_state: &StorageState| {
+            for i in 0..40 {
+                let expected = if i % 2 == 0 { "during" } else { "loaded" };
+                assert_eq!(
+                    storage_state.get(format!("k{:03}", i).as_bytes()).unwrap().unwrap(),
+                    expected.as_bytes(),
+                    "k{:03}",
+                    i
+                );
+            }
+        };
+        check(&storage_state);
+        storage_state.flush_all_memtables().unwrap();
+        check(&storage_state);
+        let l0_ids = storage_state.get_snapshot().l0_sst_ids();
+        drop(storage_state);
+        assert_eq!(StorageState::repair(dir.path()).unwrap().sst_ids, l0_ids);
+    }
+
+    #[test]
+    fn test_level_paths() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let fast_path = dir.path().join("fast");
+        let options = || StorageStateOptions {
+            sst_max_size_bytes: 10,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: path.clone(),
+            num_memtables_limit: 5,
+            level_paths: vec![fast_path.clone(), PathBuf::from("slow")],
+            ..Default::default()
+        };
+        {
+            let storage_state = StorageState::open(options()).unwrap();
+            storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+            // relative level paths are inside the store directory
+            assert_eq!(storage_state.new_sst_file(7, 1).path, PathBuf::from("slow/00007.sst"));
+            assert_eq!(storage_state.new_sst_file(7, 5).path, PathBuf::from("slow/00007.sst"));
+        }
+        assert!(fast_path.join("00000.sst").exists());
+        assert!(!path.join("00000.sst").exists());
+
+        // the manifest records the full path, so level_paths isn't needed to reopen
+        let storage_state = StorageState::open(StorageStateOptions {
+            level_paths: Vec::new(),
+            ..options()
+        })
+        .unwrap();
+        assert_eq!(storage_state.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+        drop(storage_state);
+
+        StorageState::destroy(&path).unwrap();
+        assert!(!path.exists());
+        assert!(!fast_path.join("00000.sst").exists());
+    }
+
+    #[test]
+    fn test_open_flags() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = |create_if_missing, error_if_exists| StorageStateOptions {
+            path: path.clone(),
+            create_if_missing,
+            error_if_exists,
+            ..Default::default()
+        };
+
+        let err = StorageState::open(options(false, false)).err().unwrap();
+        assert_eq!(err.downcast_ref::<LsmError>(), Some(&LsmError::NotFound(path.clone())));
+        assert!(!path.exists());
+
+        StorageState::open(options(true, true)).unwrap();
+        let err = StorageState::open(options(true, true)).err().unwrap();
+        assert_eq!(
+            err.downcast_ref::<LsmError>(),
+            Some(&LsmError::AlreadyExists(path.clone()))
+        );
+        // an existing store can be opened without create_if_missing
+        StorageState::open(options(false, false)).unwrap();
+    }
+
+    #[test]
+    fn test_count_and_sum() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 20,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        for i in 1..=4_u64 {
+            storage_state
+                .put(format!("k{}", i).as_bytes(), &i.to_le_bytes())
+                .unwrap();
+        }
+        storage_state.flush_all_memtables().unwrap();
+        // overwrite and delete on top of the flushed SSTs
+        storage_state.put("k1".as_bytes(), &10_u64.to_le_bytes()).unwrap();
+        storage_state.delete("k4".as_bytes()).unwrap();
+
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 3);
+        assert_eq!(
+            storage_state
+                .count(Bound::Excluded("k1".as_bytes()), Bound::Included("k3".as_bytes()))
+                .unwrap(),
+            2
+        );
+        assert_eq!(
+            storage_state
+                .sum_values_as_u64(Bound::Unbounded, Bound::Unbounded)
+                .unwrap(),
+            10 + 2 + 3
+        );
+
+        // k1 and k4 are counted once in the memtable and once in an SST
+        assert_eq!(
+            storage_state
+                .estimate_count(Bound::Unbounded, Bound::Unbounded)
+                .unwrap(),
+            6
+        );
+
+        storage_state.put("k5".as_bytes(), "not a u64".as_bytes()).unwrap();
+        assert!(storage_state
+            .sum_values_as_u64(Bound::Unbounded, Bound::Unbounded)
+            .is_err());
+    }
+
+    #[test]
+    fn test_count_from_block_stats() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 1024,
+            // a handful of entries a block
+            block_max_size_bytes: 48,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        let key = |i: usize| format!("k{:02}", i);
+        for i in 0..40 {
+            storage_state.put(key(i).as_bytes(), b"v").unwrap();
+        }
+        // versions and tombstones that end up in the same SST
+        for i in (0..40).step_by(3) {
+            storage_state.put(key(i).as_bytes(), b"new").unwrap();
+        }
+        for i in (0..40).step_by(7) {
+            storage_state.delete(key(i).as_bytes()).unwrap();
+        }
+        storage_state.flush_all_memtables().unwrap();
+        // a second SST and the memtable overlap parts of the first
+        for i in 22..25 {
+            storage_state.delete(key(i).as_bytes()).unwrap();
+        }
+        storage_state.put(key(40).as_bytes(), b"v").unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        storage_state.put(key(12).as_bytes(), b"v").unwrap();
+        storage_state.delete(key(31).as_bytes()).unwrap();
+        assert!(!storage_state.get_snapshot().ssts[1]
+            .block_key_counts(Bound::Unbounded, Bound::Unbounded)
+            .unwrap()
+            .is_empty());
+
+        let bounds = |i: usize| {
+            [
+                Bound::Included(key(i)),
+                Bound::Excluded(key(i)),
+                Bound::Unbounded,
+            ]
+        };
+        for lower_index in (0..42).step_by(5) {
+            for upper_index in (lower_index..42).step_by(4) {
+                for lower in bounds(lower_index).iter() {
+                    for upper in bounds(upper_index).iter() {
+                        let (lower, upper) = (lower.as_ref().map(String::as_bytes), upper.as_ref().map(String::as_bytes));
+                        let expected = storage_state.scan_keys(lower, upper).unwrap().count();
+                        assert_eq!(storage_state.count(lower, upper).unwrap(), expected, "{:?}..{:?}", lower, upper);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_flush_events() {
+        #[derive(Default)]
+        struct RecordingListener {
+            events: Mutex<Vec<(&'static str, usize, Option<u64>)>>,
+        }
+
+        impl EventListener for RecordingListener {
+            fn on_flush_begin(&self, info: &FlushJobInfo) {
+                self.events.lock().unwrap().push(("begin", info.sst_id, info.file_size));
+            }
+
+            fn on_flush_completed(&self, info: &FlushJobInfo) {
+                assert!(info.path.exists());
+                self.events.lock().unwrap().push(("completed", info.sst_id, info.file_size));
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let listener = Arc::new(RecordingListener::default());
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 128,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            listeners: vec![listener.clone()],
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        storage_state.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+
+        let sst_size = storage_state.state_lock.read().unwrap().ssts[0].get_file_size();
+        assert_eq!(
+            *listener.events.lock().unwrap(),
+            vec![("begin", 0, None), ("completed", 0, Some(sst_size))]
+        );
+    }
+
+    #[test]
+    fn test_write_options() {
+        #[derive(Default)]
+        struct StallListener {
+            stalls: Mutex<Vec<WriteStallReason>>,
+        }
+
+        impl EventListener for StallListener {
+            fn on_write_stall(&self, info: &WriteStallInfo) {
+                self.stalls.lock().unwrap().push(info.reason);
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let listener = Arc::new(StallListener::default());
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 8,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 2,
+            listeners: vec![listener.clone()],
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        let sync = WriteOptions {
+            sync: true,
+            ..Default::default()
+        };
+        storage_state.put_with_options(b"k1", b"value", &sync).unwrap();
+        let snapshot = storage_state.get_snapshot();
+        assert!(snapshot.current_memtable.is_empty());
+        assert!(snapshot.frozen_memtables.is_empty());
+        assert_eq!(snapshot.l0_sst_files.len(), 1);
+        assert_eq!(*listener.stalls.lock().unwrap(), vec![WriteStallReason::Sync]);
+
+        // two frozen memtables, which is as many as the background allows
+        for key in ["k2", "k3", "k4"] {
+            storage_state.put(key.as_bytes(), b"value").unwrap();
+        }
+        assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 2);
+        let low_priority = WriteOptions {
+            low_priority: true,
+            ..Default::default()
+        };
+        storage_state.put_with_options(b"k5", b"value", &low_priority).unwrap();
+        // k4's memtable was frozen by the put itself
+        let snapshot = storage_state.get_snapshot();
+        assert_eq!(snapshot.frozen_memtables.len(), 1);
+        assert_eq!(snapshot.l0_sst_files.len(), 3);
+        assert_eq!(
+            *listener.stalls.lock().unwrap(),
+            vec![WriteStallReason::Sync, WriteStallReason::MemtableLimit]
+        );
+        let stats = storage_state.stats();
+        assert_eq!(stats.num_write_stalls, 2);
+        assert!(stats.write_stall_duration > Duration::ZERO);
+        // below the limit, nothing to wait for
+        storage_state.put_with_options(b"k6", b"value", &low_priority).unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_files.len(), 3);
+        assert_eq!(storage_state.stats().num_write_stalls, 2);
+
+        storage_state.delete_with_options(b"k1", &sync).unwrap();
+        assert!(storage_state.get_snapshot().frozen_memtables.is_empty());
+        assert_eq!(storage_state.get(b"k1").unwrap(), None);
+    }
+
+    #[test]
+    fn test_l0_write_stall() {
+        #[derive(Default)]
+        struct StallListener {
+            stalls: Mutex<Vec<WriteStallReason>>,
+        }
+
+        impl EventListener for StallListener {
+            fn on_write_stall(&self, info: &WriteStallInfo) {
+                self.stalls.lock().unwrap().push(info.reason);
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let listener = Arc::new(StallListener::default());
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            compaction_style: CompactionStyle::Fifo,
+            // every SST is over the limit, so a compaction round empties l0
+            max_db_size_bytes: Some(1),
+            l0_stall_num_ssts: Some(2),
+            listeners: vec![listener.clone()],
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        for key in ["k1", "k2"] {
+            storage_state.put(key.as_bytes(), b"value").unwrap();
+            storage_state.flush_all_memtables().unwrap();
+        }
+        assert_eq!(storage_state.get_snapshot().l0_sst_files.len(), 2);
+        assert!(listener.stalls.lock().unwrap().is_empty());
+
+        storage_state.put(b"k3", b"value").unwrap();
+        assert!(storage_state.get_snapshot().l0_sst_files.is_empty());
+        assert_eq!(*listener.stalls.lock().unwrap(), vec![WriteStallReason::L0Limit]);
+        assert_eq!(storage_state.stats().num_write_stalls, 1);
+        assert_eq!(storage_state.get(b"k3").unwrap().unwrap(), "value".as_bytes());
+    }
+
+    #[test]
+    fn test_wait_for_flush_and_compaction() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 8,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            compaction_style: CompactionStyle::TimeWindow(TimeWindowOptions {
+                window: Duration::from_secs(3600),
+                min_merge_width: 2,
+                ttl: None,
+            }),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        // each put freezes the memtable before it, and nothing in the
+        // background flushes below num_memtables_limit
+        for key in ["k1", "k2", "k3", "k4"] {
+            storage_state.put(key.as_bytes(), "value".as_bytes()).unwrap();
+        }
+        assert_eq!(storage_state.get_snapshot().frozen_memtables.len(), 3);
+
+        storage_state.wait_for_flush().unwrap();
+        let snapshot = storage_state.get_snapshot();
+        assert!(snapshot.frozen_memtables.is_empty());
+        assert_eq!(snapshot.l0_sst_files.len(), 3);
+        assert!(!snapshot.current_memtable.is_empty());
+        // nothing frozen, nothing to wait for
+        storage_state.wait_for_flush().unwrap();
+
+        // every SST is in the current window
+        storage_state.wait_for_compaction().unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_files.len(), 1);
+        for key in ["k1", "k2", "k3", "k4"] {
+            assert_eq!(storage_state.get(key.as_bytes()).unwrap().unwrap(), "value".as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_cancel_compaction() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 8,
+            block_max_size_bytes: 1,
+            path: dir.path().to_owned(),
+            compaction_style: CompactionStyle::TimeWindow(TimeWindowOptions {
+                window: Duration::from_secs(3600),
+                min_merge_width: 2,
+                ttl: None,
+            }),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options).unwrap();
+        for key in ["k1", "k2", "k3", "k4"] {
+            storage_state.put(key.as_bytes(), "value".as_bytes()).unwrap();
+        }
+        storage_state.flush_all_memtables().unwrap();
+        let l0_ids = storage_state.get_snapshot().l0_sst_ids();
+        assert_eq!(l0_ids.len(), 4);
+
+        storage_state.cancel_compaction();
+        storage_state.wait_for_compaction().unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), l0_ids);
+        // a merge that was already running stops at its next block and
+        // leaves no output behind
+        let inputs = storage_state.get_snapshot().ssts.iter().cloned().collect();
+        storage_state.merge_ssts(inputs, true).unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), l0_ids);
+        assert_eq!(StorageState::list_sst_files(dir.path()).unwrap().len(), 4);
+        for key in ["k1", "k2", "k3", "k4"] {
+            assert_eq!(storage_state.get(key.as_bytes()).unwrap().unwrap(), "value".as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_version_retention() {
+        let merged_versions = |version_retention| {
+            let dir = tempdir().unwrap();
+            let storage_state = StorageState::open(StorageStateOptions {
+                path: dir.path().to_owned(),
+                version_retention,
+                ..Default::default()
+            })
+            .unwrap();
+            for value in ["v1", "v2", "v3"] {
+                storage_state.put(b"k1", value.as_bytes()).unwrap();
+                storage_state.put(b"k2", value.as_bytes()).unwrap();
+                storage_state.flush_all_memtables().unwrap();
+            }
+            storage_state.delete(b"k2").unwrap();
+            storage_state.flush_all_memtables().unwrap();
+            let inputs = storage_state.get_snapshot().ssts.iter().cloned().collect();
+            storage_state.merge_ssts(inputs, true).unwrap();
+            assert_eq!(storage_state.get_snapshot().ssts.len(), 1);
+            // the dropped tombstone takes the versions under it along
+            assert!(storage_state.get_versions(b"k2", 10).unwrap().is_empty());
+            let versions = storage_state.get_versions(b"k1", 10).unwrap();
+            versions.into_iter().map(|version| version.value.unwrap()).collect::<Vec<Bytes>>()
+        };
+        assert_eq!(merged_versions(VersionRetention::Latest), vec!["v3"]);
+        assert_eq!(merged_versions(VersionRetention::Count(2)), vec!["v3", "v2"]);
+        assert_eq!(
+            merged_versions(VersionRetention::Age(Duration::from_secs(3600))),
+            vec!["v3", "v2", "v1"]
+        );
+        assert_eq!(merged_versions(VersionRetention::Age(Duration::ZERO)), vec!["v3"]);
+    }
+
+    #[test]
+    fn test_sst_sequence_ranges() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options()).unwrap();
+        storage_state.put(b"k1", b"v1").unwrap();
+        storage_state.put(b"k2", b"v2").unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        storage_state.put(b"k3", b"v3").unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        let ranges: Vec<(u64, u64)> =
+            storage_state.get_snapshot().ssts.iter().map(|sst| sst.get_sequence_range()).collect();
+        assert_eq!(ranges, vec![(3, 3), (1, 2)]);
+
+        // loaded entries are stamped with the time of the load
+        storage_state.bulk_load([KeyValuePair::new(TimestampedKey::new("k0".into()), "v0".into())]).unwrap();
+        assert_eq!(storage_state.get_snapshot().ssts[0].get_sequence_range(), (4, 4));
+
+        // the output covers the range of its inputs
+        let inputs = storage_state.get_snapshot().ssts.iter().cloned().collect();
+        storage_state.merge_ssts(inputs, true).unwrap();
+        let snapshot = storage_state.get_snapshot();
+        assert_eq!(snapshot.ssts.len(), 1);
+        assert_eq!(snapshot.ssts[0].get_sequence_range(), (1, 4));
+        drop(storage_state);
+
+        // timestamps resume after the newest SST instead of starting over
+        let storage_state = StorageState::open(options()).unwrap();
+        assert_eq!(storage_state.snapshot().timestamp, 4);
+        storage_state.put(b"k4", b"v4").unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        assert_eq!(storage_state.get_snapshot().ssts[0].get_sequence_range(), (5, 5));
+    }
+
+    #[test]
+    fn test_repair_after_merge() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options()).unwrap();
+        storage_state.put(b"k1", b"old").unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        storage_state.put(b"k2", b"v2").unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        // written to a memtable older than the merge's output
+        storage_state.put(b"k1", b"new").unwrap();
+        let inputs = storage_state.get_snapshot().ssts.iter().cloned().collect();
+        storage_state.merge_ssts(inputs, true).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        let sst_ids = storage_state.get_snapshot().l0_sst_ids();
+        assert!(sst_ids[0] < sst_ids[1], "{:?}", sst_ids);
+        drop(storage_state);
+
+        std::fs::remove_file(dir.path().join("CURRENT")).unwrap();
+        assert_eq!(StorageState::repair(dir.path()).unwrap().sst_ids, sst_ids);
+        let storage_state = StorageState::open(options()).unwrap();
+        assert_eq!(storage_state.get(b"k1").unwrap().unwrap(), "new".as_bytes());
+        assert_eq!(storage_state.get(b"k2").unwrap().unwrap(), "v2".as_bytes());
+    }
+
+    #[test]
+    fn test_time_window_compaction() {
+        #[derive(Default)]
+        struct RecordingListener {
+            compactions: Mutex<Vec<(Vec<usize>, Vec<usize>)>>,
+            deleted: Mutex<Vec<usize>>,
+        }
+
+        impl EventListener for RecordingListener {
+            fn on_compaction_completed(&self, info: &CompactionJobInfo) {
+                let job = (info.input_sst_ids.clone(), info.output_sst_ids.clone());
+                self.compactions.lock().unwrap().push(job);
+            }
+
+            fn on_sst_deleted(&self, info: &SstDeletionInfo) {
+                assert!(!info.path.exists());
+                self.deleted.lock().unwrap().push(info.sst_id);
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let listener = Arc::new(RecordingListener::default());
+        let hour = Duration::from_secs(3600);
+        let options = StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            listeners: vec![listener.clone()],
+            compaction_style: CompactionStyle::TimeWindow(TimeWindowOptions {
+                window: hour,
+                min_merge_width: 3,
+                ttl: Some(2 * hour),
+            }),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        let writes: [&[(&str, &str)]; 4] = [
+            &[("a", "1"), ("b", "1")],
+            &[("a", "2"), ("b", "")],
+            &[("c", "1")],
+            &[("d", "1")],
+        ];
+        for kvs in writes {
+            for (key, value) in kvs {
+                match value.is_empty() {
+                    true => storage_state.delete(key.as_bytes()).unwrap(),
+                    false => storage_state.put(key.as_bytes(), value.as_bytes()).unwrap(),
+                }
+            }
+            storage_state.flush_all_memtables().unwrap();
+        }
+
+        // the three older SSTs are from a closed window, the newest one from
+        // the current window
+        let set_creation_times = |storage_state: &StorageState, creation_times: &[SystemTime]| {
+            let snapshot = storage_state.get_snapshot();
+            for (sst_file, created) in snapshot.l0_sst_files.iter().zip(creation_times) {
+                let file = File::options().write(true).open(dir.path().join(&sst_file.path)).unwrap();
+                file.set_modified(*created).unwrap();
+            }
+        };
+        let now = SystemTime::now();
+        let since_epoch = now.duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        let hour_start = now - Duration::from_secs(since_epoch.as_secs() % 3600);
+        let minute = Duration::from_secs(60);
+        let closed_window = hour_start - 2 * hour;
+        set_creation_times(
+            &storage_state,
+            &[now, closed_window + 3 * minute, closed_window + 2 * minute, closed_window + minute],
+        );
+        let input_ids = storage_state.get_snapshot().l0_sst_ids();
+
+        storage_state.trigger_compaction().unwrap();
+        let l0_ids = storage_state.get_snapshot().l0_sst_ids();
+        assert_eq!(l0_ids.len(), 2);
+        assert_eq!(l0_ids[0], input_ids[0]);
+        assert!(l0_ids[1] > input_ids[0]);
+        assert_eq!(
+            *listener.compactions.lock().unwrap(),
+            vec![(input_ids[1..].to_vec(), vec![l0_ids[1]])]
+        );
+        assert_eq!(*listener.deleted.lock().unwrap(), input_ids[1..].to_vec());
+        // the merged SST stays in its window
+        let merged = storage_state.get_snapshot().ssts[1].clone();
+        assert_eq!(merged.get_creation_time().unwrap(), closed_window + 3 * minute);
+        // nothing older is left, so the tombstone for b is gone
+        assert_eq!(storage_state.estimate_count(Bound::Unbounded, Bound::Unbounded).unwrap(), 3);
+        assert_eq!(storage_state.get("a".as_bytes()).unwrap().unwrap(), "2".as_bytes());
+        assert!(storage_state.get("b".as_bytes()).unwrap().is_none());
+        // a single SST per window is left alone
+        storage_state.trigger_compaction().unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), l0_ids);
+        drop(storage_state);
+
+        let storage_state = StorageState::open(options).unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), l0_ids);
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 3);
+
+        // whole windows are deleted once they age out
+        set_creation_times(&storage_state, &[now, hour_start - 4 * hour]);
+        storage_state.trigger_compaction().unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), vec![l0_ids[0]]);
+        assert_eq!(listener.deleted.lock().unwrap().last(), Some(&l0_ids[1]));
+        assert!(storage_state.get("a".as_bytes()).unwrap().is_none());
+        assert_eq!(storage_state.get("d".as_bytes()).unwrap().unwrap(), "1".as_bytes());
+    }
+
+    #[test]
+    fn test_blob_values() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            blob_threshold_bytes: Some(8),
+            ..Default::default()
+        };
+        let large = |version: u8| Bytes::from(vec![version; 100]);
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        storage_state.put("small".as_bytes(), "v1".as_bytes()).unwrap();
+        storage_state.put("large".as_bytes(), &large(1)).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        storage_state.put("large".as_bytes(), &large(2)).unwrap();
+        storage_state.put("other".as_bytes(), &large(3)).unwrap();
+        storage_state.delete("small".as_bytes()).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+
+        let snapshot = storage_state.get_snapshot();
+        let blob_paths: Vec<_> = snapshot
+            .l0_sst_files
+            .iter()
+            .map(|sst_file| blob_path(&dir.path().join(&sst_file.path)))
+            .collect();
+        assert!(blob_paths.iter().all(|path| path.exists()));
+        // only large values are moved out of the blocks
+        assert_eq!(snapshot.ssts.iter().map(|sst| sst.get_blob_file_size()).collect::<Vec<_>>(), vec![200, 100]);
+        assert_eq!(storage_state.get("large".as_bytes()).unwrap().unwrap(), large(2));
+        assert!(storage_state.get("small".as_bytes()).unwrap().is_none());
+        let values: Vec<Bytes> = storage_state
+            .scan(Bound::Unbounded, Bound::Unbounded)
+            .unwrap()
+            .map(|kv| kv.value)
+            .collect();
+        assert_eq!(values, vec![large(2), large(3), Bytes::new()]);
+
+        // compaction only carries the live values over to its blob file, and
+        // the old ones are deleted with their SSTs
+        storage_state.merge_ssts(snapshot.ssts.iter().cloned().collect(), true).unwrap();
+        let snapshot = storage_state.get_snapshot();
+        assert_eq!(snapshot.ssts.len(), 1);
+        assert_eq!(snapshot.ssts[0].get_blob_file_size(), 200);
+        assert!(blob_paths.iter().all(|path| !path.exists()));
+        drop(storage_state);
+
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        assert_eq!(storage_state.get("large".as_bytes()).unwrap().unwrap(), large(2));
+        assert_eq!(storage_state.get("other".as_bytes()).unwrap().unwrap(), large(3));
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 2);
+        drop(storage_state);
+        StorageState::destroy(dir.path()).unwrap();
+        assert!(!dir.path().exists());
+
+        // in-memory stores keep blobs in memory too
+        let storage_state = StorageState::open(StorageStateOptions {
+            in_memory: true,
+            ..options
+        })
+        .unwrap();
+        storage_state.put("large".as_bytes(), &large(1)).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        assert_eq!(storage_state.get_snapshot().ssts[0].get_blob_file_size(), 100);
+        assert_eq!(storage_state.get("large".as_bytes()).unwrap().unwrap(), large(1));
+    }
+
+    #[test]
+    fn test_blob_values_read_lazily() {
+        let dir = tempdir().unwrap();
+        let storage_state = StorageState::open(StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            blob_threshold_bytes: Some(8),
+            ..Default::default()
+        })
+        .unwrap();
+        let large = |version: u8| Bytes::from(vec![version; 100]);
+        storage_state.put("k1".as_bytes(), &large(1)).unwrap();
+        storage_state.put("k2".as_bytes(), &large(1)).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        storage_state.put("k1".as_bytes(), &large(2)).unwrap();
+        storage_state.delete("k2".as_bytes()).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+
+        // the older values can't be read anymore, and reads that don't return
+        // them never try to
+        let older_sst_file = storage_state.get_snapshot().l0_sst_files[1].clone();
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(blob_path(&dir.path().join(&older_sst_file.path)))
+            .unwrap();
+        file.set_len(0).unwrap();
+        assert_eq!(storage_state.get("k1".as_bytes()).unwrap().unwrap(), large(2));
+        assert!(storage_state.get("k2".as_bytes()).unwrap().is_none());
+        let options = ReadOptions {
+            ignore_tombstones: true,
+            ..Default::default()
+        };
+        let mut scan = storage_state
+            .scan_with_options(Bound::Unbounded, Bound::Unbounded, &options)
+            .unwrap();
+        assert_eq!(scan.by_ref().map(|kv| kv.value).collect::<Vec<_>>(), vec![large(2)]);
+        scan.check_error().unwrap();
+        assert_eq!(storage_state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 1);
+        // reading them does fail
+        assert!(storage_state.get_versions("k1".as_bytes(), 2).is_err());
+    }
+
+    #[test]
+    fn test_keys_only_scan() {
+        let dir = tempdir().unwrap();
+        let storage_state = StorageState::open(StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            blob_threshold_bytes: Some(8),
+            ..Default::default()
+        })
+        .unwrap();
+        storage_state.put("k1".as_bytes(), &[1; 100]).unwrap();
+        storage_state.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        storage_state.put("k3".as_bytes(), "v3".as_bytes()).unwrap();
+        storage_state.flush_all_memtables().unwrap();
+        storage_state.delete("k2".as_bytes()).unwrap();
+        storage_state.put("k4".as_bytes(), "v4".as_bytes()).unwrap();
+
+        // no value is read, so a blob file that can't be read doesn't matter
+        let sst_file = storage_state.get_snapshot().l0_sst_files[0].clone();
+        let file = std::fs::OpenOptions::new()
+            .write(true)
+            .open(blob_path(&dir.path().join(&sst_file.path)))
+            .unwrap();
+        file.set_len(0).unwrap();
+        let options = ReadOptions {
+            keys_only: true,
+            ..Default::default()
+        };
+        let mut scan = storage_state
+            .scan_with_options(Bound::Unbounded, Bound::Unbounded, &options)
+            .unwrap();
+        let entries: Vec<_> = scan
+            .by_ref()
+            .map(|kv| (kv.key.get_key(), kv.is_tombstone(), kv.value))
+            .collect();
+        scan.check_error().unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                (Bytes::from("k1"), false, Bytes::new()),
+                (Bytes::from("k2"), true, Bytes::new()),
+                (Bytes::from("k3"), false, Bytes::new()),
+                (Bytes::from("k4"), false, Bytes::new()),
+            ]
+        );
+        let keys: Vec<_> = storage_state.scan_keys(Bound::Unbounded, Bound::Unbounded).unwrap().collect();
+        assert_eq!(keys, vec!["k1", "k3", "k4"]);
+        assert_eq!(storage_state.get_with_options("k1".as_bytes(), &options).unwrap(), Some(Bytes::new()));
+        assert!(storage_state.get("k1".as_bytes()).is_err());
+        // a scan that reads values can't get past k1
+        let mut scan = storage_state.scan(Bound::Unbounded, Bound::Unbounded).unwrap();
+        assert_eq!(scan.by_ref().count(), 0);
+        assert!(scan.check_error().is_err());
+    }
+
+    #[test]
+    fn test_fifo_max_db_size() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            compaction_style: CompactionStyle::Fifo,
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options.clone()).unwrap();
+        for i in 0..4 {
+            storage_state.put(format!("k{}", i).as_bytes(), "v".as_bytes()).unwrap();
+            storage_state.flush_all_memtables().unwrap();
+        }
+        // no limit, nothing to evict
+        storage_state.trigger_compaction().unwrap();
+        let snapshot = storage_state.get_snapshot();
+        let l0_ids = snapshot.l0_sst_ids();
+        assert_eq!(l0_ids.len(), 4);
+        let db_size: u64 = snapshot.ssts.iter().map(|sst| sst.get_file_size()).sum();
+        drop(storage_state);
+
+        // room for a bit more than two SSTs keeps the newest two
+        let sst_size = db_size / 4;
+        let storage_state = StorageState::open(StorageStateOptions {
+            max_db_size_bytes: Some(2 * sst_size + sst_size / 2),
+            ..options
+        })
+        .unwrap();
+        storage_state.trigger_compaction().unwrap();
+        assert_eq!(storage_state.get_snapshot().l0_sst_ids(), l0_ids[..2]);
+        assert!(storage_state.get("k1".as_bytes()).unwrap().is_none());
+        assert_eq!(storage_state.get("k2".as_bytes()).unwrap().unwrap(), "v".as_bytes());
+        assert_eq!(StorageState::list_sst_files(dir.path()).unwrap().len(), 2);
+    }
 }
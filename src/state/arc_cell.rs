@@ -0,0 +1,79 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use crossbeam_epoch::{self as epoch, Atomic, Owned};
+
+// an Arc that readers load without ever blocking, while it is swapped out
+// from under them. a reader only pins the epoch for as long as it takes to
+// clone the Arc, and a replaced Arc is dropped once no pinned reader can
+// still be looking at it
+pub(crate) struct ArcCell<T: Send + Sync> {
+    current: Atomic<Arc<T>>,
+}
+
+impl<T: Send + Sync> ArcCell<T> {
+    pub(crate) fn new(value: Arc<T>) -> Self {
+        Self {
+            current: Atomic::new(value),
+        }
+    }
+
+    pub(crate) fn load(&self) -> Arc<T> {
+        let guard = epoch::pin();
+        let current = self.current.load(Ordering::Acquire, &guard);
+        // never null, and not freed while the guard is pinned
+        unsafe { current.deref() }.clone()
+    }
+
+    pub(crate) fn store(&self, value: Arc<T>) {
+        let guard = epoch::pin();
+        let replaced = self.current.swap(Owned::new(value), Ordering::AcqRel, &guard);
+        // readers that loaded it before the swap are pinned until they have
+        // cloned it
+        unsafe { guard.defer_destroy(replaced) };
+    }
+}
+
+impl<T: Send + Sync> Drop for ArcCell<T> {
+    fn drop(&mut self) {
+        // no reader can hold a reference into a cell that is being dropped
+        unsafe { drop(self.current.load(Ordering::Relaxed, epoch::unprotected()).into_owned()) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, thread};
+
+    use super::ArcCell;
+
+    #[test]
+    fn test_load_store() {
+        let cell = Arc::new(ArcCell::new(Arc::new(0)));
+        assert_eq!(*cell.load(), 0);
+        let readers: Vec<_> = (0..4)
+            .map(|_| {
+                let cell = cell.clone();
+                thread::spawn(move || {
+                    let mut last = 0;
+                    for _ in 0..1000 {
+                        let value = *cell.load();
+                        assert!(value >= last);
+                        last = value;
+                    }
+                })
+            })
+            .collect();
+        for value in 1..=1000 {
+            cell.store(Arc::new(value));
+        }
+        for reader in readers {
+            reader.join().unwrap();
+        }
+        assert_eq!(*cell.load(), 1000);
+
+        // loaded values outlive the cell
+        let value = cell.load();
+        drop(cell);
+        assert_eq!(*value, 1000);
+    }
+}
@@ -0,0 +1,265 @@
+use std::{
+    collections::HashSet,
+    fs::{create_dir_all, read_dir, File},
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Result;
+
+use crate::{
+    error::LsmError,
+    manifest::{Manifest, ManifestRecord, SstFile},
+    table::blob::blob_path,
+};
+
+use super::{StorageState, MANIFEST_MAX_RECORDS};
+
+// names the backup an incremental backup was taken on top of
+const BASE_FILE_NAME: &str = "BASE";
+
+// each backup of a store is a numbered directory in the backup directory,
+// holding a manifest of l0 as it was and the SSTs (and their blob files) at
+// the paths the manifest records. a full backup holds every SST, an
+// incremental one only those its base doesn't, so restoring looks for each
+// SST along the chain of bases. the manifest goes in last, so a backup
+// without one was interrupted and can't be restored
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BackupInfo {
+    pub id: usize,
+    pub base_id: Option<usize>,
+    // in the backup's manifest
+    pub num_ssts: usize,
+    // copied into this backup rather than taken from its base
+    pub num_copied_ssts: usize,
+    pub copied_bytes: u64,
+}
+
+impl StorageState {
+    // back up every write made so far into backup_dir/id. with a base, SSTs
+    // the base backup has are left out
+    pub(crate) fn create_backup(&self, backup_dir: impl AsRef<Path>, id: usize, base_id: Option<usize>) -> Result<BackupInfo> {
+        let backup_dir = backup_dir.as_ref();
+        let Some(manifest) = &self.manifest else {
+            return Err(LsmError::InvalidOptions("in-memory stores can't be backed up".to_string()).into());
+        };
+        if base_id.is_some_and(|base_id| base_id >= id) {
+            return Err(LsmError::InvalidOptions(format!("backup {} can't be based on newer backup {:?}", id, base_id)).into());
+        }
+        let backup_path = Self::get_backup_path(backup_dir, id);
+        if backup_path.exists() {
+            return Err(LsmError::AlreadyExists(backup_path).into());
+        }
+        let in_base: HashSet<usize> = match base_id {
+            Some(base_id) => Self::read_backup(&Self::get_backup_path(backup_dir, base_id))?
+                .into_iter()
+                .map(|sst_file| sst_file.id)
+                .collect(),
+            None => HashSet::new(),
+        };
+
+        self.flush_all_memtables()?;
+        // compactions delete the SSTs they replace, so keep them out until
+        // the files are copied
+        let _compaction_guard = self.compaction_lock.lock().unwrap();
+        let l0_sst_files = self.state_lock.read().unwrap().l0_sst_files.clone();
+        create_dir_all(&backup_path)?;
+        let mut info = BackupInfo {
+            id,
+            base_id,
+            num_ssts: l0_sst_files.len(),
+            num_copied_ssts: 0,
+            copied_bytes: 0,
+        };
+        for sst_file in l0_sst_files.iter() {
+            if sst_file.path.is_absolute() {
+                return Err(LsmError::InvalidOptions(format!(
+                    "sst {:?} is outside the store directory and can't be backed up",
+                    sst_file.path
+                ))
+                .into());
+            }
+            if in_base.contains(&sst_file.id) {
+                continue;
+            }
+            info.copied_bytes += copy_sst(&self.options.path.join(&sst_file.path), &backup_path.join(&sst_file.path))?;
+            info.num_copied_ssts += 1;
+        }
+        if let Some(base_id) = base_id {
+            std::fs::write(backup_path.join(BASE_FILE_NAME), base_id.to_string())?;
+        }
+        let (backup_manifest, _) = Manifest::open(&backup_path, MANIFEST_MAX_RECORDS, false)?;
+        if let Some(config) = manifest.config() {
+            backup_manifest.add_record(&ManifestRecord::Config(config))?;
+        }
+        backup_manifest.add_record(&ManifestRecord::Snapshot(l0_sst_files.into()))?;
+        Ok(info)
+    }
+
+    // recreate the store as of backup id at path, which must not hold one
+    pub(crate) fn restore_backup(backup_dir: impl AsRef<Path>, id: usize, path: impl AsRef<Path>) -> Result<()> {
+        let backup_dir = backup_dir.as_ref();
+        let path = path.as_ref();
+        if Manifest::exists(path) {
+            return Err(LsmError::AlreadyExists(path.to_owned()).into());
+        }
+        // the backup, then its base, and so on down to a full backup. bases
+        // are older, so the chain can't loop
+        let mut chain = vec![Self::get_backup_path(backup_dir, id)];
+        let mut chain_id = id;
+        while let Some(base_id) = Self::read_base_id(chain.last().expect("chain starts with the backup"))? {
+            if base_id >= chain_id {
+                return Err(LsmError::Corruption(format!("backup {} has newer base {}", chain_id, base_id)).into());
+            }
+            chain.push(Self::get_backup_path(backup_dir, base_id));
+            chain_id = base_id;
+        }
+        let (backup_manifest, records) = Manifest::open(&chain[0], MANIFEST_MAX_RECORDS, true)?;
+        let sst_files = Self::replay_sst_files(records);
+        for sst_file in sst_files.iter() {
+            let source = chain
+                .iter()
+                .map(|backup_path| backup_path.join(&sst_file.path))
+                .find(|source| source.exists())
+                .ok_or_else(|| {
+                    LsmError::Corruption(format!("sst {:?} of backup {} is missing", sst_file.path, id))
+                })?;
+            copy_sst(&source, &path.join(&sst_file.path))?;
+        }
+        create_dir_all(path)?;
+        let (manifest, _) = Manifest::open(path, MANIFEST_MAX_RECORDS, false)?;
+        if let Some(config) = backup_manifest.config() {
+            manifest.add_record(&ManifestRecord::Config(config))?;
+        }
+        manifest.add_record(&ManifestRecord::Snapshot(sst_files.into()))?;
+        Ok(())
+    }
+
+    // ids of the backups in backup_dir, finished or not, in increasing order
+    pub(crate) fn list_backup_ids(backup_dir: impl AsRef<Path>) -> Result<Vec<usize>> {
+        let mut ids = Vec::new();
+        let entries = match read_dir(backup_dir) {
+            Ok(entries) => entries,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(ids),
+            Err(err) => return Err(err.into()),
+        };
+        for entry in entries {
+            let entry = entry?;
+            let id = entry.file_name().to_str().and_then(|name| name.parse::<usize>().ok());
+            if let (Some(id), true) = (id, entry.file_type()?.is_dir()) {
+                ids.push(id);
+            }
+        }
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    // the SSTs of a finished backup
+    fn read_backup(backup_path: &Path) -> Result<Vec<SstFile>> {
+        if !Manifest::exists(backup_path) {
+            return Err(LsmError::NotFound(backup_path.to_owned()).into());
+        }
+        let (_, records) = Manifest::open(backup_path, MANIFEST_MAX_RECORDS, true)?;
+        Ok(Self::replay_sst_files(records).into())
+    }
+
+    fn read_base_id(backup_path: &Path) -> Result<Option<usize>> {
+        if !Manifest::exists(backup_path) {
+            return Err(LsmError::NotFound(backup_path.to_owned()).into());
+        }
+        let base_path = backup_path.join(BASE_FILE_NAME);
+        if !base_path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&base_path)?;
+        let base_id = contents.trim().parse().map_err(|_| {
+            LsmError::Corruption(format!("{} of {:?} contains invalid id {:?}", BASE_FILE_NAME, backup_path, contents))
+        })?;
+        Ok(Some(base_id))
+    }
+
+    fn get_backup_path(backup_dir: &Path, id: usize) -> PathBuf {
+        backup_dir.join(id.to_string())
+    }
+}
+
+// copy an SST and its blob file, if it has one, syncing both. returns the
+// number of bytes copied
+fn copy_sst(from: &Path, to: &Path) -> Result<u64> {
+    if let Some(parent) = to.parent() {
+        create_dir_all(parent)?;
+    }
+    let mut copied_bytes = copy_synced(from, to)?;
+    if blob_path(from).exists() {
+        copied_bytes += copy_synced(&blob_path(from), &blob_path(to))?;
+    }
+    Ok(copied_bytes)
+}
+
+fn copy_synced(from: &Path, to: &Path) -> Result<u64> {
+    let copied_bytes = std::fs::copy(from, to)?;
+    File::open(to)?.sync_all()?;
+    Ok(copied_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use crate::{
+        error::LsmError,
+        state::{storage_state_options::StorageStateOptions, StorageState},
+    };
+
+    #[test]
+    fn test_incremental_backup() {
+        let store_dir = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        let options = |path: &std::path::Path| StorageStateOptions {
+            path: path.to_owned(),
+            ..Default::default()
+        };
+        let storage_state = StorageState::open(options(store_dir.path())).unwrap();
+        storage_state.put(b"k1", b"v1").unwrap();
+        storage_state.put(b"k2", b"v2").unwrap();
+        let full = storage_state.create_backup(backup_dir.path(), 1, None).unwrap();
+        assert_eq!((full.num_ssts, full.num_copied_ssts), (1, 1));
+        assert!(full.copied_bytes > 0);
+
+        // only the SST flushed since is copied
+        storage_state.put(b"k2", b"new_v2").unwrap();
+        storage_state.put(b"k3", b"v3").unwrap();
+        let incremental = storage_state.create_backup(backup_dir.path(), 2, Some(1)).unwrap();
+        assert_eq!((incremental.num_ssts, incremental.num_copied_ssts), (2, 1));
+        // and after a compaction, only its output
+        let inputs = storage_state.get_snapshot().ssts.iter().cloned().collect();
+        storage_state.merge_ssts(inputs, true).unwrap();
+        storage_state.delete(b"k1").unwrap();
+        let after_compaction = storage_state.create_backup(backup_dir.path(), 3, Some(2)).unwrap();
+        assert_eq!((after_compaction.num_ssts, after_compaction.num_copied_ssts), (2, 2));
+        assert!(storage_state.create_backup(backup_dir.path(), 3, Some(2)).is_err());
+        assert!(storage_state.create_backup(backup_dir.path(), 4, Some(5)).is_err());
+        assert_eq!(StorageState::list_backup_ids(backup_dir.path()).unwrap(), vec![1, 2, 3]);
+        drop(storage_state);
+
+        let expected = [
+            (1, [Some("v1"), Some("v2"), None]),
+            (2, [Some("v1"), Some("new_v2"), Some("v3")]),
+            (3, [None, Some("new_v2"), Some("v3")]),
+        ];
+        for (id, values) in expected {
+            let restore_dir = tempdir().unwrap();
+            StorageState::restore_backup(backup_dir.path(), id, restore_dir.path()).unwrap();
+            let restored = StorageState::open(options(restore_dir.path())).unwrap();
+            for (key, value) in ["k1", "k2", "k3"].iter().zip(values) {
+                assert_eq!(restored.get(key.as_bytes()).unwrap(), value.map(Bytes::from), "backup {}", id);
+            }
+            drop(restored);
+            let err = StorageState::restore_backup(backup_dir.path(), id, restore_dir.path()).unwrap_err();
+            assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::AlreadyExists(_))));
+        }
+        let err = StorageState::restore_backup(backup_dir.path(), 4, tempdir().unwrap().path()).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::NotFound(_))));
+    }
+}
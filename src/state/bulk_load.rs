@@ -0,0 +1,177 @@
+use std::{
+    fs::remove_file,
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+};
+
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    kv::kv_pair::KeyValuePair,
+    manifest::SstFile,
+    memory::memtable::MemTable,
+    table::{blob::remove_blob_file, builder::SSTBuilder, Sst},
+};
+
+use super::StorageState;
+
+// writes pre-sorted key-value pairs straight into SSTs, skipping the memtable.
+// finishing installs the new SSTs together with one manifest record. loaded
+// keys take precedence over anything written before the loader was created,
+// and writes made while it is open take precedence over them. SSTs of a
+// loader that is dropped unfinished are deleted
+pub(crate) struct BulkLoader<'a> {
+    storage_state: &'a StorageState,
+    // taken when the loader is created, and given to every loaded entry
+    sequence: u64,
+    // the active memtable when the loader was created. memtables with older
+    // ids hold writes from before the load
+    boundary_memtable_id: usize,
+    sst_builder: Option<SSTBuilder>,
+    // built but not yet installed
+    ssts: Vec<(SstFile, Sst)>,
+    // removed on drop unless the SSTs were installed. empty for in-memory
+    // stores
+    sst_paths: Vec<PathBuf>,
+    last_key: Option<Bytes>,
+    finished: bool,
+}
+
+impl<'a> BulkLoader<'a> {
+    pub(super) fn new(storage_state: &'a StorageState) -> Result<Self> {
+        // counted first, so that flushes from here on don't merge memtables
+        // from both sides of the boundary
+        storage_state.bulk_loads_in_progress.fetch_add(1, Ordering::SeqCst);
+        let mut loader = Self {
+            storage_state,
+            sequence: 0,
+            boundary_memtable_id: 0,
+            sst_builder: None,
+            ssts: Vec::new(),
+            sst_paths: Vec::new(),
+            last_key: None,
+            finished: false,
+        };
+        // writes are held off while the memtable is frozen and the sequence
+        // taken, so every write before the sequence is in a frozen memtable
+        // and every write after it in the new one
+        let mut rw_guard = storage_state.state_lock.write().unwrap();
+        if !rw_guard.current_memtable.is_empty() {
+            let mut rw_snapshot = rw_guard.as_ref().clone();
+            rw_snapshot.current_memtable.freeze()?;
+            rw_snapshot
+                .frozen_memtables
+                .push_front(rw_snapshot.current_memtable.clone());
+            rw_snapshot.current_memtable = Arc::new(MemTable::new_with_rep(
+                storage_state.get_next_sst_id(),
+                storage_state.options.memtable_rep,
+            ));
+            storage_state.install(&mut rw_guard, rw_snapshot);
+        }
+        // nothing goes into the memtable, so the stamp is published at once
+        loader.sequence = storage_state.stamp().timestamp;
+        loader.boundary_memtable_id = rw_guard.current_memtable.get_id();
+        Ok(loader)
+    }
+
+    // keys must be strictly increasing
+    pub(crate) fn add(&mut self, kv: KeyValuePair) -> Result<()> {
+        let key = kv.key.get_key();
+        if self.last_key.as_ref().is_some_and(|last_key| *last_key >= key) {
+            bail!("bulk load keys must be strictly increasing, got {:?} after {:?}", key, self.last_key);
+        }
+        self.last_key = Some(key);
+        let storage_state = self.storage_state;
+        let sst_builder = self.sst_builder.get_or_insert_with(|| storage_state.new_sst_builder());
+        sst_builder.add(&kv)?;
+        if sst_builder.get_estimated_size() >= storage_state.options.sst_max_size_bytes {
+            self.finish_sst()?;
+        }
+        Ok(())
+    }
+
+    // install the loaded SSTs and return their ids
+    pub(crate) fn finish(mut self) -> Result<Vec<usize>> {
+        self.finish_sst()?;
+        if self.ssts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let storage_state = self.storage_state;
+        // memtables frozen when the loader was created are older than the
+        // load, so they have to be in l0, behind it. flushes take one memtable
+        // at a time while a load is open, so none of their SSTs holds writes
+        // from after it
+        loop {
+            let has_older_memtables = {
+                let ro_snapshot = storage_state.state_lock.read().unwrap();
+                ro_snapshot
+                    .frozen_memtables
+                    .iter()
+                    .any(|memtable| memtable.get_id() < self.boundary_memtable_id)
+            };
+            if !has_older_memtables {
+                break;
+            }
+            storage_state.flush_next_memtable_to_l0()?;
+        }
+        // a flush or merge of the loaded keys installs before or after the
+        // load, not in the middle of it
+        let first_key = self.ssts[0].1.get_first_key().get_key();
+        let last_key = self.ssts[self.ssts.len() - 1].1.get_last_key().get_key();
+        let _range_guard = storage_state.range_locks.lock(first_key, last_key);
+        let mut rw_guard = storage_state.state_lock.write().unwrap();
+        let mut rw_snapshot = rw_guard.as_ref().clone();
+        let ids: Vec<usize> = self.ssts.iter().map(|(sst_file, _)| sst_file.id).collect();
+        // ahead of every SST with only writes from before the load, and
+        // behind those flushed from memtables created since. the loaded SSTs
+        // don't overlap, so their order among themselves doesn't matter, but
+        // newest id first is the order repair gives them
+        let position = rw_snapshot
+            .ssts
+            .iter()
+            .position(|sst| sst.get_sequence_range().1 < self.sequence)
+            .unwrap_or(rw_snapshot.ssts.len());
+        for (sst_file, sst) in self.ssts.drain(..) {
+            rw_snapshot.l0_sst_files.insert(position, sst_file);
+            rw_snapshot.ssts.insert(position, Arc::new(sst));
+        }
+        // insertions in the middle can't be replayed as flushes
+        storage_state.record_snapshot(&rw_snapshot.l0_sst_files)?;
+        storage_state.install(&mut rw_guard, rw_snapshot);
+        self.finished = true;
+        Ok(ids)
+    }
+
+    fn finish_sst(&mut self) -> Result<()> {
+        let Some(sst_builder) = self.sst_builder.take() else {
+            return Ok(());
+        };
+        let storage_state = self.storage_state;
+        let mut sst_builder = sst_builder;
+        // loaded entries have no timestamps of their own
+        sst_builder.set_sequence_range(self.sequence, self.sequence);
+        let sst_id = storage_state.get_next_sst_id();
+        let sst_file = storage_state.new_sst_file(sst_id, 0);
+        let path = storage_state.options.path.join(&sst_file.path);
+        if !storage_state.options.in_memory {
+            self.sst_paths.push(path.clone());
+        }
+        let sst = storage_state.build_sst(sst_builder, sst_id, &path)?;
+        self.ssts.push((sst_file, sst));
+        Ok(())
+    }
+}
+
+impl Drop for BulkLoader<'_> {
+    fn drop(&mut self) {
+        self.storage_state.bulk_loads_in_progress.fetch_sub(1, Ordering::SeqCst);
+        if self.finished {
+            return;
+        }
+        for path in self.sst_paths.iter() {
+            let _ = remove_file(path);
+            let _ = remove_blob_file(path);
+        }
+    }
+}
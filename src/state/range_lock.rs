@@ -0,0 +1,122 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Condvar, Mutex,
+    },
+    time::Duration,
+};
+
+use bytes::Bytes;
+
+use crate::platform::Instant;
+
+// key ranges held by the jobs that install SSTs into l0: flushes, merges and
+// bulk loads. a job waits for the jobs whose ranges overlap its own, so SSTs
+// covering the same keys are installed one job at a time, in the order the
+// jobs got their locks. jobs on disjoint ranges don't wait on each other
+#[derive(Default)]
+pub(crate) struct RangeLockTable {
+    held: Mutex<HeldRanges>,
+    released: Condvar,
+    num_waits: AtomicU64,
+    wait_nanos: AtomicU64,
+}
+
+#[derive(Default)]
+struct HeldRanges {
+    next_token: u64,
+    // token, first and last key, both inclusive
+    ranges: Vec<(u64, Bytes, Bytes)>,
+}
+
+impl HeldRanges {
+    fn overlaps(&self, first_key: &[u8], last_key: &[u8]) -> bool {
+        self.ranges
+            .iter()
+            .any(|(_, held_first, held_last)| held_first.as_ref() <= last_key && first_key <= held_last.as_ref())
+    }
+}
+
+impl RangeLockTable {
+    // blocks until no held range overlaps [first_key, last_key]
+    pub(super) fn lock(&self, first_key: Bytes, last_key: Bytes) -> RangeLockGuard<'_> {
+        let mut held = self.held.lock().unwrap();
+        if held.overlaps(&first_key, &last_key) {
+            let started = Instant::now();
+            while held.overlaps(&first_key, &last_key) {
+                held = self.released.wait(held).unwrap();
+            }
+            self.num_waits.fetch_add(1, Ordering::Relaxed);
+            self.wait_nanos.fetch_add(started.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        }
+        let token = held.next_token;
+        held.next_token += 1;
+        held.ranges.push((token, first_key, last_key));
+        RangeLockGuard { table: self, token }
+    }
+
+    // locks that had to wait for an overlapping one, and how long they waited
+    // in total
+    pub(super) fn contention(&self) -> (u64, Duration) {
+        (
+            self.num_waits.load(Ordering::Relaxed),
+            Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+        )
+    }
+}
+
+pub(crate) struct RangeLockGuard<'a> {
+    table: &'a RangeLockTable,
+    token: u64,
+}
+
+impl Drop for RangeLockGuard<'_> {
+    fn drop(&mut self) {
+        let mut held = self.table.held.lock().unwrap();
+        held.ranges.retain(|(token, _, _)| *token != self.token);
+        self.table.released.notify_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use bytes::Bytes;
+
+    use super::RangeLockTable;
+
+    #[test]
+    fn test_range_lock() {
+        let table = Arc::new(RangeLockTable::default());
+        let guard = table.lock(Bytes::from("b"), Bytes::from("d"));
+        // disjoint ranges don't wait
+        drop(table.lock(Bytes::from("a"), Bytes::from("a")));
+        drop(table.lock(Bytes::from("e"), Bytes::from("f")));
+        assert_eq!(table.contention().0, 0);
+
+        let acquired = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let (table, acquired) = (table.clone(), acquired.clone());
+            thread::spawn(move || {
+                let _guard = table.lock(Bytes::from("d"), Bytes::from("z"));
+                acquired.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!acquired.load(Ordering::SeqCst));
+        drop(guard);
+        waiter.join().unwrap();
+        assert!(acquired.load(Ordering::SeqCst));
+        let (num_waits, wait_duration) = table.contention();
+        assert_eq!(num_waits, 1);
+        assert!(wait_duration > Duration::ZERO);
+    }
+}
@@ -0,0 +1,71 @@
+use crate::{
+    iterator::{latest_iterator::LatestIterator, limit_iterator::LimitIterator, StorageIterator},
+    kv::kv_pair::KeyValuePair,
+};
+
+use super::{snapshot::Snapshot, write_options::WriteToken};
+
+// per-read settings, see LsmStore::get_with_options and scan_with_options.
+// more may be added, so set the ones needed and take the rest from
+// ..Default::default()
+#[derive(Clone, Debug)]
+pub struct ReadOptions {
+    // read the store as it was when the snapshot was taken, see
+    // LsmStore::snapshot. None reads everything written so far
+    pub snapshot: Option<Snapshot>,
+    // insert blocks read into the block cache. turn this off for long scans
+    // that would otherwise evict the hot working set; blocks that are
+    // already cached are still used, and the block being iterated stays
+    // pinned in memory by the iterator either way
+    pub fill_cache: bool,
+    // scans only. read upcoming SST blocks on a background thread, up to this
+    // many bytes ahead of the iterator. helps sequential scans on slow or
+    // remote disks. 0 turns read-ahead off
+    pub readahead_bytes: usize,
+    // scans only. leave out deleted keys, like LsmStore::iter does. scans
+    // yield only the newest version of each key either way, so otherwise
+    // tombstones are returned as entries with empty values
+    pub ignore_tombstones: bool,
+    // scans only. stop after this many entries
+    pub limit: Option<usize>,
+    // return values empty, without reading them from blocks or blob files,
+    // for when only keys matter. a get then only tells whether the key has
+    // a value
+    pub keys_only: bool,
+    // fail with LsmError::SequenceNotReached unless the read sees every write
+    // up to the token's, e.g. so that a client reading from a replica sees
+    // its own writes. a store always sees the writes it returned tokens for,
+    // snapshots those made before they were taken
+    pub min_sequence: Option<WriteToken>,
+}
+
+impl Default for ReadOptions {
+    fn default() -> Self {
+        Self {
+            snapshot: None,
+            fill_cache: true,
+            readahead_bytes: 0,
+            ignore_tombstones: false,
+            limit: None,
+            keys_only: false,
+            min_sequence: None,
+        }
+    }
+}
+
+pub(crate) type ReadOptionsIterator = LimitIterator<Box<dyn StorageIterator<Item = KeyValuePair>>>;
+
+impl ReadOptions {
+    // ignore_tombstones and limit, applied on top of the merged entries
+    pub(crate) fn apply(
+        &self,
+        iterator: impl StorageIterator<Item = KeyValuePair> + 'static,
+    ) -> ReadOptionsIterator {
+        let entries: Box<dyn StorageIterator<Item = KeyValuePair>> = if self.ignore_tombstones {
+            Box::new(LatestIterator::new(iterator))
+        } else {
+            Box::new(iterator)
+        };
+        LimitIterator::new(entries, self.limit)
+    }
+}
@@ -0,0 +1,69 @@
+use std::{cmp::Reverse, path::Path};
+
+use anyhow::Result;
+
+use crate::{
+    manifest::{Manifest, ManifestRecord, SstFile},
+    table::Sst,
+};
+
+use super::{StorageState, MANIFEST_MAX_RECORDS};
+
+// what repair rebuilt the manifest from
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    // the recovered SSTs, newest to oldest
+    pub sst_ids: Vec<usize>,
+    // SSTs that failed to open or validate. they are left on disk but out of
+    // the manifest
+    pub skipped_sst_ids: Vec<usize>,
+}
+
+impl StorageState {
+    // rebuild the manifest from the SST files in path, for when the manifest
+    // is lost or corrupt. SSTs in level_paths outside path are not recovered.
+    // flushes that split into several SSTs and merges give out ids that are
+    // newer than live memtables, so l0 is ordered by the newest sequence
+    // number in each SST rather than by id. SSTs written before sequences
+    // were recorded are older than all others, and ordered by id among
+    // themselves
+    pub(crate) fn repair(path: impl AsRef<Path>) -> Result<RepairReport> {
+        let path = path.as_ref();
+        let mut ssts = Vec::new();
+        let mut skipped_sst_ids = Vec::new();
+        for (sst_id, sst_path) in Self::list_sst_files(path)? {
+            let sst = Sst::open(sst_id, sst_path.clone(), None).and_then(|sst| sst.validate().map(|_| sst));
+            let Result::Ok(sst) = sst else {
+                skipped_sst_ids.push(sst_id);
+                continue;
+            };
+            let sst_file = SstFile {
+                id: sst_id,
+                path: sst_path
+                    .strip_prefix(path)
+                    .expect("listed ssts are inside the store directory")
+                    .to_owned(),
+            };
+            ssts.push((sst.get_sequence_range().1, sst_file));
+        }
+        ssts.sort_unstable_by_key(|(max_sequence, sst_file)| Reverse((*max_sequence, sst_file.id)));
+        skipped_sst_ids.sort_unstable();
+        let sst_files: Vec<SstFile> = ssts.into_iter().map(|(_, sst_file)| sst_file).collect();
+
+        // keep the recorded config if it can still be read, so that a store
+        // this build can't read stays refused
+        let config = Manifest::open(path, MANIFEST_MAX_RECORDS, false)
+            .ok()
+            .and_then(|(manifest, _)| manifest.config());
+        Manifest::destroy(path)?;
+        let (manifest, _) = Manifest::open(path, MANIFEST_MAX_RECORDS, false)?;
+        if let Some(config) = config {
+            manifest.add_record(&ManifestRecord::Config(config))?;
+        }
+        manifest.add_record(&ManifestRecord::Snapshot(sst_files.clone()))?;
+        Ok(RepairReport {
+            sst_ids: sst_files.into_iter().map(|sst_file| sst_file.id).collect(),
+            skipped_sst_ids,
+        })
+    }
+}
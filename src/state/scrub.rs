@@ -0,0 +1,247 @@
+use std::{
+    fs::{create_dir_all, rename},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+
+use crate::{
+    error::LsmError,
+    listener::CorruptionInfo,
+    scheduler::{BackgroundScheduler, TaskPriority},
+    table::{blob::blob_path, Sst},
+};
+
+use super::StorageState;
+
+// quarantined SSTs are moved here, inside the store directory, under a name
+// that repair doesn't pick up
+const QUARANTINE_DIR_NAME: &str = "quarantine";
+
+// the format has no checksums, so the scrubber checks what can be checked:
+// that blocks decode, that their keys are in order and match the block index,
+// and that every value resolves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ScrubOptions {
+    // time between rounds
+    pub interval: Duration,
+    // blocks re-read per round. a round never goes past the last SST, so a
+    // small store isn't scrubbed over and over
+    pub blocks_per_round: usize,
+    // take corrupted SSTs out of the store and move their files to the
+    // quarantine directory, after which their keys read as missing (or as
+    // older versions). otherwise corruption is only reported to listeners
+    pub quarantine: bool,
+}
+
+impl Default for ScrubOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(1),
+            blocks_per_round: 16,
+            quarantine: false,
+        }
+    }
+}
+
+// the next block to verify. SSTs are visited in id order, so every SST gets
+// its turn wherever compaction puts it in l0
+#[derive(Default)]
+pub(super) struct ScrubCursor {
+    sst_id: usize,
+    block_index: usize,
+}
+
+impl StorageState {
+    pub(crate) fn schedule_scrub(self: &Arc<Self>, scheduler: &BackgroundScheduler) -> Result<()> {
+        let Some(scrub) = self.options.scrub else {
+            return Ok(());
+        };
+        let this = self.clone();
+        scheduler.submit_periodic(TaskPriority::Scrub, scrub.interval, move || {
+            this.scrub_round(scrub.blocks_per_round, scrub.quarantine)
+        })?;
+        Ok(())
+    }
+
+    // verify up to num_blocks blocks after the cursor. corruption is reported
+    // rather than returned, other errors (e.g. a compaction deleting the SST
+    // being read) end the round
+    pub(crate) fn scrub_round(&self, num_blocks: usize, quarantine: bool) -> Result<()> {
+        let mut ssts: Vec<Arc<Sst>> = self.published_state.load().ssts.iter().cloned().collect();
+        ssts.sort_unstable_by_key(|sst| sst.get_id());
+        let mut cursor = self.scrub_cursor.lock().unwrap();
+        if !ssts.iter().any(|sst| sst.get_id() >= cursor.sst_id) {
+            *cursor = ScrubCursor::default();
+        }
+        let mut num_verified = 0;
+        while num_verified < num_blocks {
+            let Some(sst) = ssts.iter().find(|sst| sst.get_id() >= cursor.sst_id) else {
+                // start over from the oldest SST next round
+                *cursor = ScrubCursor::default();
+                break;
+            };
+            if sst.get_id() != cursor.sst_id {
+                *cursor = ScrubCursor {
+                    sst_id: sst.get_id(),
+                    block_index: 0,
+                };
+            }
+            let verified = match cursor.block_index {
+                0 => sst.validate().and_then(|_| sst.verify_block(0)),
+                block_index => sst.verify_block(block_index),
+            }
+            .and_then(|_| sst.metadata());
+            match verified {
+                Ok(metadata) => {
+                    num_verified += 1;
+                    cursor.block_index += 1;
+                    if cursor.block_index < metadata.num_blocks() {
+                        continue;
+                    }
+                }
+                Err(err) if matches!(err.downcast_ref::<LsmError>(), Some(LsmError::Corruption(_))) => {
+                    self.report_corruption(sst, format!("{:#}", err), quarantine)?;
+                }
+                Err(err) => return Err(err),
+            }
+            *cursor = ScrubCursor {
+                sst_id: sst.get_id() + 1,
+                block_index: 0,
+            };
+        }
+        Ok(())
+    }
+
+    fn report_corruption(&self, sst: &Sst, message: String, quarantine: bool) -> Result<()> {
+        let l0_sst_files = self.state_lock.read().unwrap().l0_sst_files.clone();
+        let Some(sst_file) = l0_sst_files.into_iter().find(|sst_file| sst_file.id == sst.get_id()) else {
+            // compacted away in the meantime
+            return Ok(());
+        };
+        let quarantined = quarantine && self.quarantine_sst(sst.get_id())?;
+        let corruption_info = CorruptionInfo {
+            sst_id: sst_file.id,
+            path: self.options.path.join(&sst_file.path),
+            message,
+            quarantined,
+        };
+        for listener in self.options.listeners.iter() {
+            listener.on_corruption_detected(&corruption_info);
+        }
+        Ok(())
+    }
+
+    // drop the SST from l0 and move its files to the quarantine directory.
+    // false if it was compacted away in the meantime
+    fn quarantine_sst(&self, sst_id: usize) -> Result<bool> {
+        // a compaction mustn't be merging the SST while it is moved
+        let _compaction_guard = self.compaction_lock.lock().unwrap();
+        let mut rw_guard = self.state_lock.write().unwrap();
+        let mut rw_snapshot = rw_guard.as_ref().clone();
+        let Some(position) = rw_snapshot.l0_sst_files.iter().position(|sst_file| sst_file.id == sst_id) else {
+            return Ok(false);
+        };
+        let sst_file = rw_snapshot.l0_sst_files.remove(position).expect("position is in l0");
+        rw_snapshot.ssts.retain(|sst| sst.get_id() != sst_id);
+        self.record_snapshot(&rw_snapshot.l0_sst_files)?;
+        self.install(&mut rw_guard, rw_snapshot);
+        drop(rw_guard);
+
+        if let Some(metadata_cache) = &self.metadata_cache {
+            metadata_cache.invalidate(&sst_id);
+        }
+        if let Some(table_cache) = &self.table_cache {
+            table_cache.invalidate(&sst_id);
+        }
+        if !self.options.in_memory {
+            let path = self.options.path.join(&sst_file.path);
+            let quarantine_dir = self.options.path.join(QUARANTINE_DIR_NAME);
+            create_dir_all(&quarantine_dir)?;
+            rename(&path, quarantine_path(&quarantine_dir, sst_id, "sst"))?;
+            if blob_path(&path).exists() {
+                rename(blob_path(&path), quarantine_path(&quarantine_dir, sst_id, "blob"))?;
+            }
+        }
+        Ok(true)
+    }
+}
+
+fn quarantine_path(quarantine_dir: &std::path::Path, sst_id: usize, extension: &str) -> PathBuf {
+    quarantine_dir.join(format!("{}.{}.corrupt", sst_id, extension))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use bytes::Bytes;
+    use tempfile::tempdir;
+
+    use crate::{
+        listener::{CorruptionInfo, EventListener},
+        state::{storage_state_options::StorageStateOptions, StorageState},
+    };
+
+    #[derive(Default)]
+    struct CorruptionListener {
+        corruptions: Mutex<Vec<(usize, bool)>>,
+    }
+
+    impl EventListener for CorruptionListener {
+        fn on_corruption_detected(&self, info: &CorruptionInfo) {
+            self.corruptions.lock().unwrap().push((info.sst_id, info.quarantined));
+        }
+    }
+
+    #[test]
+    fn test_scrub() {
+        let dir = tempdir().unwrap();
+        let listener = Arc::new(CorruptionListener::default());
+        let storage_state = StorageState::open(StorageStateOptions {
+            path: dir.path().to_owned(),
+            block_max_size_bytes: 32,
+            listeners: vec![listener.clone()],
+            ..Default::default()
+        })
+        .unwrap();
+        for (i, key) in ["k1", "k2", "k3", "k4"].iter().enumerate() {
+            storage_state.put(key.as_bytes(), b"a value long enough to fill blocks").unwrap();
+            if i % 2 == 1 {
+                storage_state.flush_all_memtables().unwrap();
+            }
+        }
+        let ssts = storage_state.get_snapshot().ssts.clone();
+        let num_blocks: usize = ssts.iter().map(|sst| sst.metadata().unwrap().num_blocks()).sum();
+        for _ in 0..num_blocks {
+            storage_state.scrub_round(1, true).unwrap();
+        }
+        assert!(listener.corruptions.lock().unwrap().is_empty());
+
+        // flip a key byte of the newest SST's first block, which the block
+        // index doesn't know about
+        let (newest, oldest) = (&ssts[0], &ssts[1]);
+        let path = dir.path().join(&storage_state.get_snapshot().l0_sst_files[0].path);
+        let mut contents = std::fs::read(&path).unwrap();
+        contents[3] = b'0';
+        std::fs::write(&path, contents).unwrap();
+        storage_state.scrub_round(num_blocks, false).unwrap();
+        assert_eq!(*listener.corruptions.lock().unwrap(), vec![(newest.get_id(), false)]);
+        // without quarantine the SST is only reported
+        assert_eq!(storage_state.get_snapshot().ssts.len(), 2);
+
+        storage_state.scrub_round(num_blocks, true).unwrap();
+        assert_eq!(listener.corruptions.lock().unwrap()[1], (newest.get_id(), true));
+        let snapshot = storage_state.get_snapshot();
+        assert_eq!(snapshot.l0_sst_ids(), vec![oldest.get_id()]);
+        assert!(!path.exists());
+        assert!(dir.path().join("quarantine").read_dir().unwrap().next().is_some());
+        assert_eq!(storage_state.get(b"k1").unwrap(), Some(Bytes::from("a value long enough to fill blocks")));
+        assert_eq!(storage_state.get(b"k3").unwrap(), None);
+        // the quarantined SST stays out of a repaired manifest
+        drop(storage_state);
+        assert_eq!(StorageState::repair(dir.path()).unwrap().sst_ids, vec![oldest.get_id()]);
+    }
+}
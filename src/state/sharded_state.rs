@@ -0,0 +1,741 @@
+use std::{
+    fs::{create_dir_all, read_dir, remove_dir, remove_file, rename},
+    ops::Bound,
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU64, Arc},
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use crossbeam_channel::Receiver;
+use xxhash_rust::xxh3::xxh3_64;
+
+use crate::{
+    error::LsmError,
+    iterator::merge_iterator::MergeIterator,
+    kv::{entry::Version, kv_pair::KeyValuePair},
+    manifest::Manifest,
+    memory::accountant::MemoryAccountant,
+    platform,
+    scheduler::BackgroundScheduler,
+    stats::{description::StoreDescription, explain::{GetExplanation, ScanExplanation}, histogram::LatencyHistogram, LatencyReport, LsmStats, MemoryUsage, MergeProgress, PrefixStats},
+    table::persistent_cache::PersistentCacheOptions,
+};
+
+use super::{
+    backup::BackupInfo,
+    read_options::{ReadOptions, ReadOptionsIterator},
+    repair::RepairReport,
+    snapshot::Snapshot,
+    storage_state_options::StorageStateOptions,
+    update_log::{WriteEvent, WriteRecord},
+    write_options::{WriteOptions, WriteToken},
+    check_sorted, StorageState,
+};
+
+// records the shard count of a sharded store, which must not change once keys
+// have been routed
+const SHARDS_FILE_NAME: &str = "SHARDS";
+
+// partitions the keyspace by key hash across independent StorageStates, each
+// in its own subdirectory with its own memtables, SSTs and locks. a store with
+// a single shard keeps everything directly in the store directory.
+// writes to different shards are not atomic with respect to each other
+pub(crate) struct ShardedStorageState {
+    shards: Vec<Arc<StorageState>>,
+}
+
+impl ShardedStorageState {
+    pub(crate) fn open(options: StorageStateOptions) -> Result<Self> {
+        options.validate()?;
+        let num_shards = options.num_shards;
+        let recorded_shards = match options.in_memory {
+            true => None,
+            false => Self::read_num_shards(&options.path)?,
+        };
+        match recorded_shards {
+            Some(recorded) if recorded != num_shards => {
+                return Err(LsmError::InvalidOptions(format!(
+                    "store at {:?} has {} shards, but num_shards is {}",
+                    options.path, recorded, num_shards
+                ))
+                .into());
+            }
+            None if num_shards > 1 && !options.in_memory && Manifest::exists(&options.path) => {
+                return Err(LsmError::InvalidOptions(format!(
+                    "store at {:?} is not sharded, but num_shards is {}",
+                    options.path, num_shards
+                ))
+                .into());
+            }
+            _ => {}
+        }
+        if num_shards == 1 {
+            return Ok(Self {
+                shards: vec![Arc::new(StorageState::open(options)?)],
+            });
+        }
+        // memtable memory is budgeted across all shards together
+        let memory_accountant = Arc::new(MemoryAccountant::new());
+        // and writes are numbered in one sequence across them
+        let last_timestamp = Arc::new(AtomicU64::new(0));
+
+        // open flags apply to the store as a whole, not to each shard
+        if recorded_shards.is_some() && options.error_if_exists {
+            return Err(LsmError::AlreadyExists(options.path.clone()).into());
+        }
+        if recorded_shards.is_none() && !options.create_if_missing {
+            return Err(LsmError::NotFound(options.path.clone()).into());
+        }
+        let mut shards = Vec::with_capacity(num_shards);
+        for shard in 0..num_shards {
+            let shard_options = StorageStateOptions {
+                path: Self::get_shard_path(&options.path, shard),
+                // shard SST ids overlap, so shards can't share a directory
+                level_paths: options
+                    .level_paths
+                    .iter()
+                    .map(|level_path| match level_path.is_absolute() {
+                        true => Self::get_shard_path(level_path, shard),
+                        false => level_path.clone(),
+                    })
+                    .collect(),
+                // split the cache budget rather than multiply it
+                block_cache_size_bytes: options.block_cache_size_bytes / num_shards as u64,
+                persistent_cache: options.persistent_cache.as_ref().map(|persistent_cache| {
+                    PersistentCacheOptions {
+                        path: match persistent_cache.path.is_absolute() {
+                            true => Self::get_shard_path(&persistent_cache.path, shard),
+                            false => persistent_cache.path.clone(),
+                        },
+                        capacity_bytes: persistent_cache.capacity_bytes / num_shards as u64,
+                    }
+                }),
+                metadata_cache_capacity: options
+                    .metadata_cache_capacity
+                    .map(|capacity| capacity / num_shards as u64),
+                max_open_files: options
+                    .max_open_files
+                    .map(|max_open_files| (max_open_files / num_shards as u64).max(1)),
+                max_db_size_bytes: options
+                    .max_db_size_bytes
+                    .map(|max_db_size_bytes| max_db_size_bytes / num_shards as u64),
+                create_if_missing: true,
+                error_if_exists: false,
+                ..options.clone()
+            };
+            shards.push(Arc::new(StorageState::open_shard(
+                shard_options,
+                memory_accountant.clone(),
+                last_timestamp.clone(),
+            )?));
+        }
+        if recorded_shards.is_none() && !options.in_memory {
+            Self::write_num_shards(&options.path, num_shards)?;
+        }
+        Ok(Self { shards })
+    }
+
+    #[cfg(test)]
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub(crate) fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
+        self.shard_for_key(key).get(key)
+    }
+
+    pub(crate) fn get_with_options(&self, key: &[u8], options: &ReadOptions) -> Result<Option<Bytes>> {
+        self.shard_for_key(key).get_with_options(key, options)
+    }
+
+    pub(crate) fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        self.shard_for_key(key).put(key, value)
+    }
+
+    pub(crate) fn delete(&self, key: &[u8]) -> Result<()> {
+        self.shard_for_key(key).delete(key)
+    }
+
+    pub(crate) fn put_with_options(&self, key: &[u8], value: &[u8], options: &WriteOptions) -> Result<WriteToken> {
+        self.shard_for_key(key).put_with_options(key, value, options)
+    }
+
+    pub(crate) fn delete_with_options(&self, key: &[u8], options: &WriteOptions) -> Result<WriteToken> {
+        self.shard_for_key(key).delete_with_options(key, options)
+    }
+
+    pub(crate) fn compare_and_swap(
+        &self,
+        key: &[u8],
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<std::result::Result<(), Option<Bytes>>> {
+        self.shard_for_key(key).compare_and_swap(key, expected, new)
+    }
+
+    // each shard's part of the batch is applied atomically
+    pub(crate) fn write_batch(&self, batch: &[KeyValuePair]) -> Result<()> {
+        self.write_batch_with_options(batch, &WriteOptions::default())?;
+        Ok(())
+    }
+
+    // a synced batch is flushed in every shard it touches. the token covers
+    // every shard's part
+    pub(crate) fn write_batch_with_options(&self, batch: &[KeyValuePair], options: &WriteOptions) -> Result<WriteToken> {
+        if self.shards.len() == 1 {
+            return self.shards[0].write_batch_with_options(batch, options);
+        }
+        let mut shard_batches: Vec<Vec<KeyValuePair>> = vec![Vec::new(); self.shards.len()];
+        for kv in batch {
+            shard_batches[self.shard_index(&kv.key.get_key())].push(kv.clone());
+        }
+        let mut token = WriteToken::default();
+        for (shard, shard_batch) in self.shards.iter().zip(shard_batches) {
+            if !shard_batch.is_empty() {
+                token = token.max(shard.write_batch_with_options(&shard_batch, options)?);
+            }
+        }
+        Ok(token)
+    }
+
+    // shards hold disjoint keys, so merging their scans keeps every key's
+    // versions together and newest first
+    #[cfg(test)]
+    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<ReadOptionsIterator> {
+        self.scan_with_options(lower, upper, &ReadOptions::default())
+    }
+
+    pub(crate) fn scan_with_options(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+        options: &ReadOptions,
+    ) -> Result<ReadOptionsIterator> {
+        let shard_iterators = self
+            .shards
+            .iter()
+            .map(|shard| shard.scan_entries(lower, upper, options))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(options.apply(MergeIterator::new(shard_iterators)))
+    }
+
+    pub(crate) fn snapshot(&self) -> Snapshot {
+        Snapshot::new(self.shards.iter().map(|shard| shard.snapshot()).collect())
+    }
+
+    pub(crate) fn count(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<usize> {
+        let mut count = 0;
+        for shard in self.shards.iter() {
+            count += shard.count(lower, upper)?;
+        }
+        Ok(count)
+    }
+
+    pub(crate) fn sum_values_as_u64(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        let mut sum: u64 = 0;
+        for shard in self.shards.iter() {
+            sum = sum
+                .checked_add(shard.sum_values_as_u64(lower, upper)?)
+                .ok_or_else(|| anyhow!("sum of values overflows u64"))?;
+        }
+        Ok(sum)
+    }
+
+    pub(crate) fn estimate_count(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        let mut estimate = 0;
+        for shard in self.shards.iter() {
+            estimate += shard.estimate_count(lower, upper)?;
+        }
+        Ok(estimate)
+    }
+
+    pub(crate) fn num_pending_flushes(&self) -> usize {
+        self.shards.iter().map(|shard| shard.num_pending_flushes()).sum()
+    }
+
+    pub(crate) fn merge_progress(&self) -> Vec<MergeProgress> {
+        self.shards.iter().filter_map(|shard| shard.merge_progress()).collect()
+    }
+
+    // keys with the prefix hash to any shard, so every shard is looked at
+    pub(crate) fn prefix_stats(&self, prefix: &[u8]) -> Result<PrefixStats> {
+        self.shards.iter().map(|shard| shard.prefix_stats(prefix)).sum()
+    }
+
+    // keys are routed to their shards as they arrive, so only one SST per
+    // shard is held in memory at a time
+    pub(crate) fn bulk_load(&self, kvs: impl IntoIterator<Item = KeyValuePair>) -> Result<()> {
+        let mut loaders = self
+            .shards
+            .iter()
+            .map(|shard| shard.bulk_loader())
+            .collect::<Result<Vec<_>>>()?;
+        for kv in kvs {
+            let shard_index = self.shard_index(&kv.key.get_key());
+            loaders[shard_index].add(kv)?;
+        }
+        for loader in loaders {
+            loader.finish()?;
+        }
+        Ok(())
+    }
+
+    // the whole batch is checked before any shard writes its part
+    pub(crate) fn append_sorted(&self, batch: &[KeyValuePair]) -> Result<()> {
+        if self.shards.len() == 1 {
+            return self.shards[0].append_sorted(batch);
+        }
+        check_sorted(batch)?;
+        let mut shard_batches: Vec<Vec<KeyValuePair>> = vec![Vec::new(); self.shards.len()];
+        for kv in batch {
+            shard_batches[self.shard_index(&kv.key.get_key())].push(kv.clone());
+        }
+        for (shard, shard_batch) in self.shards.iter().zip(shard_batches) {
+            if !shard_batch.is_empty() {
+                shard.append_sorted(&shard_batch)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn stats(&self) -> LsmStats {
+        let mut stats = LsmStats::default();
+        for shard in self.shards.iter() {
+            let shard_stats = shard.stats();
+            stats.num_memtables += shard_stats.num_memtables;
+            stats.memtable_bytes += shard_stats.memtable_bytes;
+            stats.num_l0_ssts += shard_stats.num_l0_ssts;
+            stats.num_write_stalls += shard_stats.num_write_stalls;
+            stats.write_stall_duration += shard_stats.write_stall_duration;
+            stats.num_range_lock_waits += shard_stats.num_range_lock_waits;
+            stats.range_lock_wait_duration += shard_stats.range_lock_wait_duration;
+        }
+        stats
+    }
+
+    pub(crate) fn describe(&self) -> Result<StoreDescription> {
+        let shards = self.shards.iter().map(|shard| shard.describe()).collect::<Result<_>>()?;
+        Ok(StoreDescription { shards })
+    }
+
+    pub(crate) fn get_versions(&self, key: &[u8], limit: usize) -> Result<Vec<Version>> {
+        self.shards[self.shard_index(key)].get_versions(key, limit)
+    }
+
+    pub(crate) fn explain_get(&self, key: &[u8]) -> Result<GetExplanation> {
+        let shard = self.shard_index(key);
+        Ok(GetExplanation {
+            shard,
+            num_shards: self.shards.len(),
+            ..self.shards[shard].explain_get(key)?
+        })
+    }
+
+    pub(crate) fn explain_scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<ScanExplanation> {
+        let shards = self
+            .shards
+            .iter()
+            .map(|shard| shard.explain_scan(lower, upper))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(ScanExplanation { shards })
+    }
+
+    pub(crate) fn approximate_memory_usage(&self) -> MemoryUsage {
+        self.shards.iter().map(|shard| shard.approximate_memory_usage()).sum()
+    }
+
+    // flushes and compactions. the foreground operations are timed by the
+    // store
+    pub(crate) fn latency_report(&self) -> LatencyReport {
+        LatencyReport {
+            flush: LatencyHistogram::merged_summary(self.shards.iter().map(|shard| shard.flush_latency())),
+            compaction: LatencyHistogram::merged_summary(self.shards.iter().map(|shard| shard.compaction_latency())),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn reset_latencies(&self) {
+        for shard in self.shards.iter() {
+            shard.flush_latency().reset();
+            shard.compaction_latency().reset();
+        }
+    }
+
+    pub(crate) fn flush_all_memtables(&self) -> Result<()> {
+        for shard in self.shards.iter() {
+            shard.flush_all_memtables()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn wait_for_flush(&self) -> Result<()> {
+        for shard in self.shards.iter() {
+            shard.wait_for_flush()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn wait_for_compaction(&self) -> Result<()> {
+        for shard in self.shards.iter() {
+            shard.wait_for_compaction()?;
+        }
+        Ok(())
+    }
+
+    // every shard gets its own periodic flush task, so shards flush in parallel
+    // up to the size of the scheduler's pool
+    pub(crate) fn schedule_flush(&self, scheduler: &BackgroundScheduler) -> Result<()> {
+        for shard in self.shards.iter() {
+            shard.schedule_flush(scheduler)?;
+        }
+        Ok(())
+    }
+
+    // shards share one sequence, see StorageState::latest_sequence
+    pub(crate) fn latest_sequence(&self) -> u64 {
+        self.shards[0].latest_sequence()
+    }
+
+    pub(crate) fn set_sequence_floor(&self, sequence: u64) -> Result<()> {
+        for shard in self.shards.iter() {
+            shard.set_sequence_floor(sequence)?;
+        }
+        Ok(())
+    }
+
+    // writes after since, in sequence order. a write in progress on one shard
+    // may have an older sequence than a finished one on another, so this
+    // stops short of the oldest sequence any shard may still be writing
+    pub(crate) fn get_updates_since(&self, since: u64) -> Result<Vec<WriteRecord>> {
+        let until = self
+            .shards
+            .iter()
+            .map(|shard| shard.completed_sequence())
+            .min()
+            .unwrap_or(since);
+        let mut records = Vec::new();
+        for shard in self.shards.iter() {
+            records.extend(shard.get_updates(since, until)?);
+        }
+        records.sort_by_key(|record| record.sequence);
+        Ok(records)
+    }
+
+    // keys are spread over the shards by hash, so every shard is watched
+    pub(crate) fn watch_prefix(&self, prefix: &[u8]) -> Receiver<WriteEvent> {
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        for shard in self.shards.iter() {
+            shard.watch_prefix(prefix, sender.clone());
+        }
+        receiver
+    }
+
+    pub(crate) fn cancel_compaction(&self) {
+        for shard in self.shards.iter() {
+            shard.cancel_compaction();
+        }
+    }
+
+    pub(crate) fn schedule_scrub(&self, scheduler: &BackgroundScheduler) -> Result<()> {
+        for shard in self.shards.iter() {
+            shard.schedule_scrub(scheduler)?;
+        }
+        Ok(())
+    }
+
+    // shards compact independently, each with its own periodic task
+    pub(crate) fn schedule_compaction(&self, scheduler: &BackgroundScheduler) -> Result<()> {
+        for shard in self.shards.iter() {
+            shard.schedule_compaction(scheduler)?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn destroy(path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let Some(num_shards) = Self::read_num_shards(path)? else {
+            return StorageState::destroy(path);
+        };
+        for shard in 0..num_shards {
+            StorageState::destroy(Self::get_shard_path(path, shard))?;
+        }
+        remove_file(path.join(SHARDS_FILE_NAME))?;
+        if read_dir(path)?.next().is_none() {
+            remove_dir(path)?;
+        }
+        Ok(())
+    }
+
+    // a sharded store's backups keep each shard's backups in a subdirectory,
+    // under the same ids
+    pub(crate) fn create_backup(&self, backup_dir: impl AsRef<Path>, base_id: Option<usize>) -> Result<BackupInfo> {
+        let backup_dir = backup_dir.as_ref();
+        if self.shards.len() == 1 {
+            let id = Self::next_backup_id(backup_dir)?;
+            return self.shards[0].create_backup(backup_dir, id, base_id);
+        }
+        let shard_dirs: Vec<PathBuf> =
+            (0..self.shards.len()).map(|shard| Self::get_shard_path(backup_dir, shard)).collect();
+        let mut id = 0;
+        for shard_dir in shard_dirs.iter() {
+            id = id.max(Self::next_backup_id(shard_dir)?);
+        }
+        create_dir_all(backup_dir)?;
+        match Self::read_num_shards(backup_dir)? {
+            Some(recorded) if recorded != self.shards.len() => {
+                return Err(LsmError::InvalidOptions(format!(
+                    "backups in {:?} have {} shards, but the store has {}",
+                    backup_dir,
+                    recorded,
+                    self.shards.len()
+                ))
+                .into());
+            }
+            Some(_) => {}
+            None => Self::write_num_shards(backup_dir, self.shards.len())?,
+        }
+        let mut info = BackupInfo {
+            id,
+            base_id,
+            num_ssts: 0,
+            num_copied_ssts: 0,
+            copied_bytes: 0,
+        };
+        for (shard, shard_dir) in self.shards.iter().zip(shard_dirs) {
+            let shard_info = shard.create_backup(shard_dir, id, base_id)?;
+            info.num_ssts += shard_info.num_ssts;
+            info.num_copied_ssts += shard_info.num_copied_ssts;
+            info.copied_bytes += shard_info.copied_bytes;
+        }
+        Ok(info)
+    }
+
+    pub(crate) fn restore_backup(backup_dir: impl AsRef<Path>, id: usize, path: impl AsRef<Path>) -> Result<()> {
+        let (backup_dir, path) = (backup_dir.as_ref(), path.as_ref());
+        let Some(num_shards) = Self::read_num_shards(backup_dir)? else {
+            return StorageState::restore_backup(backup_dir, id, path);
+        };
+        if Self::read_num_shards(path)?.is_some() {
+            return Err(LsmError::AlreadyExists(path.to_owned()).into());
+        }
+        for shard in 0..num_shards {
+            StorageState::restore_backup(
+                Self::get_shard_path(backup_dir, shard),
+                id,
+                Self::get_shard_path(path, shard),
+            )?;
+        }
+        Self::write_num_shards(path, num_shards)
+    }
+
+    fn next_backup_id(backup_dir: &Path) -> Result<usize> {
+        Ok(StorageState::list_backup_ids(backup_dir)?.last().map_or(1, |id| id + 1))
+    }
+
+    // one report per shard
+    pub(crate) fn repair(path: impl AsRef<Path>) -> Result<Vec<RepairReport>> {
+        let path = path.as_ref();
+        let Some(num_shards) = Self::read_num_shards(path)? else {
+            return Ok(vec![StorageState::repair(path)?]);
+        };
+        (0..num_shards)
+            .map(|shard| StorageState::repair(Self::get_shard_path(path, shard)))
+            .collect()
+    }
+
+    fn shard_index(&self, key: &[u8]) -> usize {
+        xxh3_64(key) as usize % self.shards.len()
+    }
+
+    fn shard_for_key(&self, key: &[u8]) -> &StorageState {
+        &self.shards[self.shard_index(key)]
+    }
+
+    fn read_num_shards(dir: &Path) -> Result<Option<usize>> {
+        let shards_path = dir.join(SHARDS_FILE_NAME);
+        if !shards_path.exists() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&shards_path)?;
+        let num_shards = contents.trim().parse().map_err(|_| {
+            LsmError::Corruption(format!("{} contains invalid shard count {:?}", SHARDS_FILE_NAME, contents))
+        })?;
+        Ok(Some(num_shards))
+    }
+
+    fn write_num_shards(dir: &Path, num_shards: usize) -> Result<()> {
+        let tmp_path = dir.join(format!("{}.tmp", SHARDS_FILE_NAME));
+        std::fs::write(&tmp_path, format!("{}\n", num_shards))?;
+        std::fs::File::open(&tmp_path)?.sync_all()?;
+        rename(&tmp_path, dir.join(SHARDS_FILE_NAME))?;
+        platform::sync_dir(dir)?;
+        Ok(())
+    }
+
+    fn get_shard_path(dir: &Path, shard: usize) -> PathBuf {
+        dir.join(format!("shard-{:02}", shard))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ops::Bound;
+
+    use tempfile::tempdir;
+
+    use crate::{
+        error::LsmError,
+        iterator::latest_iterator::LatestIterator,
+        kv::{entry::Entry, kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
+        state::storage_state_options::StorageStateOptions,
+    };
+
+    use super::ShardedStorageState;
+
+    fn batch(keys: &[&str]) -> Vec<KeyValuePair> {
+        keys.iter()
+            .map(|key| KeyValuePair::new(
+                TimestampedKey::new(key.as_bytes().to_vec().into()),
+                "batched".as_bytes().into(),
+            ))
+            .collect()
+    }
+
+    #[test]
+    fn test_sharded_get_scan() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            sst_max_size_bytes: 16,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            num_shards: 4,
+            ..Default::default()
+        };
+        {
+            let state = ShardedStorageState::open(options()).unwrap();
+            assert_eq!(state.num_shards(), 4);
+            for i in 0..20 {
+                state
+                    .put(format!("k{:02}", i).as_bytes(), format!("v{}", i).as_bytes())
+                    .unwrap();
+            }
+            state.flush_all_memtables().unwrap();
+        }
+        assert!(dir.path().join("shard-03").exists());
+
+        let state = ShardedStorageState::open(options()).unwrap();
+        state.delete("k05".as_bytes()).unwrap();
+        state.write_batch(&batch(&["k20", "k21"])).unwrap();
+
+        assert_eq!(state.get("k07".as_bytes()).unwrap().unwrap(), "v7".as_bytes());
+        assert!(state.get("k05".as_bytes()).unwrap().is_none());
+        assert_eq!(state.get("k21".as_bytes()).unwrap().unwrap(), "batched".as_bytes());
+
+        // scans come back in key order across shards
+        let keys: Vec<_> = LatestIterator::new(
+            state
+                .scan(Bound::Included("k03".as_bytes()), Bound::Excluded("k08".as_bytes()))
+                .unwrap(),
+        )
+        .map(|kv| kv.key.get_key())
+        .collect();
+        assert_eq!(keys, vec!["k03", "k04", "k06", "k07"]);
+        assert_eq!(state.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 21);
+    }
+
+    #[test]
+    fn test_sharded_sequence() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            path: dir.path().to_owned(),
+            num_shards: 4,
+            ..Default::default()
+        };
+        {
+            let state = ShardedStorageState::open(options()).unwrap();
+            assert_eq!(state.latest_sequence(), 0);
+            // one sequence across shards
+            for i in 0..10 {
+                state.put(format!("k{}", i).as_bytes(), b"v").unwrap();
+            }
+            assert_eq!(state.latest_sequence(), 10);
+            state.flush_all_memtables().unwrap();
+        }
+
+        // numbering carries on from the newest SST of any shard
+        let state = ShardedStorageState::open(options()).unwrap();
+        assert_eq!(state.latest_sequence(), 10);
+        state.delete(b"k0").unwrap();
+        assert_eq!(state.latest_sequence(), 11);
+
+        assert!(state.set_sequence_floor(12).is_err());
+        state.set_sequence_floor(5).unwrap();
+        state.set_sequence_floor(5).unwrap();
+        assert!(state.set_sequence_floor(4).is_err());
+        state.set_sequence_floor(11).unwrap();
+        assert!(state.shards.iter().all(|shard| shard.get_sequence_floor() == Some(11)));
+
+        // writes after the floor are kept, whichever shard they went to
+        for i in 0..10 {
+            state.put(format!("k{}", i).as_bytes(), b"v2").unwrap();
+        }
+        let records = state.get_updates_since(11).unwrap();
+        let sequences: Vec<u64> = records.iter().map(|record| record.sequence).collect();
+        assert_eq!(sequences, (12..22).collect::<Vec<u64>>());
+        assert_eq!(records[3].entries, vec![Entry::new("k3", "v2")]);
+        // a batch is split by shard
+        state.write_batch(&batch(&["k0", "k1", "k2", "k3"])).unwrap();
+        let records = state.get_updates_since(21).unwrap();
+        assert_eq!(records.iter().map(|record| record.entries.len()).sum::<usize>(), 4);
+
+        state.set_sequence_floor(21).unwrap();
+        assert_eq!(state.get_updates_since(21).unwrap(), records);
+        assert!(state.get_updates_since(20).is_err());
+    }
+
+    #[test]
+    fn test_sharded_backup() {
+        let dir = tempdir().unwrap();
+        let backup_dir = tempdir().unwrap();
+        let restore_dir = tempdir().unwrap();
+        let options = |path: &std::path::Path| StorageStateOptions {
+            path: path.to_owned(),
+            num_shards: 3,
+            ..Default::default()
+        };
+        let state = ShardedStorageState::open(options(dir.path())).unwrap();
+        for i in 0..10 {
+            state.put(format!("k{}", i).as_bytes(), b"v").unwrap();
+        }
+        let full = state.create_backup(backup_dir.path(), None).unwrap();
+        assert_eq!(full.id, 1);
+        state.put(b"k10", b"v").unwrap();
+        let incremental = state.create_backup(backup_dir.path(), Some(1)).unwrap();
+        assert_eq!((incremental.id, incremental.num_copied_ssts), (2, 1));
+
+        ShardedStorageState::restore_backup(backup_dir.path(), 2, restore_dir.path()).unwrap();
+        let restored = ShardedStorageState::open(options(restore_dir.path())).unwrap();
+        assert_eq!(restored.count(Bound::Unbounded, Bound::Unbounded).unwrap(), 11);
+    }
+
+    #[test]
+    fn test_shard_count_is_fixed() {
+        let dir = tempdir().unwrap();
+        let options = |num_shards| StorageStateOptions {
+            path: dir.path().to_owned(),
+            num_shards,
+            ..Default::default()
+        };
+        drop(ShardedStorageState::open(options(2)).unwrap());
+        for num_shards in [0, 1, 3] {
+            let err = ShardedStorageState::open(options(num_shards)).err().unwrap();
+            assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::InvalidOptions(_))));
+        }
+
+        ShardedStorageState::destroy(dir.path()).unwrap();
+        assert!(!dir.path().exists());
+    }
+}
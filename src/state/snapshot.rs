@@ -0,0 +1,54 @@
+use std::{
+    fmt,
+    sync::{Arc, RwLock, Weak},
+};
+
+use anyhow::{anyhow, Result};
+
+use super::StorageStateProtected;
+
+// the store as it was at one point, to read from with ReadOptions::snapshot.
+// a snapshot keeps the memtables and SSTs it sees, so hold on to it only as
+// long as it's needed. memtables flushed in the meantime stay in memory
+// without counting towards the memtable budget
+#[derive(Clone)]
+pub struct Snapshot {
+    shards: Arc<[ShardSnapshot]>,
+}
+
+// one shard's state and the last write it includes. each shard is captured
+// on its own, so like write batches a snapshot is only consistent per shard
+pub(crate) struct ShardSnapshot {
+    // identifies the shard the snapshot was taken from
+    pub(super) state_lock: Weak<RwLock<Arc<StorageStateProtected>>>,
+    pub(super) state: Arc<StorageStateProtected>,
+    // reads see the versions at or below it
+    pub(super) timestamp: u64,
+    // the store's sequence when the snapshot was taken. the writes of the
+    // shard between timestamp and it were still in progress, so a caller
+    // can't have been handed their tokens yet, see ReadOptions::min_sequence
+    pub(super) sequence: u64,
+}
+
+impl Snapshot {
+    pub(crate) fn new(shards: Vec<ShardSnapshot>) -> Self {
+        Self {
+            shards: shards.into(),
+        }
+    }
+
+    pub(super) fn for_shard(&self, state_lock: &Arc<RwLock<Arc<StorageStateProtected>>>) -> Result<&ShardSnapshot> {
+        self.shards
+            .iter()
+            .find(|shard| shard.state_lock.as_ptr() == Arc::as_ptr(state_lock))
+            .ok_or_else(|| anyhow!("snapshot was taken from a different store"))
+    }
+}
+
+impl fmt::Debug for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Snapshot")
+            .field("timestamps", &self.shards.iter().map(|shard| shard.timestamp).collect::<Vec<_>>())
+            .finish()
+    }
+}
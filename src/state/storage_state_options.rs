@@ -1,22 +1,574 @@
-use std::{path::PathBuf, str::FromStr};
+use std::{path::PathBuf, sync::Arc, time::Duration};
 use anyhow::Result;
 
+use crate::{
+    block::DEFAULT_RESTART_INTERVAL,
+    compaction::{CompactionStyle, VersionRetention},
+    error::LsmError,
+    listener::EventListener,
+    memory::{limiter::MemoryLimiter, memtable::rep::MemTableRepType},
+    platform::HAS_CLOCK,
+    state::scrub::ScrubOptions,
+    table::{
+        bloom::DEFAULT_FALSE_POSITIVE_RATE,
+        persistent_cache::PersistentCacheOptions,
+        sst_path::{FlatSstPathProvider, SstPathProvider},
+    },
+};
+
+// how the background flush finds out that frozen memtables need flushing
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FlushTrigger {
+    // check every 50ms
+    #[default]
+    Tick,
+    // freezing the memtable that reaches num_memtables_limit starts a flush
+    // right away, with a check every second as a fallback. fewer wake-ups
+    // when idle and less delay under bursty writes
+    Event,
+}
+
+#[derive(Clone)]
 pub struct StorageStateOptions {
+    // size at which the active memtable is frozen, unless
+    // memtable_memory_budget_bytes is set
     pub sst_max_size_bytes: usize,
+    // at most u16::MAX, since entries are located by 16 bit offsets. an entry
+    // larger than this gets a block of its own
     pub block_max_size_bytes: usize,
+    // entries between full keys within a block. 0 compresses every key
+    // against the first key of its block
+    pub block_restart_interval: usize,
+    // values longer than this are written to a blob file next to their SST
+    // and only referenced from its blocks, so multi-MB values don't crowd
+    // the block cache. compaction rewrites the values that are still live
+    // into new blob files and deletes the old ones. None keeps every value
+    // inline
+    pub blob_threshold_bytes: Option<usize>,
     pub block_cache_size_bytes: u64,
+    // the block cache is split into this many shards by SST id, each with an
+    // even share of block_cache_size_bytes, so that concurrent readers of
+    // different SSTs don't contend on one cache
+    pub block_cache_shards: usize,
+    // of the bloom filter written with each SST, between 0 and 1. lower rates
+    // save reads of SSTs that don't hold a key at the cost of larger filters
+    pub bloom_false_positive_rate: f64,
+    // SSTs opened with the store don't read their bloom filter until their
+    // first point lookup, so that opening a store of many SSTs that mostly
+    // serves scans is faster and holds less memory. with a
+    // metadata_cache_capacity the bloom filter is already only read with
+    // the rest of the metadata, when the SST is first read
+    pub lazy_bloom_filters: bool,
+    // the block index of an SST with more blocks than this is split into
+    // partitions of this many blocks, and only a top-level index of them is
+    // kept in memory, so that large SSTs don't pin their whole index. None
+    // keeps every block index whole
+    pub index_partition_num_blocks: Option<usize>,
+    // file-backed cache tier below the block cache, e.g. on local SSD when
+    // level_paths put the SSTs on slow remote storage
+    pub persistent_cache: Option<PersistentCacheOptions>,
+    // number of SSTs whose block index and bloom filter are kept in memory.
+    // None keeps them resident for every open SST. otherwise they are loaded
+    // when an SST is first read and evicted least recently used first
+    pub metadata_cache_capacity: Option<u64>,
+    // most SST files kept open at once. None keeps every SST's file open
+    pub max_open_files: Option<u64>,
     pub path: PathBuf,
+    // keep every SST in memory and never touch the disk, e.g. to run in the
+    // browser. path only names the SSTs, and everything is lost once the
+    // store is dropped
+    pub in_memory: bool,
     pub num_memtables_limit: usize,
+    // most frozen memtables merged into a single SST by one flush, as long as
+    // their combined size stays within sst_max_size_bytes. cuts the number of
+    // L0 SSTs when memtables are small, e.g. with a tight
+    // memtable_memory_budget_bytes. like a single memtable's flush, the merge
+    // keeps every version. 1 flushes every memtable on its own
+    pub max_memtables_per_flush: usize,
+    pub flush_trigger: FlushTrigger,
+    // size of the thread pool shared by flushes and compactions. with 0, and
+    // always on wasm, a writer that reaches num_memtables_limit frozen
+    // memtables flushes the oldest, and compaction and scrubbing only run
+    // when asked for, e.g. by LsmStore::wait_for_compaction
+    pub num_background_threads: usize,
+    // fail writes with LsmError::Background once a background task has
+    // panicked, until LsmStore::clear_background_error. otherwise the panic
+    // is only reported by LsmStore::background_error
+    pub fail_writes_on_background_error: bool,
+    // create the store if it doesn't exist yet
+    pub create_if_missing: bool,
+    // fail to open if a store already exists at path
+    pub error_if_exists: bool,
+    // treat any recoverable inconsistency found on open (e.g. a torn manifest
+    // record) as corruption instead of silently repairing it, and check each
+    // SST's block index and bloom filter before using it
+    pub paranoid_checks: bool,
+    // notified of flushes and other lifecycle events, in order
+    pub listeners: Vec<Arc<dyn EventListener>>,
+    // where new SST files are written, e.g. LeveledSstPathProvider for one
+    // subdirectory per level
+    pub sst_path_provider: Arc<dyn SstPathProvider>,
+    // data directory for each level's SSTs, e.g. fast local disk for the upper
+    // levels and larger, slower storage for the rest. level n uses
+    // level_paths[n], and levels past the end use the last entry. relative
+    // paths are inside path. empty keeps every SST under path
+    pub level_paths: Vec<PathBuf>,
+    // minimum age of a tombstone before compaction may drop it, even at the
+    // bottom level, so that lagging replication or CDC consumers still see
+    // the delete. None drops tombstones as soon as nothing older is left
+    pub tombstone_ttl: Option<Duration>,
+    // versions of each key compaction keeps when merging SSTs, for
+    // LsmStore::get_versions. snapshots pin the SSTs they read, so they stay
+    // valid whatever this is
+    pub version_retention: VersionRetention,
+    // how background compaction reorganizes SSTs. None never compacts
+    pub compaction_style: CompactionStyle,
+    // total size of the store's SST and blob files. only enforced by
+    // CompactionStyle::Fifo, which deletes the oldest SSTs to stay under it
+    pub max_db_size_bytes: Option<u64>,
+    // writes wait while l0 holds at least this many SSTs, running compaction
+    // on their own thread until it has fewer or compaction can do no more,
+    // see WriteStallReason::L0Limit. has no effect with CompactionStyle::None.
+    // None never waits
+    pub l0_stall_num_ssts: Option<usize>,
+    // data structure backing each memtable
+    pub memtable_rep: MemTableRepType,
+    // number of independent sub-trees the keyspace is hashed across. fixed
+    // when the store is created
+    pub num_shards: usize,
+    // total bytes for all memtables of the store, active and frozen, across
+    // shards. the active memtable may use whatever the others leave free, but
+    // always gets at least an equal share of the budget
+    pub memtable_memory_budget_bytes: Option<usize>,
+    // process-wide memory budget, which may be shared with other stores
+    pub memory_limiter: Option<Arc<MemoryLimiter>>,
+    // slowly re-read every SST in the background to find corruption before
+    // reads do. None never scrubs
+    pub scrub: Option<ScrubOptions>,
+}
+
+impl Default for StorageStateOptions {
+    fn default() -> Self {
+        StorageStateOptions {
+            sst_max_size_bytes: 2 << 20,  // 2MB
+            block_max_size_bytes: 4096,
+            block_restart_interval: DEFAULT_RESTART_INTERVAL,
+            blob_threshold_bytes: None,
+            block_cache_size_bytes: 1 << 20,  // 1MB
+            block_cache_shards: 1,
+            bloom_false_positive_rate: DEFAULT_FALSE_POSITIVE_RATE,
+            lazy_bloom_filters: false,
+            index_partition_num_blocks: Some(1024),
+            persistent_cache: None,
+            metadata_cache_capacity: None,
+            max_open_files: None,
+            path: PathBuf::from("lsm.db"),
+            in_memory: false,
+            num_memtables_limit: 3,
+            max_memtables_per_flush: 1,
+            flush_trigger: FlushTrigger::default(),
+            num_background_threads: 2,
+            fail_writes_on_background_error: false,
+            create_if_missing: true,
+            error_if_exists: false,
+            paranoid_checks: false,
+            listeners: Vec::new(),
+            sst_path_provider: Arc::new(FlatSstPathProvider),
+            level_paths: Vec::new(),
+            tombstone_ttl: None,
+            version_retention: VersionRetention::default(),
+            compaction_style: CompactionStyle::default(),
+            max_db_size_bytes: None,
+            l0_stall_num_ssts: None,
+            memtable_rep: MemTableRepType::default(),
+            num_shards: 1,
+            memtable_memory_budget_bytes: None,
+            memory_limiter: None,
+            scrub: None,
+        }
+    }
 }
 
 impl StorageStateOptions {
     pub fn new_with_defaults() -> Result<StorageStateOptions> {
-        Ok(StorageStateOptions { 
-            sst_max_size_bytes: 2 << 20,  // 2MB
-            block_max_size_bytes: 4096, 
-            block_cache_size_bytes: 1 << 20,  // 1MB 
-            path: PathBuf::from_str("lsm.db")?,
-            num_memtables_limit: 3
-        })
+        Ok(StorageStateOptions::default())
+    }
+
+    // starts from the defaults, and checks the options when built
+    pub fn builder() -> StorageStateOptionsBuilder {
+        StorageStateOptionsBuilder {
+            options: StorageStateOptions::default(),
+        }
+    }
+
+    // rejects values the store can't work with, as LsmError::InvalidOptions.
+    // called by open, so a bad value fails early instead of showing up as
+    // stalled writes or unreadable blocks
+    pub fn validate(&self) -> Result<()> {
+        let invalid = |message: &str| Err(LsmError::InvalidOptions(message.to_string()).into());
+        if self.sst_max_size_bytes == 0 {
+            return invalid("sst_max_size_bytes must be at least 1");
+        }
+        if self.block_max_size_bytes == 0 {
+            return invalid("block_max_size_bytes must be at least 1");
+        }
+        if self.block_max_size_bytes > u16::MAX as usize {
+            return invalid("block_max_size_bytes must be at most 65535");
+        }
+        if self.block_cache_shards == 0 {
+            return invalid("block_cache_shards must be at least 1");
+        }
+        if !(self.bloom_false_positive_rate > 0.0 && self.bloom_false_positive_rate < 1.0) {
+            return invalid("bloom_false_positive_rate must be between 0 and 1");
+        }
+        if self.index_partition_num_blocks == Some(0) {
+            return invalid("index_partition_num_blocks must be at least 1");
+        }
+        if self.max_memtables_per_flush == 0 {
+            return invalid("max_memtables_per_flush must be at least 1");
+        }
+        // these are moka caches, which need a clock
+        let has_clocked_cache =
+            self.metadata_cache_capacity.is_some() || self.max_open_files.is_some() || self.persistent_cache.is_some();
+        if !HAS_CLOCK && has_clocked_cache {
+            return invalid("metadata_cache_capacity, max_open_files and persistent_cache need a clock");
+        }
+        if self.version_retention == VersionRetention::Count(0) {
+            return invalid("version_retention must keep at least 1 version");
+        }
+        if self.l0_stall_num_ssts == Some(0) {
+            return invalid("l0_stall_num_ssts must be at least 1");
+        }
+        if self.num_shards == 0 {
+            return invalid("num_shards must be at least 1");
+        }
+        if self.memtable_memory_budget_bytes == Some(0) {
+            return invalid("memtable_memory_budget_bytes must be at least 1");
+        }
+        if self.scrub.is_some_and(|scrub| scrub.blocks_per_round == 0) {
+            return invalid("scrub blocks_per_round must be at least 1");
+        }
+        if self.in_memory && self.persistent_cache.is_some() {
+            return invalid("in-memory stores can't have a persistent cache");
+        }
+        Ok(())
+    }
+}
+
+// StorageStateOptions::builder(). each setter sets the option of the same name
+pub struct StorageStateOptionsBuilder {
+    options: StorageStateOptions,
+}
+
+impl StorageStateOptionsBuilder {
+    pub fn sst_max_size_bytes(mut self, sst_max_size_bytes: usize) -> Self {
+        self.options.sst_max_size_bytes = sst_max_size_bytes;
+        self
+    }
+
+    pub fn block_max_size_bytes(mut self, block_max_size_bytes: usize) -> Self {
+        self.options.block_max_size_bytes = block_max_size_bytes;
+        self
+    }
+
+    pub fn block_restart_interval(mut self, block_restart_interval: usize) -> Self {
+        self.options.block_restart_interval = block_restart_interval;
+        self
+    }
+
+    pub fn blob_threshold_bytes(mut self, blob_threshold_bytes: Option<usize>) -> Self {
+        self.options.blob_threshold_bytes = blob_threshold_bytes;
+        self
+    }
+
+    pub fn block_cache_size_bytes(mut self, block_cache_size_bytes: u64) -> Self {
+        self.options.block_cache_size_bytes = block_cache_size_bytes;
+        self
+    }
+
+    pub fn block_cache_shards(mut self, block_cache_shards: usize) -> Self {
+        self.options.block_cache_shards = block_cache_shards;
+        self
+    }
+
+    pub fn bloom_false_positive_rate(mut self, bloom_false_positive_rate: f64) -> Self {
+        self.options.bloom_false_positive_rate = bloom_false_positive_rate;
+        self
+    }
+
+    pub fn lazy_bloom_filters(mut self, lazy_bloom_filters: bool) -> Self {
+        self.options.lazy_bloom_filters = lazy_bloom_filters;
+        self
+    }
+
+    pub fn index_partition_num_blocks(mut self, index_partition_num_blocks: Option<usize>) -> Self {
+        self.options.index_partition_num_blocks = index_partition_num_blocks;
+        self
+    }
+
+    pub fn persistent_cache(mut self, persistent_cache: Option<PersistentCacheOptions>) -> Self {
+        self.options.persistent_cache = persistent_cache;
+        self
+    }
+
+    pub fn metadata_cache_capacity(mut self, metadata_cache_capacity: Option<u64>) -> Self {
+        self.options.metadata_cache_capacity = metadata_cache_capacity;
+        self
+    }
+
+    pub fn max_open_files(mut self, max_open_files: Option<u64>) -> Self {
+        self.options.max_open_files = max_open_files;
+        self
+    }
+
+    pub fn path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.path = path.into();
+        self
+    }
+
+    pub fn in_memory(mut self, in_memory: bool) -> Self {
+        self.options.in_memory = in_memory;
+        self
+    }
+
+    pub fn num_memtables_limit(mut self, num_memtables_limit: usize) -> Self {
+        self.options.num_memtables_limit = num_memtables_limit;
+        self
+    }
+
+    pub fn max_memtables_per_flush(mut self, max_memtables_per_flush: usize) -> Self {
+        self.options.max_memtables_per_flush = max_memtables_per_flush;
+        self
+    }
+
+    pub fn flush_trigger(mut self, flush_trigger: FlushTrigger) -> Self {
+        self.options.flush_trigger = flush_trigger;
+        self
+    }
+
+    pub fn num_background_threads(mut self, num_background_threads: usize) -> Self {
+        self.options.num_background_threads = num_background_threads;
+        self
+    }
+
+    pub fn fail_writes_on_background_error(mut self, fail_writes_on_background_error: bool) -> Self {
+        self.options.fail_writes_on_background_error = fail_writes_on_background_error;
+        self
+    }
+
+    pub fn create_if_missing(mut self, create_if_missing: bool) -> Self {
+        self.options.create_if_missing = create_if_missing;
+        self
+    }
+
+    pub fn error_if_exists(mut self, error_if_exists: bool) -> Self {
+        self.options.error_if_exists = error_if_exists;
+        self
+    }
+
+    pub fn paranoid_checks(mut self, paranoid_checks: bool) -> Self {
+        self.options.paranoid_checks = paranoid_checks;
+        self
+    }
+
+    // adds to the listeners set so far
+    pub fn listener(mut self, listener: Arc<dyn EventListener>) -> Self {
+        self.options.listeners.push(listener);
+        self
+    }
+
+    pub fn sst_path_provider(mut self, sst_path_provider: Arc<dyn SstPathProvider>) -> Self {
+        self.options.sst_path_provider = sst_path_provider;
+        self
+    }
+
+    pub fn level_paths(mut self, level_paths: Vec<PathBuf>) -> Self {
+        self.options.level_paths = level_paths;
+        self
+    }
+
+    pub fn tombstone_ttl(mut self, tombstone_ttl: Option<Duration>) -> Self {
+        self.options.tombstone_ttl = tombstone_ttl;
+        self
+    }
+
+    pub fn version_retention(mut self, version_retention: VersionRetention) -> Self {
+        self.options.version_retention = version_retention;
+        self
+    }
+
+    pub fn compaction_style(mut self, compaction_style: CompactionStyle) -> Self {
+        self.options.compaction_style = compaction_style;
+        self
+    }
+
+    pub fn max_db_size_bytes(mut self, max_db_size_bytes: Option<u64>) -> Self {
+        self.options.max_db_size_bytes = max_db_size_bytes;
+        self
+    }
+
+    pub fn l0_stall_num_ssts(mut self, l0_stall_num_ssts: Option<usize>) -> Self {
+        self.options.l0_stall_num_ssts = l0_stall_num_ssts;
+        self
+    }
+
+    pub fn memtable_rep(mut self, memtable_rep: MemTableRepType) -> Self {
+        self.options.memtable_rep = memtable_rep;
+        self
+    }
+
+    pub fn num_shards(mut self, num_shards: usize) -> Self {
+        self.options.num_shards = num_shards;
+        self
+    }
+
+    pub fn memtable_memory_budget_bytes(mut self, memtable_memory_budget_bytes: Option<usize>) -> Self {
+        self.options.memtable_memory_budget_bytes = memtable_memory_budget_bytes;
+        self
     }
-}
\ No newline at end of file
+
+    pub fn memory_limiter(mut self, memory_limiter: Option<Arc<MemoryLimiter>>) -> Self {
+        self.options.memory_limiter = memory_limiter;
+        self
+    }
+
+    pub fn scrub(mut self, scrub: Option<ScrubOptions>) -> Self {
+        self.options.scrub = scrub;
+        self
+    }
+
+    // presets tune the memtables, bloom filters, block cache and compaction
+    // triggers together for a kind of workload. they only touch those, so
+    // call them first and override single options after. the compaction
+    // style itself is kept, only its triggers are adjusted
+
+    // a few MB in total, e.g. for embedded devices or many stores per process
+    pub fn small_memory(mut self) -> Self {
+        self.options.sst_max_size_bytes = 256 << 10;
+        self.options.num_memtables_limit = 2;
+        self.options.max_memtables_per_flush = 1;
+        self.options.memtable_memory_budget_bytes = None;
+        // halves the filters
+        self.options.bloom_false_positive_rate = 0.1;
+        self.options.block_cache_size_bytes = 256 << 10;
+        self.options.metadata_cache_capacity = Some(64);
+        self.set_min_merge_width(4)
+    }
+
+    // large memtables that take bursts without stalling, and fewer, larger
+    // merges. reads pay for the extra SSTs
+    pub fn write_heavy(mut self) -> Self {
+        self.options.sst_max_size_bytes = 8 << 20;
+        self.options.num_memtables_limit = 6;
+        self.options.max_memtables_per_flush = 4;
+        self.options.bloom_false_positive_rate = 0.02;
+        self.options.block_cache_size_bytes = 8 << 20;
+        self.set_min_merge_width(8)
+    }
+
+    // a large block cache, precise bloom filters and eager merges, so that a
+    // lookup touches as few SSTs and as little disk as possible
+    pub fn read_heavy(mut self) -> Self {
+        self.options.sst_max_size_bytes = 2 << 20;
+        self.options.num_memtables_limit = 2;
+        self.options.max_memtables_per_flush = 1;
+        self.options.bloom_false_positive_rate = 0.001;
+        self.options.block_cache_size_bytes = 64 << 20;
+        self.options.block_cache_shards = 8;
+        self.options.metadata_cache_capacity = None;
+        self.set_min_merge_width(2)
+    }
+
+    fn set_min_merge_width(mut self, min_merge_width: usize) -> Self {
+        if let CompactionStyle::TimeWindow(time_window_options) = &mut self.options.compaction_style {
+            time_window_options.min_merge_width = min_merge_width;
+        }
+        self
+    }
+
+    pub fn build(self) -> Result<StorageStateOptions> {
+        self.options.validate()?;
+        Ok(self.options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        compaction::{CompactionStyle, TimeWindowOptions},
+        error::LsmError,
+        table::persistent_cache::PersistentCacheOptions,
+    };
+
+    use super::StorageStateOptions;
+
+    #[test]
+    fn test_validate() {
+        assert!(StorageStateOptions::default().validate().is_ok());
+        let invalid = [
+            StorageStateOptions { sst_max_size_bytes: 0, ..Default::default() },
+            StorageStateOptions { block_max_size_bytes: 0, ..Default::default() },
+            StorageStateOptions { block_max_size_bytes: 1 << 16, ..Default::default() },
+            StorageStateOptions { bloom_false_positive_rate: 0.0, ..Default::default() },
+            StorageStateOptions { bloom_false_positive_rate: 1.0, ..Default::default() },
+            StorageStateOptions { bloom_false_positive_rate: f64::NAN, ..Default::default() },
+            StorageStateOptions { index_partition_num_blocks: Some(0), ..Default::default() },
+            StorageStateOptions { max_memtables_per_flush: 0, ..Default::default() },
+            StorageStateOptions { num_shards: 0, ..Default::default() },
+            StorageStateOptions { memtable_memory_budget_bytes: Some(0), ..Default::default() },
+            StorageStateOptions {
+                in_memory: true,
+                persistent_cache: Some(PersistentCacheOptions { path: "cache".into(), capacity_bytes: 1 }),
+                ..Default::default()
+            },
+        ];
+        for options in invalid {
+            let err = options.validate().err().unwrap();
+            assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::InvalidOptions(_))), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_builder() {
+        let options = StorageStateOptions::builder()
+            .path("store")
+            .sst_max_size_bytes(1 << 10)
+            .num_shards(2)
+            .build()
+            .unwrap();
+        assert_eq!(options.path.to_str(), Some("store"));
+        assert_eq!(options.sst_max_size_bytes, 1 << 10);
+        assert_eq!(options.num_shards, 2);
+        assert_eq!(options.block_max_size_bytes, StorageStateOptions::default().block_max_size_bytes);
+
+        assert!(StorageStateOptions::builder().block_max_size_bytes(0).build().is_err());
+    }
+
+    #[test]
+    fn test_presets() {
+        let default = StorageStateOptions::default();
+        let small_memory = StorageStateOptions::builder().small_memory().build().unwrap();
+        assert!(small_memory.block_cache_size_bytes < default.block_cache_size_bytes);
+        assert!(small_memory.sst_max_size_bytes < default.sst_max_size_bytes);
+        let write_heavy = StorageStateOptions::builder().write_heavy().build().unwrap();
+        assert!(write_heavy.sst_max_size_bytes > default.sst_max_size_bytes);
+        assert!(write_heavy.num_memtables_limit > default.num_memtables_limit);
+        let read_heavy = StorageStateOptions::builder().read_heavy().build().unwrap();
+        assert!(read_heavy.block_cache_size_bytes > default.block_cache_size_bytes);
+        assert!(read_heavy.block_cache_shards > default.block_cache_shards);
+        assert!(read_heavy.bloom_false_positive_rate < default.bloom_false_positive_rate);
+
+        // single options override the preset, and the compaction style is kept
+        let options = StorageStateOptions::builder()
+            .compaction_style(CompactionStyle::TimeWindow(TimeWindowOptions::default()))
+            .read_heavy()
+            .block_cache_size_bytes(1 << 20)
+            .build()
+            .unwrap();
+        assert_eq!(options.block_cache_size_bytes, 1 << 20);
+        match options.compaction_style {
+            CompactionStyle::TimeWindow(time_window_options) => assert_eq!(time_window_options.min_merge_width, 2),
+            _ => panic!("compaction style changed"),
+        }
+    }
+}
@@ -0,0 +1,178 @@
+use std::{collections::VecDeque, sync::Mutex};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use crossbeam_channel::Sender;
+
+use crate::kv::entry::Entry;
+
+// one write as LsmStore::get_updates_since hands it out. the entries of a
+// batch share its sequence, so a batch comes back as one record per shard it
+// touched
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteRecord {
+    pub sequence: u64,
+    pub entries: Vec<Entry>,
+}
+
+// a change to one key under a watched prefix, see LsmStore::watch_prefix
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WriteEvent {
+    pub sequence: u64,
+    pub entry: Entry,
+}
+
+// writes kept for consumers that follow the store, e.g. replicas. there is no
+// write-ahead log to keep them in, so they are held in memory from the time
+// a sequence floor is first set, and released as the floor moves past them.
+// they don't survive a restart. bulk loads aren't writes and aren't kept
+#[derive(Default)]
+pub(crate) struct UpdateLog {
+    inner: Mutex<UpdateLogInner>,
+}
+
+#[derive(Default)]
+struct UpdateLogInner {
+    // None until a consumer sets one, and nothing is kept until then
+    floor: Option<u64>,
+    // writes after the floor. appended as they are made, which isn't quite
+    // sequence order when writers race
+    records: VecDeque<WriteRecord>,
+    // sent every write to a key under their prefix as it is made, whether or
+    // not a floor is set. dropped once their receiver is
+    watchers: Vec<(Bytes, Sender<WriteEvent>)>,
+}
+
+impl UpdateLog {
+    #[cfg(test)]
+    pub fn get_floor(&self) -> Option<u64> {
+        self.inner.lock().unwrap().floor
+    }
+
+    // release every write at or below sequence
+    pub(crate) fn set_floor(&self, sequence: u64) -> Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(floor) = inner.floor {
+            if sequence < floor {
+                return Err(anyhow!("sequence floor can't move back from {} to {}", floor, sequence));
+            }
+        }
+        inner.floor = Some(sequence);
+        inner.records.retain(|record| record.sequence > sequence);
+        Ok(())
+    }
+
+    pub(crate) fn watch(&self, prefix: Bytes, sender: Sender<WriteEvent>) {
+        self.inner.lock().unwrap().watchers.push((prefix, sender));
+    }
+
+    // entries is only called if the write is kept or watched
+    pub(crate) fn append(&self, sequence: u64, entries: impl FnOnce() -> Vec<Entry>) {
+        let mut inner = self.inner.lock().unwrap();
+        let keep = inner.floor.is_some_and(|floor| sequence > floor);
+        if !keep && inner.watchers.is_empty() {
+            return;
+        }
+        let entries = entries();
+        inner.watchers.retain(|(prefix, sender)| {
+            entries
+                .iter()
+                .filter(|entry| entry.key.starts_with(prefix))
+                .all(|entry| {
+                    let event = WriteEvent {
+                        sequence,
+                        entry: entry.clone(),
+                    };
+                    sender.send(event).is_ok()
+                })
+        });
+        if keep {
+            inner.records.push_back(WriteRecord { sequence, entries });
+        }
+    }
+
+    // writes in (since, until], in sequence order
+    pub(crate) fn get_updates(&self, since: u64, until: u64) -> Result<Vec<WriteRecord>> {
+        let inner = self.inner.lock().unwrap();
+        match inner.floor {
+            None => return Err(anyhow!("no sequence floor is set, so updates aren't kept")),
+            Some(floor) if since < floor => {
+                return Err(anyhow!("updates up to sequence {} have been released", floor));
+            }
+            Some(_) => {}
+        }
+        let mut records: Vec<WriteRecord> = inner
+            .records
+            .iter()
+            .filter(|record| record.sequence > since && record.sequence <= until)
+            .cloned()
+            .collect();
+        records.sort_by_key(|record| record.sequence);
+        Ok(records)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use crate::kv::entry::Entry;
+
+    use super::{UpdateLog, WriteEvent};
+
+    #[test]
+    fn test_update_log() {
+        let log = UpdateLog::default();
+        // nothing is kept before a floor is set
+        log.append(1, || vec![Entry::new("k1", "v1")]);
+        assert!(log.get_updates(0, 1).is_err());
+
+        log.set_floor(1).unwrap();
+        log.append(1, || unreachable!());
+        log.append(3, || vec![Entry::tombstone("k1")]);
+        log.append(2, || vec![Entry::new("k2", "v2"), Entry::new("k3", "v3")]);
+        let sequences = |since, until| -> Vec<u64> {
+            log.get_updates(since, until).unwrap().iter().map(|record| record.sequence).collect()
+        };
+        assert_eq!(sequences(1, 3), vec![2, 3]);
+        assert_eq!(sequences(2, 3), vec![3]);
+        assert_eq!(sequences(1, 2), vec![2]);
+        assert_eq!(log.get_updates(1, 2).unwrap()[0].entries.len(), 2);
+
+        log.set_floor(2).unwrap();
+        assert!(log.get_updates(1, 3).is_err());
+        assert_eq!(sequences(2, 3), vec![3]);
+        assert!(log.set_floor(1).is_err());
+        assert_eq!(log.get_floor(), Some(2));
+    }
+
+    #[test]
+    fn test_watch() {
+        let log = UpdateLog::default();
+        let (sender, receiver) = crossbeam_channel::unbounded();
+        log.watch(Bytes::from("config/"), sender);
+        // watched writes are sent without a floor, and aren't kept
+        log.append(1, || vec![Entry::new("config/a", "1"), Entry::new("data/a", "1")]);
+        log.append(2, || vec![Entry::tombstone("config/b")]);
+        log.append(3, || vec![Entry::new("data/b", "1")]);
+        let events: Vec<WriteEvent> = receiver.try_iter().collect();
+        assert_eq!(
+            events,
+            vec![
+                WriteEvent {
+                    sequence: 1,
+                    entry: Entry::new("config/a", "1"),
+                },
+                WriteEvent {
+                    sequence: 2,
+                    entry: Entry::tombstone("config/b"),
+                },
+            ]
+        );
+        assert!(log.get_updates(0, 3).is_err());
+
+        drop(receiver);
+        log.append(4, || vec![Entry::new("config/a", "2")]);
+        assert!(log.inner.lock().unwrap().watchers.is_empty());
+    }
+}
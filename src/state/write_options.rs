@@ -0,0 +1,23 @@
+// per-write settings, see LsmStore::put_with_options. more may be added, so
+// set the ones needed and take the rest from ..Default::default()
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WriteOptions {
+    // return only once the write is in an SST on disk. without a write-ahead
+    // log that means freezing and flushing the active memtable, so every
+    // synced write costs a small SST; use it for the few writes that must
+    // survive a crash. in-memory stores ignore it
+    pub sync: bool,
+    // yield to the background when flushes are behind: the write waits for
+    // the frozen memtables to be flushed, helping with the flushing, before it
+    // adds to them. for bulk or maintenance writes that shouldn't crowd out
+    // foreground ones
+    pub low_priority: bool,
+}
+
+// identifies a write, so that a later read can demand to see it, see
+// ReadOptions::min_sequence. returned by the *_with_options writes. the
+// sequence can be sent to other processes and turned back into a token
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct WriteToken {
+    pub sequence: u64,
+}
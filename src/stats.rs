@@ -0,0 +1,217 @@
+use std::{fmt, iter::Sum, ops::Add, sync::Mutex, time::Duration};
+
+use crate::{
+    iterator::IteratorStats,
+    scheduler::{SchedulerStatus, TaskPriority},
+};
+
+use self::histogram::{LatencyHistogram, LatencySummary};
+
+pub(crate) mod description;
+pub(crate) mod explain;
+pub(crate) mod histogram;
+
+// point-in-time view of a store's resources, see LsmStore::stats
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LsmStats {
+    // active and frozen memtables across all shards
+    pub num_memtables: usize,
+    pub memtable_bytes: usize,
+    pub num_l0_ssts: usize,
+    // scans that have been opened and not dropped yet
+    pub num_open_scans: usize,
+    // iterators and pinned blocks held by open scans, as of when each scan was
+    // opened. a scan's share is released once it is exhausted
+    pub open_scan_iterators: IteratorStats,
+    // writes that had to wait for a flush or compaction since the store was
+    // opened, see EventListener::on_write_stall, and how long they waited in
+    // total
+    pub num_write_stalls: u64,
+    pub write_stall_duration: Duration,
+    // flushes, merges and bulk loads that had to wait for another one
+    // covering some of the same keys, and how long they waited in total
+    pub num_range_lock_waits: u64,
+    pub range_lock_wait_duration: Duration,
+}
+
+// approximate bytes a store holds in memory, see
+// LsmStore::approximate_memory_usage
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct MemoryUsage {
+    // keys and values written, not counting the memtable's own overhead
+    pub active_memtable_bytes: usize,
+    pub frozen_memtable_bytes: usize,
+    pub block_cache_bytes: usize,
+    // of SSTs whose metadata is resident or in the metadata cache
+    pub bloom_filter_bytes: usize,
+    // block indexes, likewise
+    pub sst_metadata_bytes: usize,
+}
+
+impl MemoryUsage {
+    pub fn total_bytes(&self) -> usize {
+        self.active_memtable_bytes
+            + self.frozen_memtable_bytes
+            + self.block_cache_bytes
+            + self.bloom_filter_bytes
+            + self.sst_metadata_bytes
+    }
+}
+
+impl Add for MemoryUsage {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            active_memtable_bytes: self.active_memtable_bytes + other.active_memtable_bytes,
+            frozen_memtable_bytes: self.frozen_memtable_bytes + other.frozen_memtable_bytes,
+            block_cache_bytes: self.block_cache_bytes + other.block_cache_bytes,
+            bloom_filter_bytes: self.bloom_filter_bytes + other.bloom_filter_bytes,
+            sst_metadata_bytes: self.sst_metadata_bytes + other.sst_metadata_bytes,
+        }
+    }
+}
+
+impl Sum for MemoryUsage {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+// estimated size of the keys starting with a prefix, see
+// LsmStore::prefix_stats. like LsmStore::estimate_count, every version and
+// tombstone counts
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct PrefixStats {
+    pub num_entries: u64,
+    // keys and values in memtables, plus the on-disk bytes of the SST blocks
+    // holding the entries. a block only partly under the prefix counts in
+    // proportion to its entries that are
+    pub size_bytes: u64,
+    // blocks only partly under the prefix, read to count their entries.
+    // blocks wholly under it are counted from their metadata
+    pub num_sampled_blocks: u64,
+}
+
+impl Add for PrefixStats {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Self {
+            num_entries: self.num_entries + other.num_entries,
+            size_bytes: self.size_bytes + other.size_bytes,
+            num_sampled_blocks: self.num_sampled_blocks + other.num_sampled_blocks,
+        }
+    }
+}
+
+impl Sum for PrefixStats {
+    fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+        iter.fold(Self::default(), Add::add)
+    }
+}
+
+// a merge of SSTs in progress, see BackgroundStatus
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MergeProgress {
+    pub num_inputs: usize,
+    // entries that came out of the merge so far, of the entries in the
+    // inputs. shadowed versions never come out, so a merge may finish short
+    // of num_entries
+    pub entries_merged: u64,
+    pub num_entries: u64,
+}
+
+impl MergeProgress {
+    pub fn percent(&self) -> f64 {
+        match self.num_entries {
+            0 => 100.0,
+            num_entries => (100.0 * self.entries_merged as f64 / num_entries as f64).min(100.0),
+        }
+    }
+}
+
+// what the store's background work is doing, see
+// LsmStore::background_status. printing it gives one line per item
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BackgroundStatus {
+    pub scheduler: SchedulerStatus,
+    // frozen memtables waiting to be flushed, across shards
+    pub num_pending_flushes: usize,
+    // at most one per shard
+    pub merges: Vec<MergeProgress>,
+}
+
+impl fmt::Display for BackgroundStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tasks = |priorities: &[TaskPriority]| match priorities.is_empty() {
+            true => "none".to_string(),
+            false => priorities
+                .iter()
+                .map(|priority| format!("{:?}", priority).to_lowercase())
+                .collect::<Vec<_>>()
+                .join(", "),
+        };
+        let state = if self.scheduler.is_paused { "paused" } else { "running" };
+        writeln!(f, "background work: {}", state)?;
+        writeln!(f, "running tasks: {}", tasks(&self.scheduler.running))?;
+        writeln!(f, "queued tasks: {}", tasks(&self.scheduler.queued))?;
+        writeln!(f, "pending flushes: {}", self.num_pending_flushes)?;
+        for merge in self.merges.iter() {
+            writeln!(f, "merge of {} ssts: {:.0}%", merge.num_inputs, merge.percent())?;
+        }
+        Ok(())
+    }
+}
+
+// how long operations have taken since the store was opened or the report
+// was last reset, see LsmStore::latency_report
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencyReport {
+    pub get: LatencySummary,
+    // deletes and batch writes included
+    pub put: LatencySummary,
+    // each call to next on a scan handed out by the store
+    pub scan_next: LatencySummary,
+    // across all shards
+    pub flush: LatencySummary,
+    pub compaction: LatencySummary,
+}
+
+// totals for the open scans of a store
+#[derive(Default)]
+pub(crate) struct ScanRegistry {
+    open_scans: Mutex<(usize, IteratorStats)>,
+    next_latency: LatencyHistogram,
+}
+
+impl ScanRegistry {
+    pub(crate) fn register(&self, stats: IteratorStats) {
+        let mut open_scans = self.open_scans.lock().unwrap();
+        open_scans.0 += 1;
+        open_scans.1 = open_scans.1 + stats;
+    }
+
+    pub(crate) fn update(&self, old_stats: IteratorStats, new_stats: IteratorStats) {
+        let mut open_scans = self.open_scans.lock().unwrap();
+        open_scans.1 = open_scans.1 - old_stats + new_stats;
+    }
+
+    pub(crate) fn unregister(&self, stats: IteratorStats) {
+        let mut open_scans = self.open_scans.lock().unwrap();
+        open_scans.0 -= 1;
+        open_scans.1 = open_scans.1 - stats;
+    }
+
+    pub(crate) fn open_scans(&self) -> (usize, IteratorStats) {
+        *self.open_scans.lock().unwrap()
+    }
+
+    pub(crate) fn record_next(&self, duration: Duration) {
+        self.next_latency.record(duration);
+    }
+
+    pub(crate) fn next_latency(&self) -> &LatencyHistogram {
+        &self.next_latency
+    }
+}
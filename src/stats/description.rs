@@ -0,0 +1,189 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+// the layout of a store, see LsmStore::describe. printing it draws one table
+// of memtables and one per level for each shard
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StoreDescription {
+    pub shards: Vec<ShardDescription>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShardDescription {
+    // active memtable first, then the frozen ones newest to oldest
+    pub memtables: Vec<MemTableDescription>,
+    // only level 0 for now. empty levels are left out
+    pub levels: Vec<LevelDescription>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MemTableDescription {
+    pub id: usize,
+    pub frozen: bool,
+    pub size_bytes: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LevelDescription {
+    pub level: usize,
+    // in lookup order, newest first
+    pub ssts: Vec<SstDescription>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SstDescription {
+    pub id: usize,
+    pub first_key: Bytes,
+    pub last_key: Bytes,
+    pub file_size_bytes: u64,
+    pub num_blocks: usize,
+    // every version and tombstone
+    pub num_entries: u64,
+}
+
+impl fmt::Display for StoreDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (shard_index, shard) in self.shards.iter().enumerate() {
+            if shard_index > 0 {
+                writeln!(f)?;
+            }
+            // a single shard is the whole store, no need to name it
+            let indent = if self.shards.len() > 1 {
+                writeln!(f, "shard {}", shard_index)?;
+                "  "
+            } else {
+                ""
+            };
+            write_shard(f, shard, indent)?;
+        }
+        Ok(())
+    }
+}
+
+fn write_shard(f: &mut fmt::Formatter<'_>, shard: &ShardDescription, indent: &str) -> fmt::Result {
+    writeln!(f, "{}memtables", indent)?;
+    let rows = shard.memtables.iter().map(|memtable| {
+        vec![
+            memtable.id.to_string(),
+            if memtable.frozen { "frozen" } else { "active" }.to_string(),
+            memtable.size_bytes.to_string(),
+        ]
+    });
+    write_table(f, &format!("{}  ", indent), &["id", "state", "bytes"], rows.collect())?;
+    for level in shard.levels.iter() {
+        let total_bytes: u64 = level.ssts.iter().map(|sst| sst.file_size_bytes).sum();
+        let total_entries: u64 = level.ssts.iter().map(|sst| sst.num_entries).sum();
+        writeln!(
+            f,
+            "{}level {}: {} ssts, {} bytes, {} entries",
+            indent,
+            level.level,
+            level.ssts.len(),
+            total_bytes,
+            total_entries
+        )?;
+        let rows = level.ssts.iter().map(|sst| {
+            vec![
+                sst.id.to_string(),
+                String::from_utf8_lossy(&sst.first_key).into_owned(),
+                String::from_utf8_lossy(&sst.last_key).into_owned(),
+                sst.file_size_bytes.to_string(),
+                sst.num_blocks.to_string(),
+                sst.num_entries.to_string(),
+            ]
+        });
+        write_table(
+            f,
+            &format!("{}  ", indent),
+            &["id", "first key", "last key", "bytes", "blocks", "entries"],
+            rows.collect(),
+        )?;
+    }
+    Ok(())
+}
+
+// left-aligned columns as wide as their widest cell
+fn write_table(f: &mut fmt::Formatter<'_>, indent: &str, headers: &[&str], rows: Vec<Vec<String>>) -> fmt::Result {
+    let mut widths: Vec<usize> = headers.iter().map(|header| header.chars().count()).collect();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    let header_row: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    for row in std::iter::once(header_row.as_slice()).chain(rows.iter().map(Vec::as_slice)) {
+        let cells: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:width$}", cell, width = width))
+            .collect();
+        writeln!(f, "{}{}", indent, cells.join("  ").trim_end())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{LevelDescription, MemTableDescription, ShardDescription, SstDescription, StoreDescription};
+
+    #[test]
+    fn test_display() {
+        let description = StoreDescription {
+            shards: vec![ShardDescription {
+                memtables: vec![
+                    MemTableDescription {
+                        id: 3,
+                        frozen: false,
+                        size_bytes: 10,
+                    },
+                    MemTableDescription {
+                        id: 2,
+                        frozen: true,
+                        size_bytes: 1024,
+                    },
+                ],
+                levels: vec![LevelDescription {
+                    level: 0,
+                    ssts: vec![
+                        SstDescription {
+                            id: 1,
+                            first_key: Bytes::from("apple"),
+                            last_key: Bytes::from("banana"),
+                            file_size_bytes: 4096,
+                            num_blocks: 2,
+                            num_entries: 40,
+                        },
+                        SstDescription {
+                            id: 0,
+                            first_key: Bytes::from("a"),
+                            last_key: Bytes::from("z"),
+                            file_size_bytes: 100,
+                            num_blocks: 1,
+                            num_entries: 2,
+                        },
+                    ],
+                }],
+            }],
+        };
+        let expected = "\
+memtables
+  id  state   bytes
+  3   active  10
+  2   frozen  1024
+level 0: 2 ssts, 4196 bytes, 42 entries
+  id  first key  last key  bytes  blocks  entries
+  1   apple      banana    4096   2       40
+  0   a          z         100    1       2
+";
+        assert_eq!(description.to_string(), expected);
+
+        let sharded = StoreDescription {
+            shards: vec![description.shards[0].clone(), ShardDescription::default()],
+        };
+        assert!(sharded.to_string().starts_with("shard 0\n  memtables\n    id  state   bytes\n"));
+        assert!(sharded.to_string().ends_with("\nshard 1\n  memtables\n    id  state  bytes\n"));
+    }
+}
@@ -0,0 +1,264 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+// what a get looked at to find its key, in the order it looked, see
+// LsmStore::explain_get. printing it gives one line per step
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GetExplanation {
+    pub key: Bytes,
+    // the shard the key hashes to. it is the only one searched
+    pub shard: usize,
+    pub num_shards: usize,
+    pub steps: Vec<GetStep>,
+    pub value: Option<Bytes>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum GetStep {
+    Memtable {
+        id: usize,
+        frozen: bool,
+        outcome: ProbeOutcome,
+    },
+    // skipped without reading anything
+    SstOutsideKeyRange {
+        id: usize,
+        first_key: Bytes,
+        last_key: Bytes,
+    },
+    SstFilteredByBloom {
+        id: usize,
+    },
+    // the one block that can hold the key was searched. cached is whether it
+    // was in the block cache, otherwise it was read from the file
+    SstBlock {
+        id: usize,
+        block_index: usize,
+        cached: bool,
+        outcome: ProbeOutcome,
+    },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProbeOutcome {
+    // the search goes on to the next memtable or SST
+    NotFound,
+    Found,
+    // a tombstone ends the search like a value does
+    Deleted,
+}
+
+impl fmt::Display for ProbeOutcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProbeOutcome::NotFound => write!(f, "not found"),
+            ProbeOutcome::Found => write!(f, "found"),
+            ProbeOutcome::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+impl fmt::Display for GetExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.num_shards > 1 {
+            writeln!(f, "shard {} of {}", self.shard, self.num_shards)?;
+        }
+        for step in self.steps.iter() {
+            match step {
+                GetStep::Memtable { id, frozen, outcome } => {
+                    let state = if *frozen { "frozen" } else { "active" };
+                    writeln!(f, "memtable {} ({}): {}", id, state, outcome)?;
+                }
+                GetStep::SstOutsideKeyRange {
+                    id,
+                    first_key,
+                    last_key,
+                } => writeln!(
+                    f,
+                    "sst {}: skipped, key outside {}..={}",
+                    id,
+                    String::from_utf8_lossy(first_key),
+                    String::from_utf8_lossy(last_key)
+                )?,
+                GetStep::SstFilteredByBloom { id } => writeln!(f, "sst {}: skipped by bloom filter", id)?,
+                GetStep::SstBlock {
+                    id,
+                    block_index,
+                    cached,
+                    outcome,
+                } => {
+                    let source = if *cached { "cached" } else { "read from file" };
+                    writeln!(f, "sst {}: block {} ({}): {}", id, block_index, source, outcome)?;
+                }
+            }
+        }
+        match &self.value {
+            Some(value) => writeln!(f, "result: {}", String::from_utf8_lossy(value)),
+            None => writeln!(f, "result: not found"),
+        }
+    }
+}
+
+// what a scan reads, see LsmStore::explain_scan. each shard's memtables are
+// heap merged, its SSTs overlapping the range are heap merged, and the two
+// are merged with each other. l0 SSTs overlap, so they are never simply
+// concatenated. with several shards the shards are heap merged too
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ScanExplanation {
+    pub shards: Vec<ShardScanPlan>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ShardScanPlan {
+    // every memtable is scanned, whatever keys it holds
+    pub num_memtables: usize,
+    // those overlapping the range, newest first
+    pub ssts: Vec<SstScanPlan>,
+    // skipped without reading anything
+    pub num_ssts_outside_range: usize,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SstScanPlan {
+    pub id: usize,
+    // the blocks overlapping the range, of num_blocks in the SST
+    pub num_blocks_read: usize,
+    pub num_blocks: usize,
+    // of the blocks read, those in the block cache now
+    pub num_cached_blocks: usize,
+}
+
+impl ScanExplanation {
+    pub fn num_memtables(&self) -> usize {
+        self.shards.iter().map(|shard| shard.num_memtables).sum()
+    }
+
+    pub fn num_ssts(&self) -> usize {
+        self.shards.iter().map(|shard| shard.ssts.len()).sum()
+    }
+
+    pub fn num_blocks_read(&self) -> usize {
+        self.shards
+            .iter()
+            .flat_map(|shard| shard.ssts.iter())
+            .map(|sst| sst.num_blocks_read)
+            .sum()
+    }
+}
+
+// how a merge of num_inputs iterators is done
+fn merge_kind(num_inputs: usize) -> &'static str {
+    match num_inputs {
+        0 | 1 => "no merge",
+        _ => "heap merge",
+    }
+}
+
+impl fmt::Display for ScanExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (index, shard) in self.shards.iter().enumerate() {
+            if self.shards.len() > 1 {
+                writeln!(f, "shard {} of {}", index, self.shards.len())?;
+            }
+            writeln!(f, "memtables: {}, {}", shard.num_memtables, merge_kind(shard.num_memtables))?;
+            for sst in shard.ssts.iter() {
+                writeln!(
+                    f,
+                    "sst {}: {} of {} blocks, {} cached",
+                    sst.id, sst.num_blocks_read, sst.num_blocks, sst.num_cached_blocks
+                )?;
+            }
+            writeln!(
+                f,
+                "ssts: {} of {} overlap the range, {}",
+                shard.ssts.len(),
+                shard.ssts.len() + shard.num_ssts_outside_range,
+                merge_kind(shard.ssts.len())
+            )?;
+        }
+        if self.shards.len() > 1 {
+            writeln!(f, "shards: {}, {}", self.shards.len(), merge_kind(self.shards.len()))?;
+        }
+        writeln!(
+            f,
+            "total: {} memtables, {} ssts, {} blocks",
+            self.num_memtables(),
+            self.num_ssts(),
+            self.num_blocks_read()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+
+    use super::{GetExplanation, GetStep, ProbeOutcome, ScanExplanation, ShardScanPlan, SstScanPlan};
+
+    #[test]
+    fn test_display() {
+        let explanation = GetExplanation {
+            key: Bytes::from("k"),
+            shard: 0,
+            num_shards: 1,
+            steps: vec![
+                GetStep::Memtable {
+                    id: 4,
+                    frozen: false,
+                    outcome: ProbeOutcome::NotFound,
+                },
+                GetStep::SstOutsideKeyRange {
+                    id: 3,
+                    first_key: Bytes::from("a"),
+                    last_key: Bytes::from("c"),
+                },
+                GetStep::SstFilteredByBloom { id: 2 },
+                GetStep::SstBlock {
+                    id: 1,
+                    block_index: 0,
+                    cached: false,
+                    outcome: ProbeOutcome::Found,
+                },
+            ],
+            value: Some(Bytes::from("v")),
+        };
+        let expected = "\
+memtable 4 (active): not found
+sst 3: skipped, key outside a..=c
+sst 2: skipped by bloom filter
+sst 1: block 0 (read from file): found
+result: v
+";
+        assert_eq!(explanation.to_string(), expected);
+    }
+
+    #[test]
+    fn test_display_scan() {
+        let shard = ShardScanPlan {
+            num_memtables: 2,
+            ssts: vec![SstScanPlan {
+                id: 3,
+                num_blocks_read: 2,
+                num_blocks: 4,
+                num_cached_blocks: 1,
+            }],
+            num_ssts_outside_range: 1,
+        };
+        let explanation = ScanExplanation {
+            shards: vec![shard, ShardScanPlan::default()],
+        };
+        let expected = "\
+shard 0 of 2
+memtables: 2, heap merge
+sst 3: 2 of 4 blocks, 1 cached
+ssts: 1 of 2 overlap the range, no merge
+shard 1 of 2
+memtables: 0, no merge
+ssts: 0 of 0 overlap the range, no merge
+shards: 2, heap merge
+total: 2 memtables, 1 ssts, 2 blocks
+";
+        assert_eq!(explanation.to_string(), expected);
+    }
+}
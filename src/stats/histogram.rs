@@ -0,0 +1,172 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+// each power of two of nanoseconds is split into this many buckets, so a
+// recorded duration is off by at most 1/16th
+const SUB_BUCKET_BITS: u32 = 4;
+const SUB_BUCKETS: usize = 1 << SUB_BUCKET_BITS;
+const NUM_BUCKETS: usize = (64 - SUB_BUCKET_BITS as usize + 1) * SUB_BUCKETS;
+
+// durations of one kind of operation, see LsmStore::latency_report
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct LatencySummary {
+    pub count: u64,
+    pub mean: Duration,
+    // percentiles are rounded up to the end of their bucket
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+// log-linear histogram of durations in the style of HdrHistogram. recording
+// is a few atomic adds, so it can stay on in hot paths
+pub(crate) struct LatencyHistogram {
+    counts: Box<[AtomicU64]>,
+    total_nanos: AtomicU64,
+    max_nanos: AtomicU64,
+}
+
+impl Default for LatencyHistogram {
+    fn default() -> Self {
+        Self {
+            counts: (0..NUM_BUCKETS).map(|_| AtomicU64::new(0)).collect(),
+            total_nanos: AtomicU64::new(0),
+            max_nanos: AtomicU64::new(0),
+        }
+    }
+}
+
+impl LatencyHistogram {
+    pub(crate) fn record(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        self.counts[bucket_index(nanos)].fetch_add(1, Ordering::Relaxed);
+        self.total_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.max_nanos.fetch_max(nanos, Ordering::Relaxed);
+    }
+
+    // durations recorded while resetting may be partly kept
+    pub(crate) fn reset(&self) {
+        for count in self.counts.iter() {
+            count.store(0, Ordering::Relaxed);
+        }
+        self.total_nanos.store(0, Ordering::Relaxed);
+        self.max_nanos.store(0, Ordering::Relaxed);
+    }
+
+    pub(crate) fn summary(&self) -> LatencySummary {
+        Self::merged_summary([self])
+    }
+
+    // one summary over several histograms, e.g. one per shard
+    pub(crate) fn merged_summary<'a>(histograms: impl IntoIterator<Item = &'a LatencyHistogram>) -> LatencySummary {
+        let mut counts = vec![0; NUM_BUCKETS];
+        let mut total_nanos: u64 = 0;
+        let mut max_nanos = 0;
+        for histogram in histograms {
+            for (count, bucket) in counts.iter_mut().zip(histogram.counts.iter()) {
+                *count += bucket.load(Ordering::Relaxed);
+            }
+            total_nanos = total_nanos.saturating_add(histogram.total_nanos.load(Ordering::Relaxed));
+            max_nanos = max_nanos.max(histogram.max_nanos.load(Ordering::Relaxed));
+        }
+        let count: u64 = counts.iter().sum();
+        if count == 0 {
+            return LatencySummary::default();
+        }
+        let percentile = |quantile: f64| {
+            let rank = ((count as f64 * quantile).ceil() as u64).max(1);
+            let mut seen = 0;
+            for (index, bucket_count) in counts.iter().enumerate() {
+                seen += bucket_count;
+                if seen >= rank {
+                    return Duration::from_nanos(bucket_upper_bound(index).min(max_nanos));
+                }
+            }
+            Duration::from_nanos(max_nanos)
+        };
+        LatencySummary {
+            count,
+            mean: Duration::from_nanos(total_nanos / count),
+            p50: percentile(0.5),
+            p90: percentile(0.9),
+            p99: percentile(0.99),
+            max: Duration::from_nanos(max_nanos),
+        }
+    }
+}
+
+// values below SUB_BUCKETS get a bucket each, larger ones share a bucket with
+// the values that have the same highest SUB_BUCKET_BITS + 1 bits
+fn bucket_index(nanos: u64) -> usize {
+    if nanos < SUB_BUCKETS as u64 {
+        return nanos as usize;
+    }
+    let exponent = 63 - nanos.leading_zeros();
+    let sub_bucket = (nanos >> (exponent - SUB_BUCKET_BITS)) as usize & (SUB_BUCKETS - 1);
+    (((exponent - SUB_BUCKET_BITS + 1) as usize) << SUB_BUCKET_BITS) + sub_bucket
+}
+
+// largest value that falls in the bucket
+fn bucket_upper_bound(index: usize) -> u64 {
+    if index < SUB_BUCKETS {
+        return index as u64;
+    }
+    let exponent = (index >> SUB_BUCKET_BITS) as u32 + SUB_BUCKET_BITS - 1;
+    let sub_bucket = (index & (SUB_BUCKETS - 1)) as u64;
+    let lower_bound = (1 << exponent) | (sub_bucket << (exponent - SUB_BUCKET_BITS));
+    lower_bound + ((1 << (exponent - SUB_BUCKET_BITS)) - 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{bucket_index, bucket_upper_bound, LatencyHistogram, LatencySummary, NUM_BUCKETS};
+
+    #[test]
+    fn test_buckets() {
+        for nanos in [0, 1, 15, 16, 17, 31, 32, 1000, 123_456_789, u64::MAX / 3, u64::MAX] {
+            let index = bucket_index(nanos);
+            assert!(index < NUM_BUCKETS);
+            assert!(bucket_upper_bound(index) >= nanos);
+            // the bucket is no wider than a 16th of its values
+            assert!(bucket_upper_bound(index) - nanos <= nanos / 16, "{}", nanos);
+            if index > 0 {
+                assert!(bucket_upper_bound(index - 1) < nanos);
+            }
+        }
+        assert_eq!(bucket_index(u64::MAX), NUM_BUCKETS - 1);
+    }
+
+    #[test]
+    fn test_latency_histogram() {
+        let histogram = LatencyHistogram::default();
+        assert_eq!(histogram.summary(), LatencySummary::default());
+        for micros in 1..=100 {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let summary = histogram.summary();
+        assert_eq!(summary.count, 100);
+        assert_eq!(summary.mean, Duration::from_nanos(50_500));
+        assert_eq!(summary.max, Duration::from_micros(100));
+        let within = |actual: Duration, expected_micros: u64| {
+            let expected = Duration::from_micros(expected_micros);
+            actual >= expected && actual <= expected + expected / 16
+        };
+        assert!(within(summary.p50, 50), "{:?}", summary);
+        assert!(within(summary.p90, 90), "{:?}", summary);
+        assert!(within(summary.p99, 99), "{:?}", summary);
+
+        let other = LatencyHistogram::default();
+        other.record(Duration::from_millis(1));
+        let merged = LatencyHistogram::merged_summary([&histogram, &other]);
+        assert_eq!(merged.count, 101);
+        assert_eq!(merged.max, Duration::from_millis(1));
+
+        histogram.reset();
+        assert_eq!(histogram.summary(), LatencySummary::default());
+    }
+}
@@ -1,110 +1,1792 @@
 use std::{
+    iter::FusedIterator,
     ops::Bound,
-    sync::{Arc, Mutex},
-    thread,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, RwLock, RwLockReadGuard,
+    },
 };
 
 use anyhow::{anyhow, Result};
 use bytes::Bytes;
+use crossbeam_channel::Receiver;
 
 use crate::{
-    iterator::StorageIterator, kv::kv_pair::KeyValuePair, state::{storage_state_options::StorageStateOptions, StorageState}
+    batch::WriteBatchWithIndex,
+    error::LsmError,
+    iterator::{
+        keys_only_iterator::KeysOnlyIterator,
+        latest_iterator::LatestIterator,
+        limit_iterator::LimitIterator,
+        lsm_iterator::{utf8_string, LsmIterator, LsmStrIterator},
+        projection_iterator::{Projection, ProjectionIterator},
+        scan_iterator::ScanIterator,
+        tracked_iterator::TrackedIterator,
+        IteratorStats,
+    },
+    kv::{
+        entry::{Entry, Version},
+        key_range::KeyRange,
+        kv_pair::KeyValuePair,
+    },
+    platform::Instant,
+    scheduler::BackgroundScheduler,
+    state::{
+        backup::BackupInfo,
+        read_options::{ReadOptions, ReadOptionsIterator},
+        repair::RepairReport,
+        sharded_state::ShardedStorageState,
+        snapshot::Snapshot,
+        storage_state_options::StorageStateOptions,
+        update_log::{WriteEvent, WriteRecord},
+        write_options::{WriteOptions, WriteToken},
+    },
+    stats::{
+        description::StoreDescription,
+        explain::{GetExplanation, ScanExplanation},
+        histogram::LatencyHistogram,
+        BackgroundStatus, LatencyReport, LsmStats, MemoryUsage, PrefixStats, ScanRegistry,
+    },
 };
 
-pub struct LsmStore {
-    // send notification to end flush
-    flush_notifier: crossbeam_channel::Sender<()>,
-    // handle for flush thread
-    flush_thread: Mutex<Option<thread::JoinHandle<()>>>,
-    storage_state: Arc<StorageState>,
+// what scan returns. the iterators it is built from stay internal, so they
+// can change without breaking callers that store it in a struct
+pub struct Scan(ScanIterator<TrackedIterator<ReadOptionsIterator>>);
+
+impl Scan {
+    pub fn is_valid(&self) -> bool {
+        self.0.is_valid()
+    }
+
+    pub fn error(&self) -> Option<&anyhow::Error> {
+        self.0.error()
+    }
+
+    pub fn check_error(&self) -> Result<()> {
+        self.0.check_error()
+    }
+
+    pub fn stats(&self) -> IteratorStats {
+        self.0.stats()
+    }
+
+    pub fn num_active_iterators(&self) -> usize {
+        self.0.num_active_iterators()
+    }
 }
 
-impl Drop for LsmStore {
-    fn drop(&mut self) {
-        self.flush_notifier.send(()).ok();
-        // join all threads to avoid unexpected behavior
-        // https://matklad.github.io/2019/08/23/join-your-threads.html
-        let mut flush_thread = self.flush_thread.lock().unwrap();
-        if let Some(thread) = flush_thread.take() {
-            thread.join().unwrap();
-        }
+impl Iterator for Scan {
+    type Item = Entry;
+
+    fn next(&mut self) -> Option<Entry> {
+        self.0.next()
+    }
+}
+
+impl FusedIterator for Scan {}
+
+// what scan_keys returns, see Scan
+pub struct KeyScan(KeysOnlyIterator<TrackedIterator<ReadOptionsIterator>>);
+
+impl KeyScan {
+    pub fn is_valid(&self) -> bool {
+        self.0.is_valid()
+    }
+
+    pub fn error(&self) -> Option<&anyhow::Error> {
+        self.0.error()
+    }
+
+    pub fn check_error(&self) -> Result<()> {
+        self.0.check_error()
+    }
+
+    pub fn stats(&self) -> IteratorStats {
+        self.0.stats()
+    }
+}
+
+impl Iterator for KeyScan {
+    type Item = Bytes;
+
+    fn next(&mut self) -> Option<Bytes> {
+        self.0.next()
     }
 }
 
+impl FusedIterator for KeyScan {}
+
+pub struct LsmStore {
+    // runs flushes and other background work. shuts itself down when dropped
+    scheduler: BackgroundScheduler,
+    storage_state: ShardedStorageState,
+    // scans handed out by this store that are still alive
+    scan_registry: Arc<ScanRegistry>,
+    get_latency: LatencyHistogram,
+    put_latency: LatencyHistogram,
+    // set as soon as close is called, so that everything else fails with
+    // LsmError::Closed from then on
+    closed: AtomicBool,
+    // writes hold the read side from checking closed until they are done, and
+    // close takes the write side to set it, so every write that got past the
+    // check is in a memtable by the time close flushes them
+    write_lock: RwLock<()>,
+    // whether a close has finished. a close that failed, e.g. to flush, is
+    // retried by calling close again
+    close_finished: Mutex<bool>,
+    // see StorageStateOptions::fail_writes_on_background_error
+    fail_writes_on_background_error: bool,
+}
+
 impl LsmStore {
     pub fn open(options: StorageStateOptions) -> Result<LsmStore> {
-        let storage_state = Arc::new(StorageState::open(options)?);
+        let scheduler = BackgroundScheduler::new(options.num_background_threads)?;
+        let fail_writes_on_background_error = options.fail_writes_on_background_error;
+        let storage_state = ShardedStorageState::open(options)?;
 
-        // set up flush background thread
-        let (flush_notifier, receiver) = crossbeam_channel::unbounded();
-        let flush_thread = Mutex::new(storage_state.spawn_flush_thread(receiver)?);
+        // set up background flushes, compactions and scrubbing
+        storage_state.schedule_flush(&scheduler)?;
+        storage_state.schedule_compaction(&scheduler)?;
+        storage_state.schedule_scrub(&scheduler)?;
         Ok(Self {
-            flush_notifier,
-            flush_thread,
+            scheduler,
             storage_state,
+            scan_registry: Arc::new(ScanRegistry::default()),
+            get_latency: LatencyHistogram::default(),
+            put_latency: LatencyHistogram::default(),
+            closed: AtomicBool::new(false),
+            write_lock: RwLock::new(()),
+            close_finished: Mutex::new(false),
+            fail_writes_on_background_error,
         })
     }
 
+    // reads and writes fail with LsmError::Closed once this is called.
+    // calling it again does nothing once a close has finished
     pub fn close(&self) -> Result<()> {
-        // end flush thread
-        self.flush_notifier.send(()).ok();
-        let mut flush_thread = self.flush_thread.lock().map_err(|e| anyhow!("{:?}", e))?;
-        if let Some(thread) = flush_thread.take() {
-            thread.join().map_err(|e| anyhow!("{:?}", e))?;
+        {
+            let _write_guard = self.write_lock.write().unwrap();
+            self.closed.store(true, Ordering::SeqCst);
+        }
+        let mut close_finished = self.close_finished.lock().unwrap();
+        if *close_finished {
+            return Ok(());
         }
+        // stop background work. a long merge is abandoned rather than waited
+        // for, its inputs are still in place
+        self.storage_state.cancel_compaction();
+        self.scheduler.shutdown()?;
         // flush all memtables
         self.storage_state.flush_all_memtables()?;
+        *close_finished = true;
         Ok(())
     }
 
-    pub fn get(&self, key: &[u8]) -> Result<Option<Bytes>> {
-        self.storage_state.get(key)
+    fn check_open(&self) -> Result<()> {
+        match self.closed.load(Ordering::SeqCst) {
+            true => Err(LsmError::Closed.into()),
+            false => Ok(()),
+        }
+    }
+
+    // hold the guard until the write is done, see write_lock
+    fn check_writable(&self) -> Result<RwLockReadGuard<'_, ()>> {
+        let write_guard = self.write_lock.read().unwrap();
+        self.check_open()?;
+        match self.scheduler.background_error() {
+            Some(error) if self.fail_writes_on_background_error => Err(error.into()),
+            _ => Ok(write_guard),
+        }
+    }
+
+    // the first panic of a background task, e.g. a flush, as
+    // LsmError::Background. the task still runs on schedule, so the cause
+    // may have passed, see BackgroundScheduler::background_error
+    pub fn background_error(&self) -> Result<Option<LsmError>> {
+        self.check_open()?;
+        Ok(self.scheduler.background_error())
+    }
+
+    // e.g. once the cause is fixed, so that writes are accepted again, see
+    // StorageStateOptions::fail_writes_on_background_error
+    pub fn clear_background_error(&self) -> Result<()> {
+        self.check_open()?;
+        self.scheduler.clear_background_error();
+        Ok(())
     }
 
-    pub fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
-        self.storage_state.put(key, value)
+    // flushes, merges and scrubbing, queued, running and pending
+    pub fn background_status(&self) -> Result<BackgroundStatus> {
+        self.check_open()?;
+        Ok(BackgroundStatus {
+            scheduler: self.scheduler.status(),
+            num_pending_flushes: self.storage_state.num_pending_flushes(),
+            merges: self.storage_state.merge_progress(),
+        })
     }
 
-    pub fn delete(&self, key: &[u8]) -> Result<()> {
-        self.storage_state.delete(key)
+    // see BackgroundScheduler::pause. wait_for_flush and wait_for_compaction
+    // still work while paused, as they run on the caller's thread
+    pub fn pause_background_work(&self) -> Result<()> {
+        self.check_open()?;
+        self.scheduler.pause();
+        Ok(())
     }
 
-    #[allow(clippy::implied_bounds_in_impls)]
-    pub fn scan(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<impl StorageIterator + Iterator<Item = KeyValuePair>> {
-        self.storage_state.scan(lower, upper)
+    pub fn resume_background_work(&self) -> Result<()> {
+        self.check_open()?;
+        self.scheduler.resume();
+        Ok(())
+    }
+
+    // durability barrier: returns once every memtable frozen before the call
+    // is in an SST. the active memtable isn't frozen, close does that
+    pub fn wait_for_flush(&self) -> Result<()> {
+        self.check_open()?;
+        self.storage_state.wait_for_flush()
+    }
+
+    // returns once the compaction style has nothing left to do for the SSTs
+    // as they are now, e.g. to measure a compacted store
+    pub fn wait_for_compaction(&self) -> Result<()> {
+        self.check_open()?;
+        self.storage_state.wait_for_compaction()
+    }
+
+    // delete a closed store's files from disk
+    pub fn destroy(path: impl AsRef<Path>) -> Result<()> {
+        ShardedStorageState::destroy(path)
+    }
+
+    // rebuild a closed store's manifest from the SSTs on disk. returns what
+    // was recovered and skipped, one report per shard
+    pub fn repair(path: impl AsRef<Path>) -> Result<Vec<RepairReport>> {
+        ShardedStorageState::repair(path)
+    }
+
+    // sequence number of the newest write. every write gets the next one,
+    // across all shards, and numbering carries on after a reopen
+    pub fn latest_sequence(&self) -> Result<u64> {
+        self.check_open()?;
+        Ok(self.storage_state.latest_sequence())
+    }
+
+    // for consumers that follow the store's writes, e.g. replicas: the
+    // sequence they have caught up to. writes after the first floor is set
+    // are kept for get_updates_since until the floor moves past them, so it
+    // can only move forward
+    pub fn set_sequence_floor(&self, sequence: u64) -> Result<()> {
+        self.check_open()?;
+        self.storage_state.set_sequence_floor(sequence)
+    }
+
+    // the writes after sequence, oldest first, for incremental backups and
+    // replicas catching up. sequence must be at or after the floor. the
+    // writes are kept in memory, so a consumer has to start over after a
+    // restart
+    pub fn get_updates_since(&self, sequence: u64) -> Result<impl Iterator<Item = WriteRecord>> {
+        self.check_open()?;
+        Ok(self.storage_state.get_updates_since(sequence)?.into_iter())
+    }
+
+    // every write to a key under prefix from now on, as it is made, e.g. to
+    // reload configuration when it changes. a batch comes through as one
+    // event per key, under one sequence per shard it touched. writes to
+    // different shards may arrive slightly out of sequence order. bulk loads
+    // aren't writes and aren't sent. dropping the receiver stops the watch
+    pub fn watch_prefix(&self, prefix: impl AsRef<[u8]>) -> Result<Receiver<WriteEvent>> {
+        self.check_open()?;
+        Ok(self.storage_state.watch_prefix(prefix.as_ref()))
+    }
+
+    // copy every write made so far into a new backup in backup_dir, and
+    // return its id among the other information
+    pub fn create_backup(&self, backup_dir: impl AsRef<Path>) -> Result<BackupInfo> {
+        self.check_open()?;
+        self.storage_state.create_backup(backup_dir, None)
+    }
+
+    // like create_backup, but only copies the SSTs that backup since_backup_id
+    // doesn't have. restoring it needs that backup and its bases
+    pub fn create_incremental_backup(&self, backup_dir: impl AsRef<Path>, since_backup_id: usize) -> Result<BackupInfo> {
+        self.check_open()?;
+        self.storage_state.create_backup(backup_dir, Some(since_backup_id))
+    }
+
+    // recreate a store at path as of a backup in backup_dir
+    pub fn restore_backup(backup_dir: impl AsRef<Path>, backup_id: usize, path: impl AsRef<Path>) -> Result<()> {
+        ShardedStorageState::restore_backup(backup_dir, backup_id, path)
+    }
+
+    pub fn get(&self, key: impl AsRef<[u8]>) -> Result<Option<Bytes>> {
+        self.check_open()?;
+        timed(&self.get_latency, || self.storage_state.get(key.as_ref()))
+    }
+
+    // like get, for values that are UTF-8 text. a value that isn't is an error
+    pub fn get_str(&self, key: impl AsRef<[u8]>) -> Result<Option<String>> {
+        let key = key.as_ref();
+        self.get(key)?
+            .map(|value| utf8_string(value, "value", key))
+            .transpose()
+    }
+
+    // e.g. to read from a snapshot. see ReadOptions for which options gets use
+    pub fn get_with_options(&self, key: impl AsRef<[u8]>, options: &ReadOptions) -> Result<Option<Bytes>> {
+        self.check_open()?;
+        timed(&self.get_latency, || self.storage_state.get_with_options(key.as_ref(), options))
+    }
+
+    // a view of the store as of now that reads can be pointed at through
+    // ReadOptions::snapshot. later writes don't show up in it, and the data
+    // it sees is kept until it is dropped
+    pub fn snapshot(&self) -> Result<Snapshot> {
+        self.check_open()?;
+        Ok(self.storage_state.snapshot())
+    }
+
+    // SSTs are only searched when their bloom filter and key range allow the
+    // key. the key's block is still read to tell values from tombstones, but
+    // the value isn't, see ReadOptions::keys_only. not counted as a get by
+    // latency_report
+    pub fn exists(&self, key: impl AsRef<[u8]>) -> Result<bool> {
+        self.check_open()?;
+        let options = ReadOptions {
+            keys_only: true,
+            ..Default::default()
+        };
+        Ok(self.storage_state.get_with_options(key.as_ref(), &options)?.is_some())
+    }
+
+    pub fn put(&self, key: impl AsRef<[u8]>, value: impl AsRef<[u8]>) -> Result<()> {
+        let _write_guard = self.check_writable()?;
+        timed(&self.put_latency, || self.storage_state.put(key.as_ref(), value.as_ref()))
+    }
+
+    pub fn delete(&self, key: impl AsRef<[u8]>) -> Result<()> {
+        let _write_guard = self.check_writable()?;
+        timed(&self.put_latency, || self.storage_state.delete(key.as_ref()))
+    }
+
+    // e.g. sync for a write that must survive a crash, see WriteOptions. the
+    // token lets later reads demand to see the write, see
+    // ReadOptions::min_sequence
+    pub fn put_with_options(
+        &self,
+        key: impl AsRef<[u8]>,
+        value: impl AsRef<[u8]>,
+        options: &WriteOptions,
+    ) -> Result<WriteToken> {
+        let _write_guard = self.check_writable()?;
+        timed(&self.put_latency, || {
+            self.storage_state.put_with_options(key.as_ref(), value.as_ref(), options)
+        })
+    }
+
+    pub fn delete_with_options(&self, key: impl AsRef<[u8]>, options: &WriteOptions) -> Result<WriteToken> {
+        let _write_guard = self.check_writable()?;
+        timed(&self.put_latency, || self.storage_state.delete_with_options(key.as_ref(), options))
+    }
+
+    pub fn write_with_options(&self, batch: &WriteBatchWithIndex, options: &WriteOptions) -> Result<WriteToken> {
+        let _write_guard = self.check_writable()?;
+        let kvs: Vec<KeyValuePair> = batch.entries().collect();
+        timed(&self.put_latency, || self.storage_state.write_batch_with_options(&kvs, options))
+    }
+
+    // atomically replace the key's value with new, or delete it if new is
+    // None, provided it currently holds expected (None meaning absent).
+    // returns Err with the current value if it doesn't, so counters and locks
+    // can retry from there
+    pub fn compare_and_swap(
+        &self,
+        key: impl AsRef<[u8]>,
+        expected: Option<&[u8]>,
+        new: Option<&[u8]>,
+    ) -> Result<std::result::Result<(), Option<Bytes>>> {
+        let _write_guard = self.check_writable()?;
+        timed(&self.put_latency, || self.storage_state.compare_and_swap(key.as_ref(), expected, new))
+    }
+
+    // atomically add delta to the key's value, read as a little-endian i64
+    // and 0 if the key is absent, and return the sum. a compare_and_swap loop,
+    // so concurrent increments of one key retry rather than block
+    pub fn increment(&self, key: impl AsRef<[u8]>, delta: i64) -> Result<i64> {
+        let _write_guard = self.check_writable()?;
+        let key = key.as_ref();
+        timed(&self.put_latency, || {
+            let mut current = self.storage_state.get(key)?;
+            loop {
+                let count = match &current {
+                    None => 0,
+                    Some(value) => i64::from_le_bytes(value.as_ref().try_into().map_err(|_| {
+                        anyhow!("value of {:?} is {} bytes, not an 8 byte counter", key, value.len())
+                    })?),
+                };
+                let sum = count
+                    .checked_add(delta)
+                    .ok_or_else(|| anyhow!("incrementing {} by {} overflows", count, delta))?;
+                match self.storage_state.compare_and_swap(key, current.as_deref(), Some(&sum.to_le_bytes()))? {
+                    Ok(()) => return Ok(sum),
+                    Err(actual) => current = actual,
+                }
+            }
+        })
+    }
+
+    pub fn write(&self, batch: &WriteBatchWithIndex) -> Result<()> {
+        let _write_guard = self.check_writable()?;
+        let kvs: Vec<KeyValuePair> = batch.entries().collect();
+        timed(&self.put_latency, || self.storage_state.write_batch(&kvs))
+    }
+
+    // fast initial ingestion of a sorted dataset: pairs are written straight
+    // into SSTs, skipping the memtable. keys must be strictly increasing.
+    // loaded keys take precedence over anything written before the load
+    // starts, and writes made while it runs take precedence over them
+    pub fn bulk_load(&self, entries: impl IntoIterator<Item = Entry>) -> Result<()> {
+        let _write_guard = self.check_writable()?;
+        self.storage_state.bulk_load(entries.into_iter().map(KeyValuePair::from))
+    }
+
+    // for keys written in ascending order, e.g. time series. keys must be
+    // strictly increasing within the batch, or nothing is written. the batch
+    // goes into the memtable in one sorted insert, unless it is larger than
+    // all the memtables that may be frozen at once, in which case it is bulk
+    // loaded, see bulk_load
+    pub fn append_sorted(&self, entries: impl IntoIterator<Item = Entry>) -> Result<()> {
+        let _write_guard = self.check_writable()?;
+        let kvs: Vec<KeyValuePair> = entries.into_iter().map(KeyValuePair::from).collect();
+        timed(&self.put_latency, || self.storage_state.append_sorted(&kvs))
+    }
+
+    // a scan that hits an I/O error or corruption part way through stops
+    // early. call check_error on the iterator once it is exhausted
+    pub fn scan(&self, range: impl KeyRange) -> Result<Scan> {
+        self.scan_with_options(range, &ReadOptions::default())
+    }
+
+    #[deprecated(note = "pass the bounds to scan as a pair, e.g. scan((lower, upper))")]
+    pub fn scan_bounds(
+        &self,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Scan> {
+        self.scan((lower, upper))
+    }
+
+    // e.g. fill_cache: false for a full-table scan, or a snapshot to scan
+    pub fn scan_with_options(
+        &self,
+        range: impl KeyRange,
+        options: &ReadOptions,
+    ) -> Result<Scan> {
+        Ok(Scan(ScanIterator::new(self.tracked_scan(range, options)?)))
+    }
+
+    // the scan underneath scan_with_options, still over internal keys, for
+    // the iterators that build on it
+    pub(crate) fn tracked_scan(
+        &self,
+        range: impl KeyRange,
+        options: &ReadOptions,
+    ) -> Result<TrackedIterator<ReadOptionsIterator>> {
+        self.check_open()?;
+        let (lower, upper) = range.bounds();
+        let scan = self.storage_state.scan_with_options(lower, upper, options)?;
+        Ok(TrackedIterator::new(scan, self.scan_registry.clone()))
+    }
+
+    // a scan of the live pairs in range with filtering and projection done
+    // as entries come out of the merge, see ScanBuilder
+    pub fn scan_builder(&self, range: impl KeyRange) -> ScanBuilder<'_> {
+        let (lower, upper) = range.bounds();
+        ScanBuilder {
+            store: self,
+            lower: lower.map(Bytes::copy_from_slice),
+            upper: upper.map(Bytes::copy_from_slice),
+            options: ReadOptions::default(),
+            projection: Projection::default(),
+        }
+    }
+
+    // the live key-value pairs in range as a concrete type that can be stored
+    // or boxed, unlike scan's. errors are yielded as the last item instead of
+    // having to be checked for
+    pub fn iter(&self, range: impl KeyRange) -> Result<LsmIterator> {
+        Ok(LsmIterator::new(LatestIterator::new(self.tracked_scan(range, &ReadOptions::default())?)))
+    }
+
+    // like iter, for stores of UTF-8 text. a key or value that isn't is
+    // yielded as an error, and the scan carries on after it
+    pub fn scan_str(&self, range: impl KeyRange) -> Result<LsmStrIterator> {
+        Ok(LsmStrIterator::new(self.iter(range)?))
+    }
+
+    // keys in range that have a value. values are never copied out of
+    // memtables or blocks, so this is cheaper than scan when only keys matter.
+    // like scan, check_error tells a failed scan apart from a finished one
+    pub fn scan_keys(&self, range: impl KeyRange) -> Result<KeyScan> {
+        let options = ReadOptions {
+            keys_only: true,
+            ..Default::default()
+        };
+        Ok(KeyScan(KeysOnlyIterator::new(self.tracked_scan(range, &options)?)))
+    }
+
+    // memtables, SSTs and the resources held by open scans. a scan's own
+    // iterators and pinned blocks are available from its stats method
+    pub fn stats(&self) -> Result<LsmStats> {
+        self.check_open()?;
+        let (num_open_scans, open_scan_iterators) = self.scan_registry.open_scans();
+        Ok(LsmStats {
+            num_open_scans,
+            open_scan_iterators,
+            ..self.storage_state.stats()
+        })
+    }
+
+    // every shard's memtables and SSTs with their key ranges, sizes and
+    // entry counts. printing the description draws it as tables
+    pub fn describe(&self) -> Result<StoreDescription> {
+        self.check_open()?;
+        self.storage_state.describe()
+    }
+
+    // recent versions of key, newest first, at most limit of them, for
+    // debugging and auditing. deletes are versions too. versions shadowed
+    // when SSTs were merged are gone, see StorageState::get_versions, and
+    // flushed versions mostly come back without their sequence, see Version
+    pub fn get_versions(&self, key: impl AsRef<[u8]>, limit: usize) -> Result<Vec<Version>> {
+        self.check_open()?;
+        self.storage_state.get_versions(key.as_ref(), limit)
+    }
+
+    // the memtables and SSTs a get of key looks at, in order, with what it
+    // found in each and why SSTs were skipped. for debugging slow or
+    // surprising gets. printing the explanation gives one line per step
+    pub fn explain_get(&self, key: impl AsRef<[u8]>) -> Result<GetExplanation> {
+        self.check_open()?;
+        self.storage_state.explain_get(key.as_ref())
+    }
+
+    // the memtables, SSTs and blocks a scan of range would read and how they
+    // are merged, for reasoning about what a range query costs before
+    // running it
+    pub fn explain_scan(&self, range: impl KeyRange) -> Result<ScanExplanation> {
+        self.check_open()?;
+        let (lower, upper) = range.bounds();
+        self.storage_state.explain_scan(lower, upper)
+    }
+
+    // memtables, cached blocks and SST metadata, for enforcing a process
+    // memory budget or finding out what is using memory. see MemoryUsage for
+    // what each part counts
+    pub fn approximate_memory_usage(&self) -> Result<MemoryUsage> {
+        self.check_open()?;
+        Ok(self.storage_state.approximate_memory_usage())
+    }
+
+    // latency percentiles of reads, writes, scans and background work since
+    // the store was opened or reset_latencies was last called
+    pub fn latency_report(&self) -> Result<LatencyReport> {
+        self.check_open()?;
+        Ok(LatencyReport {
+            get: self.get_latency.summary(),
+            put: self.put_latency.summary(),
+            scan_next: self.scan_registry.next_latency().summary(),
+            ..self.storage_state.latency_report()
+        })
+    }
+
+    pub fn reset_latencies(&self) -> Result<()> {
+        self.check_open()?;
+        self.get_latency.reset();
+        self.put_latency.reset();
+        self.scan_registry.next_latency().reset();
+        self.storage_state.reset_latencies();
+        Ok(())
+    }
+
+    // aggregates are computed inside the iterator stack, without handing
+    // every key-value pair back to the caller. count adds up the live keys
+    // the block index records for SST blocks that nothing else overlaps, and
+    // merges only the keys around them, so compacted ranges are counted
+    // mostly without reading data blocks. estimate_count reads none at all
+    // when an upper bound will do
+    pub fn count(&self, range: impl KeyRange) -> Result<usize> {
+        self.check_open()?;
+        let (lower, upper) = range.bounds();
+        self.storage_state.count(lower, upper)
+    }
+
+    pub fn sum_values_as_u64(&self, range: impl KeyRange) -> Result<u64> {
+        self.check_open()?;
+        let (lower, upper) = range.bounds();
+        self.storage_state.sum_values_as_u64(lower, upper)
+    }
+
+    // estimated entries and bytes under a key prefix, for watching how parts
+    // of the keyspace grow. reads only the SST blocks that straddle the
+    // prefix's edges, see PrefixStats
+    pub fn prefix_stats(&self, prefix: impl AsRef<[u8]>) -> Result<PrefixStats> {
+        self.check_open()?;
+        self.storage_state.prefix_stats(prefix.as_ref())
+    }
+
+    // cheap upper bound on count, see StorageState::estimate_count
+    pub fn estimate_count(&self, range: impl KeyRange) -> Result<u64> {
+        self.check_open()?;
+        let (lower, upper) = range.bounds();
+        self.storage_state.estimate_count(lower, upper)
+    }
+}
+
+// configures a scan, see LsmStore::scan_builder. filters and transforms run
+// on each live entry before it is handed out, so a limit counts only the
+// entries that pass, and keys_only never hands out values
+pub struct ScanBuilder<'a> {
+    store: &'a LsmStore,
+    lower: Bound<Bytes>,
+    upper: Bound<Bytes>,
+    options: ReadOptions,
+    projection: Projection,
+}
+
+impl ScanBuilder<'_> {
+    // snapshot, fill_cache and the rest. ignore_tombstones is always on, and
+    // the limit applies after filtering
+    pub fn options(mut self, options: ReadOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.options.limit = Some(limit);
+        self
+    }
+
+    // yield empty values
+    pub fn keys_only(mut self) -> Self {
+        self.projection.keys_only = true;
+        self
+    }
+
+    // keep only the pairs f accepts. filters added together must all accept
+    pub fn filter(mut self, f: impl Fn(&[u8], &[u8]) -> bool + 'static) -> Self {
+        self.projection.filters.push(Box::new(f));
+        self
+    }
+
+    // replace each value with f(key, value), after filtering
+    pub fn values_transformed(mut self, f: impl Fn(&[u8], Bytes) -> Bytes + 'static) -> Self {
+        self.projection.value_transforms.push(Box::new(f));
+        self
+    }
+
+    pub fn build(self) -> Result<LsmIterator> {
+        let limit = self.options.limit;
+        // filters see values, so they are only left unread without filters
+        let keys_only = self.options.keys_only || (self.projection.keys_only && self.projection.filters.is_empty());
+        let options = ReadOptions {
+            ignore_tombstones: true,
+            limit: None,
+            keys_only,
+            ..self.options
+        };
+        let bounds = (
+            self.lower.as_ref().map(|key| key.as_ref()),
+            self.upper.as_ref().map(|key| key.as_ref()),
+        );
+        let scan = self.store.tracked_scan(bounds, &options)?;
+        Ok(LsmIterator::new(LimitIterator::new(
+            ProjectionIterator::new(scan, self.projection),
+            limit,
+        )))
     }
 }
 
+// the scheduler joins its workers when dropped, so don't keep it waiting on
+// a merge either
+impl Drop for LsmStore {
+    fn drop(&mut self) {
+        self.storage_state.cancel_compaction();
+    }
+}
+
+fn timed<T>(histogram: &LatencyHistogram, operation: impl FnOnce() -> T) -> T {
+    let started = Instant::now();
+    let res = operation();
+    histogram.record(started.elapsed());
+    res
+}
+
 #[cfg(test)]
 mod tests {
+    use std::{
+        iter::FusedIterator,
+        ops::Bound,
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use bytes::Bytes;
     use tempfile::tempdir;
 
-    use crate::state::storage_state_options::StorageStateOptions;
+    use crate::{
+        error::LsmError,
+        iterator::{lsm_iterator::LsmIterator, IteratorStats},
+        kv::entry::Entry,
+        listener::{EventListener, FlushJobInfo},
+        state::{
+            read_options::ReadOptions,
+            storage_state_options::{FlushTrigger, StorageStateOptions},
+        },
+        stats::{
+            explain::{GetStep, ProbeOutcome},
+            LatencyReport, PrefixStats,
+        },
+    };
 
-    use super::LsmStore;
+    use super::{KeyScan, LsmStore, Scan, WriteBatchWithIndex, WriteEvent, WriteOptions, WriteToken};
 
     #[test]
     fn test_open_close() {
         let dir = tempdir().unwrap();
         let options = StorageStateOptions {
             sst_max_size_bytes: 128,
-            block_max_size_bytes: 0,
+            block_max_size_bytes: 1,
             block_cache_size_bytes: 0,
             path: dir.path().to_owned(),
             num_memtables_limit: 5,
+            ..Default::default()
         };
 
         let store = LsmStore::open(options).unwrap();
-        {
-            let thread = store.flush_thread.lock().unwrap();
-            assert!(!thread.as_ref().unwrap().is_finished());
+        assert!(!store.scheduler.is_shutdown());
+        store.close().unwrap();
+        // background threads have been stopped and joined
+        assert!(store.scheduler.is_shutdown());
+        assert_eq!(store.scheduler.num_workers(), 0);
+    }
+
+    #[test]
+    fn test_reopen() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            sst_max_size_bytes: 128,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+
+        let store = LsmStore::open(options()).unwrap();
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        store.close().unwrap();
+        drop(store);
+
+        // closing a store without any new writes must not leave an empty SST behind
+        let store = LsmStore::open(options()).unwrap();
+        store.close().unwrap();
+        drop(store);
+
+        let store = LsmStore::open(options()).unwrap();
+        assert_eq!(store.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+        let keys: Vec<_> = store
+            .scan(..)
+            .unwrap()
+            .map(|entry| entry.key)
+            .collect();
+        assert_eq!(keys, vec!["k1".as_bytes()]);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_use_after_close() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+
+        let store = LsmStore::open(options()).unwrap();
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        store.close().unwrap();
+        // closing again does nothing
+        store.close().unwrap();
+        let is_closed = |error: anyhow::Error| error.downcast_ref::<LsmError>() == Some(&LsmError::Closed);
+        assert!(is_closed(store.put("k2".as_bytes(), "v2".as_bytes()).unwrap_err()));
+        assert!(is_closed(store.get("k1".as_bytes()).unwrap_err()));
+        assert!(is_closed(store.scan(..).err().unwrap()));
+        assert!(is_closed(store.write(&WriteBatchWithIndex::new()).unwrap_err()));
+        assert!(is_closed(store.snapshot().unwrap_err()));
+        assert!(is_closed(store.watch_prefix("k").unwrap_err()));
+        assert!(is_closed(store.stats().unwrap_err()));
+        assert!(is_closed(store.latency_report().unwrap_err()));
+        assert!(is_closed(store.latest_sequence().unwrap_err()));
+        assert!(is_closed(store.approximate_memory_usage().unwrap_err()));
+        assert!(is_closed(store.background_status().unwrap_err()));
+        assert!(is_closed(store.pause_background_work().unwrap_err()));
+        drop(store);
+
+        let store = LsmStore::open(options()).unwrap();
+        assert_eq!(store.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+        assert!(store.get("k2".as_bytes()).unwrap().is_none());
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_close_during_writes() {
+        let dir = tempdir().unwrap();
+        let options = || StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+
+        // every write that succeeds is flushed by close, however the two
+        // interleave
+        let store = LsmStore::open(options()).unwrap();
+        let written: Vec<Vec<String>> = thread::scope(|scope| {
+            let writers: Vec<_> = (0..4)
+                .map(|writer| {
+                    let store = &store;
+                    scope.spawn(move || {
+                        let mut written = Vec::new();
+                        for i in 0.. {
+                            let key = format!("w{}-{}", writer, i);
+                            if store.put(&key, "v").is_err() {
+                                return written;
+                            }
+                            written.push(key);
+                        }
+                        unreachable!()
+                    })
+                })
+                .collect();
+            thread::sleep(Duration::from_millis(10));
+            store.close().unwrap();
+            writers.into_iter().map(|writer| writer.join().unwrap()).collect()
+        });
+        drop(store);
+
+        let store = LsmStore::open(options()).unwrap();
+        for key in written.iter().flatten() {
+            assert!(store.exists(key).unwrap(), "{} was lost", key);
         }
         store.close().unwrap();
-        {
-            let thread = store.flush_thread.lock().unwrap();
-            // Option::take() replaces value in the mutex with None
-            // JoinHandle is moved out of the option right before joining
-            assert!(thread.as_ref().is_none());
+    }
+
+    #[test]
+    fn test_background_error() {
+        // panics in background flushes only, not in the test's own
+        struct PanickingListener {
+            test_thread: thread::ThreadId,
+        }
+
+        impl EventListener for PanickingListener {
+            fn on_flush_begin(&self, _info: &FlushJobInfo) {
+                if thread::current().id() != self.test_thread {
+                    panic!("flush listener failed");
+                }
+            }
+        }
+
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 4,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 1,
+            fail_writes_on_background_error: true,
+            listeners: vec![Arc::new(PanickingListener {
+                test_thread: thread::current().id(),
+            })],
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        assert_eq!(store.background_error().unwrap(), None);
+        // the second put freezes the first memtable, which triggers a flush
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        store.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        let started = Instant::now();
+        while store.background_error().unwrap().is_none() {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+        let error = LsmError::Background("Flush task panicked: flush listener failed".to_string());
+        assert_eq!(store.background_error().unwrap(), Some(error.clone()));
+        let put_error = store.put("k3".as_bytes(), "v3".as_bytes()).unwrap_err();
+        assert_eq!(put_error.downcast_ref::<LsmError>(), Some(&error));
+        // reads still work
+        assert_eq!(store.get("k1".as_bytes()).unwrap().unwrap(), "v1".as_bytes());
+
+        store.clear_background_error().unwrap();
+        assert_eq!(store.background_error().unwrap(), None);
+        store.put("k3".as_bytes(), "v3".as_bytes()).unwrap();
+    }
+
+    #[test]
+    fn test_scan_stats() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 4,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        for key in ["k1", "k2", "k3"] {
+            store.put(key.as_bytes(), "v".as_bytes()).unwrap();
+        }
+        store.storage_state.flush_all_memtables().unwrap();
+        store.put("k4".as_bytes(), "v".as_bytes()).unwrap();
+        let stats = store.stats().unwrap();
+        assert_eq!(stats.num_l0_ssts, 3);
+        assert_eq!(stats.num_memtables, 1);
+        assert_eq!(stats.memtable_bytes, 3);
+        assert_eq!(stats.num_open_scans, 0);
+
+        // each SST holds one key, and nothing has been read yet
+        let mut scan = store.scan(..).unwrap();
+        let expected = IteratorStats {
+            num_memtable_iterators: 1,
+            num_sst_iterators: 3,
+            num_pinned_blocks: 3,
+        };
+        assert_eq!(scan.stats(), expected);
+        assert_eq!(scan.num_active_iterators(), 4);
+        assert_eq!(store.stats().unwrap().num_open_scans, 1);
+        assert_eq!(store.stats().unwrap().open_scan_iterators, expected);
+
+        // sst iterators are released as the scan moves past them
+        scan.next();
+        assert_eq!(scan.stats().num_sst_iterators, 2);
+        assert_eq!(scan.by_ref().count(), 3);
+        assert_eq!(scan.stats(), IteratorStats::default());
+        assert_eq!(store.stats().unwrap().open_scan_iterators, IteratorStats::default());
+        assert_eq!(store.stats().unwrap().num_open_scans, 1);
+        drop(scan);
+        assert_eq!(store.stats().unwrap().num_open_scans, 0);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_scan_keys() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 4,
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 5,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        store.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        store.put("k3".as_bytes(), "v3".as_bytes()).unwrap();
+        // move the first versions into SSTs
+        store.storage_state.flush_all_memtables().unwrap();
+        store.delete("k2".as_bytes()).unwrap();
+        store.put("k3".as_bytes(), "new_v3".as_bytes()).unwrap();
+
+        let keys: Vec<_> = store
+            .scan_keys(..)
+            .unwrap()
+            .collect();
+        assert_eq!(keys, vec!["k1".as_bytes(), "k3".as_bytes()]);
+        let keys: Vec<_> = store
+            .scan_keys((Bound::Excluded("k1".as_bytes()), Bound::Unbounded))
+            .unwrap()
+            .collect();
+        assert_eq!(keys, vec!["k3".as_bytes()]);
+
+        // a scan can be kept and resumed later by naming its type
+        struct Cursor {
+            keys: KeyScan,
+        }
+        let mut cursor = Cursor {
+            keys: store.scan_keys(..).unwrap(),
+        };
+        assert_eq!(cursor.keys.next().unwrap(), "k1".as_bytes());
+        assert_eq!(cursor.keys.next().unwrap(), "k3".as_bytes());
+        assert!(cursor.keys.next().is_none());
+        drop(cursor);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_scan_ranges() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        for key in ["a", "b", "c", "d"] {
+            store.put(key.as_bytes(), "v".as_bytes()).unwrap();
         }
+        let keys = |scan: Scan| -> Vec<Bytes> { scan.map(|entry| entry.key).collect() };
+        assert_eq!(keys(store.scan(..).unwrap()), vec!["a", "b", "c", "d"]);
+        assert_eq!(keys(store.scan(b"b"..b"d").unwrap()), vec!["b", "c"]);
+        assert_eq!(keys(store.scan("b"..="d").unwrap()), vec!["b", "c", "d"]);
+        assert_eq!(keys(store.scan(b"c".to_vec()..).unwrap()), vec!["c", "d"]);
+        assert_eq!(keys(store.scan(..=b"a").unwrap()), vec!["a"]);
+        assert_eq!(store.count(.."c").unwrap(), 2);
+        // the old two-bound form still works
+        #[allow(deprecated)]
+        let scan = store.scan_bounds(Bound::Excluded("a".as_bytes()), Bound::Excluded("d".as_bytes())).unwrap();
+        assert_eq!(keys(scan), vec!["b", "c"]);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_exists() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        store.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        store.storage_state.flush_all_memtables().unwrap();
+        store.delete("k2".as_bytes()).unwrap();
+
+        assert!(store.exists("k1".as_bytes()).unwrap());
+        assert!(!store.exists("k2".as_bytes()).unwrap());
+        assert!(!store.exists("k3".as_bytes()).unwrap());
+        assert_eq!(store.latency_report().unwrap().get.count, 0);
+
+        // gets can leave values out too
+        let keys_only = ReadOptions {
+            keys_only: true,
+            ..Default::default()
+        };
+        assert_eq!(store.get_with_options("k1".as_bytes(), &keys_only).unwrap(), Some(Bytes::new()));
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        assert_eq!(store.compare_and_swap(b"k1", None, Some(b"v1")).unwrap(), Ok(()));
+        assert_eq!(
+            store.compare_and_swap(b"k1", None, Some(b"v2")).unwrap(),
+            Err(Some(Bytes::from("v1")))
+        );
+        // the current value may be in an SST
+        store.storage_state.flush_all_memtables().unwrap();
+        assert_eq!(store.compare_and_swap(b"k1", Some(b"v1"), Some(b"v2")).unwrap(), Ok(()));
+        assert_eq!(store.get(b"k1").unwrap(), Some(Bytes::from("v2")));
+        assert_eq!(store.compare_and_swap(b"k1", Some(b"v2"), None).unwrap(), Ok(()));
+        assert_eq!(store.get(b"k1").unwrap(), None);
+        assert_eq!(store.compare_and_swap(b"k1", Some(b"v2"), None).unwrap(), Err(None));
+        assert_eq!(store.compare_and_swap(b"k2", None, None).unwrap(), Ok(()));
+
+        // a counter that no increment is lost from
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        let mut current = store.get(b"counter").unwrap();
+                        loop {
+                            let count: u64 = current.as_ref().map_or(0, |value| {
+                                std::str::from_utf8(value).unwrap().parse().unwrap()
+                            });
+                            let new = (count + 1).to_string();
+                            match store
+                                .compare_and_swap(b"counter", current.as_deref(), Some(new.as_bytes()))
+                                .unwrap()
+                            {
+                                Ok(()) => break,
+                                Err(actual) => current = actual,
+                            }
+                        }
+                    }
+                });
+            }
+        });
+        assert_eq!(store.get(b"counter").unwrap(), Some(Bytes::from("400")));
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_increment() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        assert_eq!(store.increment(b"counter", 5).unwrap(), 5);
+        assert_eq!(store.increment(b"counter", -7).unwrap(), -2);
+        assert_eq!(store.get(b"counter").unwrap(), Some(Bytes::copy_from_slice(&(-2i64).to_le_bytes())));
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    for _ in 0..100 {
+                        store.increment(b"counter", 1).unwrap();
+                    }
+                });
+            }
+        });
+        assert_eq!(store.increment(b"counter", 0).unwrap(), 398);
+
+        store.put(b"k1", b"v1").unwrap();
+        assert!(store.increment(b"k1", 1).is_err());
+        store.put(b"max", i64::MAX.to_le_bytes()).unwrap();
+        assert!(store.increment(b"max", 1).is_err());
+        assert_eq!(store.get(b"max").unwrap(), Some(Bytes::copy_from_slice(&i64::MAX.to_le_bytes())));
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_write_token() {
+        let dir = tempdir().unwrap();
+        let store = LsmStore::open(StorageStateOptions {
+            path: dir.path().to_owned(),
+            num_shards: 2,
+            ..Default::default()
+        })
+        .unwrap();
+        let before = store.snapshot().unwrap();
+        let token = store.put_with_options("k1", "v1", &WriteOptions::default()).unwrap();
+        let read_options = |min_sequence, snapshot| ReadOptions {
+            min_sequence: Some(min_sequence),
+            snapshot,
+            ..Default::default()
+        };
+        assert_eq!(store.get_with_options("k1", &read_options(token, None)).unwrap(), Some(Bytes::from("v1")));
+        // a snapshot from before the write can't see it, and neither can a
+        // store that hasn't got to it
+        let err = store.get_with_options("k1", &read_options(token, Some(before))).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::SequenceNotReached { .. })));
+        let ahead = WriteToken {
+            sequence: token.sequence + 1,
+        };
+        assert!(store.scan_with_options(.., &read_options(ahead, None)).is_err());
+
+        let mut batch = WriteBatchWithIndex::new();
+        for key in ["k2", "k3", "k4", "k5"] {
+            batch.put(key, "v").unwrap();
+        }
+        let batch_token = store.write_with_options(&batch, &WriteOptions::default()).unwrap();
+        assert_eq!(batch_token.sequence, store.latest_sequence().unwrap());
+        let delete_token = store.delete_with_options("k1", &WriteOptions::default()).unwrap();
+        assert!(delete_token > batch_token);
+        assert_eq!(store.get_with_options("k1", &read_options(delete_token, None)).unwrap(), None);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_scan_tombstones() {
+        for num_shards in [1, 2] {
+            let dir = tempdir().unwrap();
+            let store = LsmStore::open(StorageStateOptions {
+                path: dir.path().to_owned(),
+                num_shards,
+                ..Default::default()
+            })
+            .unwrap();
+            store.put("k1", "v1").unwrap();
+            store.put("k2", "v2").unwrap();
+            store.put("k3", "v3").unwrap();
+            store.storage_state.flush_all_memtables().unwrap();
+            store.put("k1", "new_v1").unwrap();
+            store.delete("k2").unwrap();
+
+            // only the newest version of a key is returned, whether it is in
+            // a memtable or an SST, and a delete as an entry with no value
+            let entries: Vec<Entry> = store.scan(..).unwrap().collect();
+            assert_eq!(
+                entries,
+                vec![Entry::new("k1", "new_v1"), Entry::tombstone("k2"), Entry::new("k3", "v3")],
+                "{} shards",
+                num_shards
+            );
+            let live = ReadOptions {
+                ignore_tombstones: true,
+                ..Default::default()
+            };
+            let entries: Vec<Entry> = store.scan_with_options(.., &live).unwrap().collect();
+            assert_eq!(entries, vec![Entry::new("k1", "new_v1"), Entry::new("k3", "v3")]);
+            store.close().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_options() {
+        let collect = |iterator: &mut dyn Iterator<Item = Entry>| -> Vec<(Bytes, Bytes)> {
+            iterator.map(|entry| (entry.key, entry.value)).collect()
+        };
+        let pair = |key: &'static str, value: &'static str| (Bytes::from(key), Bytes::from(value));
+
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            num_shards: 2,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        store.put(b"k1", b"v1").unwrap();
+        store.put(b"k2", b"v2").unwrap();
+        let snapshot = store.snapshot().unwrap();
+        store.put(b"k1", b"v1 updated").unwrap();
+        store.delete(b"k2").unwrap();
+        store.put(b"k3", b"v3").unwrap();
+        // the snapshot keeps seeing the flushed memtables
+        store.storage_state.flush_all_memtables().unwrap();
+
+        let at_snapshot = ReadOptions {
+            snapshot: Some(snapshot.clone()),
+            ..Default::default()
+        };
+        assert_eq!(store.get_with_options(b"k1", &at_snapshot).unwrap(), Some(Bytes::from("v1")));
+        assert_eq!(store.get_with_options(b"k2", &at_snapshot).unwrap(), Some(Bytes::from("v2")));
+        assert_eq!(store.get_with_options(b"k3", &at_snapshot).unwrap(), None);
+        assert_eq!(store.get(b"k1").unwrap(), Some(Bytes::from("v1 updated")));
+
+        let live_at_snapshot = ReadOptions {
+            ignore_tombstones: true,
+            ..at_snapshot.clone()
+        };
+        let mut iterator = store.scan_with_options(.., &live_at_snapshot).unwrap();
+        assert_eq!(collect(&mut iterator), vec![pair("k1", "v1"), pair("k2", "v2")]);
+
+        let live = ReadOptions {
+            ignore_tombstones: true,
+            ..Default::default()
+        };
+        let mut iterator = store.scan_with_options(.., &live).unwrap();
+        assert_eq!(collect(&mut iterator), vec![pair("k1", "v1 updated"), pair("k3", "v3")]);
+        let first_live = ReadOptions {
+            limit: Some(1),
+            ..live
+        };
+        let mut iterator = store.scan_with_options(.., &first_live).unwrap();
+        assert_eq!(collect(&mut iterator), vec![pair("k1", "v1 updated")]);
+        // without ignore_tombstones deleted keys count towards the limit
+        let first_two = ReadOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let mut iterator = store.scan_with_options(.., &first_two).unwrap();
+        assert_eq!(collect(&mut iterator), vec![pair("k1", "v1 updated"), pair("k2", "")]);
+        let deleted: Vec<_> = store.scan_with_options(.., &first_two).unwrap().filter(Entry::is_tombstone).collect();
+        assert_eq!(deleted, vec![Entry::tombstone("k2")]);
+
+        let other_dir = tempdir().unwrap();
+        let other_store = LsmStore::open(StorageStateOptions {
+            path: other_dir.path().to_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        assert!(other_store.get_with_options(b"k1", &at_snapshot).is_err());
+        assert!(other_store.scan_with_options(.., &at_snapshot).is_err());
+        other_store.close().unwrap();
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_describe() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        assert!(store.describe().unwrap().shards[0].levels.is_empty());
+        store.put(b"k1", b"v1").unwrap();
+        store.put(b"k3", b"v3").unwrap();
+        store.storage_state.flush_all_memtables().unwrap();
+        store.put(b"k2", b"v2").unwrap();
+
+        let description = store.describe().unwrap();
+        assert_eq!(description.shards.len(), 1);
+        let shard = &description.shards[0];
+        assert_eq!(shard.memtables.len(), 1);
+        assert!(!shard.memtables[0].frozen);
+        assert_eq!(shard.memtables[0].size_bytes, 4);
+        assert_eq!(shard.levels.len(), 1);
+        let sst = &shard.levels[0].ssts[0];
+        assert_eq!((sst.first_key.as_ref(), sst.last_key.as_ref()), (&b"k1"[..], &b"k3"[..]));
+        assert_eq!(sst.num_entries, 2);
+        assert_eq!(sst.num_blocks, 1);
+        assert!(description.to_string().contains("level 0: 1 ssts"));
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_get_versions() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        store.put("k", "v1").unwrap();
+        store.put("k", "v2").unwrap();
+        store.storage_state.flush_all_memtables().unwrap();
+        store.delete("k").unwrap();
+        let token = store.put_with_options("k", "v3", &WriteOptions::default()).unwrap();
+        store.put("other", "v").unwrap();
+
+        let versions = store.get_versions("k", 10).unwrap();
+        let values: Vec<Option<&str>> = versions
+            .iter()
+            .map(|version| version.value.as_deref().map(|value| std::str::from_utf8(value).unwrap()))
+            .collect();
+        assert_eq!(values, vec![Some("v3"), None, Some("v2"), Some("v1")]);
+        assert_eq!(versions[0].sequence, Some(token.sequence));
+        assert!(versions[1].sequence.unwrap() < token.sequence);
+        // flushed versions don't keep their sequences
+        assert_eq!((versions[2].sequence, versions[3].sequence), (None, None));
+
+        assert_eq!(store.get_versions("k", 3).unwrap(), versions[..3]);
+        assert!(store.get_versions("k", 0).unwrap().is_empty());
+        assert!(store.get_versions("missing", 10).unwrap().is_empty());
+
+        // unless every entry in the SST has the same one
+        store.storage_state.flush_all_memtables().unwrap();
+        let last_token = store.put_with_options("k", "v4", &WriteOptions::default()).unwrap();
+        store.storage_state.flush_all_memtables().unwrap();
+        let versions = store.get_versions("k", 2).unwrap();
+        assert_eq!(versions[0].sequence, Some(last_token.sequence));
+        assert_eq!(versions[1].sequence, None);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_explain_get() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        store.put(b"k1", b"v1").unwrap();
+        store.put(b"k3", b"v3").unwrap();
+        store.storage_state.flush_all_memtables().unwrap();
+        store.put(b"k5", b"v5").unwrap();
+        store.storage_state.flush_all_memtables().unwrap();
+        store.delete(b"k5").unwrap();
+
+        let explanation = store.explain_get(b"k3").unwrap();
+        assert_eq!(explanation.value, Some(Bytes::from("v3")));
+        assert!(matches!(
+            explanation.steps[..],
+            [
+                GetStep::Memtable {
+                    frozen: false,
+                    outcome: ProbeOutcome::NotFound,
+                    ..
+                },
+                GetStep::SstOutsideKeyRange { .. },
+                GetStep::SstBlock {
+                    block_index: 0,
+                    outcome: ProbeOutcome::Found,
+                    ..
+                }
+            ]
+        ));
+        let text = explanation.to_string();
+        assert!(text.contains("key outside k5..=k5") && text.ends_with("result: v3\n"));
+
+        let explanation = store.explain_get(b"k5").unwrap();
+        assert_eq!(explanation.value, None);
+        assert!(matches!(
+            explanation.steps[..],
+            [GetStep::Memtable {
+                outcome: ProbeOutcome::Deleted,
+                ..
+            }]
+        ));
+        let explanation = store.explain_get(b"k2").unwrap();
+        assert_eq!(explanation.value, None);
+        assert!(matches!(explanation.steps[2], GetStep::SstFilteredByBloom { .. }));
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_explain_scan() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            block_max_size_bytes: 16,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        for key in ["k1", "k2", "k3", "k4"] {
+            store.put(key, "value").unwrap();
+        }
+        store.storage_state.flush_all_memtables().unwrap();
+        store.put("k9", "value").unwrap();
+        store.storage_state.flush_all_memtables().unwrap();
+
+        let explanation = store.explain_scan("k2"..="k3").unwrap();
+        let shard = &explanation.shards[0];
+        assert_eq!((shard.num_memtables, shard.num_ssts_outside_range), (1, 1));
+        assert_eq!(shard.ssts.len(), 1);
+        let sst = &shard.ssts[0];
+        assert!(sst.num_blocks > sst.num_blocks_read && sst.num_blocks_read > 0);
+        let all = store.explain_scan(..).unwrap();
+        assert_eq!((all.num_ssts(), all.num_blocks_read()), (2, sst.num_blocks + 1));
+        assert!(all.to_string().contains("ssts: 2 of 2 overlap the range, heap merge"));
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_append_sorted() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            sst_max_size_bytes: 1024,
+            num_memtables_limit: 3,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        // 10 bytes each
+        let entries = |range: std::ops::Range<u32>| range.map(|i| Entry::new(format!("t{:04}", i), "value"));
+
+        // batches go to the memtable, even ones nearly as large as it
+        store.append_sorted(entries(0..2)).unwrap();
+        store.append_sorted(entries(2..100)).unwrap();
+        let stats = store.stats().unwrap();
+        assert_eq!((stats.num_memtables, stats.num_l0_ssts), (1, 0));
+        // one larger than every memtable there may be is loaded into SSTs
+        store.append_sorted(entries(100..500)).unwrap();
+        assert!(store.stats().unwrap().num_l0_ssts > 0);
+        assert_eq!(store.count(..).unwrap(), 500);
+        assert_eq!(store.get("t0000").unwrap(), Some(Bytes::from("value")));
+        assert_eq!(store.get("t0499").unwrap(), Some(Bytes::from("value")));
+
+        let unsorted = [Entry::new("t0530", "value"), Entry::new("t0521", "value")];
+        assert!(store.append_sorted(unsorted).is_err());
+        let duplicate = [Entry::new("t0540", "value"), Entry::new("t0540", "value")];
+        assert!(store.append_sorted(duplicate).is_err());
+        assert_eq!(store.count(..).unwrap(), 500);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_prefix_stats() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            block_max_size_bytes: 64,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        for prefix in ["a", "b", "c"] {
+            for i in 0..20 {
+                store.put(format!("{}/{:02}", prefix, i), "value").unwrap();
+            }
+        }
+        store.storage_state.flush_all_memtables().unwrap();
+        store.put("b/20", "value").unwrap();
+
+        let stats = store.prefix_stats("b/").unwrap();
+        assert_eq!(stats.num_entries, 21);
+        assert!(stats.size_bytes > 0);
+        // blocks straddling a/ and b/, and b/ and c/
+        assert_eq!(stats.num_sampled_blocks, 2);
+        let all = store.prefix_stats("").unwrap();
+        assert_eq!((all.num_entries, all.num_sampled_blocks), (61, 0));
+        assert!(all.size_bytes > stats.size_bytes);
+        assert_eq!(store.prefix_stats("d").unwrap(), PrefixStats::default());
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_background_status() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            sst_max_size_bytes: 8,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        assert!(!store.background_status().unwrap().scheduler.is_paused);
+        store.pause_background_work().unwrap();
+        for key in ["k1", "k2", "k3"] {
+            store.put(key, "value").unwrap();
+        }
+        let status = store.background_status().unwrap();
+        assert!(status.scheduler.is_paused && status.merges.is_empty());
+        assert!(status.num_pending_flushes > 0);
+        let text = status.to_string();
+        assert!(text.starts_with("background work: paused\n"));
+        assert!(text.contains(&format!("pending flushes: {}\n", status.num_pending_flushes)));
+
+        store.wait_for_flush().unwrap();
+        assert_eq!(store.background_status().unwrap().num_pending_flushes, 0);
+        store.resume_background_work().unwrap();
+        assert!(!store.background_status().unwrap().scheduler.is_paused);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_scan_builder() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        for i in 0..10 {
+            store.put(format!("k{}", i), format!("{}", i * i)).unwrap();
+        }
+        store.storage_state.flush_all_memtables().unwrap();
+        store.delete("k4").unwrap();
+
+        let collect = |iterator: LsmIterator| -> Vec<(Bytes, Bytes)> { iterator.map(|kv| kv.unwrap()).collect() };
+        // even squares, the limit counting only the pairs that pass
+        let even = store
+            .scan_builder("k1"..)
+            .filter(|_, value| value.last().is_some_and(|digit| digit % 2 == 0))
+            .limit(2)
+            .build()
+            .unwrap();
+        let pairs = collect(even);
+        assert_eq!(pairs, vec![(Bytes::from("k2"), Bytes::from("4")), (Bytes::from("k6"), Bytes::from("36"))]);
+
+        let lengths = store
+            .scan_builder("k7"..)
+            .values_transformed(|_, value| Bytes::from(value.len().to_string()))
+            .build()
+            .unwrap();
+        let values: Vec<Bytes> = collect(lengths).into_iter().map(|(_, value)| value).collect();
+        assert_eq!(values, vec!["2", "2", "2"]);
+
+        let keys = store.scan_builder("k3"..="k5").keys_only().build().unwrap();
+        assert_eq!(collect(keys), vec![(Bytes::from("k3"), Bytes::new()), (Bytes::from("k5"), Bytes::new())]);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_latency_report() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        assert_eq!(store.latency_report().unwrap(), LatencyReport::default());
+        for key in ["k1", "k2", "k3"] {
+            store.put(key.as_bytes(), "v".as_bytes()).unwrap();
+        }
+        store.delete("k2".as_bytes()).unwrap();
+        store.get("k1".as_bytes()).unwrap();
+        assert!(store.exists("k3".as_bytes()).unwrap());
+        // the last next, which returns None, is timed too
+        let num_entries = store.scan(..).unwrap().count() as u64;
+        store.storage_state.flush_all_memtables().unwrap();
+
+        let report = store.latency_report().unwrap();
+        assert_eq!(report.put.count, 4);
+        // exists isn't a get
+        assert_eq!(report.get.count, 1);
+        assert_eq!(report.scan_next.count, num_entries + 1);
+        assert_eq!(report.flush.count, 1);
+        assert_eq!(report.compaction.count, 0);
+        assert!(report.put.max >= report.put.p50 && report.put.max > Duration::ZERO);
+
+        store.reset_latencies().unwrap();
+        assert_eq!(store.latency_report().unwrap(), LatencyReport::default());
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_event_flush_trigger() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 8,
+            path: dir.path().to_owned(),
+            num_memtables_limit: 2,
+            flush_trigger: FlushTrigger::Event,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        // each put freezes the memtable before it, the third reaches the limit
+        for key in ["k1", "k2", "k3"] {
+            store.put(key.as_bytes(), "value".as_bytes()).unwrap();
+        }
+        // flushed well before the fallback check a second from now
+        let started = Instant::now();
+        while store.stats().unwrap().num_l0_ssts == 0 {
+            assert!(started.elapsed() < Duration::from_millis(500));
+            thread::sleep(Duration::from_millis(1));
+        }
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_no_background_threads() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("db");
+        let options = StorageStateOptions {
+            sst_max_size_bytes: 8,
+            path: path.clone(),
+            in_memory: true,
+            num_memtables_limit: 2,
+            num_background_threads: 0,
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        // each put freezes the memtable before it, and the third put, which
+        // reaches the limit, flushes the oldest itself
+        for key in ["k1", "k2", "k3"] {
+            store.put(key, "value").unwrap();
+        }
+        let status = store.background_status().unwrap();
+        assert!(status.scheduler.running.is_empty());
+        assert_eq!(status.num_pending_flushes, 1);
+        assert_eq!(store.stats().unwrap().num_l0_ssts, 1);
+        for key in ["k1", "k2", "k3"] {
+            assert_eq!(store.get(key).unwrap().unwrap(), "value".as_bytes());
+        }
+        store.close().unwrap();
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn test_str_api() {
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        // anything that is bytes can be a key or value
+        store.put("k1", "v1").unwrap();
+        store.put(String::from("k2"), b"v2").unwrap();
+        store.put(b"k3", [0xff]).unwrap();
+        store.delete("k2").unwrap();
+        assert!(store.exists(b"k1").unwrap());
+        assert_eq!(store.get_str("k1").unwrap().as_deref(), Some("v1"));
+        assert_eq!(store.get_str("k2").unwrap(), None);
+        assert!(store.get_str("k3").is_err());
+
+        let items: Vec<_> = store.scan_str(..).unwrap().collect();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].as_ref().unwrap(), &("k1".to_string(), "v1".to_string()));
+        assert!(items[1].is_err());
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_scans_are_fused() {
+        fn assert_fused<I: FusedIterator>(iterator: &mut I) {
+            assert!(iterator.next().is_none());
+            assert!(iterator.next().is_none());
+        }
+
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        store.put("k1".as_bytes(), "v1".as_bytes()).unwrap();
+        let mut scan = store.scan(..).unwrap();
+        let mut keys = store.scan_keys(..).unwrap();
+        let mut iter = store.iter(..).unwrap();
+        assert!(scan.next().is_some());
+        assert!(keys.next().is_some());
+        assert!(iter.next().is_some());
+        assert!(scan.next().is_none());
+        // later writes don't bring an exhausted scan back
+        store.put("k2".as_bytes(), "v2".as_bytes()).unwrap();
+        assert_fused(&mut scan);
+        assert_fused(&mut keys);
+        assert_fused(&mut iter);
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_iter() {
+        // the point of LsmIterator is that it can be named
+        struct Cursor {
+            iterator: LsmIterator,
+        }
+
+        let dir = tempdir().unwrap();
+        let options = StorageStateOptions {
+            block_max_size_bytes: 1,
+            block_cache_size_bytes: 0,
+            path: dir.path().to_owned(),
+            ..Default::default()
+        };
+        let store = LsmStore::open(options).unwrap();
+        let old = [b'v'; 64];
+        for key in ["k1", "k2", "k3"] {
+            store.put(key.as_bytes(), old).unwrap();
+        }
+        store.storage_state.flush_all_memtables().unwrap();
+        store.put("k1".as_bytes(), "new".as_bytes()).unwrap();
+        store.delete("k2".as_bytes()).unwrap();
+
+        let cursor = Cursor {
+            iterator: store.iter(..).unwrap(),
+        };
+        let items: Vec<(Bytes, Bytes)> = cursor.iterator.map(|item| item.unwrap()).collect();
+        assert_eq!(
+            items,
+            vec![
+                (Bytes::from("k1"), Bytes::from("new")),
+                (Bytes::from("k3"), Bytes::copy_from_slice(&old)),
+            ]
+        );
+
+        // a failed read ends the scan with an error instead of a short result.
+        // values make up most of the file, so this loses the later blocks
+        let sst_path = dir.path().join("00000.sst");
+        let file = std::fs::OpenOptions::new().write(true).open(&sst_path).unwrap();
+        file.set_len(file.metadata().unwrap().len() / 2).unwrap();
+        let items: Vec<_> = store.iter(..).unwrap().collect();
+        assert!(items.last().unwrap().is_err());
+        assert_eq!(items.iter().filter(|item| item.is_err()).count(), 1);
+    }
+
+    #[test]
+    fn test_watch_prefix() {
+        let dir = tempdir().unwrap();
+        let store = LsmStore::open(StorageStateOptions {
+            path: dir.path().to_owned(),
+            num_shards: 3,
+            ..Default::default()
+        })
+        .unwrap();
+        store.put("config/a", "0").unwrap();
+        let receiver = store.watch_prefix("config/").unwrap();
+        let mut batch = WriteBatchWithIndex::new();
+        batch.put("config/b", "1").unwrap();
+        batch.put("data/b", "1").unwrap();
+        batch.delete("config/c").unwrap();
+        store.write(&batch).unwrap();
+        store.put("data/a", "2").unwrap();
+        store.put("config/a", "3").unwrap();
+
+        // each shard a batch touches writes its part under its own sequence
+        let mut events: Vec<WriteEvent> = receiver.try_iter().collect();
+        events.sort_by_key(|event| event.sequence);
+        let entries: Vec<Entry> = events.iter().map(|event| event.entry.clone()).collect();
+        assert_eq!(entries.len(), 3);
+        assert!(entries[..2].contains(&Entry::new("config/b", "1")));
+        assert!(entries[..2].contains(&Entry::tombstone("config/c")));
+        assert_eq!(entries[2], Entry::new("config/a", "3"));
+        assert_eq!(events[2].sequence, store.latest_sequence().unwrap());
+        store.close().unwrap();
     }
 }
@@ -1,151 +1,739 @@
-use std::cmp::Ordering;
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::ops::{Bound, RangeBounds};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+use std::time::SystemTime;
 
 use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use blob::{blob_path, BlobFile};
 use block_cache::BlockCache;
 use bloom::BloomFilter;
+use index::BlockIndex;
+use metadata_cache::MetadataCache;
+use persistent_cache::PersistentBlockCache;
+use table_cache::TableCache;
 
 use crate::block::metadata::BlockMetadata;
 use crate::block::Block;
+use crate::error::LsmError;
+use crate::kv::kv_pair::KeyValuePair;
 use crate::kv::timestamped_key::TimestampedKey;
+use crate::stats::PrefixStats;
 use crate::table::file::File;
+use crate::utils::prefix_upper_bound;
 
 #[cfg(test)]
-mod test_utils;
+pub(crate) mod test_utils;
 
-pub mod block_cache;
-pub mod bloom;
-pub mod builder;
-pub mod file;
-pub mod iterator;
+pub(crate) mod blob;
+pub(crate) mod block_cache;
+pub(crate) mod bloom;
+pub(crate) mod builder;
+pub(crate) mod file;
+pub(crate) mod index;
+pub(crate) mod iterator;
+pub(crate) mod metadata_cache;
+pub(crate) mod persistent_cache;
+mod prefetch;
+#[cfg(feature = "rocksdb-sst")]
+pub mod rocksdb;
+pub(crate) mod sst_path;
+pub(crate) mod table_cache;
+
+// trailing magic number of versioned sst files ("MLSM")
+pub(crate) const SST_MAGIC: u32 = 0x4d4c_534d;
+// files written before the versioned footer existed
+pub(crate) const SST_FORMAT_VERSION_LEGACY: u32 = 1;
+// block metadata carries per-block stats
+pub(crate) const SST_FORMAT_VERSION_STATS: u32 = 2;
+// block metadata carries the restart interval of the block
+pub(crate) const SST_FORMAT_VERSION_RESTARTS: u32 = 3;
+// every non-empty value starts with a VALUE_TAG_*, and large values live in
+// a blob file next to the SST. up to here, an empty value is a tombstone
+pub(crate) const SST_FORMAT_VERSION_BLOBS: u32 = 4;
+// every value starts with a VALUE_TAG_*, and tombstones have a tag of their
+// own, so empty values can be stored
+pub(crate) const SST_FORMAT_VERSION_VALUE_TYPES: u32 = 5;
+// the footer records the range of sequence numbers (timestamps) of the
+// entries in the SST, | min (u64) | max (u64) |, before the format version
+pub(crate) const SST_FORMAT_VERSION_SEQUENCES: u32 = 6;
+// the block index is followed by a top-level index of its partitions and
+// | top-level index offset (u32) |, before the block index offset. the
+// top-level index is empty unless the block index is partitioned, see
+// StorageStateOptions::index_partition_num_blocks
+pub(crate) const SST_FORMAT_VERSION_PARTITIONED_INDEX: u32 = 7;
+// block stats carry the number of live keys, see BlockStats::num_live_keys
+pub(crate) const SST_FORMAT_VERSION_LIVE_KEYS: u32 = 8;
+// the newest version that can be read
+pub(crate) const SST_FORMAT_VERSION: u32 = SST_FORMAT_VERSION_LIVE_KEYS;
+
+// the rest of the value is stored inline
+pub(crate) const VALUE_TAG_INLINE: u8 = 0;
+// the rest of the value is | offset (u64) | len (u32) | into the blob file
+pub(crate) const VALUE_TAG_BLOB: u8 = 1;
+pub(crate) const BLOB_REFERENCE_SIZE: usize = 1 + 8 + 4;
+// a tombstone, with nothing after the tag
+pub(crate) const VALUE_TAG_DELETE: u8 = 2;
 
 // in-memory representation of a single SST file on disk
-pub struct Sst {
+pub(crate) struct Sst {
     id: usize,
     file: File,
-    meta_blocks: Vec<BlockMetadata>,
-    meta_block_offset: u32,
+    // kept even when the rest of the metadata isn't, so SSTs can be ruled
+    // out by key range without loading it
+    first_key: TimestampedKey,
+    last_key: TimestampedKey,
     block_cache: Option<Arc<BlockCache>>,
-    bloom_filter: BloomFilter,
+    // set when the metadata is held for the lifetime of the SST. otherwise it
+    // is loaded on first use and kept in metadata_cache
+    resident_metadata: Option<Arc<SstMetadata>>,
+    metadata_cache: Option<Arc<MetadataCache>>,
+    // checked on block cache misses before going to the file
+    persistent_cache: Option<Arc<PersistentBlockCache>>,
+    // values too large to store inline, if any were
+    blob_file: Option<BlobFile>,
+}
+
+// the block index and bloom filter of an SST
+pub(crate) struct SstMetadata {
+    index: BlockIndex,
+    meta_block_offset: u32,
+    // unset until first needed if the SST was opened with
+    // Sst::open_with_lazy_bloom_filter
+    bloom_filter: OnceLock<BloomFilter>,
+}
+
+// the live keys of one block, whose keys are all in the range asked for,
+// see Sst::block_key_counts
+pub(crate) struct BlockKeyCount {
+    pub block_index: usize,
+    pub first_key: Bytes,
+    pub last_key: Bytes,
+    pub num_live_keys: u64,
+}
+
+impl SstMetadata {
+    pub(crate) fn new(index: BlockIndex, meta_block_offset: u32, bloom_filter: BloomFilter) -> Self {
+        Self {
+            index,
+            meta_block_offset,
+            bloom_filter: OnceLock::from(bloom_filter),
+        }
+    }
+
+    fn load(file: &File) -> Result<Self> {
+        let bloom_filter_offset = file.get_bloom_filter_offset()?;
+        let bloom_filter = file.load_bloom_filter(bloom_filter_offset)?;
+        let (meta_block_offset, index) = Self::load_index(file, bloom_filter_offset)?;
+        Ok(Self::new(index, meta_block_offset, bloom_filter))
+    }
+
+    fn load_without_bloom_filter(file: &File) -> Result<Self> {
+        let (meta_block_offset, index) = Self::load_index(file, file.get_bloom_filter_offset()?)?;
+        Ok(Self {
+            index,
+            meta_block_offset,
+            bloom_filter: OnceLock::new(),
+        })
+    }
+
+    // loads the bloom filter from file if it isn't yet. concurrent first
+    // calls may each read it, and all but one copy is dropped
+    fn bloom_filter(&self, file: &File) -> Result<&BloomFilter> {
+        if let Some(bloom_filter) = self.bloom_filter.get() {
+            return Ok(bloom_filter);
+        }
+        let bloom_filter = file.load_bloom_filter(file.get_bloom_filter_offset()?)?;
+        Ok(self.bloom_filter.get_or_init(|| bloom_filter))
+    }
+
+    // the block index, of which only the top level is read if it is
+    // partitioned
+    fn load_index(file: &File, bloom_filter_offset: u32) -> Result<(u32, BlockIndex)> {
+        let meta_block_offset = file.get_meta_block_offset(bloom_filter_offset)?;
+        let partitions = file.load_index_partitions(bloom_filter_offset)?;
+        if partitions.is_empty() {
+            let meta_blocks_end = file.get_meta_blocks_end(bloom_filter_offset)?;
+            let meta_blocks = file.load_meta_blocks(meta_block_offset, meta_blocks_end)?;
+            return Ok((meta_block_offset, BlockIndex::Full(meta_blocks)));
+        }
+        if !BlockIndex::is_contiguous(&partitions) {
+            return Err(LsmError::Corruption(format!(
+                "top-level index of sst {:?} doesn't cover the blocks in order",
+                file.get_path()
+            ))
+            .into());
+        }
+        Ok((meta_block_offset, BlockIndex::new_partitioned(partitions)))
+    }
+
+    // the block index must cover the data section in order: blocks back to
+    // back from offset 0 up to the index, with key ranges that don't overlap.
+    // versions of a key may span blocks, so a block may start with the key
+    // the previous one ended with. the bloom filter must be sized for the
+    // number of entries, where the block stats record it
+    fn validate(&self, meta_blocks: &[BlockMetadata], bloom_filter: &BloomFilter) -> std::result::Result<(), String> {
+        if meta_blocks.is_empty() {
+            return Err("block index is empty".to_string());
+        }
+        let mut num_entries = Some(0);
+        for (block_index, block_meta) in meta_blocks.iter().enumerate() {
+            let (first_key, last_key) = (block_meta.get_first_key().get_key(), block_meta.get_last_key().get_key());
+            if first_key > last_key {
+                return Err(format!(
+                    "block {} has first key {:?} after its last key {:?}",
+                    block_index, first_key, last_key
+                ));
+            }
+            match block_index.checked_sub(1).map(|previous_index| &meta_blocks[previous_index]) {
+                None if block_meta.get_offset() != 0 => {
+                    return Err(format!("block 0 starts at offset {} instead of 0", block_meta.get_offset()));
+                }
+                None => {}
+                Some(previous) => {
+                    if block_meta.get_offset() <= previous.get_offset() {
+                        return Err(format!(
+                            "block {} starts at offset {}, not after block {} at {}",
+                            block_index,
+                            block_meta.get_offset(),
+                            block_index - 1,
+                            previous.get_offset()
+                        ));
+                    }
+                    let previous_last_key = previous.get_last_key().get_key();
+                    if previous_last_key > first_key {
+                        return Err(format!(
+                            "block {} starts at key {:?}, before block {} ends at {:?}",
+                            block_index,
+                            first_key,
+                            block_index - 1,
+                            previous_last_key
+                        ));
+                    }
+                }
+            }
+            num_entries = num_entries
+                .zip(block_meta.get_stats())
+                .map(|(num_entries, stats)| num_entries + stats.num_entries as usize);
+        }
+        let last_offset = meta_blocks[meta_blocks.len() - 1].get_offset();
+        if last_offset >= self.meta_block_offset {
+            return Err(format!(
+                "block {} starts at offset {}, not before the block index at {}",
+                meta_blocks.len() - 1,
+                last_offset,
+                self.meta_block_offset
+            ));
+        }
+        if let Some(num_entries) = num_entries {
+            if !bloom_filter.fits_num_keys(num_entries) {
+                return Err(format!(
+                    "bloom filter of {} bytes doesn't match the {} entries of the blocks",
+                    bloom_filter.size_bytes(),
+                    num_entries
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn num_blocks(&self) -> usize {
+        self.index.num_blocks()
+    }
+
+    // 0 unless the block index is partitioned
+    #[cfg(test)]
+    pub(crate) fn num_index_partitions(&self) -> usize {
+        self.index.num_partitions()
+    }
+
+    // 0 until a lazily loaded bloom filter is loaded
+    pub(crate) fn bloom_filter_size_bytes(&self) -> usize {
+        self.bloom_filter.get().map_or(0, |bloom_filter| bloom_filter.size_bytes())
+    }
+
+    // the block index in memory
+    pub(crate) fn index_size_bytes(&self) -> usize {
+        self.index.size_bytes()
+    }
+
+    // encoded size of a block on disk
+    fn block_size(&self, file: &File, block_index: usize) -> Result<u32> {
+        let offset = self.index.block_offset(file, block_index)?;
+        let next_offset = match block_index + 1 < self.num_blocks() {
+            true => self.index.block_offset(file, block_index + 1)?,
+            false => self.meta_block_offset,
+        };
+        Ok(next_offset - offset)
+    }
 }
 
 impl Sst {
-    pub fn new(
-        id: usize,
-        file: File,
-        meta_blocks: Vec<BlockMetadata>,
-        meta_block_offset: u32,
-        block_cache: Option<Arc<BlockCache>>,
-        bloom_filter: BloomFilter,
-    ) -> Self {
+    pub(crate) fn new(id: usize, file: File, metadata: SstMetadata, block_cache: Option<Arc<BlockCache>>) -> Self {
+        let (first_key, last_key) = metadata.index.key_range().expect("sst must contain at least one block");
         Self {
             id,
             file,
-            meta_blocks,
-            meta_block_offset,
+            first_key,
+            last_key,
             block_cache,
-            bloom_filter,
+            resident_metadata: Some(Arc::new(metadata)),
+            metadata_cache: None,
+            persistent_cache: None,
+            blob_file: None,
         }
     }
 
     // create from file
-    pub fn open(id: usize, path: PathBuf, block_cache: Option<Arc<BlockCache>>) -> Result<Self> {
-        let mut file = File::open(path)?;
+    pub(crate) fn open(id: usize, path: PathBuf, block_cache: Option<Arc<BlockCache>>) -> Result<Self> {
+        let file = File::open(&path)?;
+        let metadata = SstMetadata::load(&file)?;
+        if metadata.num_blocks() == 0 {
+            return Err(LsmError::Corruption(format!("sst {:?} has no blocks", path)).into());
+        }
+        let mut sst = Self::new(id, file, metadata, block_cache);
+        sst.blob_file = Self::open_blob_file(&sst.file, &path)?;
+        Ok(sst)
+    }
+
+    // like open, but the bloom filter is only read on the first point lookup,
+    // see StorageStateOptions::lazy_bloom_filters
+    pub(crate) fn open_with_lazy_bloom_filter(id: usize, path: PathBuf, block_cache: Option<Arc<BlockCache>>) -> Result<Self> {
+        let file = File::open(&path)?;
+        let metadata = SstMetadata::load_without_bloom_filter(&file)?;
+        if metadata.num_blocks() == 0 {
+            return Err(LsmError::Corruption(format!("sst {:?} has no blocks", path)).into());
+        }
+        let mut sst = Self::new(id, file, metadata, block_cache);
+        sst.blob_file = Self::open_blob_file(&sst.file, &path)?;
+        Ok(sst)
+    }
+
+    fn open_blob_file(file: &File, path: &Path) -> Result<Option<BlobFile>> {
+        if file.get_format_version() < SST_FORMAT_VERSION_BLOBS {
+            return Ok(None);
+        }
+        BlobFile::open(blob_path(path))
+    }
+
+    // like open, but the metadata is only loaded when it is first needed and
+    // is then kept in metadata_cache. the block index, or only its top level
+    // if it is partitioned, is still read once here to find the key range;
+    // the bloom filter isn't read at all
+    pub(crate) fn open_with_metadata_cache(
+        id: usize,
+        path: PathBuf,
+        block_cache: Option<Arc<BlockCache>>,
+        metadata_cache: Arc<MetadataCache>,
+    ) -> Result<Self> {
+        let file = File::open(&path)?;
         let bloom_filter_offset = file.get_bloom_filter_offset()?;
-        let bloom_filter = file.load_bloom_filter(bloom_filter_offset)?;
-        let meta_block_offset = file.get_meta_block_offset(bloom_filter_offset)?;
-        let meta_blocks = file.load_meta_blocks(meta_block_offset, bloom_filter_offset)?;
-        Ok(Self::new(
+        let (_, index) = SstMetadata::load_index(&file, bloom_filter_offset)?;
+        let Some((first_key, last_key)) = index.key_range() else {
+            return Err(LsmError::Corruption(format!("sst {:?} has no blocks", path)).into());
+        };
+        let blob_file = Self::open_blob_file(&file, &path)?;
+        Ok(Self {
             id,
             file,
-            meta_blocks,
-            meta_block_offset,
+            first_key,
+            last_key,
             block_cache,
-            bloom_filter,
-        ))
+            resident_metadata: None,
+            metadata_cache: Some(metadata_cache),
+            persistent_cache: None,
+            blob_file,
+        })
     }
 
-    pub fn read_block(&self, block_index: usize) -> Result<Arc<Block>> {
-        let offset = self.meta_blocks[block_index].get_offset();
-        let next_block_index = block_index + 1;
-        let next_offset = if self.meta_blocks.len() < next_block_index + 1 {
-            self.meta_block_offset
-        } else {
-            self.meta_blocks[next_block_index].get_offset()
-        };
-        let block_size = next_offset - offset;
-        let res = self.file.load_block_to_mem(offset, block_size)?;
+    // hand the metadata of a newly built SST over to metadata_cache
+    pub(crate) fn set_metadata_cache(&mut self, metadata_cache: Arc<MetadataCache>) {
+        if let Some(metadata) = self.resident_metadata.take() {
+            metadata_cache.insert(self.id, metadata);
+        }
+        self.metadata_cache = Some(metadata_cache);
+    }
+
+    // close the file until it is next read, and keep it open through
+    // table_cache from then on
+    pub(crate) fn set_table_cache(&mut self, table_cache: Arc<TableCache>) {
+        self.file.set_table_cache(self.id, table_cache);
+    }
+
+    pub(crate) fn set_persistent_cache(&mut self, persistent_cache: Arc<PersistentBlockCache>) {
+        self.persistent_cache = Some(persistent_cache);
+    }
+
+    // check the block index and bloom filter for corruption that opening
+    // doesn't catch, see StorageStateOptions::paranoid_checks. loads the
+    // metadata if it isn't in memory, and every partition of a partitioned
+    // block index
+    pub(crate) fn validate(&self) -> Result<()> {
+        let metadata = self.metadata()?;
+        let bloom_filter = metadata.bloom_filter(&self.file)?;
+        let meta_blocks: Vec<BlockMetadata> = metadata
+            .index
+            .blocks_in_range(&self.file, Bound::Unbounded, Bound::Unbounded)?
+            .into_iter()
+            .map(|(_, block_meta)| block_meta)
+            .collect();
+        metadata
+            .validate(&meta_blocks, bloom_filter)
+            .map_err(|msg| LsmError::Corruption(format!("sst {}: {}", self.id, msg)).into())
+    }
+
+    // None when the metadata lives in the metadata cache instead
+    pub(crate) fn resident_metadata(&self) -> Option<&Arc<SstMetadata>> {
+        self.resident_metadata.as_ref()
+    }
+
+    pub(crate) fn metadata(&self) -> Result<Arc<SstMetadata>> {
+        if let Some(metadata) = &self.resident_metadata {
+            return Ok(metadata.clone());
+        }
+        let metadata_cache = self
+            .metadata_cache
+            .as_ref()
+            .expect("metadata is either resident or cached");
+        metadata_cache
+            .try_get_with(self.id, || SstMetadata::load(&self.file).map(Arc::new))
+            .map_err(|err| anyhow!(err))
+    }
+
+    pub(crate) fn read_block(&self, block_index: usize) -> Result<Arc<Block>> {
+        let metadata = self.metadata()?;
+        let block_meta = metadata.index.block_meta(&self.file, block_index)?;
+        let res = self.file.load_block_to_mem(
+            block_meta.get_offset(),
+            metadata.block_size(&self.file, block_index)?,
+            block_meta.get_restart_interval(),
+        )?;
         Ok(Arc::new(res))
     }
 
+    // re-read a block from the file, past the caches, and check it against
+    // the block index, see Block::verify. every value has to resolve, so blob
+    // values are read too
+    pub(crate) fn verify_block(&self, block_index: usize) -> Result<()> {
+        let corruption =
+            |message: String| LsmError::Corruption(format!("sst {} block {}: {}", self.id, block_index, message));
+        if block_index >= self.metadata()?.num_blocks() {
+            return Err(anyhow!("sst {} has no block {}", self.id, block_index));
+        }
+        let block_meta = self.block_meta(block_index)?;
+        let block = self.read_block(block_index)?;
+        block.verify().map_err(corruption)?;
+        let num_entries = block.num_entries();
+        if let Some(stats) = block_meta.get_stats() {
+            if stats.num_entries as usize != num_entries {
+                return Err(corruption(format!("{} entries, but the index records {}", num_entries, stats.num_entries)).into());
+            }
+        }
+        let (Some(first), Some(last)) = (block.entry(0), num_entries.checked_sub(1).and_then(|last| block.entry(last)))
+        else {
+            return Err(corruption("block is empty".to_string()).into());
+        };
+        if first.key.get_key() != block_meta.get_first_key().get_key()
+            || last.key.get_key() != block_meta.get_last_key().get_key()
+        {
+            return Err(corruption(format!(
+                "keys {:?}..={:?} don't match the index's {:?}..={:?}",
+                first.key.get_key(),
+                last.key.get_key(),
+                block_meta.get_first_key().get_key(),
+                block_meta.get_last_key().get_key()
+            ))
+            .into());
+        }
+        // a key continued from the block before was counted there
+        let mut previous_key = match block_index {
+            0 => None,
+            _ => Some(self.block_meta(block_index - 1)?.get_last_key().get_key()),
+        };
+        let mut num_live_keys = 0;
+        for index in 0..num_entries {
+            let kv = self.resolve_entry(&block.entry(index).expect("index is below num_entries"))?;
+            let key = kv.key.get_key();
+            if !kv.is_tombstone() && previous_key.as_ref() != Some(&key) {
+                num_live_keys += 1;
+            }
+            previous_key = Some(key);
+        }
+        if let Some(recorded) = block_meta.get_stats().and_then(|stats| stats.num_live_keys) {
+            if recorded != num_live_keys {
+                return Err(corruption(format!("{} live keys, but the index records {}", num_live_keys, recorded)).into());
+            }
+        }
+        Ok(())
+    }
+
+    // like read_block_cached, but a block that isn't cached yet is read without
+    // being inserted when fill_cache is false
+    fn read_block_for_scan(&self, block_index: usize, fill_cache: bool) -> Result<Arc<Block>> {
+        if fill_cache {
+            return self.read_block_cached(block_index);
+        }
+        match self.block_cache.as_ref().and_then(|cache| cache.get(&(self.id, block_index))) {
+            Some(block) => Ok(block),
+            None => self.read_block_persistent_cached(block_index, false),
+        }
+    }
+
     fn read_block_cached(&self, block_index: usize) -> Result<Arc<Block>> {
         // attempt to read from cache first
         if let Some(cache) = &self.block_cache {
-            let cache_res =
-                cache.try_get_with((self.id, block_index), || self.read_block(block_index));
+            let cache_res = cache.try_get_with((self.id, block_index), || {
+                self.read_block_persistent_cached(block_index, true)
+            });
             match cache_res {
                 Ok(res) => Ok(res),
                 Err(err) => Err(anyhow!(err)),
             }
         } else {
-            self.read_block(block_index)
+            self.read_block_persistent_cached(block_index, true)
         }
     }
 
-    fn get_block_index_for_key(&self, key: &TimestampedKey) -> usize {
-        let (mut lo, mut hi) = (0, self.meta_blocks.len() - 1);
-        // seek to last block with first_key less than or equal to key
-        while lo < hi {
-            let mid = (lo + hi).div_ceil(2); // use right mid to avoid infinite loop
-            let first_key = self.meta_blocks[mid].get_first_key();
-            match first_key.cmp(key) {
-                Ordering::Less => lo = mid,
-                Ordering::Greater => hi = mid - 1,
-                Ordering::Equal => return mid,
-            }
+    fn read_block_persistent_cached(&self, block_index: usize, fill_cache: bool) -> Result<Arc<Block>> {
+        let Some(persistent_cache) = &self.persistent_cache else {
+            return self.read_block(block_index);
+        };
+        let restart_interval = self.block_meta(block_index)?.get_restart_interval();
+        if let Some(block) = persistent_cache.get((self.id, block_index), restart_interval) {
+            return Ok(Arc::new(block));
+        }
+        let block = self.read_block(block_index)?;
+        if fill_cache {
+            // the persistent tier is best effort, a failed write only costs
+            // a later read from the file
+            let _ = persistent_cache.insert((self.id, block_index), &block);
         }
-        (lo + hi).div_ceil(2)
+        Ok(block)
     }
 
-    pub fn get_id(&self) -> usize {
+    pub(crate) fn get_id(&self) -> usize {
         self.id
     }
 
-    pub fn get_first_key(&self) -> TimestampedKey {
-        self.meta_blocks
-            .first()
-            .expect("sst must contain at least one block")
-            .get_first_key()
+    fn block_meta(&self, block_index: usize) -> Result<BlockMetadata> {
+        self.metadata()?.index.block_meta(&self.file, block_index)
     }
 
-    pub fn get_last_key(&self) -> TimestampedKey {
-        self.meta_blocks
-            .last()
-            .expect("sst must contain at least one block")
-            .get_last_key()
+    // first block that can hold the newest version of key or anything after it
+    pub(crate) fn get_block_index_for_key(&self, key: &TimestampedKey) -> Result<usize> {
+        self.metadata()?.index.block_index_for_key(&self.file, key)
     }
 
-    pub fn maybe_contains_key(&self, key: &[u8]) -> bool {
-        self.bloom_filter.maybe_contains(key)
-            && self.get_first_key().get_key() <= key
-            && key <= self.get_last_key().get_key()
+    // in the in-memory block cache, so reading it costs no I/O
+    pub(crate) fn is_block_cached(&self, block_index: usize) -> bool {
+        self.block_cache
+            .as_ref()
+            .is_some_and(|cache| cache.contains_key(&(self.id, block_index)))
+    }
+
+    pub(crate) fn get_first_key(&self) -> TimestampedKey {
+        self.first_key.clone()
+    }
+
+    pub(crate) fn get_last_key(&self) -> TimestampedKey {
+        self.last_key.clone()
+    }
+
+    pub(crate) fn get_file_size(&self) -> u64 {
+        self.file.get_size()
+    }
+
+    // SSTs are never modified after they are written, so this is when the
+    // file was created. copying the file without preserving times resets it
+    pub(crate) fn get_creation_time(&self) -> Result<SystemTime> {
+        self.file.get_modified_time()
+    }
+
+    // for outputs that stand in for older SSTs, e.g. of a time-window
+    // compaction
+    pub(crate) fn set_creation_time(&self, time: SystemTime) -> Result<()> {
+        self.file.set_modified_time(time)
+    }
+
+    pub(crate) fn get_format_version(&self) -> u32 {
+        self.file.get_format_version()
+    }
+
+    // the smallest and largest sequence numbers of the entries in the SST.
+    // (0, 0) for SSTs written before they were recorded, which are older
+    // than anything written since
+    pub(crate) fn get_sequence_range(&self) -> (u64, u64) {
+        self.file.get_sequence_range()
+    }
+
+
+    // 0 if the SST has no blob file
+    pub(crate) fn get_blob_file_size(&self) -> u64 {
+        self.blob_file.as_ref().map_or(0, |blob_file| blob_file.get_size())
+    }
+
+    // the entry a block entry stands for, reading its value from the blob file
+    // if it was too large to store inline
+    pub(crate) fn resolve_entry(&self, stored: &KeyValuePair) -> Result<KeyValuePair> {
+        let format_version = self.get_format_version();
+        // older versions store tombstones as empty values, and values before
+        // blobs as they are
+        if stored.value.is_empty() && format_version < SST_FORMAT_VERSION_VALUE_TYPES {
+            return Ok(KeyValuePair::tombstone(stored.key.clone()));
+        }
+        if format_version < SST_FORMAT_VERSION_BLOBS {
+            return Ok(KeyValuePair::new(stored.key.clone(), stored.value.clone()));
+        }
+        if stored.value.first() == Some(&VALUE_TAG_DELETE) && format_version >= SST_FORMAT_VERSION_VALUE_TYPES {
+            return match stored.value.len() {
+                1 => Ok(KeyValuePair::tombstone(stored.key.clone())),
+                len => Err(LsmError::Corruption(format!("sst {} has a tombstone of {} bytes", self.id, len)).into()),
+            };
+        }
+        Ok(KeyValuePair::new(stored.key.clone(), self.resolve_value(stored.value.clone())?))
+    }
+
+    // like resolve_entry, but a value in the blob file is left empty and its
+    // reference returned, to be read with resolve_value if it is needed
+    pub(crate) fn resolve_entry_without_blob(&self, stored: &KeyValuePair) -> Result<(KeyValuePair, Option<Bytes>)> {
+        if self.get_format_version() >= SST_FORMAT_VERSION_BLOBS && stored.value.first() == Some(&VALUE_TAG_BLOB) {
+            return Ok((KeyValuePair::new(stored.key.clone(), Bytes::new()), Some(stored.value.clone())));
+        }
+        Ok((self.resolve_entry(stored)?, None))
+    }
+
+    pub(crate) fn resolve_value(&self, stored: Bytes) -> Result<Bytes> {
+        match stored.first().copied().unwrap_or(VALUE_TAG_DELETE) {
+            VALUE_TAG_INLINE => Ok(stored.slice(1..)),
+            VALUE_TAG_BLOB if stored.len() == BLOB_REFERENCE_SIZE => {
+                let offset = u64::from_be_bytes(stored[1..9].try_into().expect("chunk of size 8"));
+                let len = u32::from_be_bytes(stored[9..].try_into().expect("chunk of size 4"));
+                let blob_file = self
+                    .blob_file
+                    .as_ref()
+                    .ok_or_else(|| LsmError::Corruption(format!("sst {} references a missing blob file", self.id)))?;
+                blob_file.read(offset, len as usize)
+            }
+            tag => Err(LsmError::Corruption(format!(
+                "sst {} has a value with unknown tag {} of {} bytes",
+                self.id,
+                tag,
+                stored.len()
+            ))
+            .into()),
+        }
+    }
+
+    // number of entries, including tombstones, in the blocks overlapping the
+    // range. only reads data blocks of legacy files that carry no block stats
+    pub(crate) fn estimate_num_entries(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<u64> {
+        let mut num_entries = 0;
+        for (block_index, block_meta) in self.metadata()?.index.blocks_in_range(&self.file, lower, upper)? {
+            num_entries += match block_meta.get_stats() {
+                Some(stats) => u64::from(stats.num_entries),
+                None => self.read_block_cached(block_index)?.num_entries() as u64,
+            };
+        }
+        Ok(num_entries)
+    }
+
+    // the blocks wholly in range that know their live keys, from the block
+    // stats alone. a block starting part way through the versions of a key
+    // is left out, as that key is counted in the block before
+    pub(crate) fn block_key_counts(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<Vec<BlockKeyCount>> {
+        let blocks = self.metadata()?.index.blocks_in_range(&self.file, lower, upper)?;
+        let mut counts = Vec::new();
+        for (position, (block_index, block_meta)) in blocks.iter().enumerate() {
+            let Some(num_live_keys) = block_meta.get_stats().and_then(|stats| stats.num_live_keys) else {
+                continue;
+            };
+            let first_key = block_meta.get_first_key().get_key();
+            let last_key = block_meta.get_last_key().get_key();
+            let in_range = |key: &Bytes| RangeBounds::<[u8]>::contains(&(lower, upper), key.as_ref());
+            // the block before overlaps the range too if it shares a key
+            let is_continued = position > 0 && blocks[position - 1].1.get_last_key().get_key() == first_key;
+            if !in_range(&first_key) || !in_range(&last_key) || is_continued {
+                continue;
+            }
+            counts.push(BlockKeyCount {
+                block_index: *block_index,
+                first_key,
+                last_key,
+                num_live_keys: num_live_keys.into(),
+            });
+        }
+        Ok(counts)
+    }
+
+    // see PrefixStats. blocks read for sampling aren't added to the block cache
+    pub(crate) fn prefix_stats(&self, prefix: &[u8]) -> Result<PrefixStats> {
+        let upper = prefix_upper_bound(prefix);
+        let upper = upper.as_deref().map_or(Bound::Unbounded, Bound::Excluded);
+        let metadata = self.metadata()?;
+        let mut stats = PrefixStats::default();
+        for (block_index, block_meta) in metadata.index.blocks_in_range(&self.file, Bound::Included(prefix), upper)? {
+            let block_size = u64::from(metadata.block_size(&self.file, block_index)?);
+            let is_inside = block_meta.get_first_key().get_key().starts_with(prefix)
+                && block_meta.get_last_key().get_key().starts_with(prefix);
+            if let (true, Some(block_stats)) = (is_inside, block_meta.get_stats()) {
+                stats.num_entries += u64::from(block_stats.num_entries);
+                stats.size_bytes += block_size;
+                continue;
+            }
+            let block = self.read_block_for_scan(block_index, false)?;
+            let num_matching = (0..block.num_entries())
+                .filter_map(|index| block.entry(index))
+                .filter(|kv| kv.key.get_key().starts_with(prefix))
+                .count() as u64;
+            stats.num_entries += num_matching;
+            stats.size_bytes += block_size * num_matching / (block.num_entries() as u64).max(1);
+            stats.num_sampled_blocks += u64::from(!is_inside);
+        }
+        Ok(stats)
+    }
+
+    // indexes of the blocks overlapping the range, which a scan of it reads
+    pub(crate) fn blocks_in_range(&self, lower: Bound<&[u8]>, upper: Bound<&[u8]>) -> Result<Vec<usize>> {
+        Ok(self
+            .metadata()?
+            .index
+            .blocks_in_range(&self.file, lower, upper)?
+            .into_iter()
+            .map(|(block_index, _)| block_index)
+            .collect())
+    }
+
+    pub(crate) fn maybe_contains_key(&self, key: &[u8]) -> Result<bool> {
+        if key < &self.first_key.get_key()[..] || &self.last_key.get_key()[..] < key {
+            return Ok(false);
+        }
+        Ok(self.metadata()?.bloom_filter(&self.file)?.maybe_contains(key))
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{ops::Bound, sync::Arc};
+
+    use tempfile::tempdir;
 
     use crate::{
-        block::Block, kv::timestamped_key::TimestampedKey, table::test_utils::build_sst_with_cache,
+        block::Block,
+        error::LsmError,
+        kv::timestamped_key::TimestampedKey,
+        table::{
+            builder::SSTBuilder,
+            iterator::SSTIterator,
+            metadata_cache::MetadataCache,
+            test_utils::{build_sst_with_cache, set_up_builder},
+        },
     };
 
-    use super::test_utils::build_sst;
+    use super::{test_utils::build_sst, Sst, SST_FORMAT_VERSION_LIVE_KEYS};
 
     #[test]
     fn test_read_block() {
-        let mut sst = build_sst();
+        let sst = build_sst();
         let mut expected_block_data = vec![];
         expected_block_data.extend(sst.read_block(0).unwrap().encode());
         expected_block_data.extend(sst.read_block(1).unwrap().encode());
@@ -170,16 +758,168 @@ mod tests {
     fn test_get_block_index_for_key() {
         let sst = build_sst();
         assert_eq!(
-            sst.get_block_index_for_key(&TimestampedKey::new("k1".as_bytes().into())),
+            sst.get_block_index_for_key(&TimestampedKey::new("k1".as_bytes().into())).unwrap(),
             0
         );
         assert_eq!(
-            sst.get_block_index_for_key(&TimestampedKey::new("k2".as_bytes().into())),
+            sst.get_block_index_for_key(&TimestampedKey::new("k2".as_bytes().into())).unwrap(),
             0
         );
         assert_eq!(
-            sst.get_block_index_for_key(&TimestampedKey::new("k3".as_bytes().into())),
+            sst.get_block_index_for_key(&TimestampedKey::new("k3".as_bytes().into())).unwrap(),
             1
         );
     }
+
+    #[test]
+    fn test_estimate_num_entries() {
+        let sst = build_sst();
+        assert_eq!(sst.get_format_version(), SST_FORMAT_VERSION_LIVE_KEYS);
+        assert_eq!(sst.estimate_num_entries(Bound::Unbounded, Bound::Unbounded).unwrap(), 3);
+        // only the second block overlaps
+        assert_eq!(
+            sst.estimate_num_entries(Bound::Included("k3".as_bytes()), Bound::Unbounded).unwrap(),
+            1
+        );
+        // estimates are per block, so the whole first block is counted
+        assert_eq!(
+            sst.estimate_num_entries(Bound::Unbounded, Bound::Included("k1".as_bytes())).unwrap(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(build_sst().validate().is_ok());
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test_sst.sst");
+        drop(set_up_builder().build(0, path.clone(), None).unwrap());
+        let contents = std::fs::read(&path).unwrap();
+        // block 1's metadata follows block 0's 32 bytes in the index, which
+        // ends with the top-level index offset and the index offset right
+        // before the bloom filter offset.
+        // the footer is the sequence range, format version and magic
+        let footer_start = contents.len() - 8 - 16;
+        let bloom_filter_offset =
+            u32::from_be_bytes(contents[footer_start - 4..footer_start].try_into().unwrap()) as usize;
+        let meta_block_offset =
+            u32::from_be_bytes(contents[bloom_filter_offset - 4..bloom_filter_offset].try_into().unwrap()) as usize;
+        let block_1_meta = meta_block_offset + 32;
+        let corrupt = |patch: &dyn Fn(&mut Vec<u8>)| {
+            let mut corrupted = contents.clone();
+            patch(&mut corrupted);
+            std::fs::write(&path, corrupted).unwrap();
+            // opening trusts the index, validating doesn't
+            let err = Sst::open(0, path.clone(), None).unwrap().validate().unwrap_err();
+            assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::Corruption(_))), "{:#}", err);
+            err.to_string()
+        };
+
+        // block 1 at offset 0, where block 0 is
+        let err = corrupt(&|contents| contents[block_1_meta..block_1_meta + 4].fill(0));
+        assert!(err.contains("not after block 0"), "{}", err);
+        // block 1 starting at k0, inside block 0's k1..k2
+        let err = corrupt(&|contents| contents[block_1_meta + 7] = b'0');
+        assert!(err.contains("before block 0 ends"), "{}", err);
+        // one more entry than the bloom filter was built for
+        let err = corrupt(&|contents| contents[meta_block_offset + 15] += 1);
+        assert!(err.contains("bloom filter"), "{}", err);
+    }
+
+    #[test]
+    fn test_partitioned_index() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test_sst.sst");
+        let keys: Vec<String> = (0..100).map(|i| format!("k{:03}", i)).collect();
+        let build = |index_partition_num_blocks| {
+            // a block of size 0 takes one entry
+            let mut builder = SSTBuilder::new(0);
+            builder.set_index_partition_num_blocks(index_partition_num_blocks);
+            for key in keys.iter() {
+                builder.add_entry(key.as_bytes(), 0, Some(b"v")).unwrap();
+            }
+            builder.build(0, path.clone(), None).unwrap()
+        };
+        let full_index_size_bytes = build(None).metadata().unwrap().index_size_bytes();
+        let sst = Arc::new(build(Some(10)));
+        let metadata = sst.metadata().unwrap();
+        assert_eq!((metadata.num_blocks(), metadata.num_index_partitions()), (100, 10));
+
+        let block_index_for_key = |key: &str| sst.get_block_index_for_key(&TimestampedKey::new(key.to_string().into()));
+        assert_eq!(block_index_for_key("k").unwrap(), 0);
+        assert_eq!(block_index_for_key("k055").unwrap(), 55);
+        assert_eq!(block_index_for_key("k0555").unwrap(), 56);
+        assert_eq!(block_index_for_key("k999").unwrap(), 99);
+        // only one partition is held on to, whatever was read
+        assert!(metadata.index_size_bytes() < full_index_size_bytes / 2);
+
+        let scanned: Vec<_> = SSTIterator::create_and_seek_to_first(sst.clone())
+            .unwrap()
+            .map(|kv| kv.key.get_key())
+            .collect();
+        assert_eq!(scanned, keys);
+        let iterator = SSTIterator::create_and_seek_to_key(sst.clone(), TimestampedKey::new("k095".into())).unwrap();
+        assert_eq!(iterator.map(|kv| kv.key.get_key()).collect::<Vec<_>>(), keys[95..]);
+        assert_eq!(
+            sst.blocks_in_range(Bound::Included("k018".as_bytes()), Bound::Excluded("k021".as_bytes())).unwrap(),
+            vec![18, 19, 20]
+        );
+        assert_eq!(sst.estimate_num_entries(Bound::Unbounded, Bound::Unbounded).unwrap(), 100);
+        sst.validate().unwrap();
+        sst.verify_block(42).unwrap();
+
+        // reopened, only the top-level index is read
+        drop(metadata);
+        let metadata_cache = Arc::new(MetadataCache::new(1));
+        for sst in [
+            Sst::open(0, path.clone(), None).unwrap(),
+            Sst::open_with_metadata_cache(0, path.clone(), None, metadata_cache).unwrap(),
+        ] {
+            assert_eq!(sst.get_first_key().get_key(), "k000");
+            assert_eq!(sst.get_last_key().get_key(), "k099");
+            assert_eq!(sst.metadata().unwrap().num_index_partitions(), 10);
+            sst.validate().unwrap();
+            let scanned: Vec<_> = SSTIterator::create_and_seek_to_first(Arc::new(sst))
+                .unwrap()
+                .map(|kv| kv.key.get_key())
+                .collect();
+            assert_eq!(scanned, keys);
+        }
+
+        // a partition that doesn't match the top-level index is corruption,
+        // found once the partition is read. block 0's metadata starts with its
+        // offset and the length of its first key
+        let mut contents = std::fs::read(&path).unwrap();
+        let meta_block_offset = sst.metadata().unwrap().meta_block_offset as usize;
+        contents[meta_block_offset + 6 + 3] = b'9';
+        std::fs::write(&path, contents).unwrap();
+        let sst = Sst::open(0, path.clone(), None).unwrap();
+        sst.get_block_index_for_key(&TimestampedKey::new("k099".into())).unwrap();
+        let err = sst.get_block_index_for_key(&TimestampedKey::new("k000".into())).unwrap_err();
+        assert!(matches!(err.downcast_ref::<LsmError>(), Some(LsmError::Corruption(_))), "{:#}", err);
+    }
+
+    #[test]
+    fn test_lazy_bloom_filter() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test_sst.sst");
+        let bloom_filter_size_bytes = set_up_builder()
+            .build(0, path.clone(), None)
+            .unwrap()
+            .metadata()
+            .unwrap()
+            .bloom_filter_size_bytes();
+        let sst = Arc::new(Sst::open_with_lazy_bloom_filter(0, path, None).unwrap());
+        let metadata = sst.metadata().unwrap();
+        assert_eq!(metadata.bloom_filter_size_bytes(), 0);
+        // scans don't need it
+        assert_eq!(SSTIterator::create_and_seek_to_first(sst.clone()).unwrap().count(), 3);
+        assert_eq!(metadata.bloom_filter_size_bytes(), 0);
+        // the first lookup reads it
+        assert!(sst.maybe_contains_key("k2".as_bytes()).unwrap());
+        assert_eq!(metadata.bloom_filter_size_bytes(), bloom_filter_size_bytes);
+        assert!(!sst.maybe_contains_key("k0".as_bytes()).unwrap());
+        sst.validate().unwrap();
+    }
 }
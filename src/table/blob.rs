@@ -0,0 +1,129 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::error::LsmError;
+use crate::platform::read_exact_at;
+
+// values larger than StorageStateOptions::blob_threshold_bytes, stored back
+// to back next to the SST that references them. each value is stored as
+// | tag (u8) | offset (u64) | len (u32) | in the SST's blocks instead, so the
+// blocks stay small enough to cache
+pub(crate) struct BlobFile {
+    path: PathBuf,
+    handle: BlobHandle,
+    size: u64,
+}
+
+enum BlobHandle {
+    File(std::fs::File),
+    // for stores that never touch the disk
+    Memory(Bytes),
+}
+
+// the blob file of the SST at sst_path
+pub(crate) fn blob_path(sst_path: &Path) -> PathBuf {
+    sst_path.with_extension("blob")
+}
+
+// SSTs without large values have no blob file, so a missing one is fine
+pub(crate) fn remove_blob_file(sst_path: &Path) -> io::Result<()> {
+    match std::fs::remove_file(blob_path(sst_path)) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        res => res,
+    }
+}
+
+impl BlobFile {
+    pub(crate) fn create(path: impl AsRef<Path>, data: Vec<u8>) -> Result<Self> {
+        std::fs::write(&path, &data)?;
+        let file = std::fs::File::open(&path)?;
+        // the SST referencing the values may be recorded as soon as it's built
+        file.sync_all()?;
+        Ok(Self {
+            path: path.as_ref().to_owned(),
+            handle: BlobHandle::File(file),
+            size: data.len() as u64,
+        })
+    }
+
+    // None if the file doesn't exist
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Option<Self>> {
+        let file = match std::fs::File::open(&path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            res => res?,
+        };
+        let size = file.metadata()?.len();
+        Ok(Some(Self {
+            path: path.as_ref().to_owned(),
+            handle: BlobHandle::File(file),
+            size,
+        }))
+    }
+
+    pub(crate) fn create_in_memory(path: impl AsRef<Path>, data: Vec<u8>) -> Self {
+        Self {
+            path: path.as_ref().to_owned(),
+            size: data.len() as u64,
+            handle: BlobHandle::Memory(Bytes::from(data)),
+        }
+    }
+
+    pub(crate) fn read(&self, offset: u64, len: usize) -> Result<Bytes> {
+        let end = offset.checked_add(len as u64).filter(|end| *end <= self.size);
+        if end.is_none() {
+            return Err(LsmError::Corruption(format!(
+                "blob of {} bytes at offset {} is past the end of {:?}",
+                len, offset, self.path
+            ))
+            .into());
+        }
+        match &self.handle {
+            BlobHandle::Memory(data) => Ok(data.slice(offset as usize..offset as usize + len)),
+            BlobHandle::File(file) => {
+                let mut buffer = vec![0; len];
+                read_exact_at(file, &mut buffer, offset)?;
+                Ok(buffer.into())
+            }
+        }
+    }
+
+    pub(crate) fn get_size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use super::{blob_path, remove_blob_file, BlobFile};
+
+    #[test]
+    fn test_blob_file() {
+        let dir = tempdir().unwrap();
+        let sst_path = dir.path().join("00001.sst");
+        let path = blob_path(&sst_path);
+        assert_eq!(path, dir.path().join("00001.blob"));
+        assert!(BlobFile::open(&path).unwrap().is_none());
+
+        let data = b"firstsecond".to_vec();
+        let blob_file = BlobFile::create(&path, data.clone()).unwrap();
+        assert_eq!(blob_file.read(5, 6).unwrap(), "second".as_bytes());
+        assert!(blob_file.read(5, 7).is_err());
+        let reopened = BlobFile::open(&path).unwrap().unwrap();
+        assert_eq!(reopened.get_size(), 11);
+        assert_eq!(reopened.read(0, 5).unwrap(), "first".as_bytes());
+
+        let in_memory = BlobFile::create_in_memory(&path, data);
+        assert_eq!(in_memory.read(5, 6).unwrap(), "second".as_bytes());
+        assert!(in_memory.read(u64::MAX, 1).is_err());
+
+        remove_blob_file(&sst_path).unwrap();
+        assert!(!path.exists());
+        // already gone
+        remove_blob_file(&sst_path).unwrap();
+    }
+}
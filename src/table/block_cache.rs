@@ -1,5 +1,154 @@
 use std::sync::Arc;
 
-use crate::block::Block;
+use xxhash_rust::xxh3::xxh3_64;
 
-pub type BlockCache = moka::sync::Cache<(usize, usize), Arc<Block>>;
\ No newline at end of file
+use crate::{block::Block, platform::HAS_CLOCK};
+
+// SST id and block index
+pub(crate) type BlockCacheKey = (usize, usize);
+
+type BlockCacheShard = moka::sync::Cache<BlockCacheKey, Arc<Block>>;
+
+// in-memory cache of decoded blocks, split into shards by SST id so that
+// readers of different SSTs don't contend on the same cache. the byte budget
+// is split evenly between the shards, so an SST's blocks can only use its
+// shard's share. with no shards nothing is cached
+pub struct BlockCache {
+    shards: Vec<BlockCacheShard>,
+}
+
+// a cache holding at most capacity_bytes of encoded blocks across num_shards
+// shards, at least one. without a weigher moka would count entries instead.
+// moka needs a clock, so without one nothing is cached
+pub(crate) fn new_block_cache(capacity_bytes: u64, num_shards: usize) -> BlockCache {
+    if capacity_bytes == 0 || !HAS_CLOCK {
+        return BlockCache { shards: Vec::new() };
+    }
+    let num_shards = num_shards.max(1);
+    let shard_capacity_bytes = capacity_bytes.div_ceil(num_shards as u64);
+    let shards = (0..num_shards)
+        .map(|_| {
+            BlockCacheShard::builder()
+                .max_capacity(shard_capacity_bytes)
+                .weigher(|_, block: &Arc<Block>| u32::try_from(block.size_bytes()).unwrap_or(u32::MAX))
+                .build()
+        })
+        .collect();
+    BlockCache { shards }
+}
+
+impl BlockCache {
+    fn shard(&self, key: &BlockCacheKey) -> Option<&BlockCacheShard> {
+        if self.shards.is_empty() {
+            return None;
+        }
+        let sst_id = key.0 as u64;
+        Some(&self.shards[(xxh3_64(&sst_id.to_le_bytes()) % self.shards.len() as u64) as usize])
+    }
+
+    pub fn num_shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn get(&self, key: &BlockCacheKey) -> Option<Arc<Block>> {
+        self.shard(key)?.get(key)
+    }
+
+    pub fn contains_key(&self, key: &BlockCacheKey) -> bool {
+        self.shard(key).is_some_and(|shard| shard.contains_key(key))
+    }
+
+    pub fn insert(&self, key: BlockCacheKey, block: Arc<Block>) {
+        if let Some(shard) = self.shard(&key) {
+            shard.insert(key, block)
+        }
+    }
+
+    pub fn invalidate(&self, key: &BlockCacheKey) {
+        if let Some(shard) = self.shard(key) {
+            shard.invalidate(key)
+        }
+    }
+
+    // the cached block, or init's, which is cached. concurrent misses on the
+    // same key share one call of init
+    pub fn try_get_with<E: Send + Sync + 'static>(
+        &self,
+        key: BlockCacheKey,
+        init: impl FnOnce() -> Result<Arc<Block>, E>,
+    ) -> Result<Arc<Block>, Arc<E>> {
+        match self.shard(&key) {
+            Some(shard) => shard.try_get_with(key, init),
+            None => init().map_err(Arc::new),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Arc<BlockCacheKey>, Arc<Block>)> + '_ {
+        self.shards.iter().flat_map(|shard| shard.iter())
+    }
+
+    // apply pending evictions, so that sizes and counts are up to date
+    pub fn run_pending_tasks(&self) {
+        self.shards.iter().for_each(|shard| shard.run_pending_tasks());
+    }
+
+    pub fn entry_count(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.entry_count()).sum()
+    }
+
+    pub fn weighted_size(&self) -> u64 {
+        self.shards.iter().map(|shard| shard.weighted_size()).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::block::Block;
+
+    use super::new_block_cache;
+
+    #[test]
+    fn test_block_cache_weighs_bytes() {
+        let cache = new_block_cache(100, 1);
+        for i in 0..10 {
+            // 20 bytes of data, 2 bytes of offsets and 2 for the end of data offset
+            cache.insert((0, i), Arc::new(Block::new(vec![0; 20], vec![0], 20)));
+        }
+        cache.run_pending_tasks();
+        assert!(cache.weighted_size() <= 100);
+        assert!(cache.entry_count() <= 4);
+    }
+
+    #[test]
+    fn test_sharded_block_cache() {
+        let cache = new_block_cache(4 * 24 * 8, 8);
+        assert_eq!(cache.num_shards(), 8);
+        for sst_id in 0..64 {
+            cache.insert((sst_id, 0), Arc::new(Block::new(vec![0; 20], vec![0], 20)));
+        }
+        cache.run_pending_tasks();
+        // the budget holds across shards
+        assert!(cache.weighted_size() <= 4 * 24 * 8);
+        assert!(cache.entry_count() > 0);
+        assert_eq!(cache.iter().count() as u64, cache.entry_count());
+        let (key, _) = cache.iter().next().unwrap();
+        assert!(cache.contains_key(&key));
+        cache.invalidate(&key);
+        assert!(cache.get(&key).is_none());
+        assert_eq!(new_block_cache(100, 0).num_shards(), 1);
+    }
+
+    #[test]
+    fn test_empty_block_cache() {
+        let cache = new_block_cache(0, 4);
+        assert_eq!(cache.num_shards(), 0);
+        let block = Arc::new(Block::new(vec![0; 20], vec![0], 20));
+        cache.insert((0, 0), block.clone());
+        assert!(cache.get(&(0, 0)).is_none());
+        let loaded = cache.try_get_with::<()>((0, 0), || Ok(block.clone())).unwrap();
+        assert!(Arc::ptr_eq(&loaded, &block));
+        assert_eq!(cache.entry_count(), 0);
+    }
+}
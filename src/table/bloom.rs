@@ -2,36 +2,65 @@ use bitvec::{bitvec, field::BitField, order::Lsb0, vec::BitVec};
 use bytes::Bytes;
 use xxhash_rust::xxh3::xxh3_64;
 
-use crate::kv::timestamped_key::TimestampedKey;
+pub(crate) const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
 
-const FALSE_POSITIVE_RATE: f64 = 0.01;
-
-pub struct BloomFilter {
+pub(crate) struct BloomFilter {
     bit_vec: BitVec<u8>,
     k: u8
 }
 
 impl BloomFilter {
-    pub fn from_keys(keys: Vec<TimestampedKey>) -> Self {
-        let n = keys.len();
-        let m = Self::get_bit_arr_len(n);
+    #[cfg(test)]
+    pub(crate) fn from_keys(keys: Vec<crate::kv::timestamped_key::TimestampedKey>) -> Self {
+        Self::from_keys_with_false_positive_rate(keys, DEFAULT_FALSE_POSITIVE_RATE)
+    }
+
+    // false_positive_rate must be between 0 and 1
+    #[cfg(test)]
+    pub(crate) fn from_keys_with_false_positive_rate(keys: Vec<crate::kv::timestamped_key::TimestampedKey>, false_positive_rate: f64) -> Self {
+        let hashes: Vec<u64> = keys.iter().map(|key| Self::key_hash(&key.get_key())).collect();
+        Self::from_key_hashes_with_false_positive_rate(&hashes, false_positive_rate)
+    }
+
+    // the same filter from_keys builds, from the keys' key_hash
+    pub(crate) fn from_key_hashes_with_false_positive_rate(hashes: &[u64], false_positive_rate: f64) -> Self {
+        let mut bloom_filter = Self::with_capacity(hashes.len(), false_positive_rate);
+        for hash in hashes {
+            bloom_filter.insert_hash(*hash);
+        }
+        bloom_filter
+    }
+
+    // an empty filter sized for n keys, for adding keys as they come. more
+    // than n keys raise the false positive rate above false_positive_rate
+    pub(crate) fn with_capacity(n: usize, false_positive_rate: f64) -> Self {
+        let m = Self::get_bit_arr_len(n, false_positive_rate);
         let k = Self::get_num_hash_functions(m, n);
+        Self {
+            bit_vec: bitvec![u8, Lsb0; 0; m],
+            k,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, key: &[u8]) {
+        self.insert_hash(Self::key_hash(key));
+    }
 
-        let mut bit_vec = bitvec![u8, Lsb0; 0; m];
+    // all a key's bits are derived from this one hash, so keys can be kept
+    // as their hashes until the filter is sized
+    pub(crate) fn key_hash(key: &[u8]) -> u64 {
+        xxh3_64(key)
+    }
 
-        // set bits for each key
-        for key in keys {
-            let indices = Self::get_indices_for_key(&key.get_key(), m, k);
-            for i in indices {
-                bit_vec.set(i, true);
-            }
+    pub(crate) fn insert_hash(&mut self, hash: u64) {
+        for i in Self::get_indices_for_hash(hash, self.bit_vec.len(), self.k) {
+            self.bit_vec.set(i, true);
         }
-        Self { bit_vec, k }
     }
 
-    fn get_bit_arr_len(n: usize) -> usize {
+    fn get_bit_arr_len(n: usize, false_positive_rate: f64) -> usize {
         let m = (
-            -1.0 * (n as f64) * FALSE_POSITIVE_RATE.ln() / 
+            -(n as f64) * false_positive_rate.ln() / 
             std::f64::consts::LN_2.powi(2)
         ).ceil() as usize;
         // pad to byte length
@@ -44,9 +73,7 @@ impl BloomFilter {
         ).round() as u8
     }
 
-    fn get_indices_for_key(key: &[u8], m: usize, k: u8) -> Vec<usize> {
-        // hash the key
-        let hash64 = xxh3_64(key);
+    fn get_indices_for_hash(hash64: u64, m: usize, k: u8) -> Vec<usize> {
         let (h1, h2) = ((hash64 >> 32) as u32, hash64 as u32); 
 
         let mut indices: Vec<usize> = vec![];
@@ -60,8 +87,8 @@ impl BloomFilter {
         indices
     }
 
-    pub fn maybe_contains(&self, key: &[u8]) -> bool {
-        let indices = Self::get_indices_for_key(key, self.bit_vec.len(), self.k);
+    pub(crate) fn maybe_contains(&self, key: &[u8]) -> bool {
+        let indices = Self::get_indices_for_hash(Self::key_hash(key), self.bit_vec.len(), self.k);
         for i in indices {
             if !self.bit_vec[i] {
                 return false;
@@ -70,7 +97,22 @@ impl BloomFilter {
         true
     }
 
-    pub fn encode(&mut self) -> Bytes {
+    // whether the filter could have been sized for num_keys keys or more:
+    // filters built with_capacity from an estimate are sized for at least as
+    // many keys as they hold, and have at most as many hash functions as one
+    // built from_keys. the false positive rate it was built with isn't
+    // recorded, so the size itself can't be checked. a filter without bits or
+    // hash functions can't be probed at all
+    pub(crate) fn fits_num_keys(&self, num_keys: usize) -> bool {
+        let m = self.bit_vec.len();
+        m > 0 && m.is_multiple_of(8) && self.k >= 1 && self.k <= Self::get_num_hash_functions(m, num_keys)
+    }
+
+    pub(crate) fn size_bytes(&self) -> usize {
+        self.bit_vec.as_raw_slice().len() + 1
+    }
+
+    pub(crate) fn encode(&mut self) -> Bytes {
         let mut bit_vec_bytes: Vec<u8> = self.bit_vec.chunks(8).map(
             |v| v.load::<u8>()
         ).collect();
@@ -78,7 +120,7 @@ impl BloomFilter {
         Bytes::from(bit_vec_bytes)
     }
 
-    pub fn decode(encoded: Vec<u8>) -> Self {
+    pub(crate) fn decode(encoded: Vec<u8>) -> Self {
         Self {
             bit_vec: BitVec::from_slice(&encoded[..encoded.len()-1]),
             k: *encoded.last().unwrap()
@@ -113,6 +155,49 @@ mod tests {
         assert!(!bloom_filter.maybe_contains("not here".as_bytes()));
     }
 
+    #[test]
+    fn test_false_positive_rate() {
+        let keys: Vec<TimestampedKey> =
+            (0..100).map(|i| TimestampedKey::new(format!("key{}", i).into_bytes().into())).collect();
+        let default = BloomFilter::from_keys(keys.clone());
+        let precise = BloomFilter::from_keys_with_false_positive_rate(keys.clone(), 0.001);
+        assert!(precise.size_bytes() > default.size_bytes());
+        assert!(precise.k > default.k);
+        // either rate passes validation
+        assert!(default.fits_num_keys(100));
+        assert!(precise.fits_num_keys(100));
+        // too small for ten times as many keys
+        assert!(!precise.fits_num_keys(1000));
+        for key in keys {
+            assert!(precise.maybe_contains(&key.get_key()));
+        }
+    }
+
+    #[test]
+    fn test_with_capacity() {
+        let keys: Vec<TimestampedKey> =
+            (0..100).map(|i| TimestampedKey::new(format!("key{}", i).into_bytes().into())).collect();
+        let mut bloom_filter = BloomFilter::with_capacity(100, 0.01);
+        for key in keys.iter() {
+            bloom_filter.insert(&key.get_key());
+        }
+        // the same filter from_keys builds
+        let from_keys = BloomFilter::from_keys(keys.clone());
+        assert_eq!(bloom_filter.bit_vec, from_keys.bit_vec);
+        assert_eq!(bloom_filter.k, from_keys.k);
+
+        // sized for more keys than it holds, e.g. from an estimate
+        let mut oversized = BloomFilter::with_capacity(400, 0.01);
+        for key in keys[..50].iter() {
+            oversized.insert(&key.get_key());
+        }
+        assert!(oversized.fits_num_keys(50));
+        assert!(!oversized.fits_num_keys(1000));
+        for key in keys[..50].iter() {
+            assert!(oversized.maybe_contains(&key.get_key()));
+        }
+    }
+
     #[test]
     fn test_encode_decode() {
         let k1 = TimestampedKey::new("hello".as_bytes().into());
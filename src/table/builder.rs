@@ -1,75 +1,279 @@
+use std::cmp::Reverse;
 use std::path::Path;
 use std::sync::Arc;
 
-use anyhow::Result;
+use anyhow::{bail, Result};
+use bytes::Bytes;
 
 use crate::{
-    block::{builder::BlockBuilder, metadata::BlockMetadata},
+    block::{
+        builder::{BlockBuilder, MAX_BLOCK_DATA_BYTES},
+        metadata::{BlockMetadata, BlockStats},
+    },
     kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
     table::File,
 };
 
-use super::{block_cache::BlockCache, bloom::BloomFilter, Sst};
+use super::{
+    blob::{blob_path, BlobFile},
+    block_cache::BlockCache,
+    bloom::{BloomFilter, DEFAULT_FALSE_POSITIVE_RATE},
+    index::{BlockIndex, IndexPartition},
+    Sst, SstMetadata, BLOB_REFERENCE_SIZE, SST_FORMAT_VERSION_LIVE_KEYS, SST_MAGIC, VALUE_TAG_BLOB,
+    VALUE_TAG_DELETE, VALUE_TAG_INLINE,
+};
 
-pub struct SSTBuilder {
+pub(crate) struct SSTBuilder {
     block_builder: BlockBuilder,
     // assume all metadata blocks can fit in memory
     block_meta_list: Vec<BlockMetadata>,
     block_size: usize,
+    restart_interval: usize,
     block_data: Vec<u8>,
     meta_block_offset: u32,
-    first_key: TimestampedKey,
-    last_key: TimestampedKey,
-    all_keys: Vec<TimestampedKey>,
+    // of the SST
+    first_key: Bytes,
+    // of the block being built
+    block_first_key: Vec<u8>,
+    block_first_timestamp: u64,
+    last_key: Vec<u8>,
+    last_timestamp: u64,
+    // of the block being built, see BlockStats::num_live_keys
+    block_num_live_keys: u32,
+    // each value is encoded into this before it's copied into the block
+    value_buffer: Vec<u8>,
+    num_keys: usize,
+    // the bloom filter is filled as keys are added when their number is
+    // known in advance, see set_expected_num_keys. otherwise the keys' hashes
+    // are kept until the SST is built, 8 bytes a key
+    expected_num_keys: Option<usize>,
+    bloom_filter: Option<BloomFilter>,
+    key_hashes: Vec<u64>,
+    // values longer than this go to the blob file. None stores every value
+    // inline
+    blob_threshold: Option<usize>,
+    blob_data: Vec<u8>,
+    bloom_false_positive_rate: f64,
+    // min and max timestamps of the keys added so far
+    sequence_range: Option<(u64, u64)>,
+    // recorded instead of sequence_range when set, see set_sequence_range
+    fixed_sequence_range: Option<(u64, u64)>,
+    // the block index is split into partitions of this many blocks when the
+    // SST has more. None never splits it
+    index_partition_num_blocks: Option<usize>,
 }
 
 impl SSTBuilder {
-    pub fn new(block_size: usize) -> Self {
+    #[cfg(any(test, feature = "rocksdb-sst"))]
+    pub(crate) fn new(block_size: usize) -> Self {
+        Self::new_with_restart_interval(block_size, crate::block::DEFAULT_RESTART_INTERVAL)
+    }
+
+    pub(crate) fn new_with_restart_interval(block_size: usize, restart_interval: usize) -> Self {
         Self {
-            block_builder: BlockBuilder::new(block_size),
+            block_builder: BlockBuilder::new_with_restart_interval(block_size, restart_interval),
             block_meta_list: Vec::new(),
             block_size,
+            restart_interval,
             block_data: Vec::new(),
             meta_block_offset: 0,
             // junk values before we add keys
-            first_key: TimestampedKey::new("".as_bytes().into()),
-            last_key: TimestampedKey::new("".as_bytes().into()),
-            all_keys: Vec::new(),
+            first_key: Bytes::new(),
+            block_first_key: Vec::new(),
+            block_first_timestamp: 0,
+            last_key: Vec::new(),
+            last_timestamp: 0,
+            block_num_live_keys: 0,
+            value_buffer: Vec::new(),
+            num_keys: 0,
+            expected_num_keys: None,
+            bloom_filter: None,
+            key_hashes: Vec::new(),
+            blob_threshold: None,
+            blob_data: Vec::new(),
+            bloom_false_positive_rate: DEFAULT_FALSE_POSITIVE_RATE,
+            sequence_range: None,
+            fixed_sequence_range: None,
+            index_partition_num_blocks: None,
         }
     }
 
-    pub fn add(&mut self, kv: KeyValuePair) -> Result<()> {
+    // must be set before anything is added
+    pub(crate) fn set_blob_threshold(&mut self, blob_threshold: Option<usize>) {
+        assert!(self.num_keys == 0, "blob threshold set after adding to the SST");
+        self.blob_threshold = blob_threshold;
+    }
+
+    // an upper bound on the number of entries that will be added, counting
+    // every version of a key. must be set before anything is added
+    pub(crate) fn set_expected_num_keys(&mut self, expected_num_keys: usize) {
+        assert!(self.num_keys == 0, "expected number of keys set after adding to the SST");
+        self.expected_num_keys = Some(expected_num_keys);
+    }
+
+    // the sequence range to record in the footer, for entries whose
+    // timestamps were lost, e.g. read back from other SSTs
+    pub(crate) fn set_sequence_range(&mut self, min: u64, max: u64) {
+        assert!(min <= max, "sequence range {}..={} is empty", min, max);
+        self.fixed_sequence_range = Some((min, max));
+    }
+
+    pub(crate) fn set_bloom_false_positive_rate(&mut self, bloom_false_positive_rate: f64) {
+        self.bloom_false_positive_rate = bloom_false_positive_rate;
+    }
+
+    pub(crate) fn set_index_partition_num_blocks(&mut self, index_partition_num_blocks: Option<usize>) {
+        assert!(index_partition_num_blocks != Some(0), "index partitions must hold at least one block");
+        self.index_partition_num_blocks = index_partition_num_blocks;
+    }
+
+    pub(crate) fn add(&mut self, kv: &KeyValuePair) -> Result<()> {
+        let value = (!kv.is_tombstone()).then_some(&kv.value[..]);
+        self.add_entry(&kv.key.get_key(), kv.key.get_timestamp(), value)
+    }
+
+    // keys must be added in TimestampedKey order, so versions of a key go in
+    // newest first. a value of None is a tombstone. an entry larger than the
+    // block size gets a block of its own, and a value too large for any block
+    // goes to the blob file. key and value are copied into the block, and the
+    // key is kept as the last key and maybe a block's first key, nothing more
+    pub(crate) fn add_entry(&mut self, key: &[u8], timestamp: u64, value: Option<&[u8]>) -> Result<()> {
+        if self.num_keys > 0 && (key, Reverse(timestamp)) <= (&self.last_key[..], Reverse(self.last_timestamp)) {
+            bail!(
+                "sst keys must be strictly increasing, got {:?} after {:?}",
+                TimestampedKey::new_with_timestamp(Bytes::copy_from_slice(key), timestamp),
+                TimestampedKey::new_with_timestamp(Bytes::copy_from_slice(&self.last_key), self.last_timestamp)
+            );
+        }
+        if 4 + key.len() + 2 + BLOB_REFERENCE_SIZE > MAX_BLOCK_DATA_BYTES {
+            bail!("key of {} bytes doesn't fit in a block", key.len());
+        }
+        self.encode_value(key.len(), value);
         // check if block is full
-        if !self.block_builder.is_empty() && self.block_builder.get_block_size_with_kv(&kv) >= self.block_size {
+        if self.block_builder.is_full_with(key, &self.value_buffer) {
             self.finalize_block();
             // update metadata
             self.meta_block_offset =
                 u32::try_from(self.block_data.len()).expect("size of SST must fit in 4 bytes");
-            self.first_key = kv.key.clone();
+        }
+        if self.block_builder.is_empty() {
+            self.block_first_key.clear();
+            self.block_first_key.extend_from_slice(key);
+            self.block_first_timestamp = timestamp;
         }
         // handle first key in SST
-        if self.first_key.get_key().is_empty() {
-            self.first_key = kv.key.clone();
+        if self.num_keys == 0 {
+            self.first_key = Bytes::copy_from_slice(key);
         }
-        self.last_key = kv.key.clone();
-        self.all_keys.push(kv.key.clone());
-        self.block_builder.add(kv)?;
+        // the newest version of a key is the first added
+        if value.is_some() && (self.num_keys == 0 || key != &self.last_key[..]) {
+            self.block_num_live_keys += 1;
+        }
+        self.last_key.clear();
+        self.last_key.extend_from_slice(key);
+        self.last_timestamp = timestamp;
+        self.num_keys += 1;
+        self.sequence_range = Some(match self.sequence_range {
+            Some((min, max)) => (min.min(timestamp), max.max(timestamp)),
+            None => (timestamp, timestamp),
+        });
+        match self.expected_num_keys {
+            Some(expected_num_keys) => {
+                debug_assert!(self.num_keys <= expected_num_keys, "more keys than expected added to the SST");
+                let bloom_false_positive_rate = self.bloom_false_positive_rate;
+                self.bloom_filter
+                    .get_or_insert_with(|| BloomFilter::with_capacity(expected_num_keys, bloom_false_positive_rate))
+                    .insert(key);
+            }
+            None => self.key_hashes.push(BloomFilter::key_hash(key)),
+        }
+        self.block_builder.add(key, &self.value_buffer)?;
         Ok(())
     }
 
-    pub fn finalize_block(&mut self) {
+    // blocks finished so far. the block being added to isn't counted
+    pub(crate) fn num_blocks(&self) -> usize {
+        self.block_meta_list.len()
+    }
+
+    // tag the value into value_buffer, moving it to the blob data if it's
+    // too large
+    fn encode_value(&mut self, key_len: usize, value: Option<&[u8]>) {
+        self.value_buffer.clear();
+        let Some(value) = value else {
+            self.value_buffer.push(VALUE_TAG_DELETE);
+            return;
+        };
+        let fits_in_block = 4 + key_len + 2 + 1 + value.len() <= MAX_BLOCK_DATA_BYTES;
+        if fits_in_block && self.blob_threshold.is_none_or(|blob_threshold| value.len() <= blob_threshold) {
+            self.value_buffer.push(VALUE_TAG_INLINE);
+            self.value_buffer.extend_from_slice(value);
+            return;
+        }
+        let offset = self.blob_data.len() as u64;
+        let len = u32::try_from(value.len()).expect("blob values must fit in 4 bytes");
+        self.blob_data.extend_from_slice(value);
+        self.value_buffer.push(VALUE_TAG_BLOB);
+        self.value_buffer.extend(offset.to_be_bytes());
+        self.value_buffer.extend(len.to_be_bytes());
+    }
+
+    pub(crate) fn finalize_block(&mut self) {
         // build block metadata
-        let block_meta =
-            BlockMetadata::new(self.meta_block_offset, self.first_key.clone(), self.last_key.clone());
+        let block_meta = BlockMetadata::new(
+            self.meta_block_offset,
+            TimestampedKey::new_with_timestamp(Bytes::copy_from_slice(&self.block_first_key), self.block_first_timestamp),
+            TimestampedKey::new_with_timestamp(Bytes::copy_from_slice(&self.last_key), self.last_timestamp),
+            Some(BlockStats {
+                num_live_keys: Some(std::mem::take(&mut self.block_num_live_keys)),
+                ..self.block_builder.get_stats()
+            }),
+            self.block_builder.get_restart_interval(),
+        );
         self.block_meta_list.push(block_meta);
         // build block
         let old_block_builder =
-            std::mem::replace(&mut self.block_builder, BlockBuilder::new(self.block_size));
+            std::mem::replace(
+                &mut self.block_builder,
+                BlockBuilder::new_with_restart_interval(self.block_size, self.restart_interval),
+            );
         let block = old_block_builder.build();
         self.block_data.extend(block.encode());
     }
 
-    pub fn build(mut self, id: usize, path: impl AsRef<Path>, block_cache: Option<Arc<BlockCache>>) -> Result<Sst> {
+    // the blob file, if any, is written next to the SST at the path given
+    // by blob_path
+    pub(crate) fn build(mut self, id: usize, path: impl AsRef<Path>, block_cache: Option<Arc<BlockCache>>) -> Result<Sst> {
+        let blob_data = std::mem::take(&mut self.blob_data);
+        // written first, so the SST never references values that aren't there
+        let blob_file = match blob_data.is_empty() {
+            true => None,
+            false => Some(BlobFile::create(blob_path(path.as_ref()), blob_data)?),
+        };
+        let (buffer, metadata) = self.encode();
+        // dump to file
+        let file = File::create(path, buffer)?;
+        let mut sst = Sst::new(id, file, metadata, block_cache);
+        sst.blob_file = blob_file;
+        Ok(sst)
+    }
+
+    // like build, but the SST is only ever kept in memory. path just names it
+    pub(crate) fn build_in_memory(mut self, id: usize, path: impl AsRef<Path>, block_cache: Option<Arc<BlockCache>>) -> Result<Sst> {
+        let blob_data = std::mem::take(&mut self.blob_data);
+        let blob_file = match blob_data.is_empty() {
+            true => None,
+            false => Some(BlobFile::create_in_memory(blob_path(path.as_ref()), blob_data)),
+        };
+        let (buffer, metadata) = self.encode();
+        let file = File::create_in_memory(path, buffer)?;
+        let mut sst = Sst::new(id, file, metadata, block_cache);
+        sst.blob_file = blob_file;
+        Ok(sst)
+    }
+
+    fn encode(mut self) -> (Vec<u8>, SstMetadata) {
+        let format_version = SST_FORMAT_VERSION_LIVE_KEYS;
         // finalize last block
         self.finalize_block();
 
@@ -77,35 +281,63 @@ impl SSTBuilder {
         let mut buffer: Vec<u8> = Vec::new();
         buffer.extend(self.block_data);
 
+        // the block index, as one partition per run of
+        // index_partition_num_blocks blocks if there are more
         self.meta_block_offset = u32::try_from(buffer.len()).expect("size of SST must fit in 4 bytes");
-        for block_meta in self.block_meta_list.iter() {
-            buffer.extend(block_meta.encode());
+        let partition_num_blocks = self
+            .index_partition_num_blocks
+            .filter(|partition_num_blocks| self.block_meta_list.len() > *partition_num_blocks)
+            .unwrap_or(self.block_meta_list.len());
+        let mut partitions = Vec::new();
+        for (partition_index, meta_blocks) in self.block_meta_list.chunks(partition_num_blocks).enumerate() {
+            let meta_offset = u32::try_from(buffer.len()).expect("size of SST must fit in 4 bytes");
+            for block_meta in meta_blocks {
+                buffer.extend(block_meta.encode());
+            }
+            let meta_len = buffer.len() as u32 - meta_offset;
+            let first_block_index = (partition_index * partition_num_blocks) as u32;
+            partitions.push(IndexPartition::new(meta_offset, meta_len, first_block_index, meta_blocks));
         }
+        // the top-level index, left empty for an index of one partition
+        let top_index_offset = u32::try_from(buffer.len()).expect("size of SST must fit in 4 bytes");
+        let index = match partitions.len() {
+            1 => BlockIndex::Full(self.block_meta_list),
+            _ => {
+                for partition in partitions.iter() {
+                    buffer.extend(partition.encode());
+                }
+                BlockIndex::new_partitioned(partitions)
+            }
+        };
+        buffer.extend(top_index_offset.to_be_bytes());
         buffer.extend(self.meta_block_offset.to_be_bytes());
 
         // build bloom filter
-        let mut bloom_filter = BloomFilter::from_keys(self.all_keys);
+        let mut bloom_filter = match self.bloom_filter {
+            Some(bloom_filter) => bloom_filter,
+            None => BloomFilter::from_key_hashes_with_false_positive_rate(&self.key_hashes, self.bloom_false_positive_rate),
+        };
         let encoded_bloom = bloom_filter.encode();
         let bloom_filter_offset = u32::try_from(buffer.len()).expect("bloom offset must fit in 4 bytes");
         
         buffer.extend(encoded_bloom);
         buffer.extend(bloom_filter_offset.to_be_bytes());
+        let (min_sequence, max_sequence) = self.fixed_sequence_range.or(self.sequence_range).unwrap_or((0, 0));
+        buffer.extend(min_sequence.to_be_bytes());
+        buffer.extend(max_sequence.to_be_bytes());
+        buffer.extend(format_version.to_be_bytes());
+        buffer.extend(SST_MAGIC.to_be_bytes());
 
-        // dump to file
-        let file = File::create(path, buffer)?;
-        Ok(
-            Sst::new(
-                id, 
-                file, 
-                self.block_meta_list,
-                self.meta_block_offset,
-                block_cache,
-                bloom_filter,
-            )
-        )
-    }
-
-    pub fn get_estimated_size(&self) -> usize {
+        let metadata = SstMetadata::new(index, self.meta_block_offset, bloom_filter);
+        (buffer, metadata)
+    }
+
+    // first and last key added, None before any are
+    pub(crate) fn get_key_range(&self) -> Option<(Bytes, Bytes)> {
+        (self.num_keys > 0).then(|| (self.first_key.clone(), Bytes::copy_from_slice(&self.last_key)))
+    }
+
+    pub(crate) fn get_estimated_size(&self) -> usize {
         // just return size of block data in bytes
         // (metadata size is negligible)
         self.block_data.len()
@@ -114,34 +346,42 @@ impl SSTBuilder {
 
 #[cfg(test)]
 mod tests {
+    use std::sync::Arc;
+
+    use bytes::Bytes;
     use tempfile::tempdir;
 
-    use crate::kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey};
+    use crate::{
+        block::builder::MAX_BLOCK_DATA_BYTES,
+        kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
+    };
+
+    use crate::table::{bloom::BloomFilter, iterator::SSTIterator, File, Sst, SST_FORMAT_VERSION_LIVE_KEYS, SST_MAGIC};
 
     use super::SSTBuilder;
 
     #[test]
     fn test_build() {
-        let mut builder: SSTBuilder = SSTBuilder::new(25);
+        let mut builder: SSTBuilder = SSTBuilder::new(27);
         assert!(builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k1".as_bytes().into()),
-                value: "v1".as_bytes().into(),
-            })
+            .add(&KeyValuePair::new(
+                TimestampedKey::new("k1".as_bytes().into()),
+                "v1".as_bytes().into(),
+            ))
             .is_ok());
         assert_eq!(builder.block_meta_list.len(), 0);
         assert!(builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k2".as_bytes().into()),
-                value: "v2".as_bytes().into(),
-            })
+            .add(&KeyValuePair::new(
+                TimestampedKey::new("k2".as_bytes().into()),
+                "v2".as_bytes().into(),
+            ))
             .is_ok());
         assert_eq!(builder.block_meta_list.len(), 0);
         assert!(builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k3".as_bytes().into()),
-                value: "v3".as_bytes().into(),
-            })
+            .add(&KeyValuePair::new(
+                TimestampedKey::new("k3".as_bytes().into()),
+                "v3".as_bytes().into(),
+            ))
             .is_ok());
         // new block started
         assert_eq!(builder.block_meta_list.len(), 1);
@@ -150,21 +390,184 @@ mod tests {
         // try build
         let dir = tempdir().unwrap();
         let path = dir.path().join("test_sst_build.sst");
-        let mut sst = builder.build(0, path, None).unwrap();
+        let sst = builder.build(0, path, None).unwrap();
         let file_contents: Vec<u8> = sst.file.get_contents_as_bytes().unwrap();
 
+        // footer ends with the sequence range, format version and magic number
+        let version_start = file_contents.len() - 8;
+        let version = u32::from_be_bytes(file_contents[version_start..version_start+4].try_into().expect("chunk of size 4"));
+        let magic = u32::from_be_bytes(file_contents[version_start+4..].try_into().expect("chunk of size 4"));
+        assert_eq!(version, SST_FORMAT_VERSION_LIVE_KEYS);
+        assert_eq!(magic, SST_MAGIC);
+        let footer_start = version_start - 16;
+
         // check that data size, meta size, and offset value are correct
-        let bloom_offset = u32::from_be_bytes(file_contents[file_contents.len()-4..].try_into().expect("chunk of size 4"));
+        let bloom_offset = u32::from_be_bytes(file_contents[footer_start-4..footer_start].try_into().expect("chunk of size 4"));
         let meta_offset = u32::from_be_bytes(file_contents[bloom_offset as usize-4..bloom_offset as usize].try_into().expect("chunk of size 4"));
 
         let expected_data_size = file_contents.len() 
-        - (file_contents.len() - bloom_offset as usize) // size of bloom filter + offset
+        - (file_contents.len() - bloom_offset as usize) // size of bloom filter + offset + footer
         - 4 // size of meta_offset
-        - 2 * 12; // two metadata blocks of 12 bytes each (4 for offset, 4 each for first and last key)
+        - 4 // size of top-level index offset, with an empty top-level index
+        - 2 * 32; // two metadata blocks of 32 bytes each (4 for offset, 4 each for first and last key, 16 for stats, 4 for restart interval)
         // start index of meta blocks should be equal to data size in bytes
         assert_eq!(meta_offset, u32::try_from(expected_data_size).expect("must fit in 4 bytes"));
 
         // assert correctness of meta offset field in sst struct
-        assert_eq!(meta_offset, sst.meta_block_offset);
+        assert_eq!(meta_offset, sst.metadata().unwrap().meta_block_offset);
+    }
+
+    #[test]
+    fn test_build_rejects_unordered_keys() {
+        let mut builder: SSTBuilder = SSTBuilder::new(27);
+        let kv = |key: &'static str, timestamp| KeyValuePair::new(
+            TimestampedKey::new_with_timestamp(key.as_bytes().into(), timestamp),
+            "v".as_bytes().into(),
+        );
+        builder.add(&kv("k2", 1)).unwrap();
+        // older versions of the same key come after newer ones
+        builder.add(&kv("k2", 0)).unwrap();
+        assert!(builder.add(&kv("k2", 0)).is_err());
+        assert!(builder.add(&kv("k2", 2)).is_err());
+        assert!(builder.add(&kv("k1", 0)).is_err());
+        builder.add(&kv("k3", 5)).unwrap();
+    }
+
+    #[test]
+    fn test_expected_num_keys() {
+        let mut builder: SSTBuilder = SSTBuilder::new(27);
+        builder.set_expected_num_keys(10);
+        for key in ["k1", "k2", "k3"] {
+            builder
+                .add(&KeyValuePair::new(TimestampedKey::new(key.as_bytes().into()), "v".as_bytes().into()))
+                .unwrap();
+        }
+        // the filter is filled as keys come, instead of keeping them
+        assert!(builder.key_hashes.is_empty());
+        assert!(builder.bloom_filter.is_some());
+
+        let dir = tempdir().unwrap();
+        let sst = builder.build(0, dir.path().join("test_expected_num_keys.sst"), None).unwrap();
+        sst.validate().unwrap();
+        for key in ["k1", "k2", "k3"] {
+            assert!(sst.maybe_contains_key(key.as_bytes()).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_key_hashes() {
+        let keys: Vec<String> = (0..500).map(|i| format!("key{:04}", i)).collect();
+        let build = |expected_num_keys: Option<usize>| {
+            let mut builder: SSTBuilder = SSTBuilder::new(4096);
+            if let Some(expected_num_keys) = expected_num_keys {
+                builder.set_expected_num_keys(expected_num_keys);
+            }
+            for key in keys.iter() {
+                builder.add_entry(key.as_bytes(), 0, Some(b"v")).unwrap();
+            }
+            builder
+        };
+        // only hashes are kept without an expected number of keys
+        let hashed = build(None);
+        assert_eq!(hashed.key_hashes.len(), keys.len());
+        let (hashed_file, mut hashed_metadata) = hashed.encode();
+        // the filter has the same bits as one built from the keys, or filled
+        // as keys come, so the whole file is the same
+        let timestamped_keys = keys.iter().map(|key| TimestampedKey::new(Bytes::copy_from_slice(key.as_bytes())));
+        let mut from_keys = BloomFilter::from_keys(timestamped_keys.collect());
+        assert_eq!(hashed_metadata.bloom_filter.get_mut().unwrap().encode(), from_keys.encode());
+        let (incremental_file, _) = build(Some(keys.len())).encode();
+        assert_eq!(hashed_file, incremental_file);
+    }
+
+    #[test]
+    fn test_sequence_range() {
+        let kv = |key: &'static str, timestamp| KeyValuePair::new(
+            TimestampedKey::new_with_timestamp(key.as_bytes().into(), timestamp),
+            "v".as_bytes().into(),
+        );
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test_sequence_range.sst");
+        let mut builder: SSTBuilder = SSTBuilder::new(27);
+        for (key, timestamp) in [("k1", 7), ("k2", 9), ("k2", 3), ("k3", 5)] {
+            builder.add(&kv(key, timestamp)).unwrap();
+        }
+        let sst = builder.build(0, &path, None).unwrap();
+        assert_eq!(sst.get_sequence_range(), (3, 9));
+        // read back from the footer
+        let sst = Sst::open(0, path, None).unwrap();
+        sst.validate().unwrap();
+        assert_eq!(sst.get_sequence_range(), (3, 9));
+
+        // an explicit range wins over the timestamps of the keys
+        let mut builder: SSTBuilder = SSTBuilder::new(27);
+        builder.set_sequence_range(2, 20);
+        builder.add(&kv("k1", 0)).unwrap();
+        let sst = builder.build_in_memory(1, "in_memory.sst", None).unwrap();
+        assert_eq!(sst.get_sequence_range(), (2, 20));
+
+        // files without a recorded range are older than everything else
+        let legacy = dir.path().join("legacy.sst");
+        let contents = sst.file.get_contents_as_bytes().unwrap();
+        let footer_start = contents.len() - 8 - 16;
+        std::fs::write(&legacy, &contents[..footer_start]).unwrap();
+        assert_eq!(File::open(&legacy).unwrap().get_sequence_range(), (0, 0));
+    }
+
+    #[test]
+    fn test_entries_larger_than_block_size() {
+        let kv = |key: &str, value_len| {
+            KeyValuePair::new(TimestampedKey::new(key.as_bytes().to_vec().into()), vec![7; value_len].into())
+        };
+        let mut builder: SSTBuilder = SSTBuilder::new(64);
+        builder.add(&kv("k1", 1)).unwrap();
+        // gets a block of its own, the block before it is finished first
+        builder.add(&kv("k2", 200)).unwrap();
+        assert_eq!(builder.num_blocks(), 1);
+        builder.add(&kv("k3", 1)).unwrap();
+        assert_eq!(builder.num_blocks(), 2);
+        // too large for any block, so it goes to the blob file even though
+        // there's no blob threshold
+        builder.add(&kv("k4", 100_000)).unwrap();
+        builder.add(&kv("k5", 1)).unwrap();
+        // too long a key is an error, not a broken SST
+        assert!(builder.add(&kv(&"k".repeat(MAX_BLOCK_DATA_BYTES), 1)).is_err());
+
+        let dir = tempdir().unwrap();
+        let sst = Arc::new(builder.build(0, dir.path().join("test_large_entries.sst"), None).unwrap());
+        sst.validate().unwrap();
+        assert_eq!(sst.metadata().unwrap().num_blocks(), 3);
+        assert_eq!(sst.get_blob_file_size(), 100_000);
+        let entries: Vec<(Bytes, usize)> = SSTIterator::create_and_seek_to_first(sst)
+            .unwrap()
+            .map(|kv| (kv.key.get_key(), kv.value.len()))
+            .collect();
+        let expected = [("k1", 1), ("k2", 200), ("k3", 1), ("k4", 100_000), ("k5", 1)];
+        assert_eq!(entries, expected.map(|(key, len)| (Bytes::from(key), len)));
+    }
+
+    #[test]
+    fn test_add_entry() {
+        let mut builder: SSTBuilder = SSTBuilder::new(27);
+        assert_eq!(builder.get_key_range(), None);
+        builder.add_entry(b"k1", 3, Some(b"v1")).unwrap();
+        builder.add_entry(b"k1", 2, None).unwrap();
+        assert!(builder.add_entry(b"k1", 2, Some(b"v1")).is_err());
+        for key in ["k2", "k3", "k4"] {
+            builder.add_entry(key.as_bytes(), 0, Some(b"v")).unwrap();
+        }
+        assert!(builder.num_blocks() > 0);
+        // the SST's range, not the current block's
+        assert_eq!(builder.get_key_range(), Some((Bytes::from("k1"), Bytes::from("k4"))));
+
+        let sst = Arc::new(builder.build_in_memory(0, "test_add_entry.sst", None).unwrap());
+        let entries: Vec<(TimestampedKey, bool)> = SSTIterator::create_and_seek_to_first(sst.clone())
+            .unwrap()
+            .map(|kv| (kv.key.clone(), kv.is_tombstone()))
+            .collect();
+        assert_eq!(entries[0], (TimestampedKey::new_with_timestamp("k1".into(), 0), false));
+        assert!(entries[1].1);
+        assert_eq!(entries.len(), 5);
+        assert_eq!(sst.get_sequence_range(), (0, 3));
     }
 }
@@ -1,80 +1,288 @@
-use std::os::unix::prelude::FileExt;
-use std::{io::Read, path::Path};
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::{path::Path, time::SystemTime};
 
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
 
 use crate::block::metadata::BlockMetadata;
 use crate::block::Block;
+use crate::error::LsmError;
+use crate::platform::{read_exact_at, system_time_now};
 
 use super::bloom::BloomFilter;
-pub struct File {
-    file: std::fs::File,
+use super::index::IndexPartition;
+use super::table_cache::TableCache;
+use super::{
+    SST_FORMAT_VERSION, SST_FORMAT_VERSION_LEGACY, SST_FORMAT_VERSION_PARTITIONED_INDEX, SST_FORMAT_VERSION_SEQUENCES,
+    SST_MAGIC,
+};
+
+pub(crate) struct File {
+    path: PathBuf,
+    handle: FileHandle,
     size: u64,
+    format_version: u32,
+    // end of the bloom filter offset; everything past it is the versioned footer
+    footer_offset: u64,
+    // min and max sequence numbers of the entries, from the footer
+    sequence_range: (u64, u64),
+}
+
+enum FileHandle {
+    // held for the lifetime of the File
+    Open(Arc<std::fs::File>),
+    // opened on demand and closed when the cache evicts it. a handle that is
+    // in use stays open until the read finishes
+    Cached { id: usize, table_cache: Arc<TableCache> },
+    // the whole file, for stores that never touch the disk
+    Memory { data: Bytes, modified: Mutex<SystemTime> },
 }
 
 impl File {
-    pub fn create(path: impl AsRef<Path>, data: Vec<u8>) -> Result<Self> {
+    pub(crate) fn create(path: impl AsRef<Path>, data: Vec<u8>) -> Result<Self> {
         std::fs::write(&path, &data)?;
-        let file = std::fs::File::open(path)?; // read-only mode
-        let size = file.metadata()?.len();
-        Ok(Self { file, size })
+        let file = std::fs::File::open(&path)?; // read-only mode
+        // make sure the SST is durable before it is recorded in the manifest
+        file.sync_all()?;
+        Self::from_file(path.as_ref().to_owned(), file)
+    }
+
+    pub(crate) fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let file = std::fs::File::open(&path)?;
+        Self::from_file(path.as_ref().to_owned(), file)
+    }
+
+    // path is only used to tell the file apart in errors
+    pub(crate) fn create_in_memory(path: impl AsRef<Path>, data: Vec<u8>) -> Result<Self> {
+        let size = data.len() as u64;
+        let handle = FileHandle::Memory {
+            data: Bytes::from(data),
+            modified: Mutex::new(system_time_now()),
+        };
+        Self::from_handle(path.as_ref().to_owned(), handle, size)
     }
 
-    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
-        let file = std::fs::File::open(path)?;
+    fn from_file(path: PathBuf, file: std::fs::File) -> Result<Self> {
         let size = file.metadata()?.len();
-        Ok(Self { file, size })
+        Self::from_handle(path, FileHandle::Open(Arc::new(file)), size)
+    }
+
+    fn from_handle(path: PathBuf, handle: FileHandle, size: u64) -> Result<Self> {
+        let mut file = Self {
+            path,
+            handle,
+            size,
+            format_version: SST_FORMAT_VERSION_LEGACY,
+            footer_offset: size,
+            sequence_range: (0, 0),
+        };
+        (file.format_version, file.footer_offset) = file.read_footer()?;
+        if file.format_version >= SST_FORMAT_VERSION_SEQUENCES {
+            file.sequence_range = file.read_sequence_range()?;
+        }
+        Ok(file)
+    }
+
+    // versioned files end with | format version (u32) | magic (u32) |.
+    // files written before the footer existed end with the bloom filter offset
+    fn read_footer(&self) -> Result<(u32, u64)> {
+        let size = self.size;
+        if size < 8 {
+            return Ok((SST_FORMAT_VERSION_LEGACY, size));
+        }
+        let mut buffer = [0; 4];
+        self.read_exact_at(&mut buffer, size - 4)?;
+        if u32::from_be_bytes(buffer) != SST_MAGIC {
+            return Ok((SST_FORMAT_VERSION_LEGACY, size));
+        }
+        self.read_exact_at(&mut buffer, size - 8)?;
+        let format_version = u32::from_be_bytes(buffer);
+        if format_version > SST_FORMAT_VERSION {
+            return Err(anyhow!("unsupported sst format version {}", format_version));
+        }
+        if format_version >= SST_FORMAT_VERSION_SEQUENCES {
+            if size < 8 + 16 {
+                return Err(LsmError::Corruption(format!("sst {:?} is too short for its footer", self.path)).into());
+            }
+            return Ok((format_version, size - 8 - 16));
+        }
+        Ok((format_version, size - 8))
+    }
+
+    // | min sequence (u64) | max sequence (u64) | right before the format version
+    fn read_sequence_range(&self) -> Result<(u64, u64)> {
+        let mut buffer = [0; 16];
+        self.read_exact_at(&mut buffer, self.footer_offset)?;
+        let min = u64::from_be_bytes(buffer[..8].try_into().expect("chunk of size 8"));
+        let max = u64::from_be_bytes(buffer[8..].try_into().expect("chunk of size 8"));
+        if min > max {
+            return Err(
+                LsmError::Corruption(format!("sst {:?} has sequence range {}..={}", self.path, min, max)).into()
+            );
+        }
+        Ok((min, max))
+    }
+
+    // hand the open handle over to table_cache, which reopens the file under
+    // id whenever it isn't cached
+    pub(crate) fn set_table_cache(&mut self, id: usize, table_cache: Arc<TableCache>) {
+        match &self.handle {
+            FileHandle::Open(file) => table_cache.insert(id, file.clone()),
+            // nothing to close
+            FileHandle::Memory { .. } => return,
+            FileHandle::Cached { .. } => {}
+        }
+        self.handle = FileHandle::Cached { id, table_cache };
+    }
+
+    fn handle(&self) -> Result<Arc<std::fs::File>> {
+        match &self.handle {
+            FileHandle::Open(file) => Ok(file.clone()),
+            FileHandle::Cached { id, table_cache } => table_cache
+                .try_get_with(*id, || std::fs::File::open(&self.path).map(Arc::new))
+                .map_err(|err| anyhow!(err)),
+            FileHandle::Memory { .. } => Err(anyhow!("in-memory file {:?} has no handle", self.path)),
+        }
+    }
+
+    fn read_exact_at(&self, buffer: &mut [u8], offset: u64) -> Result<()> {
+        if let FileHandle::Memory { data, .. } = &self.handle {
+            let range = usize::try_from(offset)
+                .ok()
+                .and_then(|start| Some(start..start.checked_add(buffer.len())?))
+                .filter(|range| range.end <= data.len())
+                .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            buffer.copy_from_slice(&data[range]);
+            return Ok(());
+        }
+        Ok(read_exact_at(&*self.handle()?, buffer, offset)?)
+    }
+
+    pub(crate) fn get_format_version(&self) -> u32 {
+        self.format_version
+    }
+
+    pub(crate) fn get_sequence_range(&self) -> (u64, u64) {
+        self.sequence_range
     }
 
-    pub fn get_contents_as_bytes(&mut self) -> Result<Vec<u8>> {
-        let mut bytes: Vec<u8> = Vec::new();
-        self.file.read_to_end(&mut bytes)?;
+    #[cfg(test)]
+    pub(crate) fn get_contents_as_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes: Vec<u8> = vec![0; self.size.try_into()?];
+        self.read_exact_at(&mut bytes, 0)?;
         Ok(bytes)
     }
 
-    pub fn get_size(&self) -> u64 {
+    pub(crate) fn get_size(&self) -> u64 {
         self.size
     }
 
-    pub fn load_block_to_mem(&self, offset: u32, block_size: u32) -> Result<Block> {
+    pub(crate) fn get_modified_time(&self) -> Result<SystemTime> {
+        match &self.handle {
+            FileHandle::Memory { modified, .. } => Ok(*modified.lock().unwrap()),
+            _ => Ok(self.handle()?.metadata()?.modified()?),
+        }
+    }
+
+    pub(crate) fn set_modified_time(&self, time: SystemTime) -> Result<()> {
+        if let FileHandle::Memory { modified, .. } = &self.handle {
+            *modified.lock().unwrap() = time;
+            return Ok(());
+        }
+        // the read handle can't change the file's times
+        let file = std::fs::File::options().write(true).open(&self.path)?;
+        file.set_modified(time)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    pub(crate) fn load_block_to_mem(&self, offset: u32, block_size: u32, restart_interval: usize) -> Result<Block> {
         let mut buffer = vec![0; block_size.try_into()?];
-        self.file.read_exact_at(&mut buffer, offset.into())?;
-        let block = Block::decode(buffer);
-        Ok(block)
+        self.read_exact_at(&mut buffer, offset.into())?;
+        Block::try_decode(buffer, restart_interval).ok_or_else(|| {
+            LsmError::Corruption(format!("block at offset {} of sst {:?} is malformed", offset, self.path)).into()
+        })
     }
 
-    pub fn get_meta_block_offset(&mut self, bloom_filter_offset: u32) -> Result<u32> {
+    pub(crate) fn get_meta_block_offset(&self, bloom_filter_offset: u32) -> Result<u32> {
         // last 4 bytes of file
         let mut buffer = [0; 4];
-        self.file.read_exact_at(&mut buffer, bloom_filter_offset as u64 - 4)?;
+        let offset = (bloom_filter_offset as u64)
+            .checked_sub(4)
+            .ok_or_else(|| anyhow!("bloom filter offset {} is out of bounds", bloom_filter_offset))?;
+        self.read_exact_at(&mut buffer, offset)?;
+        Ok(u32::from_be_bytes(buffer))
+    }
+
+    // where the block index ends: at the top-level index of its partitions,
+    // see SST_FORMAT_VERSION_PARTITIONED_INDEX, or right before the block
+    // index offset in older files
+    pub(crate) fn get_meta_blocks_end(&self, bloom_filter_offset: u32) -> Result<u32> {
+        let out_of_bounds = || anyhow!("bloom filter offset {} is out of bounds", bloom_filter_offset);
+        if self.format_version < SST_FORMAT_VERSION_PARTITIONED_INDEX {
+            return bloom_filter_offset.checked_sub(4).ok_or_else(out_of_bounds);
+        }
+        let mut buffer = [0; 4];
+        let offset = bloom_filter_offset.checked_sub(8).ok_or_else(out_of_bounds)?;
+        self.read_exact_at(&mut buffer, offset.into())?;
         Ok(u32::from_be_bytes(buffer))
     }
 
-    pub fn load_meta_blocks(&mut self, meta_block_offset: u32, bloom_filter_offset: u32) -> Result<Vec<BlockMetadata>> {
-        // start of bloom filter - start of meta blocks - 4 bytes for meta_block_offset
-        let meta_encoded_length =
-            usize::try_from(bloom_filter_offset)? - usize::try_from(meta_block_offset)? - 4;
-        let mut buffer: Vec<u8> = vec![0; meta_encoded_length];
-        self.file
-            .read_exact_at(&mut buffer, meta_block_offset.into())?;
-        let block_metadata = BlockMetadata::decode_to_list(&buffer);
-        Ok(block_metadata)
+    // the metadata of the blocks encoded from start up to end
+    pub(crate) fn load_meta_blocks(&self, start: u32, end: u32) -> Result<Vec<BlockMetadata>> {
+        let buffer = self.read_index_range(start, end)?;
+        BlockMetadata::decode_to_list(&buffer, self.format_version).ok_or_else(|| {
+            LsmError::Corruption(format!("block index of sst {:?} is truncated", self.path)).into()
+        })
     }
 
-    pub fn get_bloom_filter_offset(&mut self) -> Result<u32> {
-        // last 4 bytes of file
+    // the top-level index of the block index's partitions, empty unless it
+    // is partitioned
+    pub(crate) fn load_index_partitions(&self, bloom_filter_offset: u32) -> Result<Vec<IndexPartition>> {
+        if self.format_version < SST_FORMAT_VERSION_PARTITIONED_INDEX {
+            return Ok(Vec::new());
+        }
+        let start = self.get_meta_blocks_end(bloom_filter_offset)?;
+        let buffer = self.read_index_range(start, bloom_filter_offset - 8)?;
+        IndexPartition::decode_to_list(&buffer).ok_or_else(|| {
+            LsmError::Corruption(format!("top-level index of sst {:?} is truncated", self.path)).into()
+        })
+    }
+
+    fn read_index_range(&self, start: u32, end: u32) -> Result<Vec<u8>> {
+        let len = end
+            .checked_sub(start)
+            .ok_or_else(|| anyhow!("index offset {} is out of bounds", start))?;
+        let mut buffer: Vec<u8> = vec![0; len as usize];
+        self.read_exact_at(&mut buffer, start.into())?;
+        Ok(buffer)
+    }
+
+    pub(crate) fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub(crate) fn get_bloom_filter_offset(&self) -> Result<u32> {
+        // last 4 bytes before the footer
         let mut buffer = [0; 4];
-        self.file.read_exact_at(&mut buffer, self.get_size() - 4)?;
+        let offset = self
+            .footer_offset
+            .checked_sub(4)
+            .ok_or_else(|| anyhow!("file is too small to be an sst"))?;
+        self.read_exact_at(&mut buffer, offset)?;
         Ok(u32::from_be_bytes(buffer))
     }
 
-    pub fn load_bloom_filter(&mut self, bloom_filter_offset: u32) -> Result<BloomFilter> {
-        // size of encoded file - size of data - 4 bytes for bloom_filter_offset
-        let bloom_encoded_length =
-            usize::try_from(self.size)? - usize::try_from(bloom_filter_offset)? - 4;
+    pub(crate) fn load_bloom_filter(&self, bloom_filter_offset: u32) -> Result<BloomFilter> {
+        // start of footer - size of data - 4 bytes for bloom_filter_offset
+        let bloom_encoded_length = usize::try_from(self.footer_offset)?
+            .checked_sub(usize::try_from(bloom_filter_offset)? + 4)
+            .filter(|len| *len > 0) // must at least contain the number of hash functions
+            .ok_or_else(|| anyhow!("bloom filter offset {} is out of bounds", bloom_filter_offset))?;
         let mut buffer: Vec<u8> = vec![0; bloom_encoded_length];
-        self.file
-            .read_exact_at(&mut buffer, bloom_filter_offset.into())?;
+        self.read_exact_at(&mut buffer, bloom_filter_offset.into())?;
         Ok(BloomFilter::decode(buffer))
     }
 }
@@ -84,8 +292,12 @@ mod tests {
     use tempfile::tempdir;
 
     use crate::{
-        block::{builder::BlockBuilder, metadata::BlockMetadata},
-        kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
+        block::{
+            builder::BlockBuilder,
+            metadata::{BlockMetadata, BlockStats},
+            DEFAULT_RESTART_INTERVAL,
+        },
+        kv::timestamped_key::TimestampedKey,
         table::{file::File, test_utils::build_sst},
     };
 
@@ -93,16 +305,10 @@ mod tests {
     fn test_load_block_to_mem() {
         let mut block_builder = BlockBuilder::new(32);
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k1".as_bytes().into()),
-                value: "v1".as_bytes().into()
-            })
+            .add("k1".as_bytes(), "v1".as_bytes())
             .is_ok());
         assert!(block_builder
-            .add(KeyValuePair {
-                key: TimestampedKey::new("k2".as_bytes().into()),
-                value: "v2".as_bytes().into()
-            })
+            .add("k2".as_bytes(), "v2".as_bytes())
             .is_ok());
         // 8 bytes for first kv pair; 9 bytes for subsequent kv pairs
         // 2 * 2 bytes per offset
@@ -119,7 +325,7 @@ mod tests {
 
         let loaded_block = file
             .unwrap()
-            .load_block_to_mem(0, expected_block_size.try_into().unwrap());
+            .load_block_to_mem(0, expected_block_size.try_into().unwrap(), DEFAULT_RESTART_INTERVAL);
         assert!(loaded_block.is_ok());
         assert_eq!(loaded_block.unwrap(), block);
     }
@@ -127,21 +333,37 @@ mod tests {
     #[test]
     fn test_load_meta_blocks() {
         let sst = build_sst();
-        let mut file = sst.file;
+        let file = sst.file;
         let bloom_filter_offset = file.get_bloom_filter_offset().unwrap();
         let meta_block_offset = file.get_meta_block_offset(bloom_filter_offset).unwrap();
-        assert_eq!(meta_block_offset, 35);
+        assert_eq!(meta_block_offset, 38);
 
-        let meta_blocks = file.load_meta_blocks(meta_block_offset, bloom_filter_offset).unwrap();
+        let meta_blocks_end = file.get_meta_blocks_end(bloom_filter_offset).unwrap();
+        let meta_blocks = file.load_meta_blocks(meta_block_offset, meta_blocks_end).unwrap();
         let expected_meta_1 = BlockMetadata::new(
             0,
             TimestampedKey::new("k1".as_bytes().into()),
             TimestampedKey::new("k2".as_bytes().into()),
+            Some(BlockStats {
+                num_entries: 2,
+                // the stored values, with their tag
+                min_value_len: 3,
+                max_value_len: 3,
+                num_live_keys: Some(2),
+            }),
+            DEFAULT_RESTART_INTERVAL,
         );
         let expected_meta_2 = BlockMetadata::new(
-            23,
+            25,
             TimestampedKey::new("k3".as_bytes().into()),
             TimestampedKey::new("k3".as_bytes().into()),
+            Some(BlockStats {
+                num_entries: 1,
+                min_value_len: 3,
+                max_value_len: 3,
+                num_live_keys: Some(1),
+            }),
+            DEFAULT_RESTART_INTERVAL,
         );
 
         assert_eq!(meta_blocks.len(), 2);
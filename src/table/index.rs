@@ -0,0 +1,302 @@
+use std::{
+    ops::Bound,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Result;
+use bytes::Bytes;
+
+use crate::{
+    block::metadata::{read_u16, read_u32, take, BlockMetadata},
+    error::LsmError,
+    kv::timestamped_key::TimestampedKey,
+    utils::range_overlap,
+};
+
+use super::file::File;
+
+// index of a partition and the metadata of its blocks
+type LastReadPartition = Mutex<Option<(usize, Arc<Vec<BlockMetadata>>)>>;
+
+// the block index of an SST. the block metadata of SSTs with many blocks is
+// split into partitions, and only a top-level index of the partitions is kept
+// in memory. partitions are read from the file as lookups need them
+pub(crate) enum BlockIndex {
+    Full(Vec<BlockMetadata>),
+    Partitioned {
+        partitions: Vec<IndexPartition>,
+        num_blocks: usize,
+        // the partition read last, so that lookups near each other and scans
+        // don't read it again. at most one is kept, whatever the SST's size
+        last_read: LastReadPartition,
+    },
+}
+
+// a run of consecutive blocks in the top-level index, encoded as
+// | meta offset (u32) | meta len (u32) | first block index (u32) |
+// | num blocks (u32) | block offset (u32) | first key len (u16) | first key |
+// | last key len (u16) | last key |
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct IndexPartition {
+    // where the metadata of the partition's blocks is in the file
+    meta_offset: u32,
+    meta_len: u32,
+    first_block_index: u32,
+    num_blocks: u32,
+    // where the partition's first block is in the file
+    block_offset: u32,
+    first_key: TimestampedKey,
+    last_key: TimestampedKey,
+}
+
+impl IndexPartition {
+    // meta_blocks must not be empty
+    pub(crate) fn new(meta_offset: u32, meta_len: u32, first_block_index: u32, meta_blocks: &[BlockMetadata]) -> Self {
+        let (first, last) = (&meta_blocks[0], &meta_blocks[meta_blocks.len() - 1]);
+        Self {
+            meta_offset,
+            meta_len,
+            first_block_index,
+            num_blocks: meta_blocks.len() as u32,
+            block_offset: first.get_offset(),
+            first_key: first.get_first_key(),
+            last_key: last.get_last_key(),
+        }
+    }
+
+    // in memory, including the keys
+    pub(crate) fn size_bytes(&self) -> usize {
+        std::mem::size_of::<Self>() + self.first_key.get_key().len() + self.last_key.get_key().len()
+    }
+
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for field in [self.meta_offset, self.meta_len, self.first_block_index, self.num_blocks, self.block_offset] {
+            encoded.extend(field.to_be_bytes());
+        }
+        for key in [self.first_key.get_key(), self.last_key.get_key()] {
+            let key_size: u16 = key.len().try_into().expect("size must fit in 2 bytes");
+            encoded.extend(key_size.to_be_bytes());
+            encoded.extend(&key);
+        }
+        encoded
+    }
+
+    // None if the last partition is cut short
+    pub(crate) fn decode_to_list(encoded: &[u8]) -> Option<Vec<Self>> {
+        let mut index = 0;
+        let mut partitions = Vec::new();
+        while index < encoded.len() {
+            let meta_offset = read_u32(encoded, &mut index)?;
+            let meta_len = read_u32(encoded, &mut index)?;
+            let first_block_index = read_u32(encoded, &mut index)?;
+            let num_blocks = read_u32(encoded, &mut index)?;
+            let block_offset = read_u32(encoded, &mut index)?;
+            let first_key_size = read_u16(encoded, &mut index)?.into();
+            let first_key = Bytes::copy_from_slice(take(encoded, &mut index, first_key_size)?);
+            let last_key_size = read_u16(encoded, &mut index)?.into();
+            let last_key = Bytes::copy_from_slice(take(encoded, &mut index, last_key_size)?);
+            partitions.push(Self {
+                meta_offset,
+                meta_len,
+                first_block_index,
+                num_blocks,
+                block_offset,
+                first_key: TimestampedKey::new(first_key),
+                last_key: TimestampedKey::new(last_key),
+            });
+        }
+        Some(partitions)
+    }
+}
+
+impl BlockIndex {
+    // whether the partitions cover the blocks in order, each with at least one
+    pub(crate) fn is_contiguous(partitions: &[IndexPartition]) -> bool {
+        let mut num_blocks = 0;
+        partitions.iter().all(|partition| {
+            let is_next = partition.first_block_index == num_blocks && partition.num_blocks > 0;
+            num_blocks = num_blocks.saturating_add(partition.num_blocks);
+            is_next
+        })
+    }
+
+    // partitions must be contiguous
+    pub(crate) fn new_partitioned(partitions: Vec<IndexPartition>) -> Self {
+        let num_blocks = partitions.iter().map(|partition| partition.num_blocks as usize).sum();
+        BlockIndex::Partitioned {
+            partitions,
+            num_blocks,
+            last_read: Mutex::new(None),
+        }
+    }
+
+    pub(crate) fn num_blocks(&self) -> usize {
+        match self {
+            BlockIndex::Full(meta_blocks) => meta_blocks.len(),
+            BlockIndex::Partitioned { num_blocks, .. } => *num_blocks,
+        }
+    }
+
+    #[cfg(test)]
+    pub(crate) fn num_partitions(&self) -> usize {
+        match self {
+            BlockIndex::Full(_) => 0,
+            BlockIndex::Partitioned { partitions, .. } => partitions.len(),
+        }
+    }
+
+    // first key of the first block and last key of the last one, None without
+    // blocks
+    pub(crate) fn key_range(&self) -> Option<(TimestampedKey, TimestampedKey)> {
+        match self {
+            BlockIndex::Full(meta_blocks) => {
+                Some((meta_blocks.first()?.get_first_key(), meta_blocks.last()?.get_last_key()))
+            }
+            BlockIndex::Partitioned { partitions, .. } => {
+                Some((partitions.first()?.first_key.clone(), partitions.last()?.last_key.clone()))
+            }
+        }
+    }
+
+    // in memory, so a partitioned index counts the partition it holds on to
+    pub(crate) fn size_bytes(&self) -> usize {
+        match self {
+            BlockIndex::Full(meta_blocks) => meta_blocks.iter().map(|block_meta| block_meta.size_bytes()).sum(),
+            BlockIndex::Partitioned {
+                partitions, last_read, ..
+            } => {
+                let last_read_bytes = last_read.lock().unwrap().as_ref().map_or(0, |(_, meta_blocks)| {
+                    meta_blocks.iter().map(|block_meta| block_meta.size_bytes()).sum()
+                });
+                partitions.iter().map(|partition| partition.size_bytes()).sum::<usize>() + last_read_bytes
+            }
+        }
+    }
+
+    // the metadata of the blocks of a partition, read from the file unless it
+    // was the last read, and checked against the top-level index
+    fn read_partition(
+        file: &File,
+        partitions: &[IndexPartition],
+        last_read: &LastReadPartition,
+        partition_index: usize,
+    ) -> Result<Arc<Vec<BlockMetadata>>> {
+        if let Some((index, meta_blocks)) = last_read.lock().unwrap().as_ref() {
+            if *index == partition_index {
+                return Ok(meta_blocks.clone());
+            }
+        }
+        let partition = &partitions[partition_index];
+        let meta_blocks =
+            file.load_meta_blocks(partition.meta_offset, partition.meta_offset.saturating_add(partition.meta_len))?;
+        let matches = meta_blocks.len() == partition.num_blocks as usize
+            && meta_blocks.first().is_some_and(|first| {
+                first.get_offset() == partition.block_offset && first.get_first_key() == partition.first_key
+            })
+            && meta_blocks.last().is_some_and(|last| last.get_last_key() == partition.last_key);
+        if !matches {
+            return Err(LsmError::Corruption(format!(
+                "index partition {} of sst {:?} doesn't match the top-level index",
+                partition_index,
+                file.get_path()
+            ))
+            .into());
+        }
+        let meta_blocks = Arc::new(meta_blocks);
+        *last_read.lock().unwrap() = Some((partition_index, meta_blocks.clone()));
+        Ok(meta_blocks)
+    }
+
+    // the partition holding block_index and the block's index within it
+    fn locate(partitions: &[IndexPartition], block_index: usize) -> (usize, usize) {
+        let partition_index =
+            partitions.partition_point(|partition| partition.first_block_index as usize <= block_index) - 1;
+        (partition_index, block_index - partitions[partition_index].first_block_index as usize)
+    }
+
+    // block_index must be below num_blocks
+    pub(crate) fn block_meta(&self, file: &File, block_index: usize) -> Result<BlockMetadata> {
+        match self {
+            BlockIndex::Full(meta_blocks) => Ok(meta_blocks[block_index].clone()),
+            BlockIndex::Partitioned {
+                partitions, last_read, ..
+            } => {
+                let (partition_index, index) = Self::locate(partitions, block_index);
+                Ok(Self::read_partition(file, partitions, last_read, partition_index)?[index].clone())
+            }
+        }
+    }
+
+    // where a block starts in the file. the first block of a partition is
+    // found without reading the partition
+    pub(crate) fn block_offset(&self, file: &File, block_index: usize) -> Result<u32> {
+        if let BlockIndex::Partitioned { partitions, .. } = self {
+            let (partition_index, index) = Self::locate(partitions, block_index);
+            if index == 0 {
+                return Ok(partitions[partition_index].block_offset);
+            }
+        }
+        Ok(self.block_meta(file, block_index)?.get_offset())
+    }
+
+    // first block that can hold the newest version of key or anything after
+    // it. versions of a key may span blocks, so this goes by last keys, and
+    // the first partition whose last key isn't before key holds the block
+    pub(crate) fn block_index_for_key(&self, file: &File, key: &TimestampedKey) -> Result<usize> {
+        let key = key.get_key();
+        let find = |meta_blocks: &[BlockMetadata]| {
+            meta_blocks
+                .partition_point(|block_meta| block_meta.get_last_key().get_key() < key)
+                .min(meta_blocks.len() - 1)
+        };
+        match self {
+            BlockIndex::Full(meta_blocks) => Ok(find(meta_blocks)),
+            BlockIndex::Partitioned {
+                partitions, last_read, ..
+            } => {
+                let partition_index = partitions
+                    .partition_point(|partition| partition.last_key.get_key() < key)
+                    .min(partitions.len() - 1);
+                let meta_blocks = Self::read_partition(file, partitions, last_read, partition_index)?;
+                Ok(partitions[partition_index].first_block_index as usize + find(&meta_blocks))
+            }
+        }
+    }
+
+    // the blocks overlapping the range with their indexes. only the
+    // partitions overlapping it are read
+    pub(crate) fn blocks_in_range(
+        &self,
+        file: &File,
+        lower: Bound<&[u8]>,
+        upper: Bound<&[u8]>,
+    ) -> Result<Vec<(usize, BlockMetadata)>> {
+        let in_range = |first_block_index: usize, meta_blocks: &[BlockMetadata]| {
+            meta_blocks
+                .iter()
+                .enumerate()
+                .filter(|(_, block_meta)| {
+                    range_overlap(lower, upper, block_meta.get_first_key(), block_meta.get_last_key())
+                })
+                .map(|(index, block_meta)| (first_block_index + index, block_meta.clone()))
+                .collect::<Vec<_>>()
+        };
+        match self {
+            BlockIndex::Full(meta_blocks) => Ok(in_range(0, meta_blocks)),
+            BlockIndex::Partitioned {
+                partitions, last_read, ..
+            } => {
+                let mut blocks = Vec::new();
+                for (partition_index, partition) in partitions.iter().enumerate() {
+                    if !range_overlap(lower, upper, partition.first_key.clone(), partition.last_key.clone()) {
+                        continue;
+                    }
+                    let meta_blocks = Self::read_partition(file, partitions, last_read, partition_index)?;
+                    blocks.extend(in_range(partition.first_block_index as usize, &meta_blocks));
+                }
+                Ok(blocks)
+            }
+        }
+    }
+}
@@ -1,122 +1,259 @@
 use std::sync::Arc;
 
 use anyhow::Result;
+use bytes::Bytes;
 
 use crate::{
     block::iterator::BlockIterator,
-    iterator::StorageIterator,
+    iterator::{IteratorStats, StorageIterator},
     kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
+    platform::CAN_SPAWN_THREADS,
+    state::read_options::ReadOptions,
 };
 
-use super::Sst;
+use super::{prefetch::BlockPrefetcher, Sst, SstMetadata};
 
-pub struct SSTIterator {
+pub(crate) struct SSTIterator {
     sst: Arc<Sst>,
+    // held for the life of the iterator, even if the metadata cache evicts it
+    metadata: Arc<SstMetadata>,
     block_index: usize,
+    // holds the current entry, unless a block couldn't be read
     block_iterator: BlockIterator,
+    // the current entry with its value type and value resolved, except for a
+    // value in the blob file, which is left empty
     current_kv: Option<KeyValuePair>,
+    // where the current entry's value is in the blob file. it is only read
+    // when next returns the entry, so entries that are skipped or only
+    // peeked at never read their value
+    current_blob_reference: Option<Bytes>,
     is_valid: bool,
+    // set when a block can't be read mid-scan
+    error: Option<anyhow::Error>,
+    fill_cache: bool,
+    readahead_bytes: usize,
+    // reads the blocks after block_index when read-ahead is on
+    prefetcher: Option<BlockPrefetcher>,
+    // values are left empty without being read, see ReadOptions::keys_only
+    keys_only: bool,
 }
 
 impl SSTIterator {
-    pub fn create_and_seek_to_first(sst: Arc<Sst>) -> Result<Self> {
+    pub(crate) fn create_and_seek_to_first(sst: Arc<Sst>) -> Result<Self> {
+        Self::create_and_seek_to_first_with_options(sst, &ReadOptions::default())
+    }
+
+    pub(crate) fn create_and_seek_to_first_with_options(sst: Arc<Sst>, options: &ReadOptions) -> Result<Self> {
+        let metadata = sst.metadata()?;
         // load the first block
-        let block = sst.read_block_cached( 0)?;
-        let mut block_iterator = BlockIterator::create_and_seek_to_first(block);
-        let current_kv = block_iterator.peek();
-        Ok(Self {
+        let block = sst.read_block_for_scan(0, options.fill_cache)?;
+        let block_iterator = BlockIterator::create_and_seek_to_first(block).keys_only(options.keys_only);
+        let mut res = Self {
             sst,
+            metadata,
             block_index: 0,
             block_iterator,
-            current_kv,
+            current_kv: None,
+            current_blob_reference: None,
             is_valid: true,
-        })
+            error: None,
+            fill_cache: options.fill_cache,
+            readahead_bytes: options.readahead_bytes,
+            prefetcher: None,
+            keys_only: options.keys_only,
+        };
+        res.start_prefetch();
+        res.skip_exhausted_blocks()?;
+        res.resolve_current_value()?;
+        Ok(res)
     }
 
+    #[cfg(test)]
     pub fn create_and_seek_to_key(sst: Arc<Sst>, key: TimestampedKey) -> Result<Self> {
-        let block_index = sst.get_block_index_for_key(&key);
-        let block = sst.read_block_cached(block_index)?;
-        let mut block_iterator = BlockIterator::create_and_seek_to_key(block, key);
-        let current_kv = block_iterator.peek();
-        Ok(Self {
+        Self::create_and_seek_to_key_with_options(sst, key, &ReadOptions::default())
+    }
+
+    pub(crate) fn create_and_seek_to_key_with_options(
+        sst: Arc<Sst>,
+        key: TimestampedKey,
+        options: &ReadOptions,
+    ) -> Result<Self> {
+        let metadata = sst.metadata()?;
+        let block_index = sst.get_block_index_for_key(&key)?;
+        let block = sst.read_block_for_scan(block_index, options.fill_cache)?;
+        let block_iterator = BlockIterator::create_and_seek_to_key(block, key).keys_only(options.keys_only);
+        let mut res = Self {
             sst,
+            metadata,
             block_index,
             block_iterator,
-            current_kv,
+            current_kv: None,
+            current_blob_reference: None,
             is_valid: true,
-        })
+            error: None,
+            fill_cache: options.fill_cache,
+            readahead_bytes: options.readahead_bytes,
+            prefetcher: None,
+            keys_only: options.keys_only,
+        };
+        res.start_prefetch();
+        res.skip_exhausted_blocks()?;
+        res.resolve_current_value()?;
+        Ok(res)
     }
 
+    #[cfg(test)]
     pub fn seek_to_key(&mut self, key: TimestampedKey) -> Result<()> {
-        self.block_index = self.sst.get_block_index_for_key(&key);
-        let block = self.sst.read_block_cached(self.block_index)?;
-        self.block_iterator = BlockIterator::create_and_seek_to_key(block, key);
-        self.current_kv = self.block_iterator.peek();
+        self.block_index = self.sst.get_block_index_for_key(&key)?;
+        let block = self.sst.read_block_for_scan(self.block_index, self.fill_cache)?;
+        self.block_iterator = BlockIterator::create_and_seek_to_key(block, key).keys_only(self.keys_only);
+        self.start_prefetch();
+        self.skip_exhausted_blocks()?;
+        self.resolve_current_value()
+    }
+
+    // (re)start read-ahead from the block after the current one. the blocks
+    // are read on another thread, so there is none where threads can't be
+    // spawned
+    fn start_prefetch(&mut self) {
+        self.prefetcher = None;
+        if CAN_SPAWN_THREADS && self.readahead_bytes > 0 && self.block_index + 1 < self.metadata.num_blocks() {
+            self.prefetcher = Some(BlockPrefetcher::start(
+                self.sst.clone(),
+                &self.metadata,
+                self.block_index + 1,
+                self.readahead_bytes,
+                self.fill_cache,
+            ));
+        }
+    }
+
+    // the block iterator always points at the current entry. once a block is
+    // used up, move on to the next one (a seek can also land past the end of a
+    // block when the key is greater than everything in it)
+    fn skip_exhausted_blocks(&mut self) -> Result<()> {
+        while self.block_iterator.peek().is_none() && self.block_index + 1 < self.metadata.num_blocks() {
+            self.block_index += 1;
+            let block = match &self.prefetcher {
+                Some(prefetcher) => prefetcher.next_block(self.block_index)?,
+                None => self.sst.read_block_for_scan(self.block_index, self.fill_cache)?,
+            };
+            self.block_iterator = BlockIterator::create_and_seek_to_first(block).keys_only(self.keys_only);
+        }
+        Ok(())
+    }
+
+    fn resolve_current_value(&mut self) -> Result<()> {
+        self.current_kv = None;
+        self.current_blob_reference = None;
+        if let Some(kv) = self.block_iterator.peek() {
+            let (mut kv, blob_reference) = self.sst.resolve_entry_without_blob(kv)?;
+            if self.keys_only {
+                kv.value = Bytes::new();
+            } else {
+                self.current_blob_reference = blob_reference;
+            }
+            self.current_kv = Some(kv);
+        }
         Ok(())
     }
+
+    // the current entry with its value read, without moving past it, which
+    // could read the next block
+    pub(crate) fn read_current(&self) -> Result<Option<KeyValuePair>> {
+        let Some(kv) = self.peek() else {
+            return Ok(None);
+        };
+        let mut res = kv.clone();
+        if let Some(blob_reference) = &self.current_blob_reference {
+            res.value = self.sst.resolve_value(blob_reference.clone())?;
+        }
+        Ok(Some(res))
+    }
+
+    // move on to the entry after the current one
+    fn advance(&mut self) {
+        self.block_iterator.next();
+        if let Err(err) = self.skip_exhausted_blocks().and_then(|_| self.resolve_current_value()) {
+            self.is_valid = false;
+            self.error = Some(err);
+        }
+    }
 }
 
 impl StorageIterator for SSTIterator {
-    fn peek(&mut self) -> Option<KeyValuePair> {
-        self.current_kv.clone()
+    fn peek(&self) -> Option<&KeyValuePair> {
+        if !self.is_valid {
+            return None;
+        }
+        self.current_kv.as_ref()
     }
 
     fn is_valid(&self) -> bool {
         self.is_valid
     }
+
+    fn error(&self) -> Option<&anyhow::Error> {
+        self.error.as_ref()
+    }
+
+    // blocks waiting in the prefetch queue aren't counted
+    fn stats(&self) -> IteratorStats {
+        let is_active = self.peek().is_some().into();
+        IteratorStats {
+            num_sst_iterators: is_active,
+            num_pinned_blocks: is_active,
+            ..Default::default()
+        }
+    }
+
+    fn skip_current(&mut self) {
+        if self.peek().is_some() {
+            self.advance();
+        }
+    }
 }
 
 impl Iterator for SSTIterator {
     type Item = KeyValuePair;
 
     fn next(&mut self) -> Option<KeyValuePair> {
-        if !self.is_valid
-            || self.block_index >= self.sst.meta_blocks.len()
-            || self.current_kv.is_none()
-        {
-            return None;
-        }
-        let current_key = self.current_kv.clone()?.key;
-        let current_meta_block = &self.sst.meta_blocks[self.block_index];
-        if current_key.get_key() < current_meta_block.get_last_key().get_key() {
-            let res = self.block_iterator.next();
-            self.current_kv = self.block_iterator.peek();
-            res
-        } else {
-            let res = self.current_kv.clone();
-            self.block_index += 1;
-            if self.block_index >= self.sst.meta_blocks.len() {
-                self.current_kv = None;
-                return res;
-            }
-            // load new block
-            let block = self.sst.read_block_cached(self.block_index);
-            if block.is_err() {
+        let res = match self.read_current() {
+            Ok(res) => res?,
+            Err(err) => {
                 self.is_valid = false;
-                return res;
+                self.error = Some(err);
+                return None;
             }
-            self.block_iterator = BlockIterator::create_and_seek_to_first(block.unwrap());
-            self.current_kv = self.block_iterator.next();
-            res
-        }
+        };
+        self.advance();
+        Some(res)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::sync::Arc;
+    use std::{fs::OpenOptions, sync::Arc};
+
+    use tempfile::tempdir;
 
     use crate::{
         iterator::StorageIterator,
         kv::timestamped_key::TimestampedKey,
-        table::{iterator::SSTIterator, test_utils::build_sst},
+        kv::kv_pair::KeyValuePair,
+        state::read_options::ReadOptions,
+        table::{
+            builder::SSTBuilder,
+            iterator::SSTIterator,
+            test_utils::{build_sst, build_sst_with_cache},
+        },
     };
 
     #[test]
     fn test_create_and_seek_to_first() {
         let sst = build_sst();
         // create iterator
-        let mut iterator: SSTIterator =
+        let iterator: SSTIterator =
             SSTIterator::create_and_seek_to_first(Arc::new(sst)).unwrap();
         assert!(iterator.peek().is_some());
         assert_eq!(
@@ -151,4 +288,133 @@ mod tests {
             assert_eq!(kv.key.get_key(), format!("k{}", i + 2));
         }
     }
+
+    #[test]
+    fn test_iterate_multiple_blocks() {
+        // two entries per block: [k1, k2], [k3, k4], [k5]
+        let mut builder = SSTBuilder::new(25);
+        for i in 1..=5 {
+            builder
+                .add(&KeyValuePair::new(
+                    TimestampedKey::new(format!("k{}", i).into()),
+                    "v".as_bytes().into(),
+                ))
+                .unwrap();
+        }
+        let dir = tempdir().unwrap();
+        let sst = Arc::new(builder.build(0, dir.path().join("test_sst.sst"), None).unwrap());
+        assert_eq!(sst.metadata().unwrap().num_blocks(), 3);
+
+        let keys: Vec<_> = SSTIterator::create_and_seek_to_first(sst.clone())
+            .unwrap()
+            .map(|kv| kv.key.get_key())
+            .collect();
+        assert_eq!(keys, vec!["k1", "k2", "k3", "k4", "k5"]);
+
+        // seeking past the last key of a block continues in the next block
+        let keys: Vec<_> = SSTIterator::create_and_seek_to_key(sst.clone(), TimestampedKey::new("k25".into()))
+            .unwrap()
+            .map(|kv| kv.key.get_key())
+            .collect();
+        assert_eq!(keys, vec!["k3", "k4", "k5"]);
+        let mut iterator = SSTIterator::create_and_seek_to_key(sst, TimestampedKey::new("k6".into())).unwrap();
+        assert!(iterator.peek().is_none());
+        assert!(iterator.next().is_none());
+        assert!(iterator.check_error().is_ok());
+    }
+
+    #[test]
+    fn test_scan_without_filling_cache() {
+        let (sst, cache) = build_sst_with_cache();
+        let sst = Arc::new(sst);
+        let options = ReadOptions {
+            fill_cache: false,
+            ..Default::default()
+        };
+        let iterator = SSTIterator::create_and_seek_to_first_with_options(sst.clone(), &options).unwrap();
+        assert_eq!(iterator.count(), 3);
+        cache.run_pending_tasks();
+        assert_eq!(cache.entry_count(), 0);
+
+        // blocks that are already cached are used, but no others are added
+        sst.read_block_cached(0).unwrap();
+        let iterator = SSTIterator::create_and_seek_to_first_with_options(sst.clone(), &options).unwrap();
+        assert_eq!(iterator.count(), 3);
+        cache.run_pending_tasks();
+        assert_eq!(cache.entry_count(), 1);
+
+        // the default fills the cache
+        assert_eq!(SSTIterator::create_and_seek_to_first(sst).unwrap().count(), 3);
+        cache.run_pending_tasks();
+        assert_eq!(cache.entry_count(), 2);
+    }
+
+    #[test]
+    fn test_readahead() {
+        // one entry per block
+        let mut builder = SSTBuilder::new(0);
+        for i in 0..20 {
+            builder
+                .add(&KeyValuePair::new(
+                    TimestampedKey::new(format!("k{:02}", i).into()),
+                    "v".as_bytes().into(),
+                ))
+                .unwrap();
+        }
+        let dir = tempdir().unwrap();
+        let sst = Arc::new(builder.build(0, dir.path().join("test_sst.sst"), None).unwrap());
+        assert_eq!(sst.metadata().unwrap().num_blocks(), 20);
+
+        let expected: Vec<_> = (0..20).map(|i| format!("k{:02}", i)).collect();
+        // from a single block ahead to the whole file
+        for readahead_bytes in [1, 64, 1 << 20] {
+            let options = ReadOptions {
+                readahead_bytes,
+                ..Default::default()
+            };
+            let keys: Vec<_> = SSTIterator::create_and_seek_to_first_with_options(sst.clone(), &options)
+                .unwrap()
+                .map(|kv| kv.key.get_key())
+                .collect();
+            assert_eq!(keys, expected);
+
+            // a seek restarts read-ahead from the new position
+            let mut iterator =
+                SSTIterator::create_and_seek_to_key_with_options(sst.clone(), TimestampedKey::new("k15".into()), &options)
+                    .unwrap();
+            assert_eq!(iterator.next().unwrap().key.get_key(), "k15");
+            iterator.seek_to_key(TimestampedKey::new("k03".into())).unwrap();
+            assert_eq!(iterator.by_ref().count(), 17);
+            assert!(iterator.check_error().is_ok());
+        }
+    }
+
+    #[test]
+    fn test_read_error_mid_scan() {
+        // two entries per block: [k1, k2], [k3, k4], [k5]
+        let mut builder = SSTBuilder::new(25);
+        for i in 1..=5 {
+            builder
+                .add(&KeyValuePair::new(
+                    TimestampedKey::new(format!("k{}", i).into()),
+                    "v".as_bytes().into(),
+                ))
+                .unwrap();
+        }
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("test_sst.sst");
+        let sst = Arc::new(builder.build(0, &path, None).unwrap());
+        let mut iterator = SSTIterator::create_and_seek_to_first(sst.clone()).unwrap();
+
+        // cut the file off after the first block
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        let metadata = sst.metadata().unwrap();
+        file.set_len(metadata.index.block_offset(&sst.file, 1).unwrap().into()).unwrap();
+
+        let keys: Vec<_> = iterator.by_ref().map(|kv| kv.key.get_key()).collect();
+        assert_eq!(keys, vec!["k1", "k2"]);
+        assert!(!iterator.is_valid());
+        assert!(iterator.error().is_some());
+        assert!(iterator.check_error().is_err());
+    }
 }
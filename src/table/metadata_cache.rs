@@ -0,0 +1,6 @@
+use std::sync::Arc;
+
+use super::SstMetadata;
+
+// keyed by sst id. holds the metadata of SSTs that don't keep it resident
+pub(crate) type MetadataCache = moka::sync::Cache<usize, Arc<SstMetadata>>;
@@ -0,0 +1,117 @@
+use std::{
+    fs::{create_dir_all, remove_dir_all, remove_file},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::Result;
+use moka::{notification::RemovalCause, sync::Cache};
+
+use crate::block::Block;
+
+// where a store keeps its persistent block cache and how large it may grow
+#[derive(Clone, Debug)]
+pub struct PersistentCacheOptions {
+    // relative paths are inside the store directory. must not be shared with
+    // another store
+    pub path: PathBuf,
+    pub capacity_bytes: u64,
+}
+
+// second cache tier below the BlockCache, for when the SSTs live on slow
+// storage: encoded blocks are kept as files in a directory on fast local disk,
+// and evicted least recently used first. only the files are on disk, the
+// index is in memory, so the directory is cleared when the cache is created.
+// this also means nothing is served for an SST id that was reused after
+// a restart
+pub(crate) struct PersistentBlockCache {
+    dir: PathBuf,
+    // size of each cached block's file
+    index: Cache<(usize, usize), u32>,
+}
+
+impl PersistentBlockCache {
+    pub(crate) fn new(dir: impl AsRef<Path>, capacity_bytes: u64) -> Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        if dir.exists() {
+            remove_dir_all(&dir)?;
+        }
+        create_dir_all(&dir)?;
+        let eviction_dir = dir.clone();
+        let index = Cache::builder()
+            .max_capacity(capacity_bytes)
+            .weigher(|_, size_bytes: &u32| *size_bytes)
+            .eviction_listener(move |key: Arc<(usize, usize)>, _, cause| {
+                // a replaced entry's file has already been overwritten
+                if cause != RemovalCause::Replaced {
+                    let _ = remove_file(Self::block_path(&eviction_dir, *key));
+                }
+            })
+            .build();
+        Ok(Self { dir, index })
+    }
+
+    // None if the block isn't cached, or its file can't be read. blocks are
+    // stored encoded, so the restart interval comes from the SST
+    pub(crate) fn get(&self, key: (usize, usize), restart_interval: usize) -> Option<Block> {
+        self.index.get(&key)?;
+        match std::fs::read(Self::block_path(&self.dir, key)) {
+            Ok(data) => Some(Block::decode(data, restart_interval)),
+            Err(_) => {
+                self.index.invalidate(&key);
+                None
+            }
+        }
+    }
+
+    pub(crate) fn insert(&self, key: (usize, usize), block: &Block) -> Result<()> {
+        let data = block.encode();
+        std::fs::write(Self::block_path(&self.dir, key), &data)?;
+        self.index.insert(key, u32::try_from(data.len())?);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub(crate) fn run_pending_tasks(&self) {
+        self.index.run_pending_tasks();
+    }
+
+    fn block_path(dir: &Path, (sst_id, block_index): (usize, usize)) -> PathBuf {
+        dir.join(format!("{}-{}.blk", sst_id, block_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::block::Block;
+
+    use super::PersistentBlockCache;
+
+    #[test]
+    fn test_persistent_block_cache() {
+        let dir = tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        // 20 bytes of data, 2 bytes of offsets and 2 for the end of data offset
+        let block = Block::new(vec![1; 20], vec![0], 20);
+        {
+            let cache = PersistentBlockCache::new(&cache_dir, 60).unwrap();
+            assert!(cache.get((0, 0), 0).is_none());
+            cache.insert((0, 0), &block).unwrap();
+            assert_eq!(cache.get((0, 0), 0).unwrap().encode(), block.encode());
+
+            // evicted blocks lose their files
+            for block_index in 1..4 {
+                cache.insert((0, block_index), &block).unwrap();
+            }
+            cache.run_pending_tasks();
+            assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 2);
+        }
+
+        // nothing survives a restart
+        let cache = PersistentBlockCache::new(&cache_dir, 60).unwrap();
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 0);
+        assert!(cache.get((0, 3), 0).is_none());
+    }
+}
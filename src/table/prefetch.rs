@@ -0,0 +1,73 @@
+use std::{
+    sync::{
+        mpsc::{sync_channel, Receiver},
+        Arc,
+    },
+    thread,
+};
+
+use anyhow::{anyhow, Result};
+
+use crate::block::Block;
+
+use super::{Sst, SstMetadata};
+
+// reads the blocks after the one being iterated on a background thread, so
+// disk reads overlap with iteration. the thread stays at most readahead_bytes
+// ahead (but always at least one block) and exits once the prefetcher is
+// dropped or the last block has been read
+pub(crate) struct BlockPrefetcher {
+    receiver: Receiver<(usize, Result<Arc<Block>>)>,
+}
+
+impl BlockPrefetcher {
+    // prefetch blocks from first_block_index to the end of the sst
+    pub(super) fn start(
+        sst: Arc<Sst>,
+        metadata: &SstMetadata,
+        first_block_index: usize,
+        readahead_bytes: usize,
+        fill_cache: bool,
+    ) -> Self {
+        let num_blocks = metadata.num_blocks();
+        let mut num_blocks_ahead: usize = 0;
+        let mut bytes_ahead = 0;
+        for block_index in first_block_index..num_blocks {
+            // the thread reports the error when it gets to the block
+            let Ok(block_size) = metadata.block_size(&sst.file, block_index) else {
+                break;
+            };
+            bytes_ahead += block_size as usize;
+            if num_blocks_ahead > 0 && bytes_ahead > readahead_bytes {
+                break;
+            }
+            num_blocks_ahead += 1;
+        }
+        // the thread holds one more block while it waits to send it
+        let (sender, receiver) = sync_channel(num_blocks_ahead.saturating_sub(1));
+        thread::spawn(move || {
+            for block_index in first_block_index..num_blocks {
+                let block = sst.read_block_for_scan(block_index, fill_cache);
+                let is_err = block.is_err();
+                // the iterator has moved on or been dropped
+                if sender.send((block_index, block)).is_err() || is_err {
+                    return;
+                }
+            }
+        });
+        Self { receiver }
+    }
+
+    // blocks must be taken in order, starting from first_block_index
+    pub(super) fn next_block(&self, block_index: usize) -> Result<Arc<Block>> {
+        match self.receiver.recv() {
+            Ok((prefetched_index, block)) if prefetched_index == block_index => block,
+            Ok((prefetched_index, _)) => Err(anyhow!(
+                "prefetched block {} but block {} was requested",
+                prefetched_index,
+                block_index
+            )),
+            Err(_) => Err(anyhow!("prefetch of block {} stopped early", block_index)),
+        }
+    }
+}
@@ -0,0 +1,469 @@
+// exchange SSTs with RocksDB and LevelDB tooling (sst_dump, ldb,
+// SstFileReader). the writer produces uncompressed block-based tables with
+// the legacy LevelDB footer, which every release of both can read. the reader
+// also accepts RocksDB's newer footer up to format_version 2, as long as blocks
+// are uncompressed, checksums are crc32c and data blocks have no hash index.
+//
+// keys are RocksDB internal keys: the user key followed by a little-endian
+// u64 of (sequence number << 8 | value type). every entry is written with
+// sequence number 0 and tombstones become deletion entries. no properties or
+// filter blocks are written
+use std::{
+    fs::{self, File},
+    io::Write,
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+
+use crate::{
+    error::LsmError,
+    iterator::StorageIterator,
+    kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
+};
+
+use super::{builder::SSTBuilder, iterator::SSTIterator, Sst};
+
+// footer magic of LevelDB tables and RocksDB tables with format_version 0
+const ROCKSDB_LEGACY_MAGIC: u64 = 0xdb47_7524_8b80_fb57;
+// footer magic of RocksDB block-based tables with format_version 1 and up
+const ROCKSDB_BLOCK_BASED_MAGIC: u64 = 0x88e2_41b7_85f4_cff7;
+
+const LEGACY_FOOTER_SIZE: usize = 48;
+const FOOTER_SIZE: usize = 53;
+// varint-encoded metaindex and index handles, zero padded
+const FOOTER_HANDLES_SIZE: usize = 40;
+// compression type byte and masked crc32c after every block
+const BLOCK_TRAILER_SIZE: usize = 5;
+const NO_COMPRESSION: u8 = 0;
+const CHECKSUM_NONE: u8 = 0;
+const CHECKSUM_CRC32C: u8 = 1;
+const MAX_SUPPORTED_FORMAT_VERSION: u32 = 2;
+const RESTART_INTERVAL: usize = 16;
+const VALUE_TYPE_DELETION: u8 = 0;
+const VALUE_TYPE_VALUE: u8 = 1;
+
+// writes a RocksDB table from key-value pairs added in strictly increasing key
+// order
+pub(crate) struct RocksDbTableBuilder {
+    block_size: usize,
+    data: Vec<u8>,
+    data_block: RawBlockBuilder,
+    index_block: RawBlockBuilder,
+    last_user_key: Option<Bytes>,
+}
+
+impl RocksDbTableBuilder {
+    pub(crate) fn new(block_size: usize) -> Self {
+        Self {
+            block_size,
+            data: Vec::new(),
+            data_block: RawBlockBuilder::new(RESTART_INTERVAL),
+            // every index entry is a restart point so it can be searched
+            index_block: RawBlockBuilder::new(1),
+            last_user_key: None,
+        }
+    }
+
+    pub(crate) fn add(&mut self, kv: &KeyValuePair) -> Result<()> {
+        let user_key = kv.key.get_key();
+        if self.last_user_key.as_ref().is_some_and(|last| *last >= user_key) {
+            bail!("keys must be added in strictly increasing order, got {:?} after {:?}", user_key, self.last_user_key);
+        }
+        let value_type = if kv.is_tombstone() { VALUE_TYPE_DELETION } else { VALUE_TYPE_VALUE };
+        self.data_block.add(&internal_key(&user_key, value_type), &kv.value);
+        self.last_user_key = Some(user_key);
+        if self.data_block.size_bytes() >= self.block_size {
+            self.finish_data_block();
+        }
+        Ok(())
+    }
+
+    pub(crate) fn build(mut self, path: impl AsRef<Path>) -> Result<()> {
+        self.finish_data_block();
+        let metaindex_handle = write_block(&mut self.data, RawBlockBuilder::new(1).finish());
+        let index_handle = write_block(&mut self.data, self.index_block.finish());
+
+        let mut footer = Vec::with_capacity(LEGACY_FOOTER_SIZE);
+        metaindex_handle.encode_to(&mut footer);
+        index_handle.encode_to(&mut footer);
+        footer.resize(FOOTER_HANDLES_SIZE, 0);
+        footer.extend(ROCKSDB_LEGACY_MAGIC.to_le_bytes());
+        self.data.extend(footer);
+
+        let mut file = File::create(path)?;
+        file.write_all(&self.data)?;
+        file.sync_all()?;
+        Ok(())
+    }
+
+    fn finish_data_block(&mut self) {
+        let Some(last_key) = self.data_block.last_key.clone() else {
+            return;
+        };
+        let block = std::mem::replace(&mut self.data_block, RawBlockBuilder::new(RESTART_INTERVAL)).finish();
+        let handle = write_block(&mut self.data, block);
+        // the block's last key is a valid separator from the next block
+        let mut encoded_handle = Vec::new();
+        handle.encode_to(&mut encoded_handle);
+        self.index_block.add(&last_key, &encoded_handle);
+    }
+}
+
+// every entry of a RocksDB table, in key order
+pub(crate) fn read_rocksdb_table(path: impl AsRef<Path>) -> Result<Vec<KeyValuePair>> {
+    let path = path.as_ref();
+    let data = fs::read(path)?;
+    let corruption = |msg: String| LsmError::Corruption(format!("rocksdb table {:?}: {}", path, msg));
+    let (checksum_type, handles) = read_footer(&data).map_err(corruption)?;
+    let mut handles = handles;
+    let _metaindex_handle = BlockHandle::decode_from(&mut handles).ok_or_else(|| corruption("bad metaindex handle".into()))?;
+    let index_handle = BlockHandle::decode_from(&mut handles).ok_or_else(|| corruption("bad index handle".into()))?;
+
+    let mut kvs = Vec::new();
+    let index_block = read_block(&data, index_handle, checksum_type).map_err(corruption)?;
+    for (_, mut encoded_handle) in parse_block(index_block).map_err(corruption)? {
+        let handle = BlockHandle::decode_from(&mut encoded_handle).ok_or_else(|| corruption("bad data block handle".into()))?;
+        let data_block = read_block(&data, handle, checksum_type).map_err(corruption)?;
+        for (key, value) in parse_block(data_block).map_err(corruption)? {
+            if key.len() < 8 {
+                return Err(corruption(format!("internal key of {} bytes", key.len())).into());
+            }
+            let (user_key, trailer) = key.split_at(key.len() - 8);
+            let key = TimestampedKey::new(Bytes::copy_from_slice(user_key));
+            kvs.push(match trailer[0] {
+                VALUE_TYPE_VALUE => KeyValuePair::new(key, Bytes::copy_from_slice(value)),
+                VALUE_TYPE_DELETION => KeyValuePair::tombstone(key),
+                value_type => {
+                    return Err(anyhow!("rocksdb table {:?}: unsupported value type {}", path, value_type));
+                }
+            });
+        }
+    }
+    Ok(kvs)
+}
+
+// convert the SST file at sst_path, written by this store, into a RocksDB
+// table
+pub fn export_sst(sst_path: impl AsRef<Path>, rocksdb_path: impl AsRef<Path>, block_size: usize) -> Result<()> {
+    let sst = Sst::open(0, sst_path.as_ref().to_path_buf(), None)?;
+    let mut builder = RocksDbTableBuilder::new(block_size);
+    let mut iterator = SSTIterator::create_and_seek_to_first(Arc::new(sst))?;
+    for kv in iterator.by_ref() {
+        builder.add(&kv)?;
+    }
+    iterator.check_error()?;
+    builder.build(rocksdb_path)
+}
+
+// convert a RocksDB table into an SST file at sst_path. the SST is not part
+// of any store until it is ingested
+pub fn import_sst(rocksdb_path: impl AsRef<Path>, sst_path: impl AsRef<Path>, block_size: usize) -> Result<()> {
+    let mut builder = SSTBuilder::new(block_size);
+    for kv in read_rocksdb_table(rocksdb_path)? {
+        builder.add(&kv)?;
+    }
+    builder.build(0, sst_path, None)?;
+    Ok(())
+}
+
+fn internal_key(user_key: &[u8], value_type: u8) -> Vec<u8> {
+    let mut key = Vec::with_capacity(user_key.len() + 8);
+    key.extend_from_slice(user_key);
+    // sequence number 0
+    key.extend((value_type as u64).to_le_bytes());
+    key
+}
+
+// returns the checksum type and the encoded block handles
+fn read_footer(data: &[u8]) -> std::result::Result<(u8, &[u8]), String> {
+    if data.len() < LEGACY_FOOTER_SIZE {
+        return Err("file is too short for a footer".into());
+    }
+    let magic = u64::from_le_bytes(data[data.len() - 8..].try_into().expect("slice of size 8"));
+    match magic {
+        ROCKSDB_LEGACY_MAGIC => Ok((CHECKSUM_CRC32C, &data[data.len() - LEGACY_FOOTER_SIZE..data.len() - 8])),
+        ROCKSDB_BLOCK_BASED_MAGIC => {
+            if data.len() < FOOTER_SIZE {
+                return Err("file is too short for a footer".into());
+            }
+            let footer = &data[data.len() - FOOTER_SIZE..];
+            let format_version = u32::from_le_bytes(footer[41..45].try_into().expect("slice of size 4"));
+            if format_version > MAX_SUPPORTED_FORMAT_VERSION {
+                return Err(format!("format_version {} is not supported", format_version));
+            }
+            match footer[0] {
+                CHECKSUM_NONE | CHECKSUM_CRC32C => Ok((footer[0], &footer[1..41])),
+                checksum_type => Err(format!("checksum type {} is not supported", checksum_type)),
+            }
+        }
+        magic => Err(format!("unknown table magic {:#x}", magic)),
+    }
+}
+
+fn read_block(data: &[u8], handle: BlockHandle, checksum_type: u8) -> std::result::Result<&[u8], String> {
+    let start = handle.offset as usize;
+    let end = start
+        .checked_add(handle.size as usize)
+        .filter(|end| end + BLOCK_TRAILER_SIZE <= data.len())
+        .ok_or_else(|| format!("block at {} of {} bytes is past the end of the file", handle.offset, handle.size))?;
+    let trailer = &data[end..end + BLOCK_TRAILER_SIZE];
+    if trailer[0] != NO_COMPRESSION {
+        return Err(format!("block at {} uses compression type {}, which is not supported", handle.offset, trailer[0]));
+    }
+    if checksum_type == CHECKSUM_CRC32C {
+        let expected = u32::from_le_bytes(trailer[1..].try_into().expect("slice of size 4"));
+        if mask_crc(crc32c(&data[start..end + 1])) != expected {
+            return Err(format!("checksum mismatch in block at {}", handle.offset));
+        }
+    }
+    Ok(&data[start..end])
+}
+
+// full key and value of each entry
+type BlockEntries<'a> = Vec<(Vec<u8>, &'a [u8])>;
+
+// the entries of a data or index block
+fn parse_block(block: &[u8]) -> std::result::Result<BlockEntries<'_>, String> {
+    if block.len() < 4 {
+        return Err("block is too short".into());
+    }
+    let num_restarts = u32::from_le_bytes(block[block.len() - 4..].try_into().expect("slice of size 4"));
+    if num_restarts & (1 << 31) != 0 {
+        return Err("data block hash index is not supported".into());
+    }
+    let entries_end = (num_restarts as usize + 1)
+        .checked_mul(4)
+        .and_then(|trailer_len| block.len().checked_sub(trailer_len))
+        .ok_or_else(|| format!("block of {} bytes can't hold {} restarts", block.len(), num_restarts))?;
+    let mut entries = &block[..entries_end];
+    let mut key: Vec<u8> = Vec::new();
+    let mut res = Vec::new();
+    while !entries.is_empty() {
+        let bad_entry = || "bad block entry".to_string();
+        let shared = decode_varint(&mut entries).ok_or_else(bad_entry)? as usize;
+        let non_shared = decode_varint(&mut entries).ok_or_else(bad_entry)? as usize;
+        let value_len = decode_varint(&mut entries).ok_or_else(bad_entry)? as usize;
+        if shared > key.len() || entries.len() < non_shared + value_len {
+            return Err(bad_entry());
+        }
+        key.truncate(shared);
+        key.extend_from_slice(&entries[..non_shared]);
+        res.push((key.clone(), &entries[non_shared..non_shared + value_len]));
+        entries = &entries[non_shared + value_len..];
+    }
+    Ok(res)
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BlockHandle {
+    offset: u64,
+    size: u64,
+}
+
+impl BlockHandle {
+    fn encode_to(&self, buf: &mut Vec<u8>) {
+        encode_varint(buf, self.offset);
+        encode_varint(buf, self.size);
+    }
+
+    fn decode_from(buf: &mut &[u8]) -> Option<Self> {
+        Some(Self {
+            offset: decode_varint(buf)?,
+            size: decode_varint(buf)?,
+        })
+    }
+}
+
+// appends a finished block and its trailer to data
+fn write_block(data: &mut Vec<u8>, block: Vec<u8>) -> BlockHandle {
+    let handle = BlockHandle {
+        offset: data.len() as u64,
+        size: block.len() as u64,
+    };
+    let checksum_start = data.len();
+    data.extend(block);
+    data.push(NO_COMPRESSION);
+    let crc = mask_crc(crc32c(&data[checksum_start..]));
+    data.extend(crc.to_le_bytes());
+    handle
+}
+
+// prefix-compressed entries with a restart point every restart_interval
+// entries, as in LevelDB's block_builder.cc
+struct RawBlockBuilder {
+    buffer: Vec<u8>,
+    restarts: Vec<u32>,
+    restart_interval: usize,
+    counter: usize,
+    last_key: Option<Vec<u8>>,
+}
+
+impl RawBlockBuilder {
+    fn new(restart_interval: usize) -> Self {
+        Self {
+            buffer: Vec::new(),
+            restarts: vec![0],
+            restart_interval,
+            counter: 0,
+            last_key: None,
+        }
+    }
+
+    fn add(&mut self, key: &[u8], value: &[u8]) {
+        let mut shared = 0;
+        if self.counter < self.restart_interval {
+            if let Some(last_key) = &self.last_key {
+                shared = last_key.iter().zip(key).take_while(|(a, b)| a == b).count();
+            }
+        } else {
+            self.restarts.push(self.buffer.len() as u32);
+            self.counter = 0;
+        }
+        encode_varint(&mut self.buffer, shared as u64);
+        encode_varint(&mut self.buffer, (key.len() - shared) as u64);
+        encode_varint(&mut self.buffer, value.len() as u64);
+        self.buffer.extend_from_slice(&key[shared..]);
+        self.buffer.extend_from_slice(value);
+        self.last_key = Some(key.to_vec());
+        self.counter += 1;
+    }
+
+    fn size_bytes(&self) -> usize {
+        self.buffer.len() + 4 * (self.restarts.len() + 1)
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for restart in self.restarts.iter() {
+            self.buffer.extend(restart.to_le_bytes());
+        }
+        self.buffer.extend((self.restarts.len() as u32).to_le_bytes());
+        self.buffer
+    }
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn decode_varint(buf: &mut &[u8]) -> Option<u64> {
+    let mut value: u64 = 0;
+    for (i, byte) in buf.iter().enumerate().take(10) {
+        value |= ((byte & 0x7f) as u64) << (7 * i);
+        if byte & 0x80 == 0 {
+            *buf = &buf[i + 1..];
+            return Some(value);
+        }
+    }
+    None
+}
+
+const CRC32C_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ 0x82f6_3b78 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+};
+
+fn crc32c(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in data {
+        crc = CRC32C_TABLE[((crc ^ *byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+// stored crcs are masked so that crcs of data containing crcs stay well mixed
+fn mask_crc(crc: u32) -> u32 {
+    crc.rotate_right(15).wrapping_add(0xa282_ead8)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tempfile::tempdir;
+
+    use crate::{
+        kv::{kv_pair::KeyValuePair, timestamped_key::TimestampedKey},
+        table::{iterator::SSTIterator, test_utils::set_up_builder, Sst},
+    };
+
+    use super::{crc32c, export_sst, import_sst, read_rocksdb_table, RocksDbTableBuilder};
+
+    #[test]
+    fn test_crc32c() {
+        assert_eq!(crc32c(b"123456789"), 0xe306_9283);
+        assert_eq!(crc32c(&[0; 32]), 0x8a91_36aa);
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("000001.sst");
+        let kvs: Vec<KeyValuePair> = (0..100)
+            .map(|i| {
+                let key = TimestampedKey::new(format!("key{:03}", i).into());
+                match i % 10 {
+                    0 => KeyValuePair::tombstone(key),
+                    5 => KeyValuePair::new(key, "".into()),
+                    _ => KeyValuePair::new(key, format!("value{}", i).into()),
+                }
+            })
+            .collect();
+        let mut builder = RocksDbTableBuilder::new(256);
+        for kv in &kvs {
+            builder.add(kv).unwrap();
+        }
+        // out of order keys are rejected
+        assert!(builder.add(&kvs[0]).is_err());
+        builder.build(&path).unwrap();
+        assert_eq!(read_rocksdb_table(&path).unwrap(), kvs);
+
+        // a flipped bit fails the block checksum
+        let mut data = std::fs::read(&path).unwrap();
+        data[10] ^= 1;
+        std::fs::write(&path, data).unwrap();
+        assert!(read_rocksdb_table(&path).is_err());
+    }
+
+    #[test]
+    fn test_export_import() {
+        let dir = tempdir().unwrap();
+        let sst_path = dir.path().join("00001.sst");
+        set_up_builder().build(1, &sst_path, None).unwrap();
+        let rocksdb_path = dir.path().join("000001.sst");
+        export_sst(&sst_path, &rocksdb_path, 4096).unwrap();
+
+        let imported_path = dir.path().join("00007.sst");
+        import_sst(&rocksdb_path, &imported_path, 25).unwrap();
+        let imported = Sst::open(7, imported_path, None).unwrap();
+        let keys: Vec<_> = SSTIterator::create_and_seek_to_first(Arc::new(imported))
+            .unwrap()
+            .map(|kv| (kv.key.get_key(), kv.value))
+            .collect();
+        assert_eq!(
+            keys,
+            vec![
+                ("k1".as_bytes().into(), "v1".as_bytes().into()),
+                ("k2".as_bytes().into(), "v2".as_bytes().into()),
+                ("k3".as_bytes().into(), "v3".as_bytes().into()),
+            ]
+        );
+    }
+}
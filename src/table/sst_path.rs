@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+
+// decides where SST files live, relative to the store directory. paths are
+// recorded in the manifest, so changing the provider of an existing store only
+// affects SSTs written afterwards. file names must stay `<id>.sst` (any zero
+// padding is fine) so destroy and repair can recognize them
+pub trait SstPathProvider: Send + Sync {
+    fn sst_path(&self, sst_id: usize, level: usize) -> PathBuf;
+}
+
+// every SST directly in the store directory
+pub struct FlatSstPathProvider;
+
+impl SstPathProvider for FlatSstPathProvider {
+    fn sst_path(&self, sst_id: usize, _level: usize) -> PathBuf {
+        PathBuf::from(format!("{:05}.sst", sst_id))
+    }
+}
+
+// one subdirectory per level: L0/, L1/, ...
+pub struct LeveledSstPathProvider;
+
+impl SstPathProvider for LeveledSstPathProvider {
+    fn sst_path(&self, sst_id: usize, level: usize) -> PathBuf {
+        PathBuf::from(format!("L{}", level)).join(format!("{:05}.sst", sst_id))
+    }
+}
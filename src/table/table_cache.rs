@@ -0,0 +1,4 @@
+use std::sync::Arc;
+
+// keyed by sst id. bounds how many SST files are open at once
+pub(crate) type TableCache = moka::sync::Cache<usize, Arc<std::fs::File>>;
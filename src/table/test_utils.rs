@@ -7,31 +7,31 @@ use crate::table::Sst;
 
 use tempfile::tempdir;
 
-use super::block_cache::BlockCache;
+use super::block_cache::{new_block_cache, BlockCache};
 
 pub fn set_up_builder() -> SSTBuilder {
     // build a test SST with two blocks
     // - block 0 contains k1 and k2
     // - block 1 contains k3
-    let mut builder: SSTBuilder = SSTBuilder::new(25);
+    let mut builder: SSTBuilder = SSTBuilder::new(27);
     // add three key-value pairs
     assert!(builder
-        .add(KeyValuePair {
-            key: TimestampedKey::new("k1".as_bytes().into()),
-            value: "v1".as_bytes().into(),
-        })
+        .add(&KeyValuePair::new(
+            TimestampedKey::new("k1".as_bytes().into()),
+            "v1".as_bytes().into(),
+        ))
         .is_ok());
     assert!(builder
-        .add(KeyValuePair {
-            key: TimestampedKey::new("k2".as_bytes().into()),
-            value: "v2".as_bytes().into(),
-        })
+        .add(&KeyValuePair::new(
+            TimestampedKey::new("k2".as_bytes().into()),
+            "v2".as_bytes().into(),
+        ))
         .is_ok());
     assert!(builder
-        .add(KeyValuePair {
-            key: TimestampedKey::new("k3".as_bytes().into()),
-            value: "v3".as_bytes().into(),
-        })
+        .add(&KeyValuePair::new(
+            TimestampedKey::new("k3".as_bytes().into()),
+            "v3".as_bytes().into(),
+        ))
         .is_ok());
     builder
 }
@@ -46,7 +46,7 @@ pub fn build_sst() -> Sst {
 
 pub fn build_sst_with_cache() -> (Sst, Arc<BlockCache>) {
     let builder: SSTBuilder = set_up_builder();
-    let cache = Arc::new(BlockCache::new(50));
+    let cache = Arc::new(new_block_cache(1 << 20, 1));
     // build
     let dir = tempdir().unwrap();
     let path = dir.path().join("test_sst.sst");
@@ -0,0 +1,327 @@
+use std::{
+    iter::FusedIterator,
+    marker::PhantomData,
+    ops::{Bound, RangeBounds},
+    sync::Arc,
+};
+
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+
+use crate::{
+    iterator::lsm_iterator::LsmIterator,
+    keys::{self, KeyPart},
+    store::LsmStore,
+};
+
+#[cfg(feature = "serde")]
+pub(crate) mod serde_codec;
+
+// how a key type is stored. the store orders keys bytewise, so encodings must
+// sort like the keys they encode, or scans come back out of order and ranges
+// miss keys
+pub trait KeyCodec: Sized {
+    fn encode_key(&self) -> Vec<u8>;
+    fn decode_key(bytes: &[u8]) -> Result<Self>;
+}
+
+// how a value type is stored. order doesn't matter for values
+pub trait ValueCodec: Sized {
+    fn encode_value(&self) -> Vec<u8>;
+    fn decode_value(bytes: &[u8]) -> Result<Self>;
+}
+
+// numbers and tuples are encoded as the keys module encodes them
+impl KeyCodec for u64 {
+    fn encode_key(&self) -> Vec<u8> {
+        keys::encode_u64(*self).to_vec()
+    }
+
+    fn decode_key(bytes: &[u8]) -> Result<Self> {
+        Ok(keys::decode_u64(fixed_width(bytes, "u64 key")?))
+    }
+}
+
+impl KeyCodec for i64 {
+    fn encode_key(&self) -> Vec<u8> {
+        keys::encode_i64(*self).to_vec()
+    }
+
+    fn decode_key(bytes: &[u8]) -> Result<Self> {
+        Ok(keys::decode_i64(fixed_width(bytes, "i64 key")?))
+    }
+}
+
+impl KeyCodec for f64 {
+    fn encode_key(&self) -> Vec<u8> {
+        keys::encode_f64(*self).to_vec()
+    }
+
+    fn decode_key(bytes: &[u8]) -> Result<Self> {
+        Ok(keys::decode_f64(fixed_width(bytes, "f64 key")?))
+    }
+}
+
+macro_rules! impl_key_codec_for_tuple {
+    ($($part:ident),+) => {
+        impl<$($part: KeyPart),+> KeyCodec for ($($part,)+) {
+            fn encode_key(&self) -> Vec<u8> {
+                keys::encode(self)
+            }
+
+            fn decode_key(bytes: &[u8]) -> Result<Self> {
+                keys::decode(bytes)
+            }
+        }
+    };
+}
+
+impl_key_codec_for_tuple!(A, B);
+impl_key_codec_for_tuple!(A, B, C);
+impl_key_codec_for_tuple!(A, B, C, D);
+
+// UTF-8 sorts bytewise in code point order
+impl KeyCodec for String {
+    fn encode_key(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode_key(bytes: &[u8]) -> Result<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| anyhow!("key is not valid UTF-8: {}", err))
+    }
+}
+
+impl KeyCodec for Vec<u8> {
+    fn encode_key(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode_key(bytes: &[u8]) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+// little-endian, as LsmStore::increment reads counters
+impl ValueCodec for u64 {
+    fn encode_value(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        Ok(u64::from_le_bytes(fixed_width(bytes, "u64 value")?))
+    }
+}
+
+impl ValueCodec for i64 {
+    fn encode_value(&self) -> Vec<u8> {
+        self.to_le_bytes().to_vec()
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        Ok(i64::from_le_bytes(fixed_width(bytes, "i64 value")?))
+    }
+}
+
+impl ValueCodec for String {
+    fn encode_value(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        String::from_utf8(bytes.to_vec()).map_err(|err| anyhow!("value is not valid UTF-8: {}", err))
+    }
+}
+
+impl ValueCodec for Vec<u8> {
+    fn encode_value(&self) -> Vec<u8> {
+        self.clone()
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        Ok(bytes.to_vec())
+    }
+}
+
+impl ValueCodec for Bytes {
+    fn encode_value(&self) -> Vec<u8> {
+        self.to_vec()
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        Ok(Bytes::copy_from_slice(bytes))
+    }
+}
+
+// a value prefixed with the version of its schema, one byte, so that readers
+// can tell values written before a schema change from those written after and
+// decode each accordingly. the version isn't checked here
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Versioned<V> {
+    pub version: u8,
+    pub value: V,
+}
+
+impl<V: ValueCodec> ValueCodec for Versioned<V> {
+    fn encode_value(&self) -> Vec<u8> {
+        let mut bytes = vec![self.version];
+        bytes.extend(self.value.encode_value());
+        bytes
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        let (version, value) = bytes
+            .split_first()
+            .ok_or_else(|| anyhow!("versioned value is empty, with no schema version"))?;
+        Ok(Self {
+            version: *version,
+            value: V::decode_value(value)?,
+        })
+    }
+}
+
+fn fixed_width<const N: usize>(bytes: &[u8], what: &str) -> Result<[u8; N]> {
+    bytes
+        .try_into()
+        .map_err(|_| anyhow!("{} is {} bytes, not {}", what, bytes.len(), N))
+}
+
+// a view of a store whose keys and values all have one type each. every key
+// and value is written through the codecs, and reading back a key or value
+// written some other way is an error
+pub struct TypedStore<K: KeyCodec, V: ValueCodec> {
+    store: Arc<LsmStore>,
+    // K and V are only encoded and decoded, never held
+    _types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: KeyCodec, V: ValueCodec> TypedStore<K, V> {
+    pub fn new(store: Arc<LsmStore>) -> Self {
+        Self {
+            store,
+            _types: PhantomData,
+        }
+    }
+
+    // the untyped store, e.g. to flush or close it
+    pub fn store(&self) -> &Arc<LsmStore> {
+        &self.store
+    }
+
+    pub fn get(&self, key: &K) -> Result<Option<V>> {
+        self.store
+            .get(key.encode_key())?
+            .map(|value| V::decode_value(&value))
+            .transpose()
+    }
+
+    pub fn put(&self, key: &K, value: &V) -> Result<()> {
+        self.store.put(key.encode_key(), value.encode_value())
+    }
+
+    pub fn delete(&self, key: &K) -> Result<()> {
+        self.store.delete(key.encode_key())
+    }
+
+    // the pairs with keys in range, in key order. a pair that doesn't decode
+    // is yielded as an error, and the scan carries on after it
+    pub fn scan(&self, range: impl RangeBounds<K>) -> Result<TypedIterator<K, V>> {
+        let encode = |bound: Bound<&K>| bound.map(|key| key.encode_key());
+        let bounds = (encode(range.start_bound()), encode(range.end_bound()));
+        Ok(TypedIterator {
+            sub_iterator: self.store.iter(bounds)?,
+            _types: PhantomData,
+        })
+    }
+}
+
+// a scan of a TypedStore, see TypedStore::scan
+pub struct TypedIterator<K: KeyCodec, V: ValueCodec> {
+    sub_iterator: LsmIterator,
+    _types: PhantomData<fn() -> (K, V)>,
+}
+
+impl<K: KeyCodec, V: ValueCodec> Iterator for TypedIterator<K, V> {
+    type Item = Result<(K, V)>;
+
+    fn next(&mut self) -> Option<Result<(K, V)>> {
+        let (key, value) = match self.sub_iterator.next()? {
+            Ok(kv) => kv,
+            Err(err) => return Some(Err(err)),
+        };
+        Some(K::decode_key(&key).and_then(|key| Ok((key, V::decode_value(&value)?))))
+    }
+}
+
+impl<K: KeyCodec, V: ValueCodec> FusedIterator for TypedIterator<K, V> {}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use anyhow::Result;
+    use tempfile::tempdir;
+
+    use crate::{state::storage_state_options::StorageStateOptions, store::LsmStore};
+
+    use super::{KeyCodec, TypedStore, ValueCodec, Versioned};
+
+    #[test]
+    fn test_codecs() {
+        let ints = [i64::MIN, -300, -1, 0, 1, 255, 256, i64::MAX];
+        let encoded: Vec<Vec<u8>> = ints.iter().map(|int| int.encode_key()).collect();
+        assert!(encoded.windows(2).all(|pair| pair[0] < pair[1]));
+        for (int, bytes) in ints.iter().zip(encoded.iter()) {
+            assert_eq!(i64::decode_key(bytes).unwrap(), *int);
+        }
+        assert!(1u64.encode_key() < 256u64.encode_key());
+        assert_eq!(u64::decode_key(&u64::MAX.encode_key()).unwrap(), u64::MAX);
+        assert!(u64::decode_key(b"short").is_err());
+        let pairs = [("a".to_string(), 2u64), ("a".to_string(), 10), ("ab".to_string(), 1)];
+        assert!(pairs.windows(2).all(|pair| pair[0].encode_key() < pair[1].encode_key()));
+        assert_eq!(String::decode_value(&"é".to_string().encode_value()).unwrap(), "é");
+        assert_eq!(i64::decode_value(&(-5i64).encode_value()).unwrap(), -5);
+
+        let versioned = Versioned {
+            version: 2,
+            value: "v".to_string(),
+        };
+        assert_eq!(versioned.encode_value(), b"\x02v");
+        assert_eq!(Versioned::<String>::decode_value(b"\x02v").unwrap(), versioned);
+        assert!(Versioned::<String>::decode_value(b"").is_err());
+    }
+
+    #[test]
+    fn test_typed_store() {
+        let dir = tempdir().unwrap();
+        let store = Arc::new(
+            LsmStore::open(StorageStateOptions {
+                path: dir.path().to_owned(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let typed: TypedStore<i64, String> = TypedStore::new(store.clone());
+        for key in [10, -2, 300, -70, 0] {
+            typed.put(&key, &format!("value {}", key)).unwrap();
+        }
+        typed.delete(&0).unwrap();
+        assert_eq!(typed.get(&-70).unwrap(), Some("value -70".to_string()));
+        assert_eq!(typed.get(&0).unwrap(), None);
+
+        let keys = |range: std::ops::Range<i64>| -> Vec<i64> {
+            typed.scan(range).unwrap().map(|kv| kv.unwrap().0).collect()
+        };
+        assert_eq!(keys(-100..100), vec![-70, -2, 10]);
+        assert_eq!(keys(-2..301), vec![-2, 10, 300]);
+        let all: Vec<i64> = typed.scan(..).unwrap().map(|kv| kv.unwrap().0).collect();
+        assert_eq!(all, vec![-70, -2, 10, 300]);
+
+        // a value written some other way doesn't decode
+        store.put(11i64.encode_key(), [0xff]).unwrap();
+        assert!(typed.get(&11).is_err());
+        let pairs: Vec<Result<(i64, String)>> = typed.scan(10..=300).unwrap().collect();
+        assert_eq!(pairs.len(), 3);
+        assert!(pairs[1].is_err());
+        assert_eq!(pairs[2].as_ref().unwrap().1, "value 300");
+    }
+}
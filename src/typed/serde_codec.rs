@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::store::LsmStore;
+
+use super::{ValueCodec, Versioned};
+
+// a value of any serde type, stored as JSON. bigger and slower than Bincode,
+// but fields can be added with defaults without breaking older values, and
+// values can be read by other tools. encoding panics for values JSON can't
+// hold, e.g. maps whose keys aren't strings
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize + DeserializeOwned> ValueCodec for Json<T> {
+    fn encode_value(&self) -> Vec<u8> {
+        serde_json::to_vec(&self.0).expect("value can be encoded as JSON")
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        serde_json::from_slice(bytes)
+            .map(Json)
+            .map_err(|err| anyhow!("value doesn't decode as JSON: {}", err))
+    }
+}
+
+// a value of any serde type, stored with bincode. compact, but the encoding
+// has no field names, so adding, removing or reordering fields makes older
+// values unreadable. wrap in Versioned to tell schemas apart. encoding panics
+// for the few types bincode can't describe, e.g. sequences of unknown length
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bincode<T>(pub T);
+
+impl<T: Serialize + DeserializeOwned> ValueCodec for Bincode<T> {
+    fn encode_value(&self) -> Vec<u8> {
+        bincode::serialize(&self.0).expect("value can be encoded with bincode")
+    }
+
+    fn decode_value(bytes: &[u8]) -> Result<Self> {
+        bincode::deserialize(bytes)
+            .map(Bincode)
+            .map_err(|err| anyhow!("value doesn't decode with bincode: {}", err))
+    }
+}
+
+// quick storage of structs without a TypedStore. values are written as a
+// Versioned, the schema version byte followed by the encoded value, and read
+// back with their version so that callers can migrate older ones. unlike the
+// codecs, a value that can't be encoded is an error rather than a panic
+impl LsmStore {
+    pub fn put_json<T: Serialize>(&self, key: impl AsRef<[u8]>, version: u8, value: &T) -> Result<()> {
+        let mut bytes = vec![version];
+        serde_json::to_writer(&mut bytes, value).map_err(|err| anyhow!("value can't be encoded as JSON: {}", err))?;
+        self.put(key, bytes)
+    }
+
+    pub fn get_json<T: DeserializeOwned>(&self, key: impl AsRef<[u8]>) -> Result<Option<Versioned<T>>> {
+        self.get_versioned(key.as_ref(), |bytes| {
+            serde_json::from_slice(bytes).map_err(|err| anyhow!("value doesn't decode as JSON: {}", err))
+        })
+    }
+
+    pub fn put_bincode<T: Serialize>(&self, key: impl AsRef<[u8]>, version: u8, value: &T) -> Result<()> {
+        let mut bytes = vec![version];
+        bincode::serialize_into(&mut bytes, value).map_err(|err| anyhow!("value can't be encoded with bincode: {}", err))?;
+        self.put(key, bytes)
+    }
+
+    pub fn get_bincode<T: DeserializeOwned>(&self, key: impl AsRef<[u8]>) -> Result<Option<Versioned<T>>> {
+        self.get_versioned(key.as_ref(), |bytes| {
+            bincode::deserialize(bytes).map_err(|err| anyhow!("value doesn't decode with bincode: {}", err))
+        })
+    }
+
+    fn get_versioned<T>(&self, key: &[u8], decode: impl FnOnce(&[u8]) -> Result<T>) -> Result<Option<Versioned<T>>> {
+        let Some(bytes) = self.get(key)? else {
+            return Ok(None);
+        };
+        let Versioned { version, value } = Versioned::<Bytes>::decode_value(&bytes)?;
+        Ok(Some(Versioned {
+            version,
+            value: decode(&value)?,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde::{Deserialize, Serialize};
+    use tempfile::tempdir;
+
+    use crate::{
+        state::storage_state_options::StorageStateOptions,
+        store::LsmStore,
+        typed::{TypedStore, ValueCodec, Versioned},
+    };
+
+    use super::{Bincode, Json};
+
+    #[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+    struct User {
+        name: String,
+        age: u32,
+        tags: Vec<String>,
+    }
+
+    #[test]
+    fn test_bincode() {
+        let user = Bincode(User {
+            name: "ada".to_string(),
+            age: 36,
+            tags: vec!["admin".to_string()],
+        });
+        assert_eq!(Bincode::<User>::decode_value(&user.encode_value()).unwrap(), user);
+        assert!(Bincode::<User>::decode_value(b"\x01").is_err());
+
+        let dir = tempdir().unwrap();
+        let store = Arc::new(
+            LsmStore::open(StorageStateOptions {
+                path: dir.path().to_owned(),
+                ..Default::default()
+            })
+            .unwrap(),
+        );
+        let typed: TypedStore<u64, Versioned<Bincode<User>>> = TypedStore::new(store.clone());
+        let versioned = Versioned { version: 1, value: user };
+        typed.put(&7, &versioned).unwrap();
+        assert_eq!(typed.get(&7).unwrap(), Some(versioned));
+        store.close().unwrap();
+    }
+
+    #[test]
+    fn test_serde_helpers() {
+        let dir = tempdir().unwrap();
+        let store = LsmStore::open(StorageStateOptions {
+            path: dir.path().to_owned(),
+            ..Default::default()
+        })
+        .unwrap();
+        let user = User {
+            name: "ada".to_string(),
+            age: 36,
+            tags: vec![],
+        };
+
+        store.put_json("json", 3, &user).unwrap();
+        assert_eq!(&store.get("json").unwrap().unwrap()[..2], b"\x03{");
+        let versioned = store.get_json::<User>("json").unwrap().unwrap();
+        assert_eq!(versioned.version, 3);
+        assert_eq!(versioned.value, user);
+        assert_eq!(Json::<User>::decode_value(&store.get("json").unwrap().unwrap()[1..]).unwrap().0, user);
+
+        store.put_bincode("bincode", 1, &user).unwrap();
+        let versioned = store.get_bincode::<User>("bincode").unwrap().unwrap();
+        assert_eq!(versioned, Versioned { version: 1, value: user });
+
+        assert!(store.get_json::<User>("missing").unwrap().is_none());
+        // the wrong encoding, or no version byte, doesn't decode
+        assert!(store.get_json::<User>("bincode").is_err());
+        store.put("empty", "").unwrap();
+        assert!(store.get_bincode::<User>("empty").is_err());
+
+        // JSON can't hold maps with non-string keys
+        let map = std::collections::BTreeMap::from([((1, 2), 3)]);
+        assert!(store.put_json("map", 1, &map).is_err());
+        store.close().unwrap();
+    }
+}
@@ -2,7 +2,7 @@ use std::ops::Bound;
 
 use crate::kv::timestamped_key::TimestampedKey;
 
-pub fn range_overlap(
+pub(crate) fn range_overlap(
     query_lower: Bound<&[u8]>,
     query_upper: Bound<&[u8]>,
     target_lower: TimestampedKey,
@@ -14,20 +14,29 @@ pub fn range_overlap(
         Bound::Unbounded => { false }
     };
     let disjoint_greater = match query_lower {
-        Bound::Included(lower) => { lower >= target_upper.get_key() },
-        Bound::Excluded(lower) => { lower > target_upper.get_key() },
+        Bound::Included(lower) => { lower > target_upper.get_key() },
+        Bound::Excluded(lower) => { lower >= target_upper.get_key() },
         Bound::Unbounded => { false }
     };
     !disjoint_lesser && !disjoint_greater
 }
 
+// the smallest key past every key starting with prefix, None if there is
+// none, i.e. the prefix is empty or all 0xff
+pub(crate) fn prefix_upper_bound(prefix: &[u8]) -> Option<Vec<u8>> {
+    let last = prefix.iter().rposition(|byte| *byte != u8::MAX)?;
+    let mut upper = prefix[..=last].to_vec();
+    upper[last] += 1;
+    Some(upper)
+}
+
 #[cfg(test)]
 mod tests {
     use std::ops::Bound::{Excluded, Included};
 
     use crate::kv::timestamped_key::TimestampedKey;
 
-    use super::range_overlap;
+    use super::{prefix_upper_bound, range_overlap};
 
     #[test]
     fn test_range_overlap() {
@@ -77,6 +86,25 @@ mod tests {
             TimestampedKey::new("k1".as_bytes().into()), 
             TimestampedKey::new("k2".as_bytes().into())
         ));
+        assert!(range_overlap(
+            Included("k2".as_bytes()), 
+            Included("k3".as_bytes()), 
+            TimestampedKey::new("k1".as_bytes().into()), 
+            TimestampedKey::new("k2".as_bytes().into())
+        ));
+        assert!(!range_overlap(
+            Excluded("k2".as_bytes()), 
+            Included("k3".as_bytes()), 
+            TimestampedKey::new("k1".as_bytes().into()), 
+            TimestampedKey::new("k2".as_bytes().into())
+        ));
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_prefix_upper_bound() {
+        assert_eq!(prefix_upper_bound(b"ab"), Some(b"ac".to_vec()));
+        assert_eq!(prefix_upper_bound(b"a\xff\xff"), Some(b"b".to_vec()));
+        assert_eq!(prefix_upper_bound(b"\xff"), None);
+        assert_eq!(prefix_upper_bound(b""), None);
+    }
+}